@@ -0,0 +1,193 @@
+//! Property check of the archive pipeline: random entry sets must restore unchanged through
+//! every compressor and encryptor built in. Age passphrases take about a second each way.
+//!
+//! `cargo run --release --example roundtrip -- [iterations] [seed]`
+use k_backup::backup::compress::CompressorConfig;
+use k_backup::backup::encrypt::EncryptorConfig;
+use k_backup::backup::roundtrip::{roundtrip, RoundtripEntry};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Compressors and encryptors to combine, the ones of disabled features are skipped.
+static COMPRESSORS: [&str; 5] = [
+    "compressor_type: none",
+    "compressor_type: gzip",
+    "compressor_type: xz",
+    "compressor_type: zstd",
+    "{compressor_type: zstd, seekable: true, frame_size: 4096}",
+];
+static ENCRYPTORS: [&str; 3] = [
+    "encryptor_type: none",
+    "{encryptor_type: age, secret_type: passphrase, passphrase: roundtrip-secret}",
+    "{encryptor_type: age, secret_type: passphrase, passphrase: roundtrip-secret, armor: true}",
+];
+static NAME_PARTS: [&str; 10] = [
+    "a",
+    "file",
+    "données",
+    "日本語",
+    "🦀",
+    "with space",
+    "dot.ted",
+    "-dash",
+    "x_y",
+    "ünï",
+];
+
+/// Xorshift, enough to replay a failing case from its seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+}
+
+fn random_name(rng: &mut Rng) -> String {
+    let mut name = String::new();
+    for _ in 0..=rng.below(3) {
+        name.push_str(NAME_PARTS[rng.below(NAME_PARTS.len() as u64) as usize]);
+    }
+    // Long names need GNU long name records, past 100 and 255 bytes
+    match rng.below(8) {
+        0 => name.push_str(&"l".repeat(100 + rng.below(200) as usize)),
+        1 => name.push_str(&"é".repeat(60 + rng.below(100) as usize)),
+        _ => {}
+    }
+    name
+}
+
+fn random_size(rng: &mut Rng) -> usize {
+    match rng.below(6) {
+        0 => 0,
+        // Around tar block boundaries
+        1 => 512 * rng.below(4) as usize + rng.below(3) as usize,
+        2 => rng.below(300_000) as usize,
+        _ => rng.below(2048) as usize,
+    }
+}
+
+fn random_entries(rng: &mut Rng) -> Vec<RoundtripEntry> {
+    let mut entries = Vec::new();
+    let mut paths = HashSet::new();
+    let mut dirs = vec![PathBuf::new()];
+    for _ in 0..rng.below(40) {
+        let parent = dirs[rng.below(dirs.len() as u64) as usize].clone();
+        let path = parent.join(random_name(rng));
+        if !paths.insert(path.clone()) {
+            continue;
+        }
+        if rng.below(4) == 0 {
+            dirs.push(path.clone());
+            entries.push(RoundtripEntry { path, data: None });
+        } else {
+            let data = (0..random_size(rng)).map(|_| rng.next() as u8).collect();
+            entries.push(RoundtripEntry {
+                path,
+                data: Some(data),
+            });
+        }
+    }
+    entries
+}
+
+fn holds(
+    entries: &[RoundtripEntry],
+    compressor: &CompressorConfig,
+    encryptor: &EncryptorConfig,
+) -> Result<(), String> {
+    match roundtrip(entries, compressor, encryptor) {
+        Ok(restored) if restored == entries => Ok(()),
+        Ok(restored) => Err(format!(
+            "restored {} entries differ, first mismatch {:?}",
+            restored.len(),
+            restored
+                .iter()
+                .zip(entries)
+                .find(|(a, b)| a != b)
+                .map(|(a, _)| &a.path)
+        )),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Drop entries one by one while the check still fails, for a small reproduction.
+fn shrink(
+    mut entries: Vec<RoundtripEntry>,
+    compressor: &CompressorConfig,
+    encryptor: &EncryptorConfig,
+) -> Vec<RoundtripEntry> {
+    let mut idx = 0;
+    while idx < entries.len() {
+        let mut candidate = entries.clone();
+        candidate.remove(idx);
+        if holds(&candidate, compressor, encryptor).is_err() {
+            entries = candidate;
+        } else {
+            idx += 1;
+        }
+    }
+    entries
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let iterations: u64 = args
+        .next()
+        .map(|n| n.parse().expect("iterations must be a number"))
+        .unwrap_or(4);
+    let seed: u64 = args
+        .next()
+        .map(|n| n.parse().expect("seed must be a number"))
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64
+        })
+        .max(1);
+    let compressors: Vec<CompressorConfig> = COMPRESSORS
+        .iter()
+        .filter_map(|c| serde_yml::from_str(c).ok())
+        .collect();
+    let encryptors: Vec<EncryptorConfig> = ENCRYPTORS
+        .iter()
+        .filter_map(|e| serde_yml::from_str(e).ok())
+        .collect();
+    println!(
+        "seed {seed}: {iterations} entry sets through {} compressors and {} encryptors",
+        compressors.len(),
+        encryptors.len()
+    );
+
+    let mut rng = Rng(seed);
+    for iteration in 0..iterations {
+        let entries = random_entries(&mut rng);
+        for compressor in &compressors {
+            for encryptor in &encryptors {
+                if let Err(e) = holds(&entries, compressor, encryptor) {
+                    let shrunk = shrink(entries.clone(), compressor, encryptor);
+                    eprintln!(
+                        "iteration {iteration} of seed {seed} failed with {compressor:?} and {encryptor:?}: {e}"
+                    );
+                    eprintln!(
+                        "smallest failing entry set: {:?}",
+                        shrunk
+                            .iter()
+                            .map(|e| (&e.path, e.data.as_ref().map(Vec::len)))
+                            .collect::<Vec<_>>()
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        println!("entry set {iteration}: {} entries restored", entries.len());
+    }
+}
@@ -0,0 +1,41 @@
+//! Compare archiving many tiny files with and without `pack_small_files`.
+//!
+//! `cargo run --release --example small_files -- [file_count]`
+use chrono::Utc;
+use k_backup::backup::backup_config::BackupConfig;
+use rayon::ThreadPoolBuilder;
+use std::sync::Arc;
+use std::time::Instant;
+
+fn main() {
+    let file_count: usize = std::env::args()
+        .nth(1)
+        .map(|n| n.parse().expect("file_count must be a number"))
+        .unwrap_or(100_000);
+    let src_dir = tempfile::tempdir().unwrap();
+    for i in 0..file_count {
+        let dir = src_dir.path().join(format!("{:03}", i % 1000));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(format!("{i}.txt")), format!("small file {i}\n")).unwrap();
+    }
+    let pool = Arc::new(ThreadPoolBuilder::new().build().unwrap());
+
+    for pack in [false, true] {
+        let out_dir = tempfile::tempdir().unwrap();
+        let config: BackupConfig = serde_yml::from_str(&format!(
+            "cron: \"0 1 * * *\"\narchive_base_name: bench\nout_dir: {:?}\nfiles:\n  - type: glob\n    src_dir: {:?}\ncompressor:\n  compressor_type: none\nencryptor:\n  encryptor_type: none\n{}",
+            out_dir.path(),
+            src_dir.path(),
+            if pack { "pack_small_files: {}\n" } else { "" }
+        ))
+        .unwrap();
+        let started = Instant::now();
+        let (archive, non_fatal_error) = config.create_archive(Utc::now(), pool.clone()).unwrap();
+        assert!(non_fatal_error.is_none(), "{non_fatal_error:?}");
+        println!(
+            "pack_small_files={pack}: {file_count} files in {:?}, archive {} bytes",
+            started.elapsed(),
+            std::fs::metadata(archive).unwrap().len()
+        );
+    }
+}
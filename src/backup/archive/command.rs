@@ -0,0 +1,133 @@
+use crate::backup::archive::{
+    ArchiveEntry, ArchiveEntryIterable, PlannedEntry, PlannedEntryIterator,
+};
+use crate::backup::hook::CommandHook;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+static DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+static SPOOL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Output of a command archived as a file, e.g. `crontab -l` or `docker inspect`. The command
+/// failing or timing out fails this entry only.
+///
+/// Output is spooled to a file readable by the owner only, as it may hold secrets, and deleted
+/// once archived.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CommandSource {
+    #[serde(flatten)]
+    command: CommandHook,
+    dst: Arc<Path>,
+    /// Directory output is spooled to instead of the system temp dir.
+    #[serde(skip)]
+    snapshot_dir: Option<Arc<Path>>,
+}
+
+impl CommandSource {
+    pub fn dst(&self) -> &Path {
+        &self.dst
+    }
+
+    pub fn with_snapshot_dir(&self, snapshot_dir: Arc<Path>) -> Self {
+        Self {
+            snapshot_dir: Some(snapshot_dir),
+            ..self.clone()
+        }
+    }
+
+    fn spool_path(&self) -> Result<PathBuf> {
+        let dir = match &self.snapshot_dir {
+            None => std::env::temp_dir(),
+            Some(snapshot_dir) => {
+                std::fs::create_dir_all(snapshot_dir)?;
+                snapshot_dir.to_path_buf()
+            }
+        };
+        let path = dir.join(format!(
+            "k_backup-{}-{}.out",
+            std::process::id(),
+            SPOOL_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        Ok(path)
+    }
+
+    /// Path of the spooled stdout of the command, which must exit successfully within the
+    /// timeout.
+    fn capture(&self) -> Result<PathBuf> {
+        let path = self.spool_path()?;
+        let res = self.capture_into(&path);
+        if res.is_err() {
+            let _ = std::fs::remove_file(&path);
+        }
+        res.map(|_| path)
+    }
+
+    fn capture_into(&self, path: &Path) -> Result<()> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        let mut child = self
+            .command
+            .to_command()
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdout is not piped"))?;
+        // Read while waiting, a full pipe would block the command forever
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || tx.send(std::io::copy(&mut stdout, &mut file)));
+
+        let started = Instant::now();
+        self.command.wait(&mut child, DEFAULT_TIMEOUT)?;
+        // Processes started by the command may keep stdout open after it exits
+        let timeout = self.command.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        match rx.recv_timeout(timeout.saturating_sub(started.elapsed())) {
+            Ok(res) => res.map(|_| ()).map_err(Error::from),
+            Err(RecvTimeoutError::Timeout) => Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("stdout of {:?} still open after {timeout:?}", self.command),
+            )
+            .into()),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(std::io::Error::other("stdout reader panicked").into())
+            }
+        }
+    }
+}
+
+impl ArchiveEntryIterable for CommandSource {
+    fn archive_entry_iterator(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = Result<ArchiveEntry>> + Send>> {
+        let entry = self
+            .capture()
+            .map(|path| ArchiveEntry::delete_src(path, self.dst.clone()));
+        Ok(Box::new(std::iter::once(entry)))
+    }
+
+    fn planned_entries(&self) -> Result<PlannedEntryIterator> {
+        Ok(Box::new(std::iter::once(Ok(PlannedEntry {
+            src: Path::new(self.command.command.as_ref()).into(),
+            dst: self.dst.clone(),
+            size: None,
+        }))))
+    }
+}
@@ -0,0 +1,213 @@
+use crate::backup::archive::walkdir_globset::WalkdirAndGlobsetSource;
+use crate::backup::archive::{
+    ArchiveEntry, ArchiveEntryIterable, PlannedEntry, PlannedEntryIterator,
+};
+use crate::backup::report::SpecialFileStats;
+use crate::backup::result_error::result::Result;
+use crate::backup::storage::http::{curl, output, uri_encode, Body};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+static DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// Bind mounts and volumes of containers, found through the Docker Engine API at each backup,
+/// so compose stacks are backed up without path lists going stale. Podman serves the same API
+/// on its socket. Files of a container are stored under
+/// `<dst_dir>/<container name>/<mount destination>`.
+#[skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContainerSource {
+    /// Names of the containers, stopped ones included.
+    names: Option<Vec<Arc<str>>>,
+    /// Labels the containers all have, `key` or `key=value`, e.g.
+    /// `com.docker.compose.project=nextcloud`.
+    labels: Option<Vec<Arc<str>>>,
+    /// Socket of the engine API, `/var/run/docker.sock` by default, e.g.
+    /// `/run/podman/podman.sock`.
+    socket: Option<Arc<Path>>,
+    dst_dir: Option<Arc<Path>>,
+    /// Archive named volumes besides bind mounts. On by default.
+    volumes: Option<bool>,
+    /// Path to the curl binary.
+    command: Option<Arc<str>>,
+    #[serde(skip)]
+    excluded_dirs: Option<Arc<Vec<PathBuf>>>,
+    #[serde(skip)]
+    special_files: Option<Arc<SpecialFileStats>>,
+}
+
+/// Container as listed by `GET /containers/json`.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Container {
+    names: Vec<String>,
+    #[serde(default)]
+    mounts: Vec<Mount>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Mount {
+    #[serde(rename = "Type")]
+    kind: String,
+    source: PathBuf,
+    destination: PathBuf,
+}
+
+impl ContainerSource {
+    pub fn dst_dir(&self) -> &Path {
+        self.dst_dir.as_deref().unwrap_or(Path::new(""))
+    }
+
+    /// Whether the source selects containers, by name or label.
+    pub fn has_selector(&self) -> bool {
+        self.names.as_ref().is_some_and(|names| !names.is_empty())
+            || self
+                .labels
+                .as_ref()
+                .is_some_and(|labels| !labels.is_empty())
+    }
+
+    pub fn with_excluded_dirs(&self, excluded_dirs: Arc<Vec<PathBuf>>) -> Self {
+        Self {
+            excluded_dirs: Some(excluded_dirs),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_special_file_stats(&self, special_files: Arc<SpecialFileStats>) -> Self {
+        Self {
+            special_files: Some(special_files),
+            ..self.clone()
+        }
+    }
+
+    /// Containers matching the selectors, running or not.
+    fn containers(&self) -> Result<Vec<Container>> {
+        let mut filters = BTreeMap::new();
+        if let Some(labels) = self.labels.as_ref().filter(|labels| !labels.is_empty()) {
+            filters.insert("label", labels.clone());
+        }
+        let url = format!(
+            "http://localhost/containers/json?all=true&filters={}",
+            uri_encode(&serde_json::to_string(&filters)?, false)
+        );
+        let socket = self.socket.as_deref().unwrap_or(Path::new(DEFAULT_SOCKET));
+        let mut request = curl(self.command.as_deref(), "GET", &[]);
+        request.command.arg("--unix-socket").arg(socket).arg(url);
+        let output = output(request, &Body::None)?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "listing containers from {socket:?} failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stdout).trim()
+            )))?;
+        }
+        let mut containers: Vec<Container> = serde_json::from_slice(&output.stdout)?;
+        // The name filter of the API matches substrings
+        if let Some(names) = self.names.as_ref().filter(|names| !names.is_empty()) {
+            containers.retain(|c| names.iter().any(|name| c.name() == name.as_ref()));
+        }
+        if containers.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "no container matches names {:?} and labels {:?}",
+                    self.names.as_deref().unwrap_or_default(),
+                    self.labels.as_deref().unwrap_or_default()
+                ),
+            ))?;
+        }
+        Ok(containers)
+    }
+
+    /// Host path and archive directory of each mount of the selected containers.
+    fn mounts(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let volumes = self.volumes.unwrap_or(true);
+        let mut mounts = Vec::new();
+        for container in self.containers()? {
+            let dst_dir = self.dst_dir().join(container.name());
+            let selected = container
+                .mounts
+                .into_iter()
+                .filter(|m| m.kind == "bind" || (volumes && m.kind == "volume"));
+            for mount in selected {
+                let destination = mount
+                    .destination
+                    .strip_prefix("/")
+                    .unwrap_or(&mount.destination);
+                let dst = dst_dir.join(destination);
+                mounts.push((mount.source, dst));
+            }
+        }
+        Ok(mounts)
+    }
+
+    /// Items of the mount of `src` stored at `dst`, from `walk` for directories and from
+    /// `file` for single files, e.g. a bind mounted config. Sockets such as a mounted docker
+    /// socket are counted as special files and skipped.
+    fn mount_items<T: Send + 'static>(
+        &self,
+        src: PathBuf,
+        dst: PathBuf,
+        walk: impl Fn(&WalkdirAndGlobsetSource) -> Result<Box<dyn Iterator<Item = Result<T>> + Send>>,
+        file: impl Fn(ArchiveEntry) -> Result<T>,
+    ) -> Box<dyn Iterator<Item = Result<T>> + Send> {
+        let metadata = match std::fs::metadata(&src) {
+            Ok(metadata) => metadata,
+            Err(e) => return Box::new(std::iter::once(Err(e.into()))),
+        };
+        if metadata.is_dir() {
+            let mut source = WalkdirAndGlobsetSource::new(src, Some(dst.into()), None);
+            if let Some(excluded_dirs) = &self.excluded_dirs {
+                source = source.with_excluded_dirs(excluded_dirs.clone());
+            }
+            if let Some(special_files) = &self.special_files {
+                source = source.with_special_file_stats(special_files.clone());
+            }
+            return walk(&source).unwrap_or_else(|e| Box::new(std::iter::once(Err(e))));
+        }
+        if metadata.is_file() {
+            return Box::new(std::iter::once(file(ArchiveEntry::keep_src(src, dst))));
+        }
+        if let Some(special_files) = &self.special_files {
+            special_files.record(&metadata.file_type());
+        }
+        Box::new(std::iter::empty())
+    }
+}
+
+impl Container {
+    /// First name, without the leading `/` of the API.
+    fn name(&self) -> &str {
+        self.names
+            .first()
+            .map(|name| name.trim_start_matches('/'))
+            .unwrap_or_default()
+    }
+}
+
+impl ArchiveEntryIterable for ContainerSource {
+    fn archive_entry_iterator(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = Result<ArchiveEntry>> + Send>> {
+        // Mounts are walked one after the other as entries are consumed
+        let source = self.clone();
+        Ok(Box::new(self.mounts()?.into_iter().flat_map(
+            move |(src, dst)| source.mount_items(src, dst, |s| s.archive_entry_iterator(), Ok),
+        )))
+    }
+
+    fn planned_entries(&self) -> Result<PlannedEntryIterator> {
+        let source = self.clone();
+        Ok(Box::new(self.mounts()?.into_iter().flat_map(
+            move |(src, dst)| {
+                source.mount_items(src, dst, |s| s.planned_entries(), PlannedEntry::of)
+            },
+        )))
+    }
+}
@@ -0,0 +1,61 @@
+use crate::backup::archive::ArchiveSourceConfig;
+use std::collections::HashMap;
+use std::sync::Arc;
+use validator::ValidationError;
+
+/// Group sources into layers so that every source only depends on sources of earlier layers.
+/// Sources within a layer have no ordering between each other and may be collected in parallel.
+pub fn dependency_layers(
+    sources: &[ArchiveSourceConfig],
+) -> Result<Vec<Vec<usize>>, ValidationError> {
+    let mut name_to_idx: HashMap<&Arc<str>, usize> = HashMap::new();
+    for (idx, source) in sources.iter().enumerate() {
+        if let Some(name) = &source.name {
+            if name_to_idx.insert(name, idx).is_some() {
+                return Err(ValidationError::new("InvalidSourceDependency")
+                    .with_message(format!("Duplicated source name: {name:?}").into()));
+            }
+        }
+    }
+
+    let mut remaining_deps = Vec::with_capacity(sources.len());
+    let mut dependents = vec![Vec::new(); sources.len()];
+    for (idx, source) in sources.iter().enumerate() {
+        let deps = source.depends_on.as_deref().unwrap_or_default();
+        for dep in deps {
+            let dep_idx = *name_to_idx.get(dep).ok_or_else(|| {
+                ValidationError::new("InvalidSourceDependency")
+                    .with_message(format!("Unknown source name in depends_on: {dep:?}").into())
+            })?;
+            dependents[dep_idx].push(idx);
+        }
+        remaining_deps.push(deps.len());
+    }
+
+    let mut layers = Vec::new();
+    let mut current: Vec<usize> = (0..sources.len())
+        .filter(|idx| remaining_deps[*idx] == 0)
+        .collect();
+    let mut visited = 0;
+    while !current.is_empty() {
+        visited += current.len();
+        let mut next = Vec::new();
+        for idx in current.iter() {
+            for dependent in dependents[*idx].iter() {
+                remaining_deps[*dependent] -= 1;
+                if remaining_deps[*dependent] == 0 {
+                    next.push(*dependent);
+                }
+            }
+        }
+        layers.push(current);
+        current = next;
+    }
+
+    if visited != sources.len() {
+        return Err(ValidationError::new("InvalidSourceDependency")
+            .with_message("Circular dependency between sources".into()));
+    }
+
+    Ok(layers)
+}
@@ -0,0 +1,93 @@
+use crate::backup::archive::{ArchiveEntry, ArchiveEntryIterable, PlannedEntryIterator};
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use serde_yml::Value;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+
+/// Source built by an [`ExternalSourceFactory`].
+pub type BoxedArchiveEntryIterable = Box<dyn ArchiveEntryIterable + Send + Sync>;
+
+/// Builds the source of an `external` config from its `dst_dir` and `options`.
+pub type ExternalSourceFactory =
+    Arc<dyn Fn(&Path, &Value) -> Result<BoxedArchiveEntryIterable> + Send + Sync>;
+
+static FACTORIES: OnceLock<RwLock<HashMap<Arc<str>, ExternalSourceFactory>>> = OnceLock::new();
+
+fn factories() -> &'static RwLock<HashMap<Arc<str>, ExternalSourceFactory>> {
+    FACTORIES.get_or_init(Default::default)
+}
+
+/// Let `files` of configs use sources of type `external` with `kind`, built by `factory`. Meant
+/// for crates using k_backup as a library to add their own sources, it must be called before
+/// configs using them are validated. Registering a kind again replaces its factory.
+pub fn register_external_source<F>(kind: &str, factory: F)
+where
+    F: Fn(&Path, &Value) -> Result<BoxedArchiveEntryIterable> + Send + Sync + 'static,
+{
+    factories()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(kind.into(), Arc::new(factory));
+}
+
+/// Source of a kind registered with [`register_external_source`], e.g. a proprietary database.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ExternalSource {
+    kind: Arc<str>,
+    /// Directory of the archive the entries are stored under, the source is expected to use it.
+    dst_dir: Arc<Path>,
+    /// Settings of the source, passed to its factory as is.
+    #[serde(default)]
+    options: Value,
+}
+
+impl ExternalSource {
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn dst_dir(&self) -> &Path {
+        &self.dst_dir
+    }
+
+    pub fn is_registered(&self) -> bool {
+        factories()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(&self.kind)
+    }
+
+    fn build(&self) -> Result<BoxedArchiveEntryIterable> {
+        let factory = factories()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&self.kind)
+            .cloned()
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("no external source is registered as {:?}", self.kind),
+                )
+            })?;
+        factory(&self.dst_dir, &self.options)
+    }
+}
+
+impl ArchiveEntryIterable for ExternalSource {
+    fn archive_entry_iterator(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = Result<ArchiveEntry>> + Send>> {
+        self.build()?.archive_entry_iterator()
+    }
+
+    fn planned_entries(&self) -> Result<PlannedEntryIterator> {
+        self.build()?.planned_entries()
+    }
+
+    fn is_volatile(&self) -> bool {
+        self.build().is_ok_and(|source| source.is_volatile())
+    }
+}
@@ -1,21 +1,136 @@
+pub mod command;
+pub mod container;
+pub mod dependency;
+pub mod external;
+pub mod ownership;
+#[cfg(feature = "sqlite")]
 pub mod sqlite;
 pub mod walkdir_globset;
 
+use crate::backup::archive::command::CommandSource;
+use crate::backup::archive::container::ContainerSource;
+use crate::backup::archive::external::ExternalSource;
+use crate::backup::archive::ownership::OwnershipConfig;
+#[cfg(feature = "sqlite")]
 use crate::backup::archive::sqlite::SqliteDBSource;
 use crate::backup::archive::walkdir_globset::WalkdirAndGlobsetSource;
+use crate::backup::report::SpecialFileStats;
 use crate::backup::result_error::result::Result;
 use crate::backup::result_error::WithDebugObjectAndFnName;
 use derive_more::From;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use serde_with::skip_serializing_none;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 #[derive(Clone, From, Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum ArchiveEntryConfig {
+    #[cfg(feature = "sqlite")]
     Sqlite(SqliteDBSource),
     Glob(WalkdirAndGlobsetSource),
+    Command(CommandSource),
+    External(ExternalSource),
+    Container(ContainerSource),
+}
+
+impl ArchiveEntryConfig {
+    /// Copy of the source that never collects from the given resolved directories.
+    pub fn with_excluded_dirs(&self, excluded_dirs: Arc<Vec<PathBuf>>) -> Self {
+        match self {
+            #[cfg(feature = "sqlite")]
+            ArchiveEntryConfig::Sqlite(_) => self.clone(),
+            ArchiveEntryConfig::Glob(c) => c.with_excluded_dirs(excluded_dirs).into(),
+            ArchiveEntryConfig::Container(c) => c.with_excluded_dirs(excluded_dirs).into(),
+            ArchiveEntryConfig::Command(_) | ArchiveEntryConfig::External(_) => self.clone(),
+        }
+    }
+
+    /// Copy of the source writing intermediate snapshots to `snapshot_dir`.
+    pub fn with_snapshot_dir(&self, snapshot_dir: Arc<Path>) -> Self {
+        match self {
+            #[cfg(feature = "sqlite")]
+            ArchiveEntryConfig::Sqlite(c) => c.with_snapshot_dir(snapshot_dir).into(),
+            ArchiveEntryConfig::Command(c) => c.with_snapshot_dir(snapshot_dir).into(),
+            ArchiveEntryConfig::Glob(_)
+            | ArchiveEntryConfig::External(_)
+            | ArchiveEntryConfig::Container(_) => self.clone(),
+        }
+    }
+
+    /// Copy of the source keeping snapshots reused by the next run in `warm_dir`.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    pub fn with_warm_dir(&self, warm_dir: Arc<Path>) -> Self {
+        match self {
+            #[cfg(feature = "sqlite")]
+            ArchiveEntryConfig::Sqlite(c) => c.with_warm_dir(warm_dir).into(),
+            ArchiveEntryConfig::Glob(_)
+            | ArchiveEntryConfig::Command(_)
+            | ArchiveEntryConfig::External(_)
+            | ArchiveEntryConfig::Container(_) => self.clone(),
+        }
+    }
+
+    /// Copy of the source counting skipped special files into `special_files`.
+    pub fn with_special_file_stats(&self, special_files: Arc<SpecialFileStats>) -> Self {
+        match self {
+            #[cfg(feature = "sqlite")]
+            ArchiveEntryConfig::Sqlite(_) => self.clone(),
+            ArchiveEntryConfig::Glob(c) => c.with_special_file_stats(special_files).into(),
+            ArchiveEntryConfig::Container(c) => c.with_special_file_stats(special_files).into(),
+            ArchiveEntryConfig::Command(_) | ArchiveEntryConfig::External(_) => self.clone(),
+        }
+    }
+
+    /// Destination path in the archive of the entries of the source, or the directory they are
+    /// stored under.
+    pub fn dst_prefix(&self) -> &Path {
+        match self {
+            #[cfg(feature = "sqlite")]
+            ArchiveEntryConfig::Sqlite(c) => c.dst(),
+            ArchiveEntryConfig::Glob(c) => c.dst_dir(),
+            ArchiveEntryConfig::Command(c) => c.dst(),
+            ArchiveEntryConfig::External(c) => c.dst_dir(),
+            ArchiveEntryConfig::Container(c) => c.dst_dir(),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "sqlite")]
+            ArchiveEntryConfig::Sqlite(_) => "sqlite",
+            ArchiveEntryConfig::Glob(_) => "glob",
+            ArchiveEntryConfig::Command(_) => "command",
+            ArchiveEntryConfig::External(_) => "external",
+            ArchiveEntryConfig::Container(_) => "container",
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ArchiveSourceConfig {
+    pub name: Option<Arc<str>>,
+    pub depends_on: Option<Vec<Arc<str>>>,
+    pub ownership: Option<Arc<OwnershipConfig>>,
+    /// Content is already compressed, e.g. `pg_dump -Fc`, and is stored without going through
+    /// the compressor of the archive.
+    pub precompressed: Option<bool>,
+    #[serde(flatten)]
+    pub source: ArchiveEntryConfig,
+}
+
+/// Data size and modification time in seconds of an archived entry, as in its tar header.
+#[derive(Clone, Copy, Debug)]
+pub struct EntryStat {
+    pub size: u64,
+    pub mtime: u64,
 }
 
 #[derive(Debug)]
@@ -23,6 +138,13 @@ pub struct ArchiveEntry {
     pub src: Arc<Path>,
     pub dst: Arc<Path>,
     pub delete_src: bool,
+    pub ownership: Option<Arc<OwnershipConfig>>,
+    /// Content captured in memory, e.g. command output, archived instead of reading `src`.
+    pub data: Option<Arc<[u8]>>,
+    /// Stored without compression, see [`ArchiveSourceConfig::precompressed`].
+    pub precompressed: bool,
+    /// Byte counter of the stats of the source, see [`ArchiveEntry::record_archived`].
+    pub archived_bytes: Option<Arc<AtomicU64>>,
 }
 
 impl ArchiveEntry {
@@ -35,22 +157,182 @@ impl ArchiveEntry {
             src: src.into(),
             dst: dst.into(),
             delete_src,
+            ownership: None,
+            data: None,
+            precompressed: false,
+            archived_bytes: None,
+        }
+    }
+
+    /// Entry archiving `data` as a file owned by this process, `src` only names its origin.
+    pub fn captured<A: Into<Arc<Path>>, B: Into<Arc<Path>>>(
+        src: A,
+        dst: B,
+        data: Vec<u8>,
+    ) -> ArchiveEntry {
+        Self {
+            data: Some(data.into()),
+            ..Self::new(src, dst, false)
         }
     }
 
-    fn keep_src<A: Into<Arc<Path>>, B: Into<Arc<Path>>>(src: A, dst: B) -> ArchiveEntry {
+    /// Entry archiving the file at `src`, which is left in place.
+    pub fn keep_src<A: Into<Arc<Path>>, B: Into<Arc<Path>>>(src: A, dst: B) -> ArchiveEntry {
         Self::new(src, dst, false)
     }
 
     fn delete_src<A: Into<Arc<Path>>, B: Into<Arc<Path>>>(src: A, dst: B) -> ArchiveEntry {
         Self::new(src, dst, true)
     }
+
+    fn with_ownership(self, ownership: Option<Arc<OwnershipConfig>>) -> ArchiveEntry {
+        Self { ownership, ..self }
+    }
+
+    fn with_precompressed(self, precompressed: bool) -> ArchiveEntry {
+        Self {
+            precompressed,
+            ..self
+        }
+    }
+
+    /// Add the archived size of this entry from its [`EntryStat`] to the stats of its source.
+    pub fn record_archived(&self, stat: &EntryStat) {
+        if let Some(archived_bytes) = &self.archived_bytes {
+            archived_bytes.fetch_add(stat.size, Ordering::Relaxed);
+        }
+    }
+
+    /// Append this entry to `builder`, following symlinks and rewriting owner if configured.
+    ///
+    /// Returns the archived data size and mtime, so callers need no extra stat per entry.
+    pub fn append_to<W: Write>(&self, builder: &mut tar::Builder<W>) -> Result<EntryStat> {
+        if let Some(data) = &self.data {
+            let mut header = self.captured_header()?;
+            builder.append_data(&mut header, &self.dst, data.as_ref())?;
+            return Ok(EntryStat {
+                size: data.len() as u64,
+                mtime: header.mtime()?,
+            });
+        }
+        let metadata = std::fs::metadata(&self.src)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, tar::HeaderMode::Complete);
+        if let Some(ownership) = &self.ownership {
+            ownership.apply(&mut header)?;
+        }
+        let mtime = header.mtime()?;
+        if metadata.is_dir() {
+            builder.append_data(&mut header, &self.dst, std::io::empty())?;
+            return Ok(EntryStat { size: 0, mtime });
+        }
+        // Bytes appended after the stat would not match the header size
+        let size = metadata.len();
+        builder.append_data(&mut header, &self.dst, File::open(&self.src)?.take(size))?;
+        Ok(EntryStat { size, mtime })
+    }
+
+    /// Header of captured `data`, readable by the owner only as command output may hold
+    /// secrets.
+    pub fn captured_header(&self) -> Result<tar::Header> {
+        // Owned by the process like the files it creates
+        let process = std::fs::metadata("/proc/self")?;
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(0o600);
+        header.set_uid(process.uid() as u64);
+        header.set_gid(process.gid() as u64);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        );
+        header.set_size(self.data.as_ref().map(|d| d.len()).unwrap_or_default() as u64);
+        if let Some(ownership) = &self.ownership {
+            ownership.apply(&mut header)?;
+        }
+        Ok(header)
+    }
 }
 
+/// Entry name of the pax global header, as written by `git archive`.
+static PAX_GLOBAL_HEADER_NAME: &str = "pax_global_header";
+
+/// Append a pax global header holding `records`, applying to every following entry.
+pub fn append_pax_global_header<W: Write>(
+    builder: &mut tar::Builder<W>,
+    records: &[(&str, &str)],
+) -> Result<()> {
+    let mut data = Vec::new();
+    for (key, value) in records {
+        // Record length includes its own decimal digits
+        let rest = format!(" {key}={value}\n");
+        let mut len = rest.len() + 1;
+        while len.to_string().len() + rest.len() != len {
+            len = len.to_string().len() + rest.len();
+        }
+        data.extend_from_slice(format!("{len}{rest}").as_bytes());
+    }
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XGlobalHeader);
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    builder.append_data(&mut header, PAX_GLOBAL_HEADER_NAME, data.as_slice())?;
+    Ok(())
+}
+
+/// Entry a source would archive, see [`ArchiveEntryIterable::planned_entries`].
+#[derive(Debug)]
+pub struct PlannedEntry {
+    pub src: Arc<Path>,
+    pub dst: Arc<Path>,
+    /// `None` when only known once captured, e.g. command output.
+    pub size: Option<u64>,
+}
+
+impl PlannedEntry {
+    fn of(entry: ArchiveEntry) -> Result<PlannedEntry> {
+        let size = match &entry.data {
+            Some(data) => data.len() as u64,
+            None => {
+                let metadata = std::fs::metadata(&entry.src)?;
+                if metadata.is_dir() {
+                    0
+                } else {
+                    metadata.len()
+                }
+            }
+        };
+        Ok(PlannedEntry {
+            src: entry.src,
+            dst: entry.dst,
+            size: Some(size),
+        })
+    }
+}
+
+pub type PlannedEntryIterator = Box<dyn Iterator<Item = Result<PlannedEntry>> + Send>;
+
 pub trait ArchiveEntryIterable {
     fn archive_entry_iterator(
         &self,
     ) -> Result<Box<dyn Iterator<Item = Result<ArchiveEntry>> + Send>>;
+
+    /// Entries `archive_entry_iterator` would yield, without side effects such as snapshots or
+    /// running commands.
+    fn planned_entries(&self) -> Result<PlannedEntryIterator> {
+        Ok(Box::new(
+            self.archive_entry_iterator()?
+                .map(|res| res.and_then(PlannedEntry::of)),
+        ))
+    }
+
+    /// Whether the source content changes while applications run, so it should be captured
+    /// inside the quiesce window.
+    fn is_volatile(&self) -> bool {
+        false
+    }
 }
 
 impl ArchiveEntryIterable for ArchiveEntryConfig {
@@ -58,9 +340,70 @@ impl ArchiveEntryIterable for ArchiveEntryConfig {
         &self,
     ) -> Result<Box<dyn Iterator<Item = Result<ArchiveEntry>> + Send>> {
         match self {
+            #[cfg(feature = "sqlite")]
             ArchiveEntryConfig::Sqlite(c) => c.archive_entry_iterator(),
             ArchiveEntryConfig::Glob(c) => c.archive_entry_iterator(),
+            ArchiveEntryConfig::Command(c) => c.archive_entry_iterator(),
+            ArchiveEntryConfig::External(c) => c.archive_entry_iterator(),
+            ArchiveEntryConfig::Container(c) => c.archive_entry_iterator(),
         }
         .with_debug_object_and_fn_name(self.clone(), "archive_entry_iterator")
     }
+
+    fn planned_entries(&self) -> Result<PlannedEntryIterator> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            ArchiveEntryConfig::Sqlite(c) => c.planned_entries(),
+            ArchiveEntryConfig::Glob(c) => c.planned_entries(),
+            ArchiveEntryConfig::Command(c) => c.planned_entries(),
+            ArchiveEntryConfig::External(c) => c.planned_entries(),
+            ArchiveEntryConfig::Container(c) => c.planned_entries(),
+        }
+        .with_debug_object_and_fn_name(self.clone(), "planned_entries")
+    }
+
+    fn is_volatile(&self) -> bool {
+        match self {
+            #[cfg(feature = "sqlite")]
+            ArchiveEntryConfig::Sqlite(c) => c.is_volatile(),
+            ArchiveEntryConfig::Glob(c) => c.is_volatile(),
+            ArchiveEntryConfig::Command(c) => c.is_volatile(),
+            ArchiveEntryConfig::External(c) => c.is_volatile(),
+            ArchiveEntryConfig::Container(c) => c.is_volatile(),
+        }
+    }
+}
+
+impl ArchiveEntryIterable for ArchiveSourceConfig {
+    fn archive_entry_iterator(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = Result<ArchiveEntry>> + Send>> {
+        let iter = self.source.archive_entry_iterator()?;
+        let iter: Box<dyn Iterator<Item = Result<ArchiveEntry>> + Send> =
+            match &self.ownership {
+                None => iter,
+                Some(ownership) => {
+                    let ownership = ownership.clone();
+                    Box::new(iter.map(move |res| {
+                        res.map(|entry| entry.with_ownership(Some(ownership.clone())))
+                    }))
+                }
+            };
+        match self.precompressed.unwrap_or(false) {
+            false => Ok(iter),
+            true => {
+                Ok(Box::new(iter.map(|res| {
+                    res.map(|entry| entry.with_precompressed(true))
+                })))
+            }
+        }
+    }
+
+    fn planned_entries(&self) -> Result<PlannedEntryIterator> {
+        self.source.planned_entries()
+    }
+
+    fn is_volatile(&self) -> bool {
+        self.source.is_volatile()
+    }
 }
@@ -0,0 +1,48 @@
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Owner rewriting of archived entries, so archives restore cleanly on hosts with different
+/// `/etc/passwd` contents.
+///
+/// Forced `uid`/`gid` win over `uid_map`/`gid_map`, ids missing from the maps are kept.
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct OwnershipConfig {
+    pub uid: Option<u64>,
+    pub gid: Option<u64>,
+    /// User name stored in the tar header, empty by default.
+    pub user_name: Option<Arc<str>>,
+    /// Group name stored in the tar header, empty by default.
+    pub group_name: Option<Arc<str>>,
+    pub uid_map: Option<HashMap<u64, u64>>,
+    pub gid_map: Option<HashMap<u64, u64>>,
+}
+
+impl OwnershipConfig {
+    pub fn map_uid(&self, uid: u64) -> u64 {
+        self.uid
+            .or_else(|| self.uid_map.as_ref().and_then(|m| m.get(&uid).copied()))
+            .unwrap_or(uid)
+    }
+
+    pub fn map_gid(&self, gid: u64) -> u64 {
+        self.gid
+            .or_else(|| self.gid_map.as_ref().and_then(|m| m.get(&gid).copied()))
+            .unwrap_or(gid)
+    }
+
+    pub fn apply(&self, header: &mut tar::Header) -> Result<()> {
+        header.set_uid(self.map_uid(header.uid()?));
+        header.set_gid(self.map_gid(header.gid()?));
+        if let Some(user_name) = &self.user_name {
+            header.set_username(user_name)?;
+        }
+        if let Some(group_name) = &self.group_name {
+            header.set_groupname(group_name)?;
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,274 @@
-use crate::backup::archive::{ArchiveEntry, ArchiveEntryIterable};
+use crate::backup::archive::{
+    ArchiveEntry, ArchiveEntryIterable, PlannedEntry, PlannedEntryIterator,
+};
+use crate::backup::checksum::sha256_hex;
+use crate::backup::result_error::error::Error;
 use crate::backup::result_error::result::Result;
 use rusqlite::{Connection, DatabaseName, OpenFlags};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::sync::Arc;
+use serde_with::skip_serializing_none;
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Once};
 use tempfile::Builder;
+use tracing::{info, warn};
 
+static SNAPSHOT_COUNTER: AtomicU64 = AtomicU64::new(0);
+static DEFAULT_FULL_EVERY: u32 = 30;
+static DBPAGE_MISSING_WARNING: Once = Once::new();
+
+#[skip_serializing_none]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SqliteDBSource {
     src: Arc<Path>,
     dst: Arc<Path>,
+    /// Keep the snapshot between runs and only write back pages changed since, see
+    /// [`SqliteIncrementalConfig`].
+    incremental: Option<SqliteIncrementalConfig>,
+    /// Directory snapshots are written to instead of the system temp dir.
+    #[serde(skip)]
+    snapshot_dir: Option<Arc<Path>>,
+    /// Directory of the snapshots kept between runs by `incremental`.
+    #[serde(skip)]
+    warm_dir: Option<Arc<Path>>,
+}
+
+/// Warm-start of SQLite snapshots. The previous snapshot is kept in the state dir and every page
+/// of the database is compared against it, writing only the changed ones, so large databases
+/// with few changes cost reads but hardly any writes. Archives still store the whole database.
+///
+/// Pages are read with the `sqlite_dbpage` virtual table inside one read transaction, which
+/// needs SQLite built with `SQLITE_ENABLE_DBPAGE_VTAB`, otherwise every run is a full backup.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SqliteIncrementalConfig {
+    /// Rebuild the kept snapshot with a full backup every this many runs, 30 by default.
+    pub full_every: Option<u32>,
+}
+
+impl SqliteDBSource {
+    pub fn new<A: Into<Arc<Path>>, B: Into<Arc<Path>>>(src: A, dst: B) -> Self {
+        Self {
+            src: src.into(),
+            dst: dst.into(),
+            incremental: None,
+            snapshot_dir: None,
+            warm_dir: None,
+        }
+    }
+
+    pub fn with_warm_dir(&self, warm_dir: Arc<Path>) -> Self {
+        Self {
+            warm_dir: Some(warm_dir),
+            ..self.clone()
+        }
+    }
+
+    pub fn dst(&self) -> &Path {
+        &self.dst
+    }
+
+    pub fn with_snapshot_dir(&self, snapshot_dir: Arc<Path>) -> Self {
+        Self {
+            snapshot_dir: Some(snapshot_dir),
+            ..self.clone()
+        }
+    }
+
+    fn snapshot_path(&self) -> Result<PathBuf> {
+        match &self.snapshot_dir {
+            None => Ok(Builder::new().keep(true).tempfile()?.path().to_path_buf()),
+            Some(snapshot_dir) => {
+                std::fs::create_dir_all(snapshot_dir)?;
+                let path = snapshot_dir.join(format!(
+                    "{}-{}.sqlite3",
+                    std::process::id(),
+                    SNAPSHOT_COUNTER.fetch_add(1, Ordering::Relaxed)
+                ));
+                File::create_new(&path)?;
+                Ok(path)
+            }
+        }
+    }
+
+    /// Update the kept snapshot of `incremental` from `conn`, returning its path.
+    fn update_warm_snapshot(
+        &self,
+        conn: &Connection,
+        incremental: &SqliteIncrementalConfig,
+        warm_dir: &Path,
+    ) -> Result<PathBuf> {
+        std::fs::create_dir_all(warm_dir)?;
+        let name = sha256_hex(self.src.as_os_str().as_encoded_bytes());
+        let warm_path = warm_dir.join(format!("{}.sqlite3", &name[..16]));
+        let runs_path = warm_path.with_extension("runs");
+        // Removed until the update completes, an interrupted update forces a full backup
+        let runs: Option<u32> = std::fs::read_to_string(&runs_path)
+            .ok()
+            .and_then(|r| r.trim().parse().ok());
+        let _ = std::fs::remove_file(&runs_path);
+
+        let full_every = incremental.full_every.unwrap_or(DEFAULT_FULL_EVERY);
+        let runs = runs.filter(|r| *r < full_every && warm_path.exists());
+        if !warm_path.exists() {
+            // Readable by the owner only, like temporary snapshots
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&warm_path)?;
+        }
+        let incremental_runs = runs.and_then(|runs| match copy_changed_pages(conn, &warm_path) {
+            Ok(Some((changed, total))) => {
+                info!(
+                    "Updated {changed} of {total} pages of the snapshot of {:?}",
+                    self.src
+                );
+                Some(runs + 1)
+            }
+            Ok(None) => None,
+            Err(e) if is_dbpage_missing(&e) => {
+                DBPAGE_MISSING_WARNING.call_once(|| {
+                    warn!(
+                        "SQLite is built without sqlite_dbpage, incremental snapshots fall back \
+                         to full backups"
+                    )
+                });
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Incremental snapshot of {:?} failed, taking a full backup: {e}",
+                    self.src
+                );
+                None
+            }
+        });
+        let runs = match incremental_runs {
+            Some(runs) => runs,
+            None => {
+                conn.backup(DatabaseName::Main, &warm_path, None)?;
+                0
+            }
+        };
+        std::fs::write(&runs_path, runs.to_string())?;
+        Ok(warm_path)
+    }
+}
+
+/// Results of checking a restored database, see [`verify_restored`].
+#[derive(Debug)]
+pub struct SqliteVerification {
+    /// Rows of `PRAGMA integrity_check`, only `ok` for a sound database.
+    pub integrity: Vec<String>,
+    /// Row count of each table.
+    pub tables: Vec<(String, u64)>,
+}
+
+impl SqliteVerification {
+    pub fn is_ok(&self) -> bool {
+        self.integrity == ["ok"]
+    }
+}
+
+impl Display for SqliteVerification {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.is_ok() {
+            true => write!(f, "integrity ok")?,
+            false => write!(f, "integrity check failed: {}", self.integrity.join("; "))?,
+        }
+        write!(f, ", {} tables", self.tables.len())?;
+        for (name, rows) in &self.tables {
+            write!(f, " {name}={rows}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `PRAGMA integrity_check` and count the rows of every table of the database restored at
+/// `path`, which is opened read only.
+pub fn verify_restored(path: &Path) -> Result<SqliteVerification> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    let integrity = conn
+        .prepare("PRAGMA integrity_check")?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    let names = conn
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' \
+             ORDER BY name",
+        )?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    let tables = names
+        .into_iter()
+        .map(|name| {
+            let sql = format!("SELECT count(*) FROM \"{}\"", name.replace('"', "\"\""));
+            let rows = conn.query_row(&sql, [], |r| r.get(0))?;
+            Ok((name, rows))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(SqliteVerification { integrity, tables })
+}
+
+fn is_dbpage_missing(e: &Error) -> bool {
+    matches!(e, Error::Rusqlite(rusqlite::Error::SqliteFailure(_, Some(msg))) if msg.contains("sqlite_dbpage"))
+}
+
+/// Write the pages of the database of `conn` differing from `warm_path`, returning the changed
+/// and total page count, or `None` when the page size changed and a full backup is needed.
+fn copy_changed_pages(conn: &Connection, warm_path: &Path) -> Result<Option<(u64, u64)>> {
+    let mut warm = OpenOptions::new().read(true).write(true).open(warm_path)?;
+    conn.execute_batch("BEGIN")?;
+    let res = (|| -> Result<Option<(u64, u64)>> {
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |r| r.get(0))?;
+        let mut warm_header = [0u8; 18];
+        warm.read_exact(&mut warm_header)?;
+        let warm_page_size = match u16::from_be_bytes([warm_header[16], warm_header[17]]) {
+            1 => 65536,
+            size => size as u64,
+        };
+        if warm_page_size != page_size {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare("SELECT pgno, data FROM sqlite_dbpage ORDER BY pgno")?;
+        let mut rows = stmt.query([])?;
+        let mut warm_page = vec![0u8; page_size as usize];
+        let (mut changed, mut total) = (0, 0);
+        while let Some(row) = rows.next()? {
+            let pgno: u64 = row.get(0)?;
+            let data: Vec<u8> = row.get(1)?;
+            if data.len() as u64 != page_size {
+                return Ok(None);
+            }
+            let offset = (pgno - 1) * page_size;
+            warm.seek(SeekFrom::Start(offset))?;
+            let same = match warm.read_exact(&mut warm_page) {
+                Ok(_) => warm_page == data,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+                Err(e) => return Err(e.into()),
+            };
+            if !same {
+                warm.seek(SeekFrom::Start(offset))?;
+                warm.write_all(&data)?;
+                changed += 1;
+            }
+            total = pgno;
+        }
+        warm.set_len(total * page_size)?;
+        warm.sync_all()?;
+        Ok(Some((changed, total)))
+    })();
+    conn.execute_batch("COMMIT")?;
+    res
 }
 
 impl ArchiveEntryIterable for SqliteDBSource {
@@ -21,7 +280,15 @@ impl ArchiveEntryIterable for SqliteDBSource {
             OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
         )?;
 
-        let temp_file_path = Builder::new().keep(true).tempfile()?.path().to_path_buf();
+        if let (Some(incremental), Some(warm_dir)) = (&self.incremental, &self.warm_dir) {
+            let warm_path = self.update_warm_snapshot(&conn, incremental, warm_dir)?;
+            return Ok(Box::new(std::iter::once(Ok(ArchiveEntry::keep_src(
+                warm_path,
+                self.dst.clone(),
+            )))));
+        }
+
+        let temp_file_path = self.snapshot_path()?;
         conn.backup(DatabaseName::Main, &temp_file_path, None)?;
         conn.backup(DatabaseName::Main, &temp_file_path, None)?;
         Ok(Box::new(std::iter::once(Ok(ArchiveEntry::delete_src(
@@ -29,4 +296,18 @@ impl ArchiveEntryIterable for SqliteDBSource {
             self.dst.clone(),
         )))))
     }
+
+    /// The snapshot is about the size of the database file.
+    fn planned_entries(&self) -> Result<PlannedEntryIterator> {
+        let size = std::fs::metadata(&self.src)?.len();
+        Ok(Box::new(std::iter::once(Ok(PlannedEntry {
+            src: self.src.clone(),
+            dst: self.dst.clone(),
+            size: Some(size),
+        }))))
+    }
+
+    fn is_volatile(&self) -> bool {
+        true
+    }
 }
@@ -1,27 +1,209 @@
 use crate::backup::archive::{ArchiveEntry, ArchiveEntryIterable};
+use crate::backup::report::SpecialFileStats;
 use crate::backup::result_error::error::Error;
 use crate::backup::result_error::WithDebugObjectAndFnName;
 use derive_more::{Display, From, Into};
-use globset::{Glob, GlobBuilder, GlobSetBuilder};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use jwalk::{Parallelism, WalkDir};
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_with::skip_serializing_none;
 use std::fmt::{Debug, Formatter};
-use std::path::Path;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use walkdir::WalkDir;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WalkdirAndGlobsetSource {
     src_dir: Arc<Path>,
     dst_dir: Option<Arc<Path>>,
+    #[serde(alias = "include")]
     globset: Option<Vec<CustomDeserializedGlob>>,
+    /// Patterns of paths relative to `src_dir` left out even when included, e.g.
+    /// `**/node_modules/**` or `**/*.tmp`. Directories matched themselves, e.g.
+    /// `**/node_modules`, are not walked into.
+    exclude: Option<Vec<CustomDeserializedGlob>>,
+    /// Follow symbolic links, archiving what they point to. On by default, symlinks not followed
+    /// are skipped like other special files.
+    follow_links: Option<bool>,
+    /// Levels walked below `src_dir`, 1 only takes the entries directly in it. Unlimited by
+    /// default.
+    max_depth: Option<usize>,
+    /// Do not cross file system boundaries (mount points) while walking.
+    same_file_system: Option<bool>,
+    /// Threads walking directories in parallel, 1 walks on the collecting thread. Defaults to the
+    /// number of CPUs.
+    walk_threads: Option<usize>,
+    /// Yield entries sorted by path within each directory, so archives of unchanged trees have
+    /// the same entry order.
+    deterministic: Option<bool>,
+    /// Files smaller than this many bytes are skipped.
+    min_size: Option<u64>,
+    /// Files larger than this many bytes are skipped, e.g. VM images next to the configs.
+    max_size: Option<u64>,
+    /// Files last modified longer ago than this are skipped, e.g. `30d` for old build artifacts.
+    #[serde(default, with = "humantime_serde")]
+    modified_within: Option<Duration>,
+    /// Resolved directories never walked into, e.g. the backup out_dir.
+    #[serde(skip)]
+    excluded_dirs: Option<Arc<Vec<PathBuf>>>,
+    /// Counters of matched entries skipped for not being regular files.
+    #[serde(skip)]
+    special_files: Option<Arc<SpecialFileStats>>,
+}
+
+impl WalkdirAndGlobsetSource {
+    pub fn new<A: Into<Arc<Path>>>(
+        src_dir: A,
+        dst_dir: Option<Arc<Path>>,
+        globset: Option<Vec<CustomDeserializedGlob>>,
+    ) -> Self {
+        Self {
+            src_dir: src_dir.into(),
+            dst_dir,
+            globset,
+            exclude: None,
+            follow_links: None,
+            max_depth: None,
+            same_file_system: None,
+            walk_threads: None,
+            deterministic: None,
+            min_size: None,
+            max_size: None,
+            modified_within: None,
+            excluded_dirs: None,
+            special_files: None,
+        }
+    }
+
+    pub fn src_dir(&self) -> &Path {
+        &self.src_dir
+    }
+
+    pub fn dst_dir(&self) -> &Path {
+        self.dst_dir.as_deref().unwrap_or(Path::new(""))
+    }
+
+    pub fn with_excluded_dirs(&self, excluded_dirs: Arc<Vec<PathBuf>>) -> Self {
+        Self {
+            excluded_dirs: Some(excluded_dirs),
+            ..self.clone()
+        }
+    }
+
+    /// Whether a file at `relative` below `src_dir` would be archived.
+    pub fn archives_path(&self, relative: &Path) -> bool {
+        let (globset, exclude) = self.globsets();
+        globset.is_match(relative) && !exclude.is_match(relative)
+    }
+
+    /// A path relative to `src_dir` matched by each include pattern, wildcards replaced by `x`.
+    pub fn sample_paths(&self) -> Vec<PathBuf> {
+        match self.globset.as_deref() {
+            Some(globs) if !globs.is_empty() => {
+                globs.iter().map(|g| sample_path(g.0.glob())).collect()
+            }
+            _ => vec![sample_path(CustomDeserializedGlob::default().0.glob())],
+        }
+    }
+
+    /// Include and exclude patterns, everything is included without any pattern.
+    fn globsets(&self) -> (GlobSet, GlobSet) {
+        let mut globset = GlobSetBuilder::new();
+        match self.globset.as_deref() {
+            Some(globs) if !globs.is_empty() => globs.iter().cloned().for_each(|glob| {
+                globset.add(glob.into());
+            }),
+            _ => {
+                globset.add(CustomDeserializedGlob::default().into());
+            }
+        }
+        let mut exclude = GlobSetBuilder::new();
+        self.exclude.iter().flatten().cloned().for_each(|glob| {
+            exclude.add(glob.into());
+        });
+        (globset.build().unwrap(), exclude.build().unwrap())
+    }
+
+    pub fn with_special_file_stats(&self, special_files: Arc<SpecialFileStats>) -> Self {
+        Self {
+            special_files: Some(special_files),
+            ..self.clone()
+        }
+    }
+}
+
+fn is_excluded_dir(path: &Path, excluded_dirs: &[PathBuf]) -> bool {
+    if excluded_dirs.is_empty() || !path.is_dir() {
+        return false;
+    }
+    let excluded = path
+        .canonicalize()
+        .map(|p| excluded_dirs.contains(&p))
+        .unwrap_or(false);
+    if excluded {
+        warn!("Excluding {path:?} from backup source, it is used by k_backup itself");
+    }
+    excluded
+}
+
+/// Path matched by `pattern`, taking `x` for wildcards, the first character of classes and the
+/// first alternative.
+fn sample_path(pattern: &str) -> PathBuf {
+    let mut sample = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                while chars.next_if_eq(&'*').is_some() {}
+                sample.push('x');
+            }
+            '?' => sample.push('x'),
+            '[' => {
+                match chars.next() {
+                    Some('!' | '^') | None => sample.push('x'),
+                    Some(c) => sample.push(c),
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            '{' => {
+                let mut skipping = false;
+                for c in chars.by_ref() {
+                    match c {
+                        '}' => break,
+                        ',' => skipping = true,
+                        c if !skipping => sample.push(c),
+                        _ => {}
+                    }
+                }
+            }
+            '\\' => sample.extend(chars.next()),
+            c => sample.push(c),
+        }
+    }
+    PathBuf::from(sample)
 }
 
 #[derive(Into, Clone, Serialize, From, Display)]
 pub struct CustomDeserializedGlob(Glob);
 
+impl CustomDeserializedGlob {
+    pub fn new(pattern: &str) -> Result<Self, globset::Error> {
+        GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map(CustomDeserializedGlob::from)
+    }
+}
+
 impl Default for CustomDeserializedGlob {
     fn default() -> Self {
         CustomDeserializedGlob(
@@ -52,11 +234,7 @@ impl<'de> Visitor<'de> for CustomGlobVisitor {
     where
         E: serde::de::Error,
     {
-        GlobBuilder::new(v)
-            .literal_separator(true)
-            .build()
-            .map(CustomDeserializedGlob::from)
-            .map_err(serde::de::Error::custom)
+        CustomDeserializedGlob::new(v).map_err(serde::de::Error::custom)
     }
 }
 
@@ -73,55 +251,125 @@ impl ArchiveEntryIterable for WalkdirAndGlobsetSource {
         Box<dyn Iterator<Item = crate::backup::result_error::result::Result<ArchiveEntry>> + Send>,
     > {
         if !self.src_dir.is_dir() {
-            return Err(Error::Io(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            return Err(Error::Io(std::io::Error::other(
                 "src_dir is not a directory",
             )));
         }
 
-        let mut globset = GlobSetBuilder::new();
-
-        if let Some(gs) = &self.globset {
-            if gs.is_empty() {
-                globset.add(CustomDeserializedGlob::default().into());
-            } else {
-                gs.iter().cloned().for_each(|glob| {
-                    globset.add(glob.into());
-                });
-            }
-        } else {
-            globset.add(CustomDeserializedGlob::default().into());
-        }
-
-        let globset = globset.build().unwrap();
+        let (globset, exclude) = self.globsets();
+        let exclude = Arc::new(exclude);
+        let exclude_clone = exclude.clone();
+        let src_dir_clone_3 = self.src_dir.clone();
         let src_dir_clone_1 = self.src_dir.clone();
         let src_dir_clone_2 = self.src_dir.clone();
         let dst_dir = self.dst_dir.clone().unwrap_or(Path::new("").into());
         let self_clone = Arc::new(self.clone());
+        let excluded_dirs = self.excluded_dirs.clone().unwrap_or_default();
+        let special_files = self.special_files.clone();
+        let size_range = self.min_size.unwrap_or(0)..=self.max_size.unwrap_or(u64::MAX);
+        let modified_after = self
+            .modified_within
+            .and_then(|d| SystemTime::now().checked_sub(d));
+        let stat_filtered =
+            self.min_size.is_some() || self.max_size.is_some() || modified_after.is_some();
+
+        if is_excluded_dir(&self.src_dir, excluded_dirs.as_ref()) {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        let root_dev = match self.same_file_system.unwrap_or(false) {
+            true => Some(std::fs::metadata(self.src_dir.as_ref())?.dev()),
+            false => None,
+        };
+        let parallelism = match self.walk_threads {
+            Some(1) => Parallelism::Serial,
+            // Own pool, walking on the collecting pool may deadlock with sources blocked on
+            // the bounded entry channel
+            walk_threads => Parallelism::RayonNewPool(walk_threads.unwrap_or(0)),
+        };
 
         let y = WalkDir::new(self.src_dir.as_ref())
-            .follow_links(true)
+            .follow_links(self.follow_links.unwrap_or(true))
+            .max_depth(self.max_depth.unwrap_or(usize::MAX))
+            .skip_hidden(false)
+            .sort(self.deterministic.unwrap_or(false))
+            .parallelism(parallelism)
+            .process_read_dir(move |_, _, _, children| {
+                for child in children.iter_mut().flatten() {
+                    if child.read_children.is_none() {
+                        continue;
+                    }
+                    let path = child.path();
+                    let other_file_system = root_dev.is_some_and(|root_dev| {
+                        child.metadata().map(|m| m.dev()).ok() != Some(root_dev)
+                    });
+                    let excluded_by_pattern = path
+                        .strip_prefix(src_dir_clone_3.as_ref())
+                        .is_ok_and(|p| exclude_clone.is_match(p));
+                    if other_file_system
+                        || excluded_by_pattern
+                        || is_excluded_dir(&path, excluded_dirs.as_ref())
+                    {
+                        child.read_children = None;
+                    }
+                }
+            })
             .into_iter()
             .filter(move |res| match res {
                 Ok(de) => {
-                    let p = de.path();
-                    p.is_file()
-                        && p.strip_prefix(src_dir_clone_1.as_ref())
-                            .map(|p| globset.is_match(p))
-                            .unwrap_or(false)
+                    // File type is known from the directory walk, no extra stat per entry
+                    let file_type = de.file_type();
+                    if file_type.is_dir() {
+                        // Kept to report it when it cannot be listed
+                        return de
+                            .read_children
+                            .as_ref()
+                            .is_some_and(|c| c.error().is_some());
+                    }
+                    let matched = de
+                        .path()
+                        .strip_prefix(src_dir_clone_1.as_ref())
+                        .map(|p| globset.is_match(p) && !exclude.is_match(p))
+                        .unwrap_or(false);
+                    if matched && !file_type.is_file() {
+                        if let Some(special_files) = &special_files {
+                            special_files.record(&file_type);
+                        }
+                        return false;
+                    }
+                    if !matched || !stat_filtered {
+                        return matched;
+                    }
+                    match de.metadata() {
+                        Ok(metadata) => {
+                            size_range.contains(&metadata.len())
+                                && modified_after.is_none_or(|after| {
+                                    metadata.modified().is_ok_and(|modified| modified >= after)
+                                })
+                        }
+                        // Kept, reading the file reports the error
+                        Err(_) => true,
+                    }
                 }
                 Err(_) => true,
             })
             .map(move |res| {
                 let self_clone = self_clone.clone();
-                res.map(|de| {
-                    ArchiveEntry::keep_src(
-                        de.path().to_path_buf(),
-                        dst_dir.join(de.path().strip_prefix(src_dir_clone_2.as_ref()).unwrap()),
-                    )
-                })
-                .map_err(Error::from)
-                .map_err(|e| e.with_debug_object_and_fn_name(self_clone, "archive_entry_iterator"))
+                res.map_err(Error::from)
+                    .and_then(|de| {
+                        if let Some(e) = de.read_children.as_ref().and_then(|c| c.error()) {
+                            Err(std::io::Error::new(
+                                e.io_error().map_or(ErrorKind::Other, std::io::Error::kind),
+                                format!("cannot list directory: {e}"),
+                            ))?
+                        }
+                        let path = de.path();
+                        let dst =
+                            dst_dir.join(path.strip_prefix(src_dir_clone_2.as_ref()).unwrap());
+                        Ok(ArchiveEntry::keep_src(path, dst))
+                    })
+                    .map_err(|e| {
+                        e.with_debug_object_and_fn_name(self_clone, "archive_entry_iterator")
+                    })
             });
 
         Ok(Box::new(y))
@@ -0,0 +1,84 @@
+use crate::backup::archive::dependency::dependency_layers;
+use crate::backup::archive::ArchiveSourceConfig;
+use crate::backup::retention::RetentionConfig;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use validator::ValidationError;
+
+/// Archive holding some of the sources of a job, created on every run next to the other archives
+/// of the job, named `<archive_base_name>-<name>`.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ArchiveGroupConfig {
+    pub name: Arc<str>,
+    /// Names of the sources of `files` stored in this archive.
+    pub sources: Vec<Arc<str>>,
+    /// Overrides the retention of the job for this archive.
+    pub retention: Option<Arc<RetentionConfig>>,
+    /// Overrides the manual retention of the job for this archive.
+    pub manual_retention: Option<Arc<RetentionConfig>>,
+}
+
+impl ArchiveGroupConfig {
+    /// Sources of `files` stored in this archive, in their order in `files`.
+    pub fn select_sources(&self, files: &[ArchiveSourceConfig]) -> Vec<ArchiveSourceConfig> {
+        files
+            .iter()
+            .filter(|f| f.name.as_ref().is_some_and(|n| self.sources.contains(n)))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Every source of `files` must be named and stored in exactly one of `groups`, and depend only
+/// on sources of the same archive.
+pub fn validate_archive_groups(
+    groups: &[ArchiveGroupConfig],
+    files: &[ArchiveSourceConfig],
+) -> Result<(), ValidationError> {
+    let error = |message: String| {
+        Err(ValidationError::new("InvalidArchiveGroups").with_message(message.into()))
+    };
+    let mut names = HashSet::new();
+    let mut group_of: HashMap<&Arc<str>, &Arc<str>> = HashMap::new();
+    for group in groups {
+        if group.name.is_empty() || group.name.chars().any(|c| c == '/' || c == '\0') {
+            return error(format!("Invalid archive name {:?}", group.name));
+        }
+        if !names.insert(&group.name) {
+            return error(format!(
+                "Archive {:?} is defined more than once",
+                group.name
+            ));
+        }
+        for source in group.sources.iter() {
+            if let Some(other) = group_of.insert(source, &group.name) {
+                return error(format!(
+                    "Source {source:?} is stored in both archives {other:?} and {:?}",
+                    group.name
+                ));
+            }
+            if !files.iter().any(|f| f.name.as_ref() == Some(source)) {
+                return error(format!(
+                    "Archive {:?} stores unknown source {source:?}",
+                    group.name
+                ));
+            }
+        }
+    }
+    for file in files {
+        match &file.name {
+            None => return error("Sources must be named when archives are split".to_string()),
+            Some(name) if !group_of.contains_key(name) => {
+                return error(format!("Source {name:?} is not stored in any archive"))
+            }
+            _ => {}
+        }
+    }
+    for group in groups {
+        dependency_layers(&group.select_sources(files))?;
+    }
+    Ok(())
+}
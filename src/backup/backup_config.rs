@@ -1,43 +1,260 @@
-use crate::backup::archive::{ArchiveEntryConfig, ArchiveEntryIterable};
-use crate::backup::compress::{CompressorBuilder, CompressorConfig};
-use crate::backup::encrypt::{EncryptorBuilder, EncryptorConfig};
+use crate::backup::archive::dependency::dependency_layers;
+use crate::backup::archive::walkdir_globset::CustomDeserializedGlob;
+use crate::backup::archive::{
+    append_pax_global_header, ArchiveEntryConfig, ArchiveEntryIterable, ArchiveSourceConfig,
+    PlannedEntry,
+};
+use crate::backup::archive_group::{validate_archive_groups, ArchiveGroupConfig};
+use crate::backup::catalog::{Catalog, CatalogEntry};
+use crate::backup::checksum::{sha256_file, ArchiveChecksums, HashingWriter};
+use crate::backup::clock::{Clock, ClockSource, CronTimezone};
+use crate::backup::collect::{
+    collect_entries_into, CollectedEntries, CollectionMode, DstConflictMode, DuplicateEntryMode,
+    EntryDedup, EntrySender,
+};
+use crate::backup::compress::{CompressorConfig, PassthroughCompressor};
+use crate::backup::conditions::RunConditionsConfig;
+use crate::backup::content_type::ContentTypeStats;
+use crate::backup::control::{
+    bind_control_socket, control_socket_path, handle_connection, ScheduleMode, TriggerResponse,
+};
+use crate::backup::counting_writer::CountingWriter;
+use crate::backup::deadline::Deadline;
+use crate::backup::disk_space::{check_free_space, DiskSpaceCheckConfig, SpaceEstimate};
+use crate::backup::drill::RestoreDrillConfig;
+use crate::backup::encrypt::policy::{CryptoParameters, CryptoPolicy};
+use crate::backup::encrypt::{DecryptorBuilder, EncryptorBuilder, EncryptorConfig};
+use crate::backup::fan_out::FanOutWriter;
 use crate::backup::file_ext::FileExtProvider;
 use crate::backup::finish::Finish;
+use crate::backup::history::{BackupHistory, HistoryEntry};
+use crate::backup::hook::QuiesceConfig;
+use crate::backup::humanize::{HumanDuration, HumanNextRun, HumanSize};
+use crate::backup::index::ArchiveIndex;
+use crate::backup::manifest::ArchiveManifest;
+use crate::backup::metadata::encrypted_path;
+use crate::backup::notification::{BackupEvent, NotificationDestinationConfig, Notifier};
+use crate::backup::pack::{PackConfig, PackWriter};
+use crate::backup::permission_scan::{is_readable, PermissionScan};
+use crate::backup::pipeline::{PipelineDescriptor, StageKind};
+use crate::backup::reconcile::{Drift, ReconcileConfig, ReconcileReport};
+use crate::backup::recovery::RecoveryInstructions;
+use crate::backup::reload::ReloadWatch;
+use crate::backup::report::{
+    bytes_per_second, error_messages, BackupReport, ChangeSummary, SourceStats, UploadReport,
+};
+use crate::backup::report_sink::ReportSinkConfig;
+use crate::backup::restore::{ConfigSecretSource, PromptSecretSource};
 use crate::backup::result_error::error::Error;
-use crate::backup::result_error::result::convert_error_vec;
-use crate::backup::result_error::result::Result;
+use crate::backup::result_error::result::{chain_optional_error, convert_error_vec, Result};
 use crate::backup::result_error::{WithDebugObjectAndFnName, WithMsg};
-use crate::backup::retention::{ItemWithDateTime, RetentionConfig};
+use crate::backup::retention::{ItemWithDateTime, RetentionConfig, RetentionVerdict};
+use crate::backup::span::{backup_span, stage_span, Stage};
+use crate::backup::stat_cache::{StatCache, StatCacheConfig};
+use crate::backup::status::{SchedulerState, StatusFile};
+use crate::backup::storage::deletion::PendingDeletions;
+use crate::backup::storage::receipt::{UploadReceipt, UploadReceipts};
+use crate::backup::storage::resumable::{resumable_upload, upload_state_path, UploadState};
+use crate::backup::storage::verify::{verify_download, RemoteVerification, RemoteVerifyOptions};
+use crate::backup::storage::{StorageBackend, StorageDestinationConfig};
+use crate::backup::timings::{PhaseTimer, PhaseTimings, TimedWriter};
+use crate::backup::volume::{
+    archive_exists, archive_file_size, first_volume_archive_path, open_archive_file,
+    remove_archive, rename_archive, volume_paths, VolumeManifest, VolumeWriter,
+};
 use chrono::{DateTime, TimeZone, Utc};
+use globset::{GlobSet, GlobSetBuilder};
 use itertools::Itertools;
-use rayon::prelude::*;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use rayon::ThreadPool;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use std::cmp::Reverse;
 use std::collections::HashSet;
 use std::fmt::Display;
-use std::fs::{read_dir, File};
-use std::io::{BufWriter, IntoInnerError};
+use std::fs::{read_dir, File, TryLockError};
+use std::hash::{BuildHasher, RandomState};
+use std::io::{BufReader, BufWriter, ErrorKind, IntoInnerError, IsTerminal, Write};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
-use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::{sync_channel, RecvTimeoutError};
 use std::sync::{Arc, OnceLock};
-use tracing::{info, warn};
-use validator::{Validate, ValidationError};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use tracing::{error, info, warn};
+use validator::{Validate, ValidationError, ValidationErrors};
 
 #[skip_serializing_none]
 #[derive(Clone, Serialize, Deserialize, Debug, Validate)]
+#[validate(schema(function = "warn_sources_covering_own_dirs"))]
+#[validate(schema(function = "warn_overlapping_sources"))]
+#[validate(schema(function = "validate_archives"))]
+#[validate(schema(function = "validate_restore_drill"))]
+#[validate(schema(function = "validate_reconcile"))]
+#[validate(schema(function = "validate_schedule"))]
+#[validate(schema(function = "validate_encryptors"))]
+#[validate(schema(function = "validate_crypto_policy"))]
+#[validate(schema(function = "validate_split_size"))]
+#[validate(schema(function = "validate_disk_space_check"))]
+#[validate(schema(function = "validate_storage_ids"))]
 pub struct BackupConfig {
-    #[validate(custom(function = validate_cron_str))]
+    /// Evaluated in `timezone`. Not needed with `schedule: external`.
+    #[serde(default)]
     pub cron: Arc<str>,
+    /// Time zone of `cron` and the cron of `drill` and `reconcile`, UTC by default.
+    pub timezone: Option<CronTimezone>,
+    /// Back up on the `cron` schedule, or only when triggered from outside.
+    pub schedule: Option<ScheduleMode>,
     #[validate(custom(function = validate_valid_archive_base_name))]
     pub archive_base_name: Arc<str>,
+    /// Free text stored in the report and the archive pax header, e.g. why a manual backup was
+    /// taken.
+    pub description: Option<Arc<str>>,
     #[validate(custom(function = validate_out_dir))]
     pub out_dir: Arc<Path>,
-    pub files: Arc<Vec<ArchiveEntryConfig>>,
+    #[validate(custom(function = validate_files))]
+    pub files: Arc<Vec<ArchiveSourceConfig>>,
     pub compressor: Arc<CompressorConfig>,
     pub encryptor: Arc<EncryptorConfig>,
     pub retention: Option<Arc<RetentionConfig>>,
+    /// Retention of backups created by manual runs, which `retention` never deletes. Manual
+    /// backups are kept forever when unset.
+    pub manual_retention: Option<Arc<RetentionConfig>>,
+    /// Keep archives past retention until every storage destination confirmed a copy, see
+    /// [`UploadReceipts`].
+    pub require_offsite_before_prune: Option<bool>,
+    pub hold_file: Option<Arc<Path>>,
+    pub run_conditions: Option<Arc<RunConditionsConfig>>,
+    pub collection_mode: Option<CollectionMode>,
+    /// Entries resolving to a file already archived in the run, skipped with the same
+    /// destination path by default.
+    pub duplicate_entries: Option<DuplicateEntryMode>,
+    /// Entries at a destination path already taken in the run, all archived by default.
+    pub dst_conflicts: Option<DstConflictMode>,
+    pub quiesce: Option<Arc<QuiesceConfig>>,
+    pub notifications: Option<Arc<Vec<NotificationDestinationConfig>>>,
+    pub report: Option<bool>,
+    /// Collectors receiving the report of every created archive, see [`ReportSinkConfig`].
+    pub report_sinks: Option<Arc<Vec<ReportSinkConfig>>>,
+    /// Destinations every archive is uploaded to, e.g. S3 or another directory. `out_dir` then
+    /// only needs a short `retention` when destinations prune with their own retention.
+    pub storage: Option<Arc<Vec<StorageDestinationConfig>>>,
+    pub state_dir: Option<Arc<Path>>,
+    pub index: Option<bool>,
+    /// Write archive and tar stream checksums next to the archive, see [`ArchiveChecksums`].
+    pub checksums: Option<bool>,
+    /// Write the archive checksum and the path, size and mtime of every archived file next to
+    /// the archive, see [`ArchiveManifest`].
+    pub manifest: Option<bool>,
+    /// Algorithms archives may be encrypted with, the parameters used are recorded in the
+    /// manifest. Always strict when built with the `strict-crypto` feature.
+    pub crypto_policy: Option<CryptoPolicy>,
+    /// Suffix archives created with non-fatal errors with `-partial`, these never count toward
+    /// `retention.min_backups`.
+    pub mark_partial: Option<bool>,
+    pub encrypt_metadata: Option<bool>,
+    pub pack_small_files: Option<Arc<PackConfig>>,
+    /// Patterns of archive paths whose entries are stored without compression, e.g. `**/*.zst`
+    /// or `**/*.mp4`, as entries of `precompressed` sources are. The rest of the archive is still
+    /// compressed.
+    pub store_uncompressed: Option<Vec<CustomDeserializedGlob>>,
+    pub clock: Option<ClockSource>,
+    /// Run a backup when the daemon starts even if no scheduled run was missed.
+    pub run_on_start: Option<bool>,
+    /// Wait this long after the daemon starts before a run that is due at start, i.e. a missed
+    /// scheduled run or `run_on_start`.
+    #[serde(default, with = "humantime_serde")]
+    pub startup_delay: Option<std::time::Duration>,
+    /// Remember stat info and content hashes of archived files in the state dir, detecting
+    /// unchanged files without re-hashing them.
+    pub stat_cache: Option<Arc<StatCacheConfig>>,
+    /// Cancel creating an archive running longer than this, e.g. stuck on a hung network mount,
+    /// failing the run with a timeout error instead of blocking the schedule.
+    #[serde(default, with = "humantime_serde")]
+    pub max_duration: Option<std::time::Duration>,
+    /// Split the archive in out_dir into volumes of at most this many bytes, named
+    /// `<archive>.000`, `<archive>.001` and so on, e.g. 4294967295 on FAT32. The volumes are
+    /// listed in a `.volumes.json` file next to them and retention deletes them together. Not
+    /// supported with `storage`, uploads need the whole archive.
+    pub split_size: Option<u64>,
+    /// Never write to the system temp dir, SQLite snapshots and command output are staged in the
+    /// state dir. For
+    /// read-only root filesystems where only the volume holding out_dir is writable.
+    pub no_tempfile: Option<bool>,
+    /// Check out_dir and the temp dir have enough free space for the archive before starting,
+    /// failing right away instead of halfway through with a full disk.
+    pub disk_space_check: Option<Arc<DiskSpaceCheckConfig>>,
+    /// Before each backup, check the permissions of the source files without reading them and
+    /// log those the daemon user cannot read in one warning, instead of a warning per entry
+    /// while archiving.
+    pub permission_scan: Option<bool>,
+    /// Record every backup, created or failed, in `catalog.jsonl` of the state dir, listed by
+    /// the `list` command. On by default.
+    pub catalog: Option<bool>,
+    /// Store this config as `.k-backup/config.yml` in every archive, so a disaster restore has
+    /// the settings that produced it. Secrets are redacted. On by default.
+    pub include_config: Option<bool>,
+    /// Detect the content type of archived files from their first bytes, adding a breakdown by
+    /// type to the report. Every file is opened once more while collecting.
+    pub content_types: Option<bool>,
+    /// Split the sources into several archives created on every run, each with its own
+    /// retention, e.g. databases kept 90 days and bulky media 7. Every source must be named and
+    /// stored in one archive.
+    pub archives: Option<Arc<Vec<ArchiveGroupConfig>>>,
+    /// Periodically restore the newest archive and check the restored files, run by the daemon
+    /// or the `drill` command.
+    pub restore_drill: Option<Arc<RestoreDrillConfig>>,
+    /// Write plain `.recovery.json` and `.RECOVERY.md` files next to every archive, with the
+    /// commands restoring it using standard tools, or `k_backup` for encryption the age CLI
+    /// cannot read, and the public keys able to decrypt it.
+    pub recovery_instructions: Option<bool>,
+    /// Periodically compare the local archives, their sidecar files and upload receipts against
+    /// the out dir and the destination listings, run by the daemon or the `reconcile` command.
+    pub reconcile: Option<Arc<ReconcileConfig>>,
+    /// Set by the daemon, see [`Self::with_reload`].
+    #[serde(skip)]
+    reload: Option<Arc<ReloadWatch>>,
+}
+
+/// Entry of the archive holding the config that created it, see `include_config`.
+pub static CONFIG_ENTRY_PATH: &str = ".k-backup/config.yml";
+
+/// Writer under the tar builder of an archive, down to its compressor.
+type ArchiveWriter<W> =
+    CountingWriter<HashingWriter<TimedWriter<BufWriter<PassthroughCompressor<W>>>>>;
+
+/// Switch the compressor under the archive writer to storing entries uncompressed or back,
+/// flushing the data buffered in between first.
+fn set_passthrough<W: Write>(
+    builder: &mut tar::Builder<ArchiveWriter<W>>,
+    passthrough: bool,
+) -> Result<()> {
+    let writer = builder.get_mut().get_mut().get_mut();
+    if writer.get_mut().get_ref().is_passthrough() != passthrough {
+        writer.flush()?;
+        writer.get_mut().get_mut().set_passthrough(passthrough)?;
+    }
+    Ok(())
+}
+
+/// Append the config serialized to `yaml` as [`CONFIG_ENTRY_PATH`], returning its size. The
+/// entry is owned by the owner of `out_dir`.
+fn append_config<W: Write>(
+    builder: &mut tar::Builder<W>,
+    yaml: &str,
+    out_dir: &Path,
+    dt: DateTime<Utc>,
+) -> Result<u64> {
+    let out_dir_metadata = std::fs::metadata(out_dir)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o600);
+    header.set_uid(out_dir_metadata.uid() as u64);
+    header.set_gid(out_dir_metadata.gid() as u64);
+    header.set_mtime(dt.timestamp().max(0) as u64);
+    header.set_size(yaml.len() as u64);
+    builder.append_data(&mut header, CONFIG_ENTRY_PATH, yaml.as_bytes())?;
+    Ok(yaml.len() as u64)
 }
 
 fn validate_cron_str(cron: &Arc<str>) -> std::result::Result<(), ValidationError> {
@@ -49,6 +266,279 @@ fn validate_cron_str(cron: &Arc<str>) -> std::result::Result<(), ValidationError
     Ok(())
 }
 
+fn validate_files(
+    files: &Arc<Vec<ArchiveSourceConfig>>,
+) -> std::result::Result<(), ValidationError> {
+    for source in files.iter() {
+        match &source.source {
+            ArchiveEntryConfig::External(external) if !external.is_registered() => {
+                return Err(ValidationError::new("UnknownExternalSource").with_message(
+                    format!("no external source is registered as {:?}", external.kind()).into(),
+                ));
+            }
+            ArchiveEntryConfig::Container(container) if !container.has_selector() => {
+                return Err(ValidationError::new("MissingContainerSelector")
+                    .with_message("container sources need names or labels".into()));
+            }
+            _ => {}
+        }
+    }
+    dependency_layers(files).map(|_| ())
+}
+
+/// What a run would archive and which archives retention would delete, see
+/// [`BackupConfig::dry_run`].
+#[derive(Debug)]
+pub struct DryRun {
+    pub entries: Vec<PlannedEntry>,
+    /// Entries that cannot be collected, a real run skips them too.
+    pub errors: Vec<Error>,
+    pub out_of_retention: Vec<PathBuf>,
+}
+
+/// Tags of an archive, encoded as suffixes of the timestamp in its file name.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ArchiveTags {
+    /// Created by a manual run instead of the schedule, suffixed `-manual`.
+    pub manual: bool,
+    /// Created with non-fatal errors, suffixed `-partial` by `mark_partial`.
+    pub partial: bool,
+}
+
+impl ArchiveTags {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let Some(file_name) = path.as_ref().file_name().and_then(|n| n.to_str()) else {
+            return Self::default();
+        };
+        let partial = file_name.contains(&format!("{PARTIAL_SUFFIX}."));
+        let manual = file_name.contains(&format!("{MANUAL_SUFFIX}."))
+            || file_name.contains(&format!("{MANUAL_SUFFIX}{PARTIAL_SUFFIX}."));
+        Self { manual, partial }
+    }
+
+    fn suffix(&self) -> String {
+        format!(
+            "{}{}",
+            if self.manual { MANUAL_SUFFIX } else { "" },
+            if self.partial { PARTIAL_SUFFIX } else { "" }
+        )
+    }
+
+    /// Time part of a file name with the tag suffixes removed.
+    fn strip(time_string: &str) -> &str {
+        let time_string = time_string
+            .strip_suffix(PARTIAL_SUFFIX)
+            .unwrap_or(time_string);
+        time_string
+            .strip_suffix(MANUAL_SUFFIX)
+            .unwrap_or(time_string)
+    }
+}
+
+/// Whether `path` names an archive marked `-partial` by `mark_partial`.
+pub fn is_partial_archive<P: AsRef<Path>>(path: P) -> bool {
+    ArchiveTags::from_path(path).partial
+}
+
+/// Whether `path` names an archive created by a manual run.
+pub fn is_manual_archive<P: AsRef<Path>>(path: P) -> bool {
+    ArchiveTags::from_path(path).manual
+}
+
+/// Ask on the terminal whether retention may run on `out_dir` holding `unrecognized` files,
+/// `false` without a terminal.
+fn prompt_confirm_out_dir(out_dir: &Path, unrecognized: &[PathBuf]) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprintln!("Out dir {out_dir:?} holds files not created by k_backup:");
+    for path in unrecognized.iter().take(10) {
+        eprintln!("  {path:?}");
+    }
+    eprint!("Apply retention to archives in it anyway? [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn resolve_path(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Sources containing out_dir would archive all previous archives every run. They are excluded
+/// from every walk, we only warn here.
+#[cfg_attr(not(feature = "sqlite"), allow(irrefutable_let_patterns))]
+fn warn_sources_covering_own_dirs(
+    config: &BackupConfig,
+) -> std::result::Result<(), ValidationError> {
+    let own_dirs = config.own_dirs();
+    for source in config.files.iter() {
+        let ArchiveEntryConfig::Glob(glob) = &source.source else {
+            continue;
+        };
+        let src_dir = resolve_path(glob.src_dir());
+        for own_dir in own_dirs.iter() {
+            if own_dir.starts_with(&src_dir) {
+                warn!(
+                    "Source {:?} contains {own_dir:?} used by k_backup, it will be excluded \
+                     from the backup",
+                    glob.src_dir()
+                );
+            } else if src_dir.starts_with(own_dir) {
+                warn!(
+                    "Source {:?} is inside {own_dir:?} used by k_backup, it will be excluded \
+                     from the backup",
+                    glob.src_dir()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Glob sources with nested `src_dir`s whose patterns both match some path archive its files
+/// twice, under each `dst_dir`. Patterns are compared on sample paths, so only likely overlaps
+/// are reported.
+fn warn_overlapping_sources(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    let same_archive = |a: &ArchiveSourceConfig, b: &ArchiveSourceConfig| match &config.archives {
+        Some(groups) => groups.iter().any(|g| {
+            [a, b]
+                .iter()
+                .all(|s| s.name.as_ref().is_some_and(|n| g.sources.contains(n)))
+        }),
+        None => true,
+    };
+    let globs = config
+        .files
+        .iter()
+        .filter_map(|source| match &source.source {
+            ArchiveEntryConfig::Glob(glob) => Some((source, glob, resolve_path(glob.src_dir()))),
+            _ => None,
+        })
+        .collect_vec();
+    for ((a, a_glob, a_dir), (b, b_glob, b_dir)) in globs.iter().tuple_combinations() {
+        if !same_archive(a, b) {
+            continue;
+        }
+        let (outer, inner, relative) = match (b_dir.strip_prefix(a_dir), a_dir.strip_prefix(b_dir))
+        {
+            (Ok(relative), _) => (a_glob, b_glob, relative),
+            (_, Ok(relative)) => (b_glob, a_glob, relative),
+            _ => continue,
+        };
+        if let Some(sample) = inner.sample_paths().into_iter().find(|sample| {
+            inner.archives_path(sample) && outer.archives_path(&relative.join(sample))
+        }) {
+            warn!(
+                "Sources {:?} and {:?} overlap, files such as {:?} are archived by both",
+                a_glob.src_dir(),
+                b_glob.src_dir(),
+                inner.src_dir().join(sample)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn validate_archives(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    match &config.archives {
+        Some(groups) => validate_archive_groups(groups, &config.files),
+        None => Ok(()),
+    }
+}
+
+fn validate_restore_drill(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    match &config.restore_drill {
+        Some(drill) => validate_cron_str(&drill.cron),
+        None => Ok(()),
+    }
+}
+
+fn validate_schedule(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    match config.schedule.unwrap_or_default() {
+        ScheduleMode::Cron => validate_cron_str(&config.cron),
+        ScheduleMode::External => Ok(()),
+    }
+}
+
+/// Encryptor of the archives and of each destination with its own, with their name in the config.
+fn named_encryptors(config: &BackupConfig) -> impl Iterator<Item = (String, &EncryptorConfig)> {
+    let destinations = config
+        .storage
+        .iter()
+        .flat_map(|s| s.iter())
+        .enumerate()
+        .filter_map(|(idx, d)| {
+            d.encryptor
+                .as_deref()
+                .map(|e| (format!("storage.{idx}.encryptor"), e))
+        });
+    std::iter::once(("encryptor".to_string(), config.encryptor.as_ref())).chain(destinations)
+}
+
+fn validate_encryptors(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    match named_encryptors(config).find_map(|(name, e)| Some((name, e.validate().err()?))) {
+        Some((name, errors)) => Err(ValidationError::new("InvalidEncryptor")
+            .with_message(format!("{name}: {errors}").into())),
+        None => Ok(()),
+    }
+}
+
+fn validate_crypto_policy(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    let policy = config.crypto_policy();
+    let violation =
+        named_encryptors(config).find_map(|(name, encryptor)| policy.violation(&name, encryptor));
+    match violation {
+        Some(violation) => {
+            Err(ValidationError::new("CryptoPolicyViolation").with_message(violation.into()))
+        }
+        None => Ok(()),
+    }
+}
+
+fn validate_split_size(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    let message = match config.split_size {
+        Some(0) => "split_size must be at least 1 byte",
+        Some(_) if config.storage.is_some() => "split_size is not supported with storage",
+        _ => return Ok(()),
+    };
+    Err(ValidationError::new("InvalidSplitSize").with_message(message.into()))
+}
+
+fn validate_disk_space_check(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    match config.disk_space_check.as_ref().map(|check| check.factor()) {
+        Some(factor) if !factor.is_finite() || factor <= 0.0 => {
+            Err(ValidationError::new("InvalidDiskSpaceFactor")
+                .with_message(format!("disk_space_check factor {factor} must be above 0").into()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Upload receipts of destinations sharing an id would confirm copies for each other.
+fn validate_storage_ids(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    let ids = config
+        .storage
+        .iter()
+        .flat_map(|s| s.iter())
+        .map(|destination| destination.id())
+        .collect_vec();
+    match ids.iter().duplicates().next() {
+        Some(id) => Err(ValidationError::new("DuplicateStorageId")
+            .with_message(format!("storage destinations share the id {id:?}").into())),
+        None => Ok(()),
+    }
+}
+
+fn validate_reconcile(config: &BackupConfig) -> std::result::Result<(), ValidationError> {
+    match &config.reconcile {
+        Some(reconcile) => validate_cron_str(&reconcile.cron),
+        None => Ok(()),
+    }
+}
+
 fn validate_out_dir(dir: &Arc<Path>) -> std::result::Result<(), ValidationError> {
     if dir.exists() {
         if !dir.is_dir() {
@@ -56,7 +546,7 @@ fn validate_out_dir(dir: &Arc<Path>) -> std::result::Result<(), ValidationError>
                 .with_message("out_dir is not a directory".into()));
         }
     } else {
-        return std::fs::create_dir_all(&dir).map_err(|e| {
+        return std::fs::create_dir_all(dir).map_err(|e| {
             ValidationError::new("InvalidDirectory").with_message(
                 format!("cannot create or access out_dir path {:?}: {}", dir, e).into(),
             )
@@ -66,7 +556,6 @@ fn validate_out_dir(dir: &Arc<Path>) -> std::result::Result<(), ValidationError>
     Ok(())
 }
 
-#[cfg(target_os = "linux")]
 fn validate_valid_archive_base_name(name: &Arc<str>) -> std::result::Result<(), ValidationError> {
     if name.chars().any(|c| c == '/' || c == '\0') {
         return Err(ValidationError::new("InvalidArchiveBaseName")
@@ -81,39 +570,286 @@ fn validate_valid_archive_base_name(name: &Arc<str>) -> std::result::Result<(),
     Ok(())
 }
 
-#[cfg(target_os = "macos")]
-fn validate_valid_archive_base_name(name: &Arc<str>) -> Result<(), ValidationError> {
-    Ok(())
-}
-
-#[cfg(target_os = "windows")]
-fn validate_valid_archive_base_name(name: &Arc<str>) -> Result<(), ValidationError> {
-    Ok(())
-}
-
+static DEFAULT_HOLD_FILE_NAME: &str = ".hold";
+static OUT_DIR_CONFIRMED_FILE_NAME: &str = "out_dir_confirmed";
+static DEFAULT_STATE_DIR_NAME: &str = ".k_backup";
+static MAX_SLEEP_CHUNK: chrono::TimeDelta = chrono::TimeDelta::minutes(1);
+/// Longest sleep of the loops watching for a reload, the delay of applying it.
+static RELOAD_CHECK_INTERVAL: chrono::TimeDelta = chrono::TimeDelta::seconds(2);
+/// Delay of answering triggers and applying a reload with `schedule: external`.
+static ACCEPT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+static DEFAULT_RECONCILE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+static MAX_CLOCK_DRIFT: chrono::TimeDelta = chrono::TimeDelta::seconds(30);
+static NO_ENCRYPTOR: EncryptorConfig = EncryptorConfig::None;
+static MANUAL_SUFFIX: &str = "-manual";
+static PARTIAL_SUFFIX: &str = "-partial";
 static TIME_FORMAT: &str = "%Y-%m-%dT%Hh%Mm%Ss%z";
 static TAR_FILE_EXT: OnceLock<Arc<str>> = OnceLock::new();
 
 impl FileExtProvider for BackupConfig {
     fn file_ext(&self) -> Option<Arc<str>> {
-        Some(
-            std::iter::once(TAR_FILE_EXT.get_or_init(|| "tar".into()))
-                .chain(self.compressor.file_ext().iter())
-                .chain(self.encryptor.file_ext().iter())
-                .join(".")
-                .into(),
-        )
+        Some(self.file_ext_with_encryptor(&self.encryptor))
     }
 }
 
 impl BackupConfig {
-    fn time_file_ext<O: Display, T: TimeZone<Offset = O>>(&self, dt: DateTime<T>) -> Arc<str> {
+    /// One job per archive of `archives`, storing its sources with its own retention, or this
+    /// job alone when the sources are not split.
+    pub fn archive_jobs(&self) -> Vec<BackupConfig> {
+        let Some(groups) = &self.archives else {
+            return vec![self.clone()];
+        };
+        groups
+            .iter()
+            .map(|group| BackupConfig {
+                archive_base_name: format!("{}-{}", self.archive_base_name, group.name).into(),
+                files: group.select_sources(&self.files).into(),
+                retention: group.retention.clone().or_else(|| self.retention.clone()),
+                manual_retention: group
+                    .manual_retention
+                    .clone()
+                    .or_else(|| self.manual_retention.clone()),
+                // Default state dirs are per archive base name already
+                state_dir: self
+                    .state_dir
+                    .as_ref()
+                    .map(|d| d.join(group.name.as_ref()).into()),
+                archives: None,
+                ..self.clone()
+            })
+            .collect()
+    }
+
+    /// Make [`Self::start_loop`] return `Ok` between cycles once `reload` is requested, all its
+    /// threads stopped, so the daemon can start over with the reloaded config.
+    pub fn with_reload(self, reload: Arc<ReloadWatch>) -> Self {
+        Self {
+            reload: Some(reload),
+            ..self
+        }
+    }
+
+    fn is_reload_requested(&self) -> bool {
+        self.reload.as_ref().is_some_and(|r| r.is_requested())
+    }
+
+    /// Longest sleep of the loops, shorter when a reload must be noticed.
+    fn sleep_chunk(&self) -> chrono::TimeDelta {
+        match self.reload {
+            Some(_) => RELOAD_CHECK_INTERVAL,
+            None => MAX_SLEEP_CHUNK,
+        }
+    }
+
+    pub fn crypto_policy(&self) -> CryptoPolicy {
+        CryptoPolicy::effective(self.crypto_policy)
+    }
+
+    pub fn hold_file_path(&self) -> PathBuf {
+        self.hold_file
+            .as_ref()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|| self.out_dir.join(DEFAULT_HOLD_FILE_NAME))
+    }
+
+    pub fn state_dir_path(&self) -> PathBuf {
+        self.state_dir
+            .as_ref()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|| {
+                self.out_dir
+                    .join(DEFAULT_STATE_DIR_NAME)
+                    .join(self.archive_base_name.as_ref())
+            })
+    }
+
+    /// Claim `archive_base_name` within `out_dir` for as long as the returned file is open, so
+    /// two jobs sharing an out_dir can never apply retention to each other's archives.
+    pub fn lock_archive_base_name(&self) -> Result<File> {
+        self.lock_state_file("lock", false)
+    }
+
+    /// Like [`Self::lock_archive_base_name`], waiting for a command run outside the daemon, e.g.
+    /// `run` or `prune`, to release the lock instead of failing.
+    pub fn wait_archive_base_name_lock(&self) -> Result<File> {
+        self.lock_state_file("lock", true)
+    }
+
+    /// Claim the schedule of `archive_base_name` for the life of a daemon. Its cycles only lock
+    /// the archive base name while they run, leaving it to other commands in between.
+    fn lock_schedule(&self) -> Result<File> {
+        self.lock_state_file("daemon.lock", false)
+    }
+
+    /// Lock `<archive_base_name>.<suffix>` in the state dir of `out_dir`, recording the pid of
+    /// this process in it.
+    fn lock_state_file(&self, suffix: &str, wait: bool) -> Result<File> {
+        let lock_dir = self.out_dir.join(DEFAULT_STATE_DIR_NAME);
+        std::fs::create_dir_all(&lock_dir)?;
+        let lock_path = lock_dir.join(format!("{}.{suffix}", self.archive_base_name));
+        let mut lock_file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&lock_path)?;
+        match lock_file.try_lock() {
+            Ok(_) => {}
+            Err(TryLockError::WouldBlock) => {
+                let owner = std::fs::read_to_string(&lock_path).unwrap_or_default();
+                if !wait {
+                    return Err(Error::from(std::io::Error::other(format!(
+                        "archive_base_name {:?} in {:?} is already owned by another running job: {}",
+                        self.archive_base_name,
+                        self.out_dir,
+                        owner.trim()
+                    ))));
+                }
+                info!(
+                    "Waiting for archive_base_name {:?} to be released by {}",
+                    self.archive_base_name,
+                    owner.trim()
+                );
+                lock_file.lock()?;
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        lock_file.set_len(0)?;
+        writeln!(lock_file, "pid {}", std::process::id())?;
+        Ok(lock_file)
+    }
+
+    /// Resolved directories written by k_backup, which must never be backed up.
+    pub fn own_dirs(&self) -> Vec<PathBuf> {
+        vec![
+            resolve_path(&self.out_dir),
+            resolve_path(&self.state_dir_path()),
+        ]
+    }
+
+    /// Patterns of `store_uncompressed`, matching nothing when unset.
+    fn store_uncompressed_globset(&self) -> Result<GlobSet> {
+        let mut globset = GlobSetBuilder::new();
+        self.store_uncompressed
+            .iter()
+            .flatten()
+            .cloned()
+            .for_each(|glob| {
+                globset.add(glob.into());
+            });
+        Ok(globset.build().map_err(std::io::Error::other)?)
+    }
+
+    /// Staging directory of SQLite snapshots and command output in `no_tempfile` mode.
+    fn snapshot_dir(&self) -> PathBuf {
+        self.state_dir_path().join("snapshots")
+    }
+
+    pub fn is_on_hold(&self) -> bool {
+        self.hold_file_path().exists()
+    }
+
+    /// Marker in the state dir allowing retention to delete archives of `out_dir`, see
+    /// [`Self::check_out_dir`].
+    pub fn out_dir_confirmed_path(&self) -> PathBuf {
+        self.state_dir_path().join(OUT_DIR_CONFIRMED_FILE_NAME)
+    }
+
+    /// Allow retention on `out_dir` whatever files it holds.
+    pub fn confirm_out_dir(&self) -> Result<()> {
+        let path = self.out_dir_confirmed_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        File::create(path)?;
+        Ok(())
+    }
+
+    /// Entries of `out_dir` that are neither archives nor their sidecars, of any job, hidden
+    /// entries aside.
+    pub fn unrecognized_out_dir_files(&self) -> Result<Vec<PathBuf>> {
+        let read_dir = match read_dir(&self.out_dir) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            read_dir => read_dir?,
+        };
+        Ok(read_dir
+            .filter_map(|r| r.ok())
+            .map(|r| r.path())
+            .filter(|p| {
+                let file_name = p.file_name().unwrap_or_default().to_string_lossy();
+                // Timestamps hold no dots, archives and sidecars have one as a name component
+                !file_name.starts_with('.')
+                    && !file_name.split('.').any(|part| {
+                        let time_string = ArchiveTags::strip(part).replace('_', "+");
+                        DateTime::parse_from_str(&time_string, TIME_FORMAT).is_ok()
+                    })
+            })
+            .collect())
+    }
+
+    /// Confirm `out_dir` on the first run, unless it holds files that are not backups which a
+    /// misconfigured `archive_base_name` could have retention delete. Those need to be confirmed
+    /// on the terminal, with `--accept-existing-files` or by creating the marker, retention is
+    /// skipped until then.
+    fn check_out_dir(&self) -> Result<()> {
+        if self.out_dir_confirmed_path().exists() {
+            return Ok(());
+        }
+        let unrecognized = self.unrecognized_out_dir_files()?;
+        if unrecognized.is_empty() || prompt_confirm_out_dir(&self.out_dir, &unrecognized)? {
+            return self.confirm_out_dir();
+        }
+        warn!(
+            "Out dir {:?} holds {} file(s) not created by k_backup, e.g. {:?}. Retention is \
+             disabled until confirmed with --accept-existing-files or by creating {:?}",
+            self.out_dir,
+            unrecognized.len(),
+            unrecognized[0],
+            self.out_dir_confirmed_path()
+        );
+        Ok(())
+    }
+
+    /// Reason `run_conditions` prevent a scheduled run now.
+    pub fn unmet_run_condition(&self) -> Option<String> {
+        self.run_conditions.as_ref().and_then(|c| c.unmet_reason())
+    }
+
+    fn file_ext_with_encryptor(&self, encryptor: &EncryptorConfig) -> Arc<str> {
+        std::iter::once(TAR_FILE_EXT.get_or_init(|| "tar".into()))
+            .chain(self.compressor.file_ext().iter())
+            .chain(encryptor.file_ext().iter())
+            .join(".")
+            .into()
+    }
+
+    fn archive_file_name<O: Display, T: TimeZone<Offset = O>>(
+        &self,
+        dt: DateTime<T>,
+        encryptor: &EncryptorConfig,
+        tags: ArchiveTags,
+    ) -> String {
         format!(
-            "{}.{}",
+            "{}.{}{}.{}",
+            self.archive_base_name,
             dt.format(TIME_FORMAT).to_string().replace('+', "_"),
-            self.file_ext().unwrap_or("".into())
+            tags.suffix(),
+            self.file_ext_with_encryptor(encryptor)
         )
-        .into()
+    }
+
+    /// Location of the copy encrypted specifically for the destination at `idx`, waiting for
+    /// upload.
+    fn destination_staging_path(
+        &self,
+        idx: usize,
+        dt: DateTime<Utc>,
+        encryptor: &EncryptorConfig,
+        tags: ArchiveTags,
+    ) -> PathBuf {
+        self.out_dir
+            .join(format!(".destination-{idx}"))
+            .join(self.archive_file_name(dt, encryptor, tags))
     }
 
     pub fn get_date_time_from_file_path<P: AsRef<Path>>(
@@ -121,7 +857,13 @@ impl BackupConfig {
         file_path: P,
     ) -> Option<DateTime<Utc>> {
         let file_name = file_path.as_ref().file_name()?.to_str()?;
-        let end = format!(".{}", self.file_ext().unwrap_or("".into()));
+        self.date_time_from_file_name(file_name, &self.file_ext().unwrap_or("".into()))
+    }
+
+    /// Creation time of the archive named `file_name` with extension `ext`, e.g. a stored
+    /// copy encrypted for a destination.
+    fn date_time_from_file_name(&self, file_name: &str, ext: &str) -> Option<DateTime<Utc>> {
+        let end = format!(".{ext}");
         if !file_name.ends_with(end.as_str()) {
             return None;
         }
@@ -136,112 +878,492 @@ impl BackupConfig {
             return None;
         }
 
-        let time_string = file_name[start_idx..end_idx].replace('_', "+");
+        let time_string = ArchiveTags::strip(&file_name[start_idx..end_idx]).replace('_', "+");
 
         DateTime::parse_from_str(time_string.as_str(), TIME_FORMAT)
             .ok()
             .map(|dt| dt.to_utc())
     }
 
+    /// Entries of all sources, collected on `pre_process_pool` in the background as they are
+    /// read, e.g. to write them to another archive format or analyse them. Dropping the iterator
+    /// stops the collection.
+    pub fn collect_entries(&self, pre_process_pool: Arc<ThreadPool>) -> CollectedEntries {
+        let (tx, rx) = sync_channel(pre_process_pool.current_num_threads());
+        let (collector, stats) = self.spawn_entry_collector(
+            pre_process_pool,
+            EntrySender::new(tx, Deadline::after(None)),
+            PhaseTimer::default(),
+        );
+        CollectedEntries::new(rx, collector, stats)
+    }
+
+    fn spawn_entry_collector(
+        &self,
+        pre_process_pool: Arc<ThreadPool>,
+        result_tx: EntrySender,
+        collect_timer: PhaseTimer,
+    ) -> (JoinHandle<Result<()>>, Arc<Vec<SourceStats>>) {
+        let own_dirs = Arc::new(self.own_dirs());
+        let snapshot_dir: Option<Arc<Path>> = self
+            .no_tempfile
+            .unwrap_or(false)
+            .then(|| self.snapshot_dir().into());
+        let warm_dir: Arc<Path> = self.state_dir_path().join("warm").into();
+        let stats: Arc<Vec<SourceStats>> = Arc::new(
+            self.files
+                .iter()
+                .map(|_| SourceStats::new(self.content_types.unwrap_or(false)))
+                .collect(),
+        );
+        let files: Arc<Vec<_>> = Arc::new(
+            self.files
+                .iter()
+                .zip(stats.iter())
+                .map(|(f, stats)| {
+                    let mut source = f
+                        .source
+                        .with_excluded_dirs(own_dirs.clone())
+                        .with_special_file_stats(stats.special_files())
+                        .with_warm_dir(warm_dir.clone());
+                    if let Some(snapshot_dir) = &snapshot_dir {
+                        source = source.with_snapshot_dir(snapshot_dir.clone());
+                    }
+                    ArchiveSourceConfig {
+                        source,
+                        ..f.clone()
+                    }
+                })
+                .collect(),
+        );
+        let stats_clone = stats.clone();
+        let collection_mode = self.collection_mode.unwrap_or_default();
+        let dedup = EntryDedup::new(
+            self.duplicate_entries.unwrap_or_default(),
+            self.dst_conflicts.unwrap_or_default(),
+        );
+        let quiesce = self.quiesce.clone();
+        let span = stage_span(Stage::Collect);
+        let handle = std::thread::spawn(move || {
+            let _guard = span.enter();
+            let layers = dependency_layers(files.as_ref()).map_err(|e| {
+                let mut errors = ValidationErrors::new();
+                errors.add("files", e);
+                Error::from(errors)
+            })?;
+
+            collect_timer.time(|| {
+                pre_process_pool.install(|| {
+                    collect_entries_into(
+                        files.as_ref(),
+                        &layers,
+                        collection_mode,
+                        quiesce.as_deref(),
+                        stats_clone.as_ref(),
+                        &dedup,
+                        &result_tx,
+                    )
+                })
+            })
+        });
+        (handle, stats)
+    }
+
     pub fn create_archive(
         &self,
         dt: DateTime<Utc>,
         pre_process_pool: Arc<ThreadPool>,
     ) -> Result<(PathBuf, Option<Error>)> {
+        self.create_archive_with_tags(dt, ArchiveTags::default(), pre_process_pool)
+    }
+
+    pub fn create_archive_with_tags(
+        &self,
+        dt: DateTime<Utc>,
+        tags: ArchiveTags,
+        pre_process_pool: Arc<ThreadPool>,
+    ) -> Result<(PathBuf, Option<Error>)> {
+        let (file_path, mut non_fatal_error, report) =
+            self.create_archive_with_report(dt, tags, pre_process_pool, &PhaseTimings::default())?;
+        if let Some(report) = report.filter(|_| self.publishes_report()) {
+            self.publish_report(&report, &mut non_fatal_error);
+        }
+        Ok((file_path, non_fatal_error))
+    }
+
+    /// Create the archive, also returning its report when `report`, `report_sinks` or `catalog`
+    /// is set, which is left to publish once the archive is uploaded. Time spent is added to `timings`.
+    fn create_archive_with_report(
+        &self,
+        dt: DateTime<Utc>,
+        tags: ArchiveTags,
+        pre_process_pool: Arc<ThreadPool>,
+        timings: &PhaseTimings,
+    ) -> Result<(PathBuf, Option<Error>, Option<BackupReport>)> {
+        let started_at = Utc::now();
+        if self.no_tempfile.unwrap_or(false) {
+            // Snapshots left behind by a crashed run, the archive base name lock is held
+            let _ = std::fs::remove_dir_all(self.snapshot_dir());
+        }
+        if let Some(disk_space_check) = &self.disk_space_check {
+            self.check_disk_space(disk_space_check)?;
+        }
+        if self.permission_scan.unwrap_or(false) {
+            let scan = self.permission_scan();
+            if !scan.is_clean() {
+                warn!("Permission scan of sources found {scan}");
+            }
+        }
+        let deadline = Deadline::after(self.max_duration);
         let (result_tx, result_rx) = sync_channel(pre_process_pool.current_num_threads());
-        let config_clone = self.clone();
-        let entry_create_join_handle = std::thread::spawn(move || {
-            convert_error_vec(pre_process_pool.install(|| {
-                let i = config_clone
-                    .files
-                    .as_ref()
-                    .par_iter()
-                    .map(|archive_entry_config| {
-                        archive_entry_config.archive_entry_iterator().map(|iter| {
-                            let errors = iter
-                                .filter_map(|archive_entry_result| {
-                                    archive_entry_result
-                                        .with_msg("Ignoring entry")
-                                        .and_then(|archive_entry| {
-                                            result_tx.send(Ok(archive_entry)).map_err(Error::from)
-                                        })
-                                        .err()
-                                })
-                                .collect_vec();
-                            return convert_error_vec(errors);
-                        })
-                    })
-                    .filter_map(|res| match res {
-                        Ok(r) => r.err(),
-                        Err(e) => result_tx.send(Err(e)).map_err(Error::from).err(),
-                    })
-                    .collect();
-                i
-            }))
-        });
+        let (entry_create_join_handle, source_stats) = self.spawn_entry_collector(
+            pre_process_pool,
+            EntrySender::new(result_tx, deadline),
+            timings.collect.clone(),
+        );
 
         let config_clone = self.clone();
-        let file_name = format!(
-            "{}.{}",
-            config_clone.archive_base_name,
-            config_clone.time_file_ext(dt),
-        );
+        let file_name = config_clone.archive_file_name(dt, &config_clone.encryptor, tags);
         let file_path_tmp = Arc::new(config_clone.out_dir.join(format!("{file_name}.tmp")));
-        let file_path_tmp_clone = file_path_tmp.clone();
+        let mut outputs = vec![(file_path_tmp.clone(), self.encryptor.clone())];
+        for (idx, destination) in self.storage.iter().flat_map(|s| s.iter()).enumerate() {
+            if let Some(encryptor) = &destination.encryptor {
+                outputs.push((
+                    self.destination_staging_path(idx, dt, encryptor, tags)
+                        .into(),
+                    encryptor.clone(),
+                ));
+            }
+        }
+        let output_paths = outputs.iter().map(|(p, _)| p.clone()).collect_vec();
+        let stat_cache_path = StatCache::cache_path(self.state_dir_path());
+        let mut stat_cache = self
+            .stat_cache
+            .as_ref()
+            .map(|_| StatCache::load(&stat_cache_path))
+            .transpose()
+            .with_msg("Load stat cache failed")?;
+        let recheck_interval = self.stat_cache.as_ref().and_then(|c| c.recheck_interval);
+        let checksums = self.checksums.unwrap_or(false);
+        let manifest = self.manifest.unwrap_or(false);
+        // Secrets are skipped or redacted when serialized
+        let config_yaml = self
+            .include_config
+            .unwrap_or(true)
+            .then(|| serde_yml::to_string(self))
+            .transpose()
+            .map_err(std::io::Error::other)?;
+        let out_dir = self.out_dir.clone();
+        let store_uncompressed = self.store_uncompressed_globset()?;
+        let span = stage_span(Stage::Write);
+        let thread_timings = timings.clone();
         let archive_file_join_handle = std::thread::spawn(move || -> Result<_> {
-            let mut writer = File::create_new(file_path_tmp_clone.as_path())
-                .map(BufWriter::new)
-                .map_err(Error::from)
-                .and_then(|f| config_clone.encryptor.build_encryptor(f))
-                .map(BufWriter::new)
-                .and_then(|f| config_clone.compressor.build_compressor(f))
+            let _guard = span.enter();
+            let timings = thread_timings;
+            let write_started_at = Instant::now();
+            let encryptors = outputs
+                .iter()
+                .enumerate()
+                .map(|(idx, (path, encryptor))| {
+                    if let Some(parent) = path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    // Copies staged for destinations are uploaded whole
+                    let split_size = config_clone.split_size.filter(|_| idx == 0);
+                    VolumeWriter::create(path.as_path(), split_size)
+                        .map(|f| {
+                            let persist = timings.persist.clone();
+                            TimedWriter::new(f, persist, timings.compress_encrypt.clone())
+                        })
+                        .map(|f| HashingWriter::new(f, checksums || manifest))
+                        .and_then(|f| encryptor.build_encryptor(f))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let writer = BufWriter::new(FanOutWriter::new(encryptors));
+            let mut writer = PassthroughCompressor::new(config_clone.compressor.clone(), writer)
                 .map(BufWriter::new)
-                .map(|f| tar::Builder::new(f))?;
+                .map(|w| {
+                    let compress_encrypt = timings.compress_encrypt.clone();
+                    TimedWriter::new(w, compress_encrypt, timings.tar.clone())
+                })
+                .map(|w| HashingWriter::new(w, checksums))
+                .map(CountingWriter::new)
+                .map(tar::Builder::new)?;
 
             writer.follow_symlinks(true);
+            if let Some(description) = &config_clone.description {
+                append_pax_global_header(&mut writer, &[("comment", description)])?;
+            }
 
-            for entry in result_rx {
+            let mut index = config_clone
+                .index
+                .unwrap_or(false)
+                .then(ArchiveIndex::default);
+            let mut manifest = manifest.then(ArchiveManifest::default);
+            if let Some(config_yaml) = &config_yaml {
+                let start = writer.get_ref().count();
+                let size = append_config(&mut writer, config_yaml, &out_dir, dt)?;
+                let path: Arc<Path> = Path::new(CONFIG_ENTRY_PATH).into();
+                if let Some(index) = index.as_mut() {
+                    index.push(path.clone(), start, writer.get_ref().count(), size);
+                }
+                if let Some(manifest) = manifest.as_mut() {
+                    manifest.push(path, size, dt.timestamp().max(0) as u64);
+                }
+            }
+            let mut packer = config_clone
+                .pack_small_files
+                .as_deref()
+                .map(PackWriter::new);
+            while let Some(entry) = timings.tar.exclude(|| deadline.recv(&result_rx))? {
                 let entry = entry?;
-                writer.append_path_with_name(&entry.src, &entry.dst)?;
+                if let Some(stat_cache) = stat_cache
+                    .as_mut()
+                    .filter(|_| !entry.delete_src && entry.data.is_none())
+                {
+                    if let Err(e) = stat_cache.observe(&entry.src, started_at, recheck_interval) {
+                        warn!("Failed to hash {:?} for stat cache: {e}", entry.src)
+                    }
+                }
+                let precompressed = entry.precompressed || store_uncompressed.is_match(&entry.dst);
+                // Packs are compressed with the other entries
+                let packed = match packer.as_mut().filter(|_| !precompressed) {
+                    Some(packer) => packer.try_add(&entry)?,
+                    None => None,
+                };
+                let stat = match packed {
+                    Some(stat) => stat,
+                    None => {
+                        set_passthrough(&mut writer, precompressed)?;
+                        let start = writer.get_ref().count();
+                        let stat = entry.append_to(&mut writer)?;
+                        if let Some(index) = index.as_mut() {
+                            let end = writer.get_ref().count();
+                            index.push(entry.dst.clone(), start, end, stat.size);
+                        }
+                        stat
+                    }
+                };
+                entry.record_archived(&stat);
+                if let Some(manifest) = manifest.as_mut() {
+                    manifest.push(entry.dst.clone(), stat.size, stat.mtime);
+                }
                 if entry.delete_src {
                     std::fs::remove_file(entry.src)?
                 }
+                if let Some(packer) = packer.as_mut().filter(|p| p.is_full()) {
+                    set_passthrough(&mut writer, false)?;
+                    packer.flush(&mut writer, index.as_mut())?;
+                }
+            }
+            if let Some(packer) = packer.as_mut() {
+                set_passthrough(&mut writer, false)?;
+                packer.flush(&mut writer, index.as_mut())?;
             }
 
-            writer
-                .into_inner()?
-                .into_inner()
-                .map_err(IntoInnerError::into_error)?
-                .finish()?
-                .into_inner()
-                .map_err(IntoInnerError::into_error)?
-                .finish()?
-                .into_inner()
-                .map_err(IntoInnerError::into_error)?;
+            let writer = writer.into_inner()?;
+            let plaintext_size = writer.count();
+            let (writer, plaintext_sha256) = writer.into_inner().into_parts();
+            let file_writers = timings.tar.exclude(|| {
+                timings.compress_encrypt.time(|| -> Result<_> {
+                    Ok(writer
+                        .into_inner()
+                        .into_inner()
+                        .map_err(IntoInnerError::into_error)?
+                        .finish()?
+                        .into_inner()
+                        .map_err(IntoInnerError::into_error)?
+                        .finish()?)
+                })
+            })?;
+            let mut archive_sha256 = None;
+            let mut volumes = None;
+            for (idx, file_writer) in file_writers.into_iter().enumerate() {
+                let (file_writer, digest) = file_writer.into_parts();
+                let file_writer = file_writer.into_inner();
+                let archive_size = file_writer.size();
+                let file_volumes = timings
+                    .tar
+                    .exclude(|| timings.persist.time(|| file_writer.finish()))?;
+                // Checksums are written next to the local archive only
+                if idx == 0 {
+                    if let Some(manifest) = manifest.as_mut() {
+                        manifest.archive_size = archive_size;
+                        manifest.archive_sha256 = digest.clone().unwrap_or_default();
+                    }
+                    archive_sha256 = digest;
+                    volumes = file_volumes;
+                }
+            }
+            let digests =
+                plaintext_sha256
+                    .zip(archive_sha256)
+                    .map(|(plaintext_sha256, archive_sha256)| {
+                        (plaintext_size, plaintext_sha256, archive_sha256)
+                    });
 
-            Ok(())
+            timings.tar.add(write_started_at.elapsed());
+            Ok((index, stat_cache, digests, manifest, volumes))
         });
 
         let archive_create_res = match archive_file_join_handle.join().unwrap() {
-            Ok(_) => {
+            Ok((index, stat_cache, digests, manifest, volumes)) => {
                 let file_path = config_clone.out_dir.join(file_name);
-                std::fs::rename(file_path_tmp.as_path(), &file_path)
-                    .map(|_| file_path)
-                    .map_err(|e| Error::from(e))
+                timings
+                    .persist
+                    .time(|| {
+                        rename_archive(file_path_tmp.as_path(), &file_path).and_then(|_| {
+                            match volumes {
+                                Some(volumes) => {
+                                    volumes.write(VolumeManifest::volumes_path(&file_path))
+                                }
+                                None => Ok(()),
+                            }
+                        })
+                    })
+                    .map(|_| (file_path, index, stat_cache, digests, manifest))
             }
             Err(e) => Err(e.with_debug_object_and_fn_name(self.clone(), "create_write_archive")),
         }
         .map_err(|mut e| {
-            if let Err(e2) = std::fs::remove_file(file_path_tmp.as_path()) {
-                e = e.chain(e2.into())
+            for path in output_paths.iter() {
+                if let Err(e2) = remove_archive(path.as_path()) {
+                    e = e.chain(e2)
+                }
             }
 
-            return e.with_msg("Delete tmp file failed.");
+            e.with_msg("Delete tmp file failed.")
         });
 
-        let entry_create_res = entry_create_join_handle.join().unwrap();
+        let entry_create_res = deadline.join(entry_create_join_handle);
+        for (source, stats) in self.files.iter().zip(source_stats.iter()) {
+            let report = stats.to_report(source);
+            let name = source.name.as_deref().unwrap_or(source.source.type_name());
+            let counts = report.skipped_special_files;
+            if counts.total() > 0 {
+                warn!(
+                    "Skipped {} special files in source {name:?}: {counts:?}",
+                    counts.total(),
+                );
+            }
+            if report.duplicate_entries > 0 {
+                warn!(
+                    "Skipped {} entries of source {name:?} already archived by the run",
+                    report.duplicate_entries
+                );
+            }
+        }
         match archive_create_res {
-            Ok(fp) => Ok((fp, entry_create_res.err())),
+            Ok((fp, index, stat_cache, digests, manifest)) => {
+                let persist_started_at = Instant::now();
+                let mut non_fatal_error = entry_create_res.err();
+                let changes = stat_cache.map(|mut stat_cache| {
+                    if let Err(e) = stat_cache.save(&stat_cache_path) {
+                        non_fatal_error = Some(chain_optional_error(
+                            non_fatal_error.take(),
+                            e.with_msg("Save stat cache failed"),
+                        ));
+                    }
+                    stat_cache.summary().clone()
+                });
+                let fp = match non_fatal_error.is_some() && self.mark_partial.unwrap_or(false) {
+                    false => fp,
+                    true => match self.mark_archive_partial(&fp, dt, tags) {
+                        Ok(partial_fp) => partial_fp,
+                        Err(e) => {
+                            non_fatal_error = Some(chain_optional_error(
+                                non_fatal_error,
+                                e.with_msg("Mark archive partial failed"),
+                            ));
+                            fp
+                        }
+                    },
+                };
+                if let Some(index) = index {
+                    if let Err(e) =
+                        index.write(ArchiveIndex::index_path(&fp), self.metadata_encryptor())
+                    {
+                        non_fatal_error = Some(chain_optional_error(
+                            non_fatal_error,
+                            e.with_msg("Write archive index failed"),
+                        ));
+                    }
+                }
+                if let Some(mut manifest) = manifest {
+                    let res =
+                        CryptoParameters::read(&fp, self.crypto_policy()).and_then(|crypto| {
+                            manifest.crypto = Some(crypto);
+                            manifest.write(
+                                ArchiveManifest::manifest_path(&fp),
+                                self.metadata_encryptor(),
+                            )
+                        });
+                    if let Err(e) = res {
+                        non_fatal_error = Some(chain_optional_error(
+                            non_fatal_error,
+                            e.with_msg("Write archive manifest failed"),
+                        ));
+                    }
+                }
+                if let Some((plaintext_size, plaintext_sha256, archive_sha256)) = digests.clone() {
+                    let res = self.pipeline_descriptor().and_then(|pipeline| {
+                        ArchiveChecksums {
+                            pipeline,
+                            archive_size: archive_file_size(&fp)?,
+                            archive_sha256,
+                            plaintext_size,
+                            plaintext_sha256,
+                        }
+                        .write(&fp)
+                    });
+                    if let Err(e) = res {
+                        non_fatal_error = Some(chain_optional_error(
+                            non_fatal_error,
+                            e.with_msg("Write archive checksums failed"),
+                        ));
+                    }
+                }
+                if self.recovery_instructions.unwrap_or(false) {
+                    let res = self.pipeline_descriptor().and_then(|pipeline| {
+                        RecoveryInstructions::new(
+                            &fp,
+                            dt,
+                            pipeline,
+                            &self.encryptor,
+                            digests.as_ref().map(|(_, _, sha256)| sha256.clone()),
+                            self.pack_small_files.is_some(),
+                        )
+                        .write(&fp)
+                    });
+                    if let Err(e) = res {
+                        non_fatal_error = Some(chain_optional_error(
+                            non_fatal_error,
+                            e.with_msg("Write recovery instructions failed"),
+                        ));
+                    }
+                }
+                let mut report = None;
+                if self.publishes_report() || self.catalog.unwrap_or(true) {
+                    match self.build_report(
+                        &fp,
+                        dt,
+                        started_at,
+                        source_stats.as_ref(),
+                        changes,
+                        non_fatal_error.as_ref(),
+                    ) {
+                        Ok(r) => report = Some(r),
+                        Err(e) => {
+                            non_fatal_error = Some(chain_optional_error(
+                                non_fatal_error,
+                                e.with_msg("Write backup report failed"),
+                            ));
+                        }
+                    }
+                }
+                timings.persist.add(persist_started_at.elapsed());
+                Ok((fp, non_fatal_error, report))
+            }
             Err(e1) => match entry_create_res {
                 Ok(_) => Err(e1),
                 Err(e2) => Err(e1.chain(e2)),
@@ -249,54 +1371,1674 @@ impl BackupConfig {
         }
     }
 
-    pub fn start_loop(&self, pre_process_pool: Arc<ThreadPool>) -> Result<()> {
-        let mut set: HashSet<_> = read_dir(&self.out_dir)?
-            .into_iter()
-            .filter_map(|r| r.ok())
-            .filter_map(|r| {
-                self.get_date_time_from_file_path(&r.path())
-                    .map(|dt| ItemWithDateTime::from((r.path(), dt)))
-            })
-            .map(Rc::new)
-            .collect();
-
-        let start = set
+    /// Fail when out_dir, holding the archive and the copies staged for destinations, or the dir
+    /// SQLite snapshots are taken in lacks the space estimated by `disk_space_check`.
+    fn check_disk_space(&self, disk_space_check: &DiskSpaceCheckConfig) -> Result<()> {
+        let (sources_size, snapshots_size) = self.planned_source_sizes();
+        let last_archive_size = match disk_space_check.estimate.unwrap_or_default() {
+            SpaceEstimate::LastArchive => self
+                .read_history()?
+                .latest()
+                .map(|entry| archive_file_size(&entry.item))
+                .transpose()?,
+            SpaceEstimate::Sources => None,
+        };
+        let staged_copies = self
+            .storage
             .iter()
-            .map(|i| i.date_time.clone())
-            .sorted_unstable()
-            .last()
-            .unwrap_or(DateTime::UNIX_EPOCH.to_utc().into());
-        let cron = self.cron.as_ref();
-        let mut start = cron_parser::parse(cron, start.as_ref()).unwrap();
-        loop {
-            let now = Utc::now();
-            if now < start {
-                info!("Sleeping until {start}");
-                std::thread::sleep((start - now).to_std().unwrap())
-            } else {
-                if let Some(retention) = &self.retention {
-                    retention
-                        .get_delete(set.iter().cloned(), now)
-                        .for_each(|to_delete| {
-                            info!("Removing out of retention file {:?}", &to_delete.item);
-                            let removed = set.remove(&to_delete);
-                            if !removed {
-                                panic!("Remove item in memory {:?} failed", &to_delete.item);
-                            }
-                            let _ = std::fs::remove_file(&to_delete.item);
-                        });
-                }
-                info!("Trying to create backup...");
+            .flat_map(|s| s.iter())
+            .filter(|destination| destination.encryptor.is_some())
+            .count() as u64;
+        let archive_size = last_archive_size.unwrap_or(sources_size);
+        let snapshot_dir = match self.no_tempfile.unwrap_or(false) {
+            true => self.snapshot_dir(),
+            false => std::env::temp_dir(),
+        };
+        check_free_space(&[
+            (
+                self.out_dir.to_path_buf(),
+                disk_space_check.required(archive_size * (1 + staged_copies)),
+            ),
+            (snapshot_dir, disk_space_check.required(snapshots_size)),
+        ])
+        .with_msg("Disk space check failed")
+    }
 
-                let (file_path, non_fatal_error) =
-                    self.create_archive(now, pre_process_pool.clone())?;
-                info!("Created backup file: {:?}", &file_path);
-                if let Some(non_fatal_error) = non_fatal_error {
-                    warn!("Received non fatal error: {non_fatal_error}")
-                }
-                set.insert(Rc::new(ItemWithDateTime::from((file_path, now))));
-                start = cron_parser::parse(cron, &now).unwrap();
+    /// Size of the entries of all sources and of those snapshotted, i.e. of volatile sources.
+    /// Entries failing to list are left out, they are reported when archiving.
+    fn planned_source_sizes(&self) -> (u64, u64) {
+        let own_dirs = Arc::new(self.own_dirs());
+        let mut sources_size = 0;
+        let mut snapshots_size = 0;
+        for file in self.files.iter() {
+            let source = file.source.with_excluded_dirs(own_dirs.clone());
+            let size: u64 = source
+                .planned_entries()
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|entry| entry.size)
+                .sum();
+            sources_size += size;
+            if source.is_volatile() {
+                snapshots_size += size;
+            }
+        }
+        (sources_size, snapshots_size)
+    }
+
+    /// Whether reports are written next to archives or pushed to report sinks.
+    fn publishes_report(&self) -> bool {
+        self.report.unwrap_or(false) || self.report_sinks.as_ref().is_some_and(|s| !s.is_empty())
+    }
+
+    /// Backups recorded in the catalog, oldest first.
+    pub fn catalog_entries(&self) -> Result<Vec<CatalogEntry>> {
+        Catalog::read(Catalog::catalog_path(self.state_dir_path()))
+    }
+
+    /// Append `entry` to the catalog unless disabled, a failure is only logged.
+    fn record_in_catalog(&self, entry: CatalogEntry) {
+        if !self.catalog.unwrap_or(true) {
+            return;
+        }
+        if let Err(e) = Catalog::append(Catalog::catalog_path(self.state_dir_path()), &entry) {
+            warn!("Failed to record backup in catalog: {e}");
+        }
+    }
+
+    /// Write `report` next to the archive, log it and push it to the report sinks.
+    fn publish_report(&self, report: &BackupReport, non_fatal_error: &mut Option<Error>) {
+        if self.report.unwrap_or(false) {
+            if let Err(e) = report.write(self.metadata_encryptor()) {
+                *non_fatal_error = Some(chain_optional_error(
+                    non_fatal_error.take(),
+                    e.with_msg("Write backup report failed"),
+                ));
+                return;
+            }
+        }
+        info!("Backup report: {report}");
+        for sink in self.report_sinks.iter().flat_map(|s| s.iter()) {
+            if let Err(e) = sink.push(report) {
+                *non_fatal_error = Some(chain_optional_error(non_fatal_error.take(), e));
             }
         }
     }
+
+    /// Rename a freshly created archive and its staged copies to the `-partial` name.
+    fn mark_archive_partial(
+        &self,
+        archive_path: &Path,
+        dt: DateTime<Utc>,
+        tags: ArchiveTags,
+    ) -> Result<PathBuf> {
+        let partial_tags = ArchiveTags {
+            partial: true,
+            ..tags
+        };
+        let partial_path =
+            self.out_dir
+                .join(self.archive_file_name(dt, &self.encryptor, partial_tags));
+        rename_archive(archive_path, &partial_path)?;
+        for (idx, destination) in self.storage.iter().flat_map(|s| s.iter()).enumerate() {
+            if let Some(encryptor) = &destination.encryptor {
+                std::fs::rename(
+                    self.destination_staging_path(idx, dt, encryptor, tags),
+                    self.destination_staging_path(idx, dt, encryptor, partial_tags),
+                )?;
+            }
+        }
+        Ok(partial_path)
+    }
+
+    fn build_report(
+        &self,
+        archive_file: &Path,
+        backup_time: DateTime<Utc>,
+        started_at: DateTime<Utc>,
+        source_stats: &[SourceStats],
+        changes: Option<ChangeSummary>,
+        non_fatal_error: Option<&Error>,
+    ) -> Result<BackupReport> {
+        let finished_at = Utc::now();
+        let duration = (finished_at - started_at).to_std().unwrap_or_default();
+        let bytes_read = source_stats.iter().map(SourceStats::bytes).sum();
+        Ok(BackupReport {
+            archive_file: archive_file.into(),
+            backup_time,
+            started_at,
+            finished_at,
+            duration,
+            archive_size: archive_file_size(archive_file)?,
+            read_bytes_per_second: bytes_per_second(bytes_read, duration),
+            description: self.description.clone(),
+            manual: is_manual_archive(archive_file),
+            pipeline: Some(self.pipeline_descriptor()?),
+            sources: self
+                .files
+                .iter()
+                .zip(source_stats)
+                .map(|(source, stats)| stats.to_report(source))
+                .collect(),
+            changes,
+            content_types: self.content_types.unwrap_or(false).then(|| {
+                ContentTypeStats::to_shares(
+                    source_stats.iter().flat_map(SourceStats::content_types),
+                )
+            }),
+            uploads: None,
+            end_to_end_bytes_per_second: None,
+            non_fatal_errors: non_fatal_error.map(error_messages).unwrap_or_default(),
+        })
+    }
+
+    /// Files stored next to an archive that share its lifetime.
+    pub fn sidecar_paths<P: AsRef<Path>>(&self, archive_path: P) -> Vec<PathBuf> {
+        [
+            BackupReport::report_path(&archive_path),
+            ArchiveIndex::index_path(&archive_path),
+            ArchiveManifest::manifest_path(&archive_path),
+            ArchiveChecksums::checksums_path(&archive_path),
+            UploadReceipts::receipts_path(&archive_path),
+            RecoveryInstructions::json_path(&archive_path),
+            RecoveryInstructions::markdown_path(&archive_path),
+            VolumeManifest::volumes_path(&archive_path),
+        ]
+        .into_iter()
+        .flat_map(|path| [encrypted_path(&path, &self.encryptor), path])
+        .unique()
+        .collect()
+    }
+
+    /// Stages and parameters of the archive pipeline, recorded in the backup report.
+    pub fn pipeline_descriptor(&self) -> Result<PipelineDescriptor> {
+        PipelineDescriptor::new(&self.compressor, &self.encryptor)
+    }
+
+    /// Encryptor of report and index files, plain JSON unless `encrypt_metadata` is enabled.
+    pub fn metadata_encryptor(&self) -> &EncryptorConfig {
+        match self.encrypt_metadata.unwrap_or(false) {
+            true => self.encryptor.as_ref(),
+            false => &NO_ENCRYPTOR,
+        }
+    }
+
+    fn destination_upload_state_dir(&self, idx: usize) -> PathBuf {
+        self.state_dir_path()
+            .join("uploads")
+            .join(format!("destination-{idx}"))
+    }
+
+    /// Upload `upload_path`, the archive or its copy staged for the destination, recording a
+    /// receipt next to `archive_path` once done.
+    fn upload_to_destination(
+        &self,
+        idx: usize,
+        destination: &StorageDestinationConfig,
+        archive_path: &Path,
+        upload_path: &Path,
+    ) -> Result<UploadReport> {
+        let bytes = std::fs::metadata(upload_path)?.len();
+        let started_at = Instant::now();
+        match destination.as_resumable() {
+            Some(backend) => resumable_upload(
+                backend,
+                upload_path,
+                &upload_state_path(&self.destination_upload_state_dir(idx), upload_path),
+            ),
+            None => destination.upload(upload_path),
+        }?;
+        let duration = started_at.elapsed();
+        self.record_upload(destination, archive_path, upload_path)
+            .with_msg(format!("Record upload of {upload_path:?} failed"))?;
+        Ok(UploadReport::new(idx, bytes, duration))
+    }
+
+    /// Record a receipt of `upload_path` once `destination` confirms its copy.
+    fn record_upload(
+        &self,
+        destination: &StorageDestinationConfig,
+        archive_path: &Path,
+        upload_path: &Path,
+    ) -> Result<()> {
+        let sha256 = sha256_file(upload_path)?;
+        if upload_path == archive_path {
+            let checksums_path = ArchiveChecksums::checksums_path(archive_path);
+            if let Ok(checksums) = ArchiveChecksums::read(checksums_path) {
+                if checksums.archive_sha256 != sha256 {
+                    Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("uploaded {archive_path:?} does not match its checksums"),
+                    ))?
+                }
+            }
+        }
+        let file_name: Arc<str> = upload_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into();
+        let size = std::fs::metadata(upload_path)?.len();
+        destination
+            .confirm_upload(&file_name, size, &sha256)
+            .with_msg("Stored copy not confirmed")?;
+        UploadReceipts::record(
+            archive_path,
+            UploadReceipt {
+                destination: destination.id(),
+                file_name,
+                size,
+                sha256,
+                uploaded_at: Utc::now(),
+            },
+        )
+    }
+
+    /// Local archive a copy staged for a destination with `encryptor` was created alongside.
+    fn staged_archive_path(&self, staging_path: &Path, encryptor: &EncryptorConfig) -> PathBuf {
+        let file_name = staging_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let stem = file_name
+            .strip_suffix(&format!(".{}", self.file_ext_with_encryptor(encryptor)))
+            .unwrap_or(&file_name);
+        self.out_dir.join(format!(
+            "{stem}.{}",
+            self.file_ext_with_encryptor(&self.encryptor)
+        ))
+    }
+
+    /// Whether every storage destination confirmed a copy of `archive_path`, always `true`
+    /// unless `require_offsite_before_prune` is enabled.
+    fn is_prunable(&self, archive_path: &Path) -> bool {
+        if !self.require_offsite_before_prune.unwrap_or(false) {
+            return true;
+        }
+        let Ok(receipts) = UploadReceipts::read(archive_path) else {
+            return false;
+        };
+        self.storage
+            .iter()
+            .flat_map(|s| s.iter())
+            .all(|destination| receipts.has_destination(&destination.id()))
+    }
+
+    /// Continue uploads interrupted in previous cycles.
+    fn resume_pending_uploads(
+        &self,
+        idx: usize,
+        destination: &StorageDestinationConfig,
+    ) -> Vec<Error> {
+        let Some(backend) = destination.as_resumable() else {
+            return Vec::new();
+        };
+        let Ok(read_dir) = read_dir(self.destination_upload_state_dir(idx)) else {
+            return Vec::new();
+        };
+
+        read_dir
+            .filter_map(|r| r.ok())
+            .map(|r| r.path())
+            .filter(|p| p.to_string_lossy().ends_with(".upload.json"))
+            .filter_map(|state_path| {
+                let state = match UploadState::read(&state_path) {
+                    Ok(state) => state,
+                    Err(e) => return Some(e),
+                };
+                if !state.archive_path.exists() {
+                    return std::fs::remove_file(&state_path).err().map(Error::from);
+                }
+                let archive_path = match &destination.encryptor {
+                    Some(encryptor) if self.is_staged(idx, &state.archive_path) => {
+                        self.staged_archive_path(&state.archive_path, encryptor)
+                    }
+                    _ => state.archive_path.to_path_buf(),
+                };
+                let res =
+                    resumable_upload(backend, &state.archive_path, &state_path).and_then(|_| {
+                        self.record_upload(destination, &archive_path, &state.archive_path)
+                            .with_msg(format!("Record upload of {:?} failed", state.archive_path))
+                    });
+                if res.is_ok() {
+                    self.remove_if_staged(idx, &state.archive_path);
+                }
+                res.err()
+            })
+            .collect_vec()
+    }
+
+    fn is_staged(&self, idx: usize, path: &Path) -> bool {
+        path.starts_with(self.out_dir.join(format!(".destination-{idx}")))
+    }
+
+    fn remove_if_staged(&self, idx: usize, path: &Path) {
+        if self.is_staged(idx, path) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    pub fn upload_to_storage(&self, archive_path: &Path, dt: DateTime<Utc>) -> Result<()> {
+        convert_error_vec(self.upload_to_destinations(archive_path, dt).1)
+    }
+
+    /// Upload `archive_path` to every storage destination, returning the completed uploads and
+    /// the errors of the others.
+    fn upload_to_destinations(
+        &self,
+        archive_path: &Path,
+        dt: DateTime<Utc>,
+    ) -> (Vec<UploadReport>, Vec<Error>) {
+        let tags = ArchiveTags::from_path(archive_path);
+        let mut uploads = Vec::new();
+        let errors = self
+            .storage
+            .iter()
+            .flat_map(|s| s.iter())
+            .enumerate()
+            .flat_map(|(idx, destination)| {
+                let mut errors = self.resume_pending_uploads(idx, destination);
+                let upload_res = match &destination.encryptor {
+                    None => {
+                        self.upload_to_destination(idx, destination, archive_path, archive_path)
+                    }
+                    Some(encryptor) => {
+                        let staging_path = self.destination_staging_path(idx, dt, encryptor, tags);
+                        let res = self.upload_to_destination(
+                            idx,
+                            destination,
+                            archive_path,
+                            &staging_path,
+                        );
+                        // Resumable uploads keep the staged copy until it is fully uploaded
+                        if res.is_ok() || destination.as_resumable().is_none() {
+                            self.remove_if_staged(idx, &staging_path);
+                        }
+                        res
+                    }
+                };
+                match upload_res {
+                    Ok(upload) => uploads.push(upload),
+                    Err(e) => errors.push(e),
+                }
+                errors
+            })
+            .collect_vec();
+        (uploads, errors)
+    }
+
+    /// Download the copies of local archives from every destination and check they are
+    /// restorable, see [`verify_download`]. Only archives still in `out_dir` are checked.
+    pub fn verify_remote(&self, options: &RemoteVerifyOptions) -> Result<Vec<RemoteVerification>> {
+        let archives = self.local_archives()?;
+        let compression = self
+            .pipeline_descriptor()?
+            .stages
+            .into_iter()
+            .find(|s| s.kind == StageKind::Compress)
+            .map(|s| s.format)
+            .unwrap_or_else(|| "none".into());
+
+        let mut verifications = Vec::new();
+        for (idx, destination) in self.storage.iter().flat_map(|s| s.iter()).enumerate() {
+            if options.destination.is_some_and(|d| d != idx) {
+                continue;
+            }
+            let random_state = RandomState::new();
+            let selected = match options.sample {
+                Some(sample) if sample < archives.len() => archives
+                    .iter()
+                    .take(sample.min(1))
+                    .chain(
+                        archives
+                            .iter()
+                            .skip(1)
+                            .sorted_by_cached_key(|(p, _)| random_state.hash_one(p))
+                            .take(sample.saturating_sub(1)),
+                    )
+                    .collect_vec(),
+                _ => archives.iter().collect_vec(),
+            };
+            for (archive_path, dt) in selected {
+                let encryptor = destination.encryptor.as_deref().unwrap_or(&self.encryptor);
+                let file_name = self.destination_file_name(destination, archive_path, *dt);
+                let checksums =
+                    ArchiveChecksums::read(ArchiveChecksums::checksums_path(archive_path)).ok();
+                info!("Verifying {file_name:?} on destination {idx}");
+                let result = verify_download(
+                    destination,
+                    &file_name,
+                    encryptor,
+                    &compression,
+                    checksums.as_ref(),
+                    destination.encryptor.is_none(),
+                    options.bandwidth_limit,
+                );
+                verifications.push(RemoteVerification {
+                    destination: idx,
+                    archive_path: archive_path.clone(),
+                    file_name,
+                    result,
+                });
+            }
+        }
+        Ok(verifications)
+    }
+
+    /// History of the archives in `out_dir`, as the schedule starts with it.
+    pub fn read_history(&self) -> Result<BackupHistory> {
+        self.local_archives().map(BackupHistory::new)
+    }
+
+    /// Archives in `out_dir`, newest first.
+    fn local_archives(&self) -> Result<Vec<(PathBuf, DateTime<Utc>)>> {
+        Ok(read_dir(&self.out_dir)?
+            .filter_map(|r| r.ok())
+            .map(|r| r.path())
+            // A split archive is found by its first volume
+            .map(|p| first_volume_archive_path(&p).unwrap_or(p))
+            .filter_map(|p| self.get_date_time_from_file_path(&p).map(|dt| (p, dt)))
+            .sorted_unstable_by_key(|(_, dt)| Reverse(*dt))
+            .collect_vec())
+    }
+
+    /// Name the copy of `archive_path` is stored as on `destination`.
+    fn destination_file_name(
+        &self,
+        destination: &StorageDestinationConfig,
+        archive_path: &Path,
+        dt: DateTime<Utc>,
+    ) -> String {
+        match &destination.encryptor {
+            None => archive_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into(),
+            Some(encryptor) => {
+                self.archive_file_name(dt, encryptor, ArchiveTags::from_path(archive_path))
+            }
+        }
+    }
+
+    /// Re-encrypt `archive_path` for a destination with its own `encryptor`, the staged copy
+    /// written at creation is gone once uploaded.
+    fn stage_for_destination(
+        &self,
+        archive_path: &Path,
+        staging_path: &Path,
+        encryptor: &EncryptorConfig,
+    ) -> Result<()> {
+        if let Some(parent) = staging_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut reader = self
+            .encryptor
+            .build_decryptor(BufReader::new(open_archive_file(archive_path)?))?;
+        let mut writer = encryptor.build_encryptor(BufWriter::new(File::create(staging_path)?))?;
+        std::io::copy(&mut reader, &mut writer)?;
+        writer
+            .finish()?
+            .into_inner()
+            .map_err(IntoInnerError::into_error)?;
+        Ok(())
+    }
+
+    /// Upload local archives missing on storage destinations, e.g. after a destination was
+    /// unreachable for some runs. Destinations that can list their files are compared against
+    /// the listing, others against the upload receipts. Returns the uploaded archives with the
+    /// destination index.
+    pub fn sync_storage(&self) -> Result<Vec<(usize, PathBuf)>> {
+        let _lock = self.lock_archive_base_name()?;
+        let archives = self.local_archives()?;
+        let mut uploaded = Vec::new();
+        let mut errors = Vec::new();
+        for (idx, destination) in self.storage.iter().flat_map(|s| s.iter()).enumerate() {
+            errors.extend(self.resume_pending_uploads(idx, destination));
+            let listing = match destination.list() {
+                Ok(names) => Some(names.into_iter().collect::<HashSet<_>>()),
+                Err(e) => {
+                    info!("Destination {idx} cannot be listed, using upload receipts: {e}");
+                    None
+                }
+            };
+            for (archive_path, dt) in archives.iter() {
+                let file_name = self.destination_file_name(destination, archive_path, *dt);
+                let present = match &listing {
+                    Some(names) => names.contains(file_name.as_str()),
+                    None => UploadReceipts::read(archive_path)
+                        .is_ok_and(|r| r.has_destination(&destination.id())),
+                };
+                if present {
+                    continue;
+                }
+                info!("Uploading {file_name:?} missing on destination {idx}");
+                let res = match &destination.encryptor {
+                    None => {
+                        self.upload_to_destination(idx, destination, archive_path, archive_path)
+                    }
+                    Some(encryptor) => {
+                        let staging_path = self.destination_staging_path(
+                            idx,
+                            *dt,
+                            encryptor,
+                            ArchiveTags::from_path(archive_path),
+                        );
+                        let res = self
+                            .stage_for_destination(archive_path, &staging_path, encryptor)
+                            .and_then(|_| {
+                                self.upload_to_destination(
+                                    idx,
+                                    destination,
+                                    archive_path,
+                                    &staging_path,
+                                )
+                            });
+                        if res.is_ok() || destination.as_resumable().is_none() {
+                            self.remove_if_staged(idx, &staging_path);
+                        }
+                        res
+                    }
+                };
+                match res {
+                    Ok(_) => uploaded.push((idx, archive_path.clone())),
+                    Err(e) => errors.push(
+                        e.with_msg(format!("Sync of {file_name:?} to destination {idx} failed")),
+                    ),
+                }
+            }
+        }
+        convert_error_vec(errors)?;
+        Ok(uploaded)
+    }
+
+    fn notify(&self, event: BackupEvent) {
+        for notification in self.notifications.iter().flat_map(|n| n.iter()) {
+            if let Err(e) = notification.notify(&event) {
+                warn!("Failed to send notification: {e}")
+            }
+        }
+    }
+
+    pub fn start_loop(&self, pre_process_pool: Arc<ThreadPool>) -> Result<()> {
+        if self.archives.is_some() {
+            return Self::start_loops(self.archive_jobs(), pre_process_pool);
+        }
+        if let Some(drill) = &self.restore_drill {
+            self.spawn_restore_drill_loop(drill.clone());
+        }
+        if let Some(reconcile) = &self.reconcile {
+            self.spawn_reconcile_loop(reconcile.clone());
+        }
+        let clock = self.clock.unwrap_or_default().build_clock();
+        self.start_loop_with_clock(pre_process_pool, clock.as_ref())
+    }
+
+    /// Run restore drills on the `drill` schedule on a thread of their own, archives are opened
+    /// before retention could delete them so the backup lock is not needed.
+    fn spawn_restore_drill_loop(&self, drill: Arc<RestoreDrillConfig>) {
+        let config = self.clone();
+        std::thread::spawn(move || {
+            let clock = config.clock.unwrap_or_default().build_clock();
+            let timezone = config.timezone.unwrap_or_default();
+            loop {
+                let next = timezone.next_run(&drill.cron, &clock.now()).unwrap();
+                info!(
+                    "Next restore drill {}",
+                    HumanNextRun {
+                        at: next,
+                        now: clock.now()
+                    }
+                );
+                while clock.now() < next {
+                    if config.is_reload_requested() {
+                        return;
+                    }
+                    clock.sleep_until(next.min(clock.now() + config.sleep_chunk()));
+                }
+                let _ = config.run_restore_drill();
+            }
+        });
+    }
+
+    /// Restore the newest completed archive into a fresh directory of the drill scratch dir and
+    /// run the drill checks, notifying the result. Returns the drilled archive.
+    pub fn run_restore_drill(&self) -> Result<PathBuf> {
+        let notify_failed = |file_path: Option<&Path>, e: &Error| {
+            let event = BackupEvent::RestoreDrillFailed {
+                file_path: file_path.map(Into::into),
+                error: e.to_string().into(),
+            };
+            error!("{event}");
+            self.notify(event);
+        };
+        let archive = self
+            .local_archives()
+            .and_then(|archives| {
+                archives
+                    .into_iter()
+                    .map(|(archive, _)| archive)
+                    .find(|archive| self.is_completed_archive(archive))
+                    .ok_or_else(|| {
+                        std::io::Error::other(format!("no archive in {:?} to drill", self.out_dir))
+                            .into()
+                    })
+            })
+            .inspect_err(|e| notify_failed(None, e))?;
+
+        let started = Instant::now();
+        let checks = self
+            .drill_archive(&archive)
+            .inspect_err(|e| notify_failed(Some(&archive), e))?;
+        let event = BackupEvent::RestoreDrillPassed {
+            file_path: archive.as_path().into(),
+            duration: started.elapsed(),
+            checks,
+        };
+        info!("{event}");
+        self.notify(event);
+        Ok(archive)
+    }
+
+    /// Restore drill of `archive`, returning the results of the database checks.
+    fn drill_archive(&self, archive: &Path) -> Result<Vec<Arc<str>>> {
+        let drill = self
+            .restore_drill
+            .as_deref()
+            .ok_or_else(|| std::io::Error::other("restore_drill is not configured"))?;
+        let scratch_dir = drill
+            .scratch_dir
+            .as_ref()
+            .map(|d| d.to_path_buf())
+            .unwrap_or_else(|| self.state_dir_path());
+        let target = scratch_dir.join(format!("drill-{}", Utc::now().format("%Y%m%dT%H%M%S")));
+        let secrets = ConfigSecretSource {
+            encryptor: self.encryptor.clone(),
+            fallback: PromptSecretSource {
+                identity_files: drill
+                    .identity_files
+                    .iter()
+                    .flatten()
+                    .map(|p| p.to_path_buf())
+                    .collect(),
+            },
+        };
+        let databases = self
+            .files
+            .iter()
+            .filter_map(|source| match &source.source {
+                #[cfg(feature = "sqlite")]
+                ArchiveEntryConfig::Sqlite(sqlite) => Some(sqlite.dst()),
+                _ => None,
+            })
+            .collect_vec();
+        info!("Restore drill of {archive:?} into {target:?}");
+        let res = drill.run(archive, &target, &secrets, &databases);
+        if !drill.keep.unwrap_or(false) {
+            if let Err(e) = std::fs::remove_dir_all(&target) {
+                warn!("Failed to remove restore drill directory {target:?}: {e}");
+            }
+        }
+        res
+    }
+
+    /// Reconcile on the `reconcile` schedule on a thread of their own. The daemon holds the
+    /// backup lock, receipts are read before listing so a copy uploaded meanwhile is not reported
+    /// missing.
+    fn spawn_reconcile_loop(&self, reconcile: Arc<ReconcileConfig>) {
+        let config = self.clone();
+        std::thread::spawn(move || {
+            let clock = config.clock.unwrap_or_default().build_clock();
+            let timezone = config.timezone.unwrap_or_default();
+            loop {
+                let next = timezone.next_run(&reconcile.cron, &clock.now()).unwrap();
+                info!(
+                    "Next reconciliation {}",
+                    HumanNextRun {
+                        at: next,
+                        now: clock.now()
+                    }
+                );
+                while clock.now() < next {
+                    if config.is_reload_requested() {
+                        return;
+                    }
+                    clock.sleep_until(next.min(clock.now() + config.sleep_chunk()));
+                }
+                let res = config
+                    .wait_archive_base_name_lock()
+                    .and_then(|_lock| config.run_reconcile(reconcile.repair.unwrap_or(false)));
+                if let Err(e) = res {
+                    warn!("Reconciliation failed: {e}");
+                }
+            }
+        });
+    }
+
+    /// Compare the catalog against the out dir and the destination listings, repairing it when
+    /// `repair` is set and notifying any drift. Does not take the backup lock, callers do.
+    pub fn run_reconcile(&self, repair: bool) -> Result<ReconcileReport> {
+        let mut report = ReconcileReport::default();
+        let archives = self.local_archives()?;
+        let receipts = archives
+            .iter()
+            .map(|(archive_path, _)| UploadReceipts::read(archive_path).unwrap_or_default())
+            .collect_vec();
+
+        for path in self.orphaned_sidecars()? {
+            if repair {
+                match std::fs::remove_file(&path) {
+                    Ok(_) => report.repaired += 1,
+                    Err(e) => warn!("Failed to remove orphaned {path:?}: {e}"),
+                }
+            }
+            report
+                .drifts
+                .push(Drift::OrphanedSidecar { path: path.into() });
+        }
+
+        let interval = self
+            .reconcile
+            .as_ref()
+            .and_then(|r| r.destination_interval)
+            .unwrap_or(DEFAULT_RECONCILE_INTERVAL);
+        for (idx, destination) in self.storage.iter().flat_map(|s| s.iter()).enumerate() {
+            if idx > 0 {
+                std::thread::sleep(interval);
+            }
+            let listing = match destination.list() {
+                Ok(names) => names.into_iter().collect::<HashSet<_>>(),
+                Err(e) => {
+                    report
+                        .unchecked_destinations
+                        .push((idx, e.to_string().into()));
+                    continue;
+                }
+            };
+
+            for ((archive_path, _), receipts) in archives.iter().zip(receipts.iter()) {
+                let Some(receipt) = receipts
+                    .receipts
+                    .iter()
+                    .find(|r| r.destination == destination.id())
+                else {
+                    continue;
+                };
+                if listing.contains(&receipt.file_name) {
+                    continue;
+                }
+                if repair {
+                    match UploadReceipts::forget(archive_path, &destination.id()) {
+                        Ok(_) => report.repaired += 1,
+                        Err(e) => warn!("Failed to forget receipt of {archive_path:?}: {e}"),
+                    }
+                }
+                report.drifts.push(Drift::MissingCopy {
+                    destination: idx,
+                    file_name: receipt.file_name.clone(),
+                });
+            }
+
+            // Only a destination pruned along with the local archives has a known set of copies
+            if destination
+                .prune
+                .as_ref()
+                .is_none_or(|p| p.retention.is_some())
+            {
+                continue;
+            }
+            let pending = PendingDeletions::read(PendingDeletions::pending_path(
+                &self.state_dir_path(),
+                idx,
+            ))?;
+            let expected: HashSet<String> = archives
+                .iter()
+                .map(|(archive_path, dt)| {
+                    self.destination_file_name(destination, archive_path, *dt)
+                })
+                .collect();
+            let ext = self.file_ext_with_encryptor(
+                destination.encryptor.as_deref().unwrap_or(&self.encryptor),
+            );
+            report.drifts.extend(
+                listing
+                    .into_iter()
+                    .filter(|name| self.date_time_from_file_name(name, &ext).is_some())
+                    .filter(|name| !expected.contains(name.as_ref()))
+                    .filter(|name| !pending.file_names.contains(name))
+                    .sorted()
+                    .map(|file_name| Drift::UnexpectedCopy {
+                        destination: idx,
+                        file_name,
+                    }),
+            );
+        }
+
+        if report.drifts.is_empty() && report.unchecked_destinations.is_empty() {
+            info!("Catalog of {:?} matches storage", self.archive_base_name);
+        } else {
+            let event = BackupEvent::CatalogDrift {
+                drifts: report
+                    .drifts
+                    .iter()
+                    .map(|d| d.to_string().into())
+                    .chain(
+                        report
+                            .unchecked_destinations
+                            .iter()
+                            .map(|(idx, e)| format!("destination {idx} not checked: {e}").into()),
+                    )
+                    .collect(),
+                repaired: report.repaired,
+            };
+            warn!("{event}");
+            self.notify(event);
+        }
+        Ok(report)
+    }
+
+    /// Sidecar files in the out dir of archives of this job that no longer exist.
+    fn orphaned_sidecars(&self) -> Result<Vec<PathBuf>> {
+        let suffixes = self
+            .sidecar_paths(Path::new(""))
+            .into_iter()
+            .filter_map(|p| p.to_str().map(str::to_string))
+            .collect_vec();
+        Ok(read_dir(&self.out_dir)?
+            .filter_map(|r| r.ok())
+            .map(|r| r.path())
+            .filter(|path| {
+                let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+                    return false;
+                };
+                suffixes.iter().any(|suffix| {
+                    file_name
+                        .strip_suffix(suffix.as_str())
+                        .is_some_and(|archive| {
+                            let archive_path = path.with_file_name(archive);
+                            self.get_date_time_from_file_path(&archive_path).is_some()
+                                && !archive_exists(&archive_path)
+                        })
+                })
+            })
+            .sorted()
+            .collect())
+    }
+
+    /// Run the schedule of every job on its own thread, returning the first error.
+    fn start_loops(jobs: Vec<BackupConfig>, pre_process_pool: Arc<ThreadPool>) -> Result<()> {
+        let job_count = jobs.len();
+        let (result_tx, result_rx) = sync_channel(job_count);
+        for job in jobs {
+            let result_tx = result_tx.clone();
+            let pre_process_pool = pre_process_pool.clone();
+            std::thread::spawn(move || {
+                let res = job
+                    .start_loop(pre_process_pool)
+                    .with_msg(format!("Archive {:?} failed", job.archive_base_name));
+                let _ = result_tx.send(res);
+            });
+        }
+        // A loop returns `Ok` on a reload only, the others finish their cycle and follow
+        for _ in 0..job_count {
+            result_rx
+                .recv()
+                .map_err(|e| Error::from(std::io::Error::other(e)))??;
+        }
+        Ok(())
+    }
+
+    pub fn start_loop_with_clock(
+        &self,
+        pre_process_pool: Arc<ThreadPool>,
+        clock: &dyn Clock,
+    ) -> Result<()> {
+        let _lock = self.lock_schedule()?;
+        self.check_out_dir()?;
+        let status = StatusFile::open(self.state_dir_path());
+        let history = self.read_history()?;
+        if self.schedule == Some(ScheduleMode::External) {
+            return self.serve_triggers(&history, pre_process_pool, clock, &status);
+        }
+
+        // Manual runs and archives left by a crashed run do not move the schedule, newest first
+        // so only one report is usually read
+        let start = history
+            .entries()
+            .into_iter()
+            .filter(|i| !is_manual_archive(&i.item))
+            .find(|i| self.is_completed_archive(&i.item))
+            .map(|i| i.date_time.clone())
+            .unwrap_or(DateTime::UNIX_EPOCH.to_utc().into());
+        let cron = self.cron.as_ref();
+        let timezone = self.timezone.unwrap_or_default();
+        let mut start = timezone.next_run(cron, start.as_ref()).unwrap();
+        let started = clock.now();
+        // A reload starts over the loop, it is no daemon start
+        let reloaded = self.reload.as_ref().is_some_and(|r| r.is_reloaded());
+        if self.run_on_start.unwrap_or(false) && !reloaded {
+            start = start.min(started);
+        }
+        // Kept so the clamp below cannot cut the startup delay short
+        let mut not_before = self
+            .startup_delay
+            .filter(|_| !reloaded)
+            .map(|d| started + chrono::Duration::from_std(d).unwrap_or_default());
+        if let Some(not_before) = not_before {
+            start = start.max(not_before);
+        }
+        let mut last_wake: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+        let mut announced_start = None;
+        // DST transitions are handled by `timezone`, wall clock steps are detected on wake and
+        // the schedule is re-evaluated.
+        loop {
+            let now = clock.now();
+            if let Some((before, deadline)) = last_wake.take() {
+                if now < before {
+                    warn!("Clock jumped backward from {before} to {now}, re-evaluating schedule");
+                    // The delay was measured on the old clock
+                    not_before = None;
+                } else if now - deadline > MAX_CLOCK_DRIFT {
+                    warn!(
+                        "Woke up at {now}, expected {deadline}, clock jumped forward or system resumed from suspend"
+                    );
+                }
+            }
+
+            // Next run computed from now is the upper bound, a stale start from before a
+            // backward jump must not hold the schedule hostage. A pending startup delay is no
+            // stale start.
+            let next_from_now = timezone.next_run(cron, &now).unwrap();
+            let latest = not_before.map_or(next_from_now, |n| n.max(next_from_now));
+            if start > latest {
+                warn!("Scheduled time {start} is after next run {latest}, clamping");
+                start = latest;
+            }
+
+            if now < start {
+                if self.is_reload_requested() {
+                    return Ok(());
+                }
+                if announced_start != Some(start) {
+                    info!("Next backup {}", HumanNextRun { at: start, now });
+                    announced_start = Some(start);
+                }
+                // Rewritten on every wake, so monitors can tell the daemon is alive
+                status.update(|s| s.next_run = Some(start));
+                // Sleep in bounded chunks so a clock step is noticed on the next wake.
+                let deadline = start.min(now + self.sleep_chunk());
+                clock.sleep_until(deadline);
+                last_wake = Some((now, deadline));
+            } else {
+                let recheck_interval = self
+                    .run_conditions
+                    .as_ref()
+                    .and_then(|c| c.recheck_interval)
+                    .map(|i| chrono::Duration::from_std(i).unwrap_or_default());
+                if let Some(recheck_at) = recheck_interval
+                    .map(|i| now + i)
+                    .filter(|recheck_at| *recheck_at < next_from_now)
+                {
+                    if let Some(reason) = self.unmet_run_condition() {
+                        info!("Deferring scheduled backup to {recheck_at}, {reason}");
+                        start = recheck_at;
+                        continue;
+                    }
+                }
+                self.execute_locked_cycle(now, &history, pre_process_pool.clone(), Some(&status))?;
+                start = next_from_now;
+            }
+        }
+    }
+
+    /// Run a backup cycle for every trigger received on the control socket, answering with its
+    /// outcome. Failed cycles are reported to the trigger and do not stop the daemon.
+    fn serve_triggers(
+        &self,
+        history: &BackupHistory,
+        pre_process_pool: Arc<ThreadPool>,
+        clock: &dyn Clock,
+        status: &StatusFile,
+    ) -> Result<()> {
+        let socket_path = control_socket_path(&self.state_dir_path());
+        let listener = bind_control_socket(&socket_path)
+            .with_msg(format!("Listen on {socket_path:?} failed"))?;
+        // Polled, so a reload is noticed between triggers
+        listener.set_nonblocking(true)?;
+        info!("Waiting for backup triggers on {socket_path:?}");
+        std::thread::scope(|scope| {
+            // No wake between triggers, the status file is kept fresh from a thread of its own
+            // until the accept loop ends
+            let (stop_tx, stop_rx) = sync_channel::<()>(0);
+            scope.spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) =
+                    stop_rx.recv_timeout(MAX_SLEEP_CHUNK.to_std().unwrap_or_default())
+                {
+                    status.update(|_| {});
+                }
+            });
+            let _stop_tx = stop_tx;
+            while !self.is_reload_requested() {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                        continue;
+                    }
+                    Err(e) => {
+                        // Errors such as running out of file descriptors would repeat at once
+                        warn!("Control connection on {socket_path:?} failed: {e}");
+                        std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                        continue;
+                    }
+                };
+                let res = stream
+                    .set_nonblocking(false)
+                    .map_err(Error::from)
+                    .and_then(|_| {
+                        handle_connection(stream, || {
+                            info!("Backup triggered");
+                            let res = self.execute_locked_cycle(
+                                clock.now(),
+                                history,
+                                pre_process_pool.clone(),
+                                Some(status),
+                            );
+                            match res {
+                                Ok(Some(file_path)) => TriggerResponse::Created(file_path),
+                                Ok(None) => TriggerResponse::Skipped,
+                                Err(e) => {
+                                    error!("Triggered backup failed: {e}");
+                                    // Waiting for the next trigger, not stopped as the cron schedule
+                                    status.update(|s| s.state = SchedulerState::Idle);
+                                    TriggerResponse::Failed(e.to_string())
+                                }
+                            }
+                        })
+                    });
+                if let Err(e) = res {
+                    warn!("Control connection on {socket_path:?} failed: {e}");
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Run [`Self::execute_backup_cycle`] of the daemon, locking the archive base name for the
+    /// cycle only. `history` is read again first, commands run in between may have changed the
+    /// out dir.
+    fn execute_locked_cycle(
+        &self,
+        now: DateTime<Utc>,
+        history: &BackupHistory,
+        pre_process_pool: Arc<ThreadPool>,
+        status: Option<&StatusFile>,
+    ) -> Result<Option<PathBuf>> {
+        let _lock = self.wait_archive_base_name_lock()?;
+        history.replace(self.local_archives()?);
+        self.execute_backup_cycle(now, history, pre_process_pool, status)
+    }
+
+    /// Run a single scheduled cycle at `now`: apply retention to `history`, then create and
+    /// upload a new backup which is added to it. Progress is recorded in `status` when given.
+    /// Returns the new backup, `None` when skipped.
+    pub fn execute_backup_cycle(
+        &self,
+        now: DateTime<Utc>,
+        history: &BackupHistory,
+        pre_process_pool: Arc<ThreadPool>,
+        status: Option<&StatusFile>,
+    ) -> Result<Option<PathBuf>> {
+        if let Some(reason) = self
+            .is_on_hold()
+            .then(|| format!("hold file {:?} present", self.hold_file_path()))
+            .or_else(|| self.unmet_run_condition())
+        {
+            info!("Skipping scheduled backup, {reason}");
+            self.notify(BackupEvent::BackupSkipped {
+                reason: reason.into(),
+            });
+            return Ok(None);
+        }
+
+        let _guard = backup_span(&self.archive_base_name, false).entered();
+        if let Some(status) = status {
+            status.start_run(now);
+            status.set_phase(Stage::Retention);
+        }
+        let timings = PhaseTimings::default();
+        timings.retention.time(|| {
+            stage_span(Stage::Retention)
+                .in_scope(|| self.prune_archives(now, history, &pre_process_pool))
+        });
+
+        let res = self.create_and_upload(
+            now,
+            ArchiveTags::default(),
+            pre_process_pool,
+            status,
+            &timings,
+        );
+        info!("Phase timings: {timings}");
+        if let Some(status) = status {
+            status.finish_run(now, res.as_ref().map(PathBuf::as_path));
+        }
+        let file_path = res?;
+        history.insert(file_path.clone(), now);
+        Ok(Some(file_path))
+    }
+
+    /// Delete the archives of `out_dir` out of retention right away, outside the schedule, with
+    /// their copies on destinations with `prune`. Returns the deleted archives.
+    pub fn prune(&self, now: DateTime<Utc>, pool: &ThreadPool) -> Result<Vec<PathBuf>> {
+        let _lock = self.lock_archive_base_name()?;
+        self.check_out_dir()?;
+        let history = self.read_history()?;
+        Ok(stage_span(Stage::Retention)
+            .in_scope(|| self.prune_archives(now, &history, pool))
+            .into_iter()
+            .map(|(archive_path, _)| archive_path)
+            .collect())
+    }
+
+    /// Apply `retention` and `manual_retention` to `history` unless `out_dir` is not confirmed,
+    /// then prune remote copies. Returns the deleted archives.
+    fn prune_archives(
+        &self,
+        now: DateTime<Utc>,
+        history: &BackupHistory,
+        pool: &ThreadPool,
+    ) -> Vec<(PathBuf, DateTime<Utc>)> {
+        if !self.out_dir_confirmed_path().exists() {
+            warn!(
+                "Skipping retention, out dir {:?} is not confirmed",
+                self.out_dir
+            );
+            return Vec::new();
+        }
+        let mut pruned = self.apply_retention(self.retention.as_deref(), false, now, history, pool);
+        pruned.extend(self.apply_retention(
+            self.manual_retention.as_deref(),
+            true,
+            now,
+            history,
+            pool,
+        ));
+        self.prune_remote_copies(&pruned, now);
+        pruned
+    }
+
+    /// Delete archives of `history` created manually or not, as given by `manual`, that are out
+    /// of `retention`. Files are removed in parallel on `pool`, long histories prune many at once.
+    /// Returns the deleted archives.
+    fn apply_retention(
+        &self,
+        retention: Option<&RetentionConfig>,
+        manual: bool,
+        now: DateTime<Utc>,
+        history: &BackupHistory,
+        pool: &ThreadPool,
+    ) -> Vec<(PathBuf, DateTime<Utc>)> {
+        let mut pruned = Vec::new();
+        for to_delete in self.out_of_retention(retention, manual, now, history) {
+            info!("Removing out of retention file {:?}", &to_delete.item);
+            let removed = history.remove(&to_delete);
+            if !removed {
+                panic!("Remove item in memory {:?} failed", &to_delete.item);
+            }
+            pruned.push((to_delete.item.clone(), *to_delete.date_time));
+        }
+        let files = pruned
+            .iter()
+            .flat_map(|(archive_path, _)| {
+                std::iter::once(archive_path.clone())
+                    .chain(volume_paths(archive_path))
+                    .chain(self.sidecar_paths(archive_path))
+            })
+            .collect_vec();
+        // Most sidecar files do not exist
+        pool.install(|| {
+            files.par_iter().for_each(|path| {
+                let _ = std::fs::remove_file(path);
+            })
+        });
+        for (archive_path, _) in pruned.iter() {
+            self.notify(BackupEvent::RetentionDeleted {
+                file_path: archive_path.as_path().into(),
+            });
+        }
+        pruned
+    }
+
+    /// Archives of `history` created manually or not, as given by `manual`, that `retention`
+    /// would delete now.
+    fn out_of_retention(
+        &self,
+        retention: Option<&RetentionConfig>,
+        manual: bool,
+        now: DateTime<Utc>,
+        history: &BackupHistory,
+    ) -> Vec<HistoryEntry> {
+        self.retention_verdicts(retention, manual, now, history)
+            .into_iter()
+            .filter(|(_, verdict)| !verdict.is_kept())
+            .map(|(entry, _)| entry)
+            .collect()
+    }
+
+    /// Archives of `history` created manually or not, as given by `manual`, with the verdict of
+    /// `retention` now, newest first.
+    fn retention_verdicts(
+        &self,
+        retention: Option<&RetentionConfig>,
+        manual: bool,
+        now: DateTime<Utc>,
+        history: &BackupHistory,
+    ) -> Vec<(HistoryEntry, RetentionVerdict)> {
+        let entries = history
+            .entries()
+            .into_iter()
+            .filter(|i| is_manual_archive(&i.item) == manual);
+        let Some(retention) = retention else {
+            return entries
+                .map(|entry| (entry, RetentionVerdict::NoRetention))
+                .collect();
+        };
+        retention
+            .classify(entries, now, |p: &PathBuf| !is_partial_archive(p))
+            .map(|(entry, verdict)| {
+                if verdict.is_kept() || self.is_prunable(&entry.item) {
+                    return (entry, verdict);
+                }
+                info!(
+                    "Keeping out of retention file {:?} until off-site copies are confirmed",
+                    &entry.item
+                );
+                (entry, RetentionVerdict::AwaitingOffsite)
+            })
+            .collect()
+    }
+
+    /// Every archive of `out_dir` with whether retention keeps or deletes it now and why, newest
+    /// first.
+    pub fn retention_plan(&self, now: DateTime<Utc>) -> Result<Vec<(PathBuf, RetentionVerdict)>> {
+        let history = self.read_history()?;
+        Ok(self
+            .retention_verdicts(self.retention.as_deref(), false, now, &history)
+            .into_iter()
+            .chain(self.retention_verdicts(self.manual_retention.as_deref(), true, now, &history))
+            .sorted_by_key(|(entry, _)| Reverse(entry.date_time.clone()))
+            .map(|(entry, verdict)| (entry.item.clone(), verdict))
+            .collect())
+    }
+
+    /// Entries a run would archive now and the archives retention would delete, without writing
+    /// or removing anything. Sources with side effects, e.g. commands, are not run.
+    pub fn dry_run(&self, now: DateTime<Utc>) -> Result<DryRun> {
+        let own_dirs = Arc::new(self.own_dirs());
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        let dedup = EntryDedup::new(
+            self.duplicate_entries.unwrap_or_default(),
+            self.dst_conflicts.unwrap_or_default(),
+        );
+        for file in self.files.iter() {
+            let source = file.source.with_excluded_dirs(own_dirs.clone());
+            match source.planned_entries() {
+                Ok(planned) => {
+                    for entry in planned {
+                        match entry {
+                            Ok(mut entry) => {
+                                // Entries of unknown size are captured rather than read
+                                let src = entry.size.map(|_| entry.src.clone());
+                                if dedup.admit(src.as_deref(), &mut entry.dst) {
+                                    entries.push(entry)
+                                }
+                            }
+                            Err(e) => errors.push(e),
+                        }
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let history = self.read_history()?;
+        let out_of_retention = self
+            .out_of_retention(self.retention.as_deref(), false, now, &history)
+            .into_iter()
+            .chain(self.out_of_retention(self.manual_retention.as_deref(), true, now, &history))
+            .sorted_unstable_by_key(|i| i.date_time.clone())
+            .map(|i| i.item.clone())
+            .collect();
+        Ok(DryRun {
+            entries,
+            errors,
+            out_of_retention,
+        })
+    }
+
+    /// Source files the daemon user cannot read, found from their permissions while listing
+    /// the sources as [`Self::dry_run`] does, so no file is read.
+    pub fn permission_scan(&self) -> PermissionScan {
+        let own_dirs = Arc::new(self.own_dirs());
+        let mut scan = PermissionScan::default();
+        for file in self.files.iter() {
+            let source = file.source.with_excluded_dirs(own_dirs.clone());
+            let planned = match source.planned_entries() {
+                Ok(planned) => planned,
+                Err(e) => {
+                    scan.errors.push(e);
+                    continue;
+                }
+            };
+            for entry in planned {
+                match entry {
+                    // Entries of unknown size are captured rather than read
+                    Ok(entry) if entry.size.is_none() => {}
+                    Ok(entry) => {
+                        scan.scanned += 1;
+                        if !is_readable(&entry.src) {
+                            scan.unreadable_files.push(entry.src);
+                        }
+                    }
+                    Err(e) => scan.errors.push(e),
+                }
+            }
+        }
+        scan
+    }
+
+    /// Queue the copies of `pruned` archives for deletion on destinations with `prune`, then
+    /// work through everything queued. Failures only delay deletions to the next cycle.
+    fn prune_remote_copies(&self, pruned: &[(PathBuf, DateTime<Utc>)], now: DateTime<Utc>) {
+        for (idx, destination) in self.storage.iter().flat_map(|s| s.iter()).enumerate() {
+            let Some(prune) = &destination.prune else {
+                continue;
+            };
+            let pending_path = PendingDeletions::pending_path(&self.state_dir_path(), idx);
+            let res = PendingDeletions::read(&pending_path).and_then(|mut pending| {
+                match &prune.retention {
+                    Some(retention) => {
+                        pending.extend(self.out_of_remote_retention(destination, retention, now)?)
+                    }
+                    None => pending.extend(pruned.iter().map(|(archive_path, dt)| {
+                        self.destination_file_name(destination, archive_path, *dt)
+                            .into()
+                    })),
+                }
+                pending.write(&pending_path)?;
+                prune.delete_pending(destination, &mut pending, &pending_path)
+            });
+            if let Err(e) = res {
+                warn!("Remote deletions on destination {idx} postponed to the next cycle: {e}");
+            }
+        }
+    }
+
+    /// Scheduled archives stored on `destination` that are out of `retention`, going by the
+    /// destination listing rather than the local archives.
+    fn out_of_remote_retention(
+        &self,
+        destination: &StorageDestinationConfig,
+        retention: &RetentionConfig,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Arc<str>>> {
+        let ext = self
+            .file_ext_with_encryptor(destination.encryptor.as_deref().unwrap_or(&self.encryptor));
+        let stored = destination
+            .list()?
+            .into_iter()
+            .filter(|name| !is_manual_archive(name.as_ref()))
+            .filter_map(|name| {
+                self.date_time_from_file_name(&name, &ext)
+                    .map(|dt| Arc::new(ItemWithDateTime::from((name, dt))))
+            })
+            .collect::<Vec<_>>();
+        Ok(retention
+            .get_delete(stored, now, |name: &Arc<str>| {
+                !is_partial_archive(name.as_ref())
+            })
+            .map(|to_delete| to_delete.item.clone())
+            .collect())
+    }
+
+    /// Whether the run creating `archive_path` finished, it is non-empty and its report is
+    /// readable when reports are enabled.
+    fn is_completed_archive(&self, archive_path: &Path) -> bool {
+        let non_empty = archive_file_size(archive_path).is_ok_and(|size| size > 0);
+        let encryptor = self.metadata_encryptor();
+        let report_path = encrypted_path(BackupReport::report_path(archive_path), encryptor);
+        non_empty
+            && (!self.report.unwrap_or(false) || BackupReport::read(report_path, encryptor).is_ok())
+    }
+
+    /// Run a single scheduled cycle right away, as the daemon would at a cron time: apply
+    /// retention, then create an untagged backup. Meant for external schedulers such as systemd
+    /// timers. Returns the new backup, `None` when skipped.
+    pub fn run_scheduled(&self, pre_process_pool: Arc<ThreadPool>) -> Result<Option<PathBuf>> {
+        let _lock = self.lock_archive_base_name()?;
+        self.check_out_dir()?;
+        let status = StatusFile::open(self.state_dir_path());
+        let history = self.read_history()?;
+        self.execute_backup_cycle(Utc::now(), &history, pre_process_pool, Some(&status))
+    }
+
+    /// Create a single backup right away outside the schedule, tagged `-manual`.
+    pub fn run_once(&self, pre_process_pool: Arc<ThreadPool>) -> Result<PathBuf> {
+        let _lock = self.lock_archive_base_name()?;
+        let tags = ArchiveTags {
+            manual: true,
+            ..Default::default()
+        };
+        let _guard = backup_span(&self.archive_base_name, true).entered();
+        let timings = PhaseTimings::default();
+        let res = self.create_and_upload(Utc::now(), tags, pre_process_pool, None, &timings);
+        info!("Phase timings: {timings}");
+        res
+    }
+
+    fn create_and_upload(
+        &self,
+        now: DateTime<Utc>,
+        tags: ArchiveTags,
+        pre_process_pool: Arc<ThreadPool>,
+        status: Option<&StatusFile>,
+        timings: &PhaseTimings,
+    ) -> Result<PathBuf> {
+        info!("Trying to create backup...");
+        if let Some(status) = status {
+            status.set_phase(Stage::Write);
+        }
+        let started_at = Instant::now();
+
+        let (file_path, non_fatal_error, mut report) =
+            match self.create_archive_with_report(now, tags, pre_process_pool, timings) {
+                Ok(res) => res,
+                Err(e) => {
+                    self.record_in_catalog(CatalogEntry::failed(
+                        now,
+                        tags.manual,
+                        started_at.elapsed(),
+                        e.to_string(),
+                    ));
+                    self.notify(BackupEvent::BackupFailed {
+                        error: e.to_string().into(),
+                    });
+                    return Err(e);
+                }
+            };
+        let archive_size = archive_file_size(&file_path).unwrap_or(0);
+        let duration = started_at.elapsed();
+        info!(
+            "Created backup file: {:?} ({} in {})",
+            &file_path,
+            HumanSize(archive_size),
+            HumanDuration(duration)
+        );
+        if let Some(status) = status {
+            status.set_phase(Stage::Upload);
+        }
+        let (uploads, upload_errors) = timings.upload.time(|| {
+            stage_span(Stage::Upload).in_scope(|| self.upload_to_destinations(&file_path, now))
+        });
+        let upload_res = convert_error_vec(upload_errors);
+        if let Some(report) = report.as_mut().filter(|_| self.storage.is_some()) {
+            report.record_uploads(uploads, Utc::now(), upload_res.as_ref().err());
+        }
+        let mut non_fatal_error = match upload_res {
+            Ok(_) => non_fatal_error,
+            Err(e) => Some(chain_optional_error(non_fatal_error, e)),
+        };
+        if let Some(report) = report.as_ref().filter(|_| self.publishes_report()) {
+            self.publish_report(report, &mut non_fatal_error);
+        }
+        if let Some(report) = &report {
+            let sha256 = ArchiveChecksums::read(ArchiveChecksums::checksums_path(&file_path))
+                .ok()
+                .map(|checksums| checksums.archive_sha256);
+            self.record_in_catalog(CatalogEntry::created(
+                report,
+                sha256,
+                non_fatal_error.as_ref(),
+            ));
+        }
+        if let Some(non_fatal_error) = &non_fatal_error {
+            warn!("Received non fatal error: {non_fatal_error}")
+        }
+        self.notify(BackupEvent::BackupCreated {
+            file_path: file_path.as_path().into(),
+            archive_size,
+            duration,
+            non_fatal_error: non_fatal_error.map(|e| e.to_string().into()),
+        });
+        Ok(file_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::clock::FakeClock;
+    use crate::backup::status::SchedulerStatus;
+    use crate::backup::volume::volume_path;
+    use chrono::Duration;
+    use rayon::ThreadPoolBuilder;
+
+    /// Files left in `dir`, sorted.
+    fn file_names(dir: &Path) -> Vec<String> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn apply_retention_removes_expired_archives_with_their_volumes_and_sidecars() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config: BackupConfig = serde_yml::from_str(&format!(
+            "{{cron: '0 1 * * *', archive_base_name: backup, out_dir: {:?}, files: [], \
+             encryptor: {{encryptor_type: none}}, compressor: {{compressor_type: none}}}}",
+            dir.path()
+        ))
+        .unwrap();
+        config.retention = Some(Arc::new(RetentionConfig {
+            default_retention: std::time::Duration::from_secs(3 * 24 * 60 * 60),
+            ..Default::default()
+        }));
+        let now = Utc.with_ymd_and_hms(2024, 6, 10, 0, 0, 0).unwrap();
+        let archive = |days, tags| {
+            let date_time = now - Duration::days(days) - Duration::hours(1);
+            let file_name = config.archive_file_name(date_time, &config.encryptor, tags);
+            (dir.path().join(file_name), date_time)
+        };
+        let scheduled = (0..40).map(|days| archive(days, ArchiveTags::default()));
+        let manual = archive(
+            20,
+            ArchiveTags {
+                manual: true,
+                partial: false,
+            },
+        );
+        let archives = scheduled
+            .chain(std::iter::once(manual.clone()))
+            .collect::<Vec<_>>();
+        // Every other archive is split, each has checksums next to it
+        for (idx, (archive_path, _)) in archives.iter().enumerate() {
+            match idx % 2 {
+                0 => std::fs::write(archive_path, "archive").unwrap(),
+                _ => {
+                    std::fs::write(volume_path(archive_path, 0), "vol").unwrap();
+                    std::fs::write(volume_path(archive_path, 1), "ume").unwrap();
+                    std::fs::write(VolumeManifest::volumes_path(archive_path), "{}").unwrap();
+                }
+            }
+            std::fs::write(ArchiveChecksums::checksums_path(archive_path), "{}").unwrap();
+        }
+        let history = BackupHistory::new(archives.iter().cloned());
+        let kept = file_names(dir.path())
+            .into_iter()
+            .filter(|name| {
+                archives[..3]
+                    .iter()
+                    .chain([&manual])
+                    .any(|(path, _)| name.starts_with(path.file_name().unwrap().to_str().unwrap()))
+            })
+            .collect::<Vec<_>>();
+
+        let pool = ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let pruned =
+            config.apply_retention(config.retention.as_deref(), false, now, &history, &pool);
+        assert_eq!(pruned, archives[3..40]);
+        assert_eq!(history.count(), 4);
+        // The recent two whole and one split archives, and the manual one without retention
+        assert_eq!(kept.len(), 2 * 2 + 4 + 2);
+        assert_eq!(file_names(dir.path()), kept);
+    }
+
+    /// Fake clock requesting a reload by removing `watched` once the loop sleeps.
+    struct ReloadingClock {
+        clock: FakeClock,
+        watched: PathBuf,
+    }
+
+    impl Clock for ReloadingClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.clock.now()
+        }
+
+        fn sleep_until(&self, deadline: DateTime<Utc>) {
+            self.clock.sleep_until(deadline);
+            let _ = std::fs::remove_file(&self.watched);
+        }
+    }
+
+    #[test]
+    fn startup_delay_is_not_clamped_to_next_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let watched = dir.path().join("config.yml");
+        std::fs::write(&watched, "").unwrap();
+        let mut config: BackupConfig = serde_yml::from_str::<BackupConfig>(&format!(
+            "{{cron: '0 1 * * *', archive_base_name: backup, out_dir: {:?}, files: [], \
+             encryptor: {{encryptor_type: none}}, compressor: {{compressor_type: none}}}}",
+            dir.path()
+        ))
+        .unwrap()
+        .with_reload(Arc::new(ReloadWatch::new(&watched, true, false)));
+        config.startup_delay = Some(std::time::Duration::from_secs(10 * 60));
+        // One minute before the scheduled run, the delay ends after it
+        let started = Utc.with_ymd_and_hms(2024, 6, 10, 0, 59, 0).unwrap();
+        let clock = ReloadingClock {
+            clock: FakeClock::new(started),
+            watched,
+        };
+
+        let pool = Arc::new(ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        config.start_loop_with_clock(pool, &clock).unwrap();
+        let status =
+            SchedulerStatus::read(SchedulerStatus::status_path(config.state_dir_path())).unwrap();
+        assert_eq!(status.next_run, Some(started + Duration::minutes(10)));
+    }
 }
@@ -0,0 +1,129 @@
+use crate::backup::report::{error_messages, BackupReport, SourceReport};
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+static CATALOG_FILE_NAME: &str = "catalog.jsonl";
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CatalogStatus {
+    Succeeded,
+    /// Created with non fatal errors, e.g. skipped entries or failed uploads.
+    SucceededWithErrors,
+    Failed,
+}
+
+/// Backup of a job, created or failed, as recorded in its catalog.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CatalogEntry {
+    pub backup_time: DateTime<Utc>,
+    pub status: CatalogStatus,
+    pub manual: bool,
+    /// Name of the archive file in out_dir, missing when the backup failed.
+    pub file_name: Option<Arc<str>>,
+    pub size: Option<u64>,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    /// Hex SHA-256 of the archive file, only present with `checksums`.
+    pub sha256: Option<String>,
+    pub sources: Vec<SourceReport>,
+    pub errors: Vec<String>,
+}
+
+impl CatalogEntry {
+    /// Entry of the backup described by `report`, with its `sha256` when known, which ended
+    /// with `non_fatal_error`.
+    pub fn created(
+        report: &BackupReport,
+        sha256: Option<String>,
+        non_fatal_error: Option<&Error>,
+    ) -> Self {
+        Self {
+            backup_time: report.backup_time,
+            status: match non_fatal_error {
+                None => CatalogStatus::Succeeded,
+                Some(_) => CatalogStatus::SucceededWithErrors,
+            },
+            manual: report.manual,
+            file_name: report
+                .archive_file
+                .file_name()
+                .map(|name| name.to_string_lossy().into()),
+            size: Some(report.archive_size),
+            duration: report.duration,
+            sha256,
+            sources: report.sources.clone(),
+            errors: non_fatal_error.map(error_messages).unwrap_or_default(),
+        }
+    }
+
+    /// Entry of a backup at `backup_time` failing after `duration` with `error`.
+    pub fn failed(
+        backup_time: DateTime<Utc>,
+        manual: bool,
+        duration: Duration,
+        error: String,
+    ) -> Self {
+        Self {
+            backup_time,
+            status: CatalogStatus::Failed,
+            manual,
+            file_name: None,
+            size: None,
+            duration,
+            sha256: None,
+            sources: Vec::new(),
+            errors: vec![error],
+        }
+    }
+}
+
+/// History of the backups of a job, one JSON line per backup appended to `catalog.jsonl` in
+/// its state dir. Entries stay once their archive is deleted by retention.
+pub struct Catalog;
+
+impl Catalog {
+    pub fn catalog_path<P: AsRef<Path>>(state_dir: P) -> PathBuf {
+        state_dir.as_ref().join(CATALOG_FILE_NAME)
+    }
+
+    pub fn append<P: AsRef<Path>>(path: P, entry: &CatalogEntry) -> Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Entries of the catalog at `path`, oldest first, none when it does not exist yet.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<CatalogEntry>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => Err(e)?,
+        };
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                entries.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::backup::pipeline::PipelineDescriptor;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::volume::{archive_file_size, open_archive_file};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{copy, BufReader, BufWriter, ErrorKind, IntoInnerError, Read, Write};
+use std::path::{Path, PathBuf};
+
+static CHECKSUMS_FILE_SUFFIX: &str = ".sums.json";
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut hasher = Sha256::new();
+    copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// SHA-256 of the archive at `archive_path`, of its volumes one after the other when split.
+pub fn sha256_archive<P: AsRef<Path>>(archive_path: P) -> Result<String> {
+    let mut hasher = Sha256::new();
+    copy(
+        &mut BufReader::new(open_archive_file(archive_path)?),
+        &mut hasher,
+    )?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Writer computing the SHA-256 of everything written through it, when enabled.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Option<Sha256>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, enabled: bool) -> Self {
+        Self {
+            inner,
+            hasher: enabled.then(Sha256::new),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Inner writer and hex digest of the written bytes, `None` when disabled.
+    pub fn into_parts(self) -> (W, Option<String>) {
+        let digest = self.hasher.map(|h| format!("{:x}", h.finalize()));
+        (self.inner, digest)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Plain JSON stored next to an archive, letting it be checked without the decryption secret
+/// (size, checksum and extension of the archive file) and fully with it (the tar stream).
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ArchiveChecksums {
+    pub pipeline: PipelineDescriptor,
+    pub archive_size: u64,
+    pub archive_sha256: String,
+    /// Size of the tar stream before compression and encryption.
+    pub plaintext_size: u64,
+    pub plaintext_sha256: String,
+}
+
+impl ArchiveChecksums {
+    pub fn checksums_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+        let archive_path = archive_path.as_ref();
+        let mut file_name = archive_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(CHECKSUMS_FILE_SUFFIX);
+        archive_path.with_file_name(file_name)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, archive_path: P) -> Result<PathBuf> {
+        let path = Self::checksums_path(archive_path);
+        let mut writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.into_inner().map_err(IntoInnerError::into_error)?;
+        Ok(path)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Check the archive file is the one described, needing no secret.
+    pub fn verify_archive<P: AsRef<Path>>(&self, archive_path: P) -> Result<()> {
+        let archive_path = archive_path.as_ref();
+        let file_name = archive_path.to_string_lossy();
+        let file_ext = self.pipeline.file_ext();
+        if !file_name.ends_with(&format!(".{file_ext}")) {
+            return Err(invalid_data(format!(
+                "{archive_path:?} does not have the pipeline extension {file_ext:?}"
+            )));
+        }
+        let archive_size = archive_file_size(archive_path)?;
+        if archive_size != self.archive_size {
+            return Err(invalid_data(format!(
+                "{archive_path:?} is {archive_size} bytes, expected {}",
+                self.archive_size
+            )));
+        }
+        if sha256_archive(archive_path)? != self.archive_sha256 {
+            return Err(invalid_data(format!("{archive_path:?} checksum mismatch")));
+        }
+        Ok(())
+    }
+
+    /// Check the decrypted and decompressed tar stream read from `plaintext`.
+    pub fn verify_plaintext<R: Read>(&self, mut plaintext: R) -> Result<()> {
+        let mut hasher = Sha256::new();
+        let plaintext_size = copy(&mut plaintext, &mut hasher)?;
+        self.check_plaintext(plaintext_size, &format!("{:x}", hasher.finalize()))
+    }
+
+    /// Check the size and hex SHA-256 of a tar stream read elsewhere.
+    pub fn check_plaintext(&self, plaintext_size: u64, plaintext_sha256: &str) -> Result<()> {
+        if plaintext_size != self.plaintext_size {
+            return Err(invalid_data(format!(
+                "tar stream is {plaintext_size} bytes, expected {}",
+                self.plaintext_size
+            )));
+        }
+        if plaintext_sha256 != self.plaintext_sha256 {
+            return Err(invalid_data("tar stream checksum mismatch".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(msg: String) -> Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg).into()
+}
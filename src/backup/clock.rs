@@ -0,0 +1,169 @@
+use chrono::{DateTime, Local, NaiveDateTime, Offset, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Block the current thread until `deadline` according to this clock.
+    fn sleep_until(&self, deadline: DateTime<Utc>) {
+        let now = self.now();
+        if let Ok(duration) = (deadline - now).to_std() {
+            std::thread::sleep(duration)
+        }
+    }
+}
+
+/// Wall clock time.
+#[derive(Default, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Wall clock time at creation advanced by a monotonic timer, so NTP steps of the system clock
+/// do not move the schedule.
+#[derive(Debug)]
+pub struct MonotonicClock {
+    base_date_time: DateTime<Utc>,
+    base_instant: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self {
+            base_date_time: Utc::now(),
+            base_instant: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.base_date_time
+            + chrono::Duration::from_std(self.base_instant.elapsed()).unwrap_or_default()
+    }
+}
+
+/// Manually driven clock, sleeping only advances it, for simulating schedules without waiting.
+#[derive(Debug)]
+pub struct FakeClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: chrono::Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Utc>) {
+        let mut now = self.now.lock().unwrap();
+        if *now < deadline {
+            *now = deadline;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSource {
+    #[default]
+    System,
+    Monotonic,
+}
+
+impl ClockSource {
+    pub fn build_clock(&self) -> Box<dyn Clock> {
+        match self {
+            ClockSource::System => Box::new(SystemClock),
+            ClockSource::Monotonic => Box::new(MonotonicClock::default()),
+        }
+    }
+}
+
+/// Time zone cron expressions are evaluated in.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CronTimezone {
+    /// Runs never shift, repeat or get skipped around daylight saving time changes.
+    #[default]
+    Utc,
+    /// Time zone of the system, from `TZ` or `/etc/localtime`. Times skipped by a spring-forward
+    /// change do not run that day, times repeated by a fall-back change run once, at their first
+    /// occurrence.
+    Local,
+}
+
+impl CronTimezone {
+    /// First time of `cron` after `after`.
+    pub fn next_run(
+        &self,
+        cron: &str,
+        after: &DateTime<Utc>,
+    ) -> std::result::Result<DateTime<Utc>, cron_parser::ParseError> {
+        self.next_run_in(cron, after, &Local)
+    }
+
+    /// First time of `cron` after `after`, `local` standing for the time zone of the system.
+    pub fn next_run_in<Tz: TimeZone>(
+        &self,
+        cron: &str,
+        after: &DateTime<Utc>,
+        local: &Tz,
+    ) -> std::result::Result<DateTime<Utc>, cron_parser::ParseError> {
+        match self {
+            CronTimezone::Utc => cron_parser::parse(cron, after),
+            CronTimezone::Local => {
+                // Matched on local wall clock times, a time already reached once is done
+                let mut local_time = after.with_timezone(local).naive_local();
+                loop {
+                    local_time = cron_parser::parse(cron, &local_time.and_utc())?.naive_utc();
+                    if let Some(first) =
+                        first_instant(local, local_time).filter(|first| first > after)
+                    {
+                        return Ok(first);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// First instant the time zone `tz` shows `local`, none when a spring-forward change skips it.
+/// Only conversions from UTC are used, those from local time are off around transitions in
+/// chrono.
+fn first_instant<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    // Offsets in effect a day either side are those of any transition at `local`
+    [-1, 1]
+        .into_iter()
+        .filter_map(|days| {
+            let offset = tz
+                .offset_from_utc_datetime(&(local + chrono::Duration::days(days)))
+                .fix()
+                .local_minus_utc();
+            let utc = local - chrono::Duration::seconds(offset.into());
+            (tz.offset_from_utc_datetime(&utc).fix().local_minus_utc() == offset)
+                .then(|| utc.and_utc())
+        })
+        .min()
+}
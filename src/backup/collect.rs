@@ -0,0 +1,452 @@
+use crate::backup::archive::{ArchiveEntry, ArchiveEntryIterable, ArchiveSourceConfig};
+use crate::backup::deadline::Deadline;
+use crate::backup::hook::{CommandHook, QuiesceConfig};
+use crate::backup::report::SourceStats;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::{convert_error_vec, Result};
+use crate::backup::result_error::WithMsg;
+use crate::backup::span::{record_source_stats, source_span};
+use itertools::Itertools;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use tracing::{warn, Span};
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CollectionMode {
+    /// Stream entries of every source to the archive writer while collecting.
+    #[default]
+    Streaming,
+    /// Enumerate non-volatile sources first, then only capture volatile sources (e.g. SQLite)
+    /// while quiesced, so applications are paused for as short as possible.
+    TwoPhase,
+}
+
+/// Entries whose source resolves to a file already archived in the run, e.g. from overlapping
+/// sources or trees reachable through symlinks.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateEntryMode {
+    /// Archive every entry.
+    Keep,
+    /// Skip entries with the same resolved source and destination path as an archived entry.
+    #[default]
+    SkipSameDst,
+    /// Skip entries whose resolved source was archived under any destination path.
+    Skip,
+}
+
+/// Entries stored at a destination path already taken by another entry of the run.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DstConflictMode {
+    /// Archive all of them, the last one wins on extraction.
+    #[default]
+    Keep,
+    /// Store the later ones as `<dst>~1`, `<dst>~2` and so on.
+    Rename,
+}
+
+/// Paths of the entries archived so far in a run, shared by all sources.
+#[derive(Default, Debug)]
+pub struct EntryDedup {
+    duplicates: DuplicateEntryMode,
+    conflicts: DstConflictMode,
+    seen: Mutex<SeenEntries>,
+}
+
+#[derive(Default, Debug)]
+struct SeenEntries {
+    srcs: HashSet<PathBuf>,
+    src_dsts: HashSet<(PathBuf, Arc<Path>)>,
+    dsts: HashSet<Arc<Path>>,
+}
+
+impl EntryDedup {
+    pub fn new(duplicates: DuplicateEntryMode, conflicts: DstConflictMode) -> Self {
+        Self {
+            duplicates,
+            conflicts,
+            ..Default::default()
+        }
+    }
+
+    /// Whether to archive the entry read from `src` at `dst`, `src` is `None` for captured
+    /// content. `dst` is renamed on conflicts when enabled.
+    pub fn admit(&self, src: Option<&Path>, dst: &mut Arc<Path>) -> bool {
+        if self.duplicates == DuplicateEntryMode::Keep && self.conflicts == DstConflictMode::Keep {
+            return true;
+        }
+        let src = src
+            .filter(|_| self.duplicates != DuplicateEntryMode::Keep)
+            .and_then(|src| src.canonicalize().ok());
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(src) = src {
+            let duplicate = match self.duplicates {
+                DuplicateEntryMode::Keep => false,
+                DuplicateEntryMode::SkipSameDst => !seen.src_dsts.insert((src, dst.clone())),
+                DuplicateEntryMode::Skip => !seen.srcs.insert(src),
+            };
+            if duplicate {
+                return false;
+            }
+        }
+        if self.conflicts == DstConflictMode::Rename && !seen.dsts.insert(dst.clone()) {
+            let renamed = (1..)
+                .map(|n| {
+                    let mut name = dst.as_os_str().to_os_string();
+                    name.push(format!("~{n}"));
+                    Arc::<Path>::from(PathBuf::from(name))
+                })
+                .find(|renamed| !seen.dsts.contains(renamed))
+                .unwrap_or_else(|| dst.clone());
+            warn!("Archiving {dst:?} as {renamed:?}, another file is already stored there");
+            seen.dsts.insert(renamed.clone());
+            *dst = renamed;
+        }
+        true
+    }
+}
+
+type CollectedSource = Result<Vec<Result<ArchiveEntry>>>;
+
+/// Sends collected entries to the archive writer until the [`Deadline`] of the archive.
+pub struct EntrySender {
+    tx: SyncSender<Result<ArchiveEntry>>,
+    deadline: Deadline,
+}
+
+impl EntrySender {
+    pub fn new(tx: SyncSender<Result<ArchiveEntry>>, deadline: Deadline) -> Self {
+        Self { tx, deadline }
+    }
+
+    /// Send `entry`, failing once the deadline passed or the writer stopped.
+    fn send(&self, entry: Result<ArchiveEntry>) -> Result<()> {
+        self.deadline.check()?;
+        self.tx.send(entry).map_err(Error::from)
+    }
+}
+
+/// Entries of the sources of a config as they are collected, see
+/// [`BackupConfig::collect_entries`](crate::backup::backup_config::BackupConfig::collect_entries).
+///
+/// Failing to read a source is an error item, the entries of other sources still follow. Once
+/// all entries are read, the entries that could not be created are the last error item.
+pub struct CollectedEntries {
+    rx: Receiver<Result<ArchiveEntry>>,
+    collector: Option<JoinHandle<Result<()>>>,
+    stats: Arc<Vec<SourceStats>>,
+}
+
+impl CollectedEntries {
+    pub fn new(
+        rx: Receiver<Result<ArchiveEntry>>,
+        collector: JoinHandle<Result<()>>,
+        stats: Arc<Vec<SourceStats>>,
+    ) -> Self {
+        Self {
+            rx,
+            collector: Some(collector),
+            stats,
+        }
+    }
+
+    /// Statistics of each source, in config order, complete once all entries are read. Bytes are
+    /// counted as entries are archived, see [`ArchiveEntry::record_archived`].
+    pub fn stats(&self) -> &[SourceStats] {
+        self.stats.as_ref()
+    }
+}
+
+impl Iterator for CollectedEntries {
+    type Item = Result<ArchiveEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(entry) = self.rx.recv() {
+            return Some(entry);
+        }
+        self.collector.take()?.join().unwrap().err().map(Err)
+    }
+}
+
+/// Collect entries of all `files` following `layers` order and send them to `result_tx`. Each
+/// source is traced in a `source` span below the current span.
+///
+/// Failing to create a source iterator is sent through `result_tx` (fatal for the archive),
+/// failing to create an entry is returned as a non-fatal error.
+pub fn collect_entries_into(
+    files: &[ArchiveSourceConfig],
+    layers: &[Vec<usize>],
+    mode: CollectionMode,
+    quiesce: Option<&QuiesceConfig>,
+    stats: &[SourceStats],
+    dedup: &EntryDedup,
+    result_tx: &EntrySender,
+) -> Result<()> {
+    match mode {
+        CollectionMode::Streaming => {
+            stream_entries(files, layers, quiesce, stats, dedup, result_tx)
+        }
+        CollectionMode::TwoPhase => {
+            two_phase_entries(files, layers, quiesce, stats, dedup, result_tx)
+        }
+    }
+}
+
+fn stream_entries(
+    files: &[ArchiveSourceConfig],
+    layers: &[Vec<usize>],
+    quiesce: Option<&QuiesceConfig>,
+    stats: &[SourceStats],
+    dedup: &EntryDedup,
+    result_tx: &EntrySender,
+) -> Result<()> {
+    let quiesced = match QuiesceGuard::start(quiesce) {
+        Ok(quiesced) => quiesced,
+        Err(e) => return result_tx.send(Err(e)),
+    };
+
+    let parent = Span::current();
+    let mut errors = layers
+        .iter()
+        .flat_map(|layer| {
+            layer
+                .par_iter()
+                .filter_map(|idx| {
+                    let span = source_span(&parent, &files[*idx]);
+                    let error = span.in_scope(|| {
+                        send_source_entries(&files[*idx], &stats[*idx], dedup, result_tx)
+                    });
+                    record_source_stats(&span, &files[*idx], &stats[*idx]);
+                    error
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect_vec();
+
+    errors.extend(quiesced.resume().err());
+
+    convert_error_vec(errors)
+}
+
+fn two_phase_entries(
+    files: &[ArchiveSourceConfig],
+    layers: &[Vec<usize>],
+    quiesce: Option<&QuiesceConfig>,
+    stats: &[SourceStats],
+    dedup: &EntryDedup,
+    result_tx: &EntrySender,
+) -> Result<()> {
+    let parent = Span::current();
+    // Entered while collecting and again while sending, so their time covers both
+    let spans = files.iter().map(|f| source_span(&parent, f)).collect_vec();
+    let mut collected: Vec<Option<CollectedSource>> = files.iter().map(|_| None).collect();
+    let mut collect_layers = |volatile: bool| {
+        for layer in layers {
+            layer
+                .par_iter()
+                .filter(|idx| files[**idx].is_volatile() == volatile)
+                .map(|idx| {
+                    let res =
+                        spans[*idx].in_scope(|| collect_source_entries(&files[*idx], result_tx));
+                    (*idx, res)
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .for_each(|(idx, res)| collected[idx] = Some(res));
+        }
+    };
+
+    collect_layers(false);
+
+    let mut errors = Vec::new();
+    let quiesced = match QuiesceGuard::start(quiesce) {
+        Ok(quiesced) => quiesced,
+        Err(e) => return result_tx.send(Err(e)),
+    };
+
+    collect_layers(true);
+
+    errors.extend(quiesced.resume().err());
+
+    for idx in layers.iter().flatten() {
+        if let Err(e) = result_tx.deadline.check() {
+            errors.push(e);
+            break;
+        }
+        let _guard = spans[*idx].enter();
+        match collected[*idx].take() {
+            Some(Ok(entries)) => errors.extend(
+                entries
+                    .into_iter()
+                    .filter_map(|res| send_entry(res, &stats[*idx], dedup, result_tx)),
+            ),
+            Some(Err(e)) => errors.extend(result_tx.send(Err(e)).err()),
+            None => {}
+        }
+        record_source_stats(&spans[*idx], &files[*idx], &stats[*idx]);
+    }
+
+    convert_error_vec(errors)
+}
+
+/// Runs the quiesce `stop` hook when dropped, so applications paused by `start` are resumed
+/// even if collecting panics or returns early.
+struct QuiesceGuard<'a> {
+    stop: Option<&'a CommandHook>,
+}
+
+impl<'a> QuiesceGuard<'a> {
+    /// Run the `start` hook of `quiesce`, if any.
+    fn start(quiesce: Option<&'a QuiesceConfig>) -> Result<Self> {
+        if let Some(quiesce) = quiesce {
+            quiesce.start.run().with_msg("Quiesce start hook failed")?;
+        }
+        Ok(Self {
+            stop: quiesce.map(|quiesce| &quiesce.stop),
+        })
+    }
+
+    /// Run the `stop` hook now, returning its failure.
+    fn resume(mut self) -> Result<()> {
+        self.stop.take().map_or(Ok(()), |stop| {
+            stop.run().with_msg("Quiesce stop hook failed")
+        })
+    }
+}
+
+impl Drop for QuiesceGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            if let Err(e) = stop.run() {
+                warn!("Quiesce stop hook failed: {e}");
+            }
+        }
+    }
+}
+
+fn send_source_entries(
+    source: &ArchiveSourceConfig,
+    stats: &SourceStats,
+    dedup: &EntryDedup,
+    result_tx: &EntrySender,
+) -> Option<Error> {
+    match source.archive_entry_iterator() {
+        Ok(iter) => {
+            let mut errors = Vec::new();
+            for archive_entry_result in iter {
+                if let Err(e) = result_tx.deadline.check() {
+                    errors.push(e);
+                    break;
+                }
+                errors.extend(send_entry(archive_entry_result, stats, dedup, result_tx));
+            }
+            convert_error_vec(errors).err()
+        }
+        Err(e) => result_tx.send(Err(e)).err(),
+    }
+}
+
+fn send_entry(
+    archive_entry_result: Result<ArchiveEntry>,
+    stats: &SourceStats,
+    dedup: &EntryDedup,
+    result_tx: &EntrySender,
+) -> Option<Error> {
+    match archive_entry_result.with_msg("Ignoring entry") {
+        Ok(mut archive_entry) => {
+            let src = (archive_entry.data.is_none() && !archive_entry.delete_src)
+                .then(|| archive_entry.src.clone());
+            if !dedup.admit(src.as_deref(), &mut archive_entry.dst) {
+                stats.record_duplicate();
+                return None;
+            }
+            stats.record_entry(&archive_entry);
+            archive_entry.archived_bytes = Some(stats.archived_bytes());
+            result_tx.send(Ok(archive_entry)).err()
+        }
+        Err(e) => {
+            stats.record_skipped();
+            Some(e)
+        }
+    }
+}
+
+/// Entries of `source`, cut short once the deadline of `result_tx` passed.
+fn collect_source_entries(
+    source: &ArchiveSourceConfig,
+    result_tx: &EntrySender,
+) -> CollectedSource {
+    source.archive_entry_iterator().map(|iter| {
+        iter.take_while(|_| !result_tx.deadline.is_expired())
+            .collect_vec()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Destinations of the admitted entries, `None` for the skipped ones.
+    fn admit(dedup: &EntryDedup, entries: &[(Option<&Path>, &str)]) -> Vec<Option<PathBuf>> {
+        entries
+            .iter()
+            .map(|(src, dst)| {
+                let mut dst = Arc::<Path>::from(Path::new(dst));
+                dedup.admit(*src, &mut dst).then(|| dst.to_path_buf())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn duplicates_are_found_by_resolved_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "data").unwrap();
+        std::os::unix::fs::symlink(&file, dir.path().join("link")).unwrap();
+        let link = dir.path().join("link");
+        let entries = [
+            (Some(file.as_path()), "a"),
+            (Some(link.as_path()), "a"),
+            (Some(link.as_path()), "b"),
+            (None, "a"),
+        ];
+        let some = |dst: &str| Some(PathBuf::from(dst));
+
+        let dedup = EntryDedup::new(DuplicateEntryMode::Keep, DstConflictMode::Keep);
+        assert_eq!(
+            admit(&dedup, &entries),
+            [some("a"), some("a"), some("b"), some("a")]
+        );
+        let dedup = EntryDedup::new(DuplicateEntryMode::SkipSameDst, DstConflictMode::Keep);
+        assert_eq!(
+            admit(&dedup, &entries),
+            [some("a"), None, some("b"), some("a")]
+        );
+        let dedup = EntryDedup::new(DuplicateEntryMode::Skip, DstConflictMode::Keep);
+        assert_eq!(admit(&dedup, &entries), [some("a"), None, None, some("a")]);
+    }
+
+    #[test]
+    fn conflicting_destinations_are_renamed() {
+        let dedup = EntryDedup::new(DuplicateEntryMode::Keep, DstConflictMode::Rename);
+        let entries = [
+            (None, "a"),
+            (None, "a~1"),
+            (None, "a"),
+            (None, "a"),
+            (None, "b"),
+        ];
+        let admitted = admit(&dedup, &entries)
+            .into_iter()
+            .map(|dst| dst.unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(admitted, ["a", "a~1", "a~2", "a~3", "b"]);
+    }
+}
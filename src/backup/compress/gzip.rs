@@ -0,0 +1,31 @@
+use crate::backup::compress::{Compressor, CompressorBuilder};
+use crate::backup::result_error::result::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::io::Write;
+use validator::Validate;
+
+static DEFAULT_COMPRESSION_LEVEL: u32 = 6;
+
+/// Gzip, single threaded and with a lower ratio than xz or zstd, but readable everywhere.
+#[skip_serializing_none]
+#[derive(Clone, Default, Validate, Serialize, Deserialize, Debug)]
+pub struct GzipConfig {
+    #[validate(range(min = 0, max = 9))]
+    level: Option<u32>,
+}
+
+impl<W: Write> CompressorBuilder<W> for GzipConfig {
+    fn build_compressor(&self, writer: W) -> Result<Compressor<W>> {
+        let level = self.level.unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+        Ok(GzEncoder::new(writer, Compression::new(level)).into())
+    }
+}
+
+/// Gzip member of stored deflate blocks, for data already compressed by its producer. Members
+/// are concatenated, which gzip decoders read as one stream.
+pub fn stored_encoder<W: Write>(writer: W) -> GzEncoder<W> {
+    GzEncoder::new(writer, Compression::none())
+}
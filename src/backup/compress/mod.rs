@@ -1,23 +1,83 @@
+#[cfg(feature = "gzip")]
+pub mod gzip;
+#[cfg(feature = "xz")]
 pub mod xz;
+#[cfg(feature = "zstd")]
+pub mod zstd;
 
+#[cfg(feature = "xz")]
+use crate::backup::compress::xz::StoredXzEncoder;
+#[cfg(feature = "zstd")]
+use crate::backup::compress::zstd::{StoredZstdEncoder, ZstdSeekableEncoder};
 use crate::backup::file_ext::FileExtProvider;
 use crate::backup::finish::Finish;
 use crate::backup::result_error::result::Result;
 use crate::backup::result_error::WithDebugObjectAndFnName;
 use derive_more::From;
-use io_enum::Write;
+#[cfg(feature = "gzip")]
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use io_enum::{Read, Write};
+#[cfg(feature = "xz")]
+use liblzma::read::XzDecoder;
+#[cfg(feature = "xz")]
 use liblzma::write::XzEncoder;
 use serde::{Deserialize, Serialize};
 use std::io;
-use std::io::Write;
+#[cfg(feature = "zstd")]
+use std::io::BufReader;
+use std::io::{Read, Write};
 use std::result;
-use std::sync::{Arc, OnceLock};
+use std::sync::Arc;
+#[cfg(any(feature = "gzip", feature = "xz", feature = "zstd"))]
+use std::sync::OnceLock;
 use validator::{Validate, ValidationErrors};
 
 #[derive(Write, From)]
 pub enum Compressor<W: Write> {
     None(W),
+    #[cfg(feature = "gzip")]
+    GzEncoder(GzEncoder<W>),
+    #[cfg(feature = "xz")]
     XzEncoder(XzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    ZstdEncoder(::zstd::Encoder<'static, W>),
+    #[cfg(feature = "zstd")]
+    ZstdSeekableEncoder(ZstdSeekableEncoder<W>),
+    #[cfg(feature = "xz")]
+    StoredXzEncoder(StoredXzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    StoredZstdEncoder(StoredZstdEncoder<W>),
+}
+
+#[derive(Read, From)]
+pub enum Decompressor<R: Read> {
+    None(R),
+    #[cfg(feature = "gzip")]
+    GzDecoder(MultiGzDecoder<R>),
+    #[cfg(feature = "xz")]
+    XzDecoder(XzDecoder<R>),
+    #[cfg(feature = "zstd")]
+    ZstdDecoder(::zstd::Decoder<'static, BufReader<R>>),
+}
+
+impl<R: Read> Decompressor<R> {
+    /// Decoder for the compressor stage recorded as `format` in a pipeline descriptor.
+    pub fn from_format(format: &str, reader: R) -> Result<Self> {
+        match format {
+            "none" => Ok(Decompressor::None(reader)),
+            #[cfg(feature = "gzip")]
+            "gzip" => Ok(MultiGzDecoder::new(reader).into()),
+            #[cfg(feature = "xz")]
+            "xz" => Ok(XzDecoder::new_multi_decoder(reader).into()),
+            // Seek table of the seekable format is a skippable frame, plain decoding ignores it
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(::zstd::Decoder::new(reader)?.into()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unknown or disabled compressor format {format:?}"),
+            ))?,
+        }
+    }
 }
 
 #[derive(Clone, Default, From, Serialize, Deserialize, Debug)]
@@ -26,14 +86,24 @@ pub enum Compressor<W: Write> {
 pub enum CompressorConfig {
     #[default]
     None,
+    #[cfg(feature = "gzip")]
+    Gzip(gzip::GzipConfig),
+    #[cfg(feature = "xz")]
     Xz(xz::XzConfig),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::ZstdConfig),
 }
 
 impl Validate for CompressorConfig {
     fn validate(&self) -> result::Result<(), ValidationErrors> {
         match self {
             CompressorConfig::None => Ok(()),
+            #[cfg(feature = "gzip")]
+            CompressorConfig::Gzip(gzip) => gzip.validate(),
+            #[cfg(feature = "xz")]
             CompressorConfig::Xz(xz) => xz.validate(),
+            #[cfg(feature = "zstd")]
+            CompressorConfig::Zstd(zstd) => zstd.validate(),
         }
     }
 }
@@ -46,27 +116,137 @@ impl<W: Write> Finish<W> for Compressor<W> {
     fn finish(self) -> io::Result<W> {
         match self {
             Compressor::None(w) => Ok(w),
+            #[cfg(feature = "gzip")]
+            Compressor::GzEncoder(w) => w.finish(),
+            #[cfg(feature = "xz")]
             Compressor::XzEncoder(w) => w.finish(),
+            #[cfg(feature = "zstd")]
+            Compressor::ZstdEncoder(w) => w.finish(),
+            #[cfg(feature = "zstd")]
+            Compressor::ZstdSeekableEncoder(w) => w.finish(),
+            #[cfg(feature = "xz")]
+            Compressor::StoredXzEncoder(w) => w.finish(),
+            #[cfg(feature = "zstd")]
+            Compressor::StoredZstdEncoder(w) => w.finish(),
         }
     }
 }
 
+impl CompressorConfig {
+    /// Writer storing data uncompressed in the format of this compressor.
+    fn build_stored<W: Write>(&self, writer: W) -> Result<Compressor<W>> {
+        match self {
+            CompressorConfig::None => Ok(Compressor::None(writer)),
+            #[cfg(feature = "gzip")]
+            CompressorConfig::Gzip(_) => Ok(gzip::stored_encoder(writer).into()),
+            #[cfg(feature = "xz")]
+            CompressorConfig::Xz(_) => Ok(StoredXzEncoder::new(writer)?.into()),
+            #[cfg(feature = "zstd")]
+            CompressorConfig::Zstd(_) => Ok(StoredZstdEncoder::new(writer).into()),
+        }
+    }
+}
+
+/// Compressor writing runs of already compressed data as stored blocks of its format, so they are
+/// not compressed twice while the archive stays one stream for standard decoders.
+pub struct PassthroughCompressor<W: Write> {
+    config: Arc<CompressorConfig>,
+    /// `None` only after switching failed.
+    compressor: Option<Compressor<W>>,
+    passthrough: bool,
+}
+
+impl<W: Write> PassthroughCompressor<W> {
+    pub fn new(config: Arc<CompressorConfig>, writer: W) -> Result<Self> {
+        Ok(Self {
+            compressor: Some(config.build_compressor(writer)?),
+            config,
+            passthrough: false,
+        })
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough
+    }
+
+    /// Store the following data uncompressed, or compress it again. Ends the current stream of
+    /// the format unless it supports stored blocks within a stream.
+    pub fn set_passthrough(&mut self, passthrough: bool) -> Result<()> {
+        if passthrough == self.passthrough {
+            return Ok(());
+        }
+        let compressor = match self.compressor.take() {
+            None => Err(poisoned())?,
+            #[cfg(feature = "zstd")]
+            Some(Compressor::ZstdSeekableEncoder(mut w)) => {
+                w.set_stored(passthrough)?;
+                w.into()
+            }
+            Some(compressor) => {
+                let writer = compressor.finish()?;
+                match passthrough {
+                    true => self.config.build_stored(writer)?,
+                    false => self.config.build_compressor(writer)?,
+                }
+            }
+        };
+        self.compressor = Some(compressor);
+        self.passthrough = passthrough;
+        Ok(())
+    }
+}
+
+fn poisoned() -> io::Error {
+    io::Error::other("compressor is unusable after a failed passthrough switch")
+}
+
+impl<W: Write> Write for PassthroughCompressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.compressor.as_mut().ok_or_else(poisoned)?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.compressor.as_mut().ok_or_else(poisoned)?.flush()
+    }
+}
+
+impl<W: Write> Finish<W> for PassthroughCompressor<W> {
+    fn finish(self) -> io::Result<W> {
+        self.compressor.ok_or_else(poisoned)?.finish()
+    }
+}
+
 impl<W: Write> CompressorBuilder<W> for CompressorConfig {
     fn build_compressor(&self, writer: W) -> Result<Compressor<W>> {
         match self {
             CompressorConfig::None => Ok(Compressor::None(writer)),
+            #[cfg(feature = "gzip")]
+            CompressorConfig::Gzip(gzip) => gzip.build_compressor(writer),
+            #[cfg(feature = "xz")]
             CompressorConfig::Xz(xz) => xz.build_compressor(writer),
+            #[cfg(feature = "zstd")]
+            CompressorConfig::Zstd(zstd) => zstd.build_compressor(writer),
         }
         .with_debug_object_and_fn_name(self.clone(), "build_compressor")
     }
 }
 
+#[cfg(feature = "gzip")]
+static GZIP_FILE_EXT: OnceLock<Arc<str>> = OnceLock::new();
+#[cfg(feature = "xz")]
 static XZ_FILE_EXT: OnceLock<Arc<str>> = OnceLock::new();
+#[cfg(feature = "zstd")]
+static ZSTD_FILE_EXT: OnceLock<Arc<str>> = OnceLock::new();
 impl FileExtProvider for CompressorConfig {
     fn file_ext(&self) -> Option<Arc<str>> {
         match self {
             CompressorConfig::None => None,
+            #[cfg(feature = "gzip")]
+            CompressorConfig::Gzip(_) => Some(GZIP_FILE_EXT.get_or_init(|| "gz".into()).clone()),
+            #[cfg(feature = "xz")]
             CompressorConfig::Xz(_) => Some(XZ_FILE_EXT.get_or_init(|| "xz".into()).clone()),
+            #[cfg(feature = "zstd")]
+            CompressorConfig::Zstd(_) => Some(ZSTD_FILE_EXT.get_or_init(|| "zst".into()).clone()),
         }
     }
 }
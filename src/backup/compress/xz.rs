@@ -1,4 +1,5 @@
 use crate::backup::compress::{Compressor, CompressorBuilder};
+use crate::backup::finish::Finish;
 use crate::backup::result_error::result::Result;
 use liblzma::stream::{Check, MtStreamBuilder};
 use liblzma::write::XzEncoder;
@@ -11,6 +12,15 @@ use validator::Validate;
 static DEFAULT_COMPRESSION_LEVEL: u32 = 3;
 static DEFAULT_MAX_PARALLELIZATION: usize = 32;
 
+static STREAM_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0];
+static FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+/// Stream flags with no integrity check, the archive checksum covers the content.
+static STREAM_FLAGS: [u8; 2] = [0, 0];
+/// Block header size byte, flags, LZMA2 filter with a 1 MiB dictionary, then padding.
+static BLOCK_HEADER: [u8; 8] = [2, 0, 0x21, 1, 16, 0, 0, 0];
+/// Largest uncompressed LZMA2 chunk.
+static MAX_CHUNK_SIZE: usize = 64 * 1024;
+
 #[skip_serializing_none]
 #[derive(Clone, Default, Validate, Serialize, Deserialize, Debug)]
 pub struct XzConfig {
@@ -43,3 +53,120 @@ impl<W: Write> CompressorBuilder<W> for XzConfig {
         }
     }
 }
+
+/// Writer of an xz stream holding uncompressed LZMA2 chunks, for data already compressed by its
+/// producer. Decoders reading concatenated streams read it as part of the archive stream.
+pub struct StoredXzEncoder<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    /// Bytes of LZMA2 data written in the block, `None` before the block header.
+    block_size: Option<u64>,
+    uncompressed_size: u64,
+}
+
+impl<W: Write> StoredXzEncoder<W> {
+    pub fn new(mut inner: W) -> std::io::Result<Self> {
+        inner.write_all(&STREAM_MAGIC)?;
+        inner.write_all(&STREAM_FLAGS)?;
+        inner.write_all(&crc32(&STREAM_FLAGS).to_le_bytes())?;
+        Ok(Self {
+            inner,
+            buf: Vec::with_capacity(MAX_CHUNK_SIZE),
+            block_size: None,
+            uncompressed_size: 0,
+        })
+    }
+
+    fn write_chunk(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        // First chunk resets the dictionary
+        let control = match self.block_size {
+            None => {
+                self.inner.write_all(&BLOCK_HEADER)?;
+                self.inner.write_all(&crc32(&BLOCK_HEADER).to_le_bytes())?;
+                1u8
+            }
+            Some(_) => 2u8,
+        };
+        self.inner.write_all(&[control])?;
+        self.inner
+            .write_all(&((self.buf.len() - 1) as u16).to_be_bytes())?;
+        self.inner.write_all(&self.buf)?;
+        self.block_size = Some(self.block_size.unwrap_or(0) + 3 + self.buf.len() as u64);
+        self.uncompressed_size += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for StoredXzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(MAX_CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        if self.buf.len() == MAX_CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Finish<W> for StoredXzEncoder<W> {
+    fn finish(mut self) -> std::io::Result<W> {
+        self.write_chunk()?;
+        let mut index = vec![0u8];
+        match self.block_size {
+            None => index.push(0),
+            Some(block_size) => {
+                // LZMA2 end marker, then padding of the block to 4 bytes
+                let data_size = block_size + 1;
+                self.inner.write_all(&[0])?;
+                self.inner
+                    .write_all(&vec![0; (4 - data_size % 4) as usize % 4])?;
+                let unpadded_size = (BLOCK_HEADER.len() + 4) as u64 + data_size;
+                index.push(1);
+                push_varint(&mut index, unpadded_size);
+                push_varint(&mut index, self.uncompressed_size);
+            }
+        }
+        index.resize(index.len().div_ceil(4) * 4, 0);
+        index.extend_from_slice(&crc32(&index).to_le_bytes());
+        self.inner.write_all(&index)?;
+
+        let mut footer = ((index.len() / 4 - 1) as u32).to_le_bytes().to_vec();
+        footer.extend_from_slice(&STREAM_FLAGS);
+        self.inner.write_all(&crc32(&footer).to_le_bytes())?;
+        self.inner.write_all(&footer)?;
+        self.inner.write_all(&FOOTER_MAGIC)?;
+        Ok(self.inner)
+    }
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// CRC-32 of the xz headers, only ever computed over a few bytes.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,219 @@
+use crate::backup::compress::{Compressor, CompressorBuilder};
+use crate::backup::finish::Finish;
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::io::Write;
+use std::num::NonZero;
+use validator::Validate;
+
+static DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+static DEFAULT_MAX_PARALLELIZATION: usize = 32;
+static DEFAULT_SEEKABLE_FRAME_SIZE: u32 = 2 * 1024 * 1024;
+
+static SKIPPABLE_FRAME_MAGIC: u32 = 0x184D2A5E;
+static SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+static FRAME_MAGIC: u32 = 0xFD2FB528;
+/// Largest block allowed by the format, also the window of stored frames.
+static MAX_BLOCK_SIZE: usize = 128 * 1024;
+/// Window descriptor of a 128 KiB window: exponent 7, mantissa 0.
+static STORED_WINDOW_DESCRIPTOR: u8 = 7 << 3;
+
+#[skip_serializing_none]
+#[derive(Clone, Default, Validate, Serialize, Deserialize, Debug)]
+pub struct ZstdConfig {
+    #[validate(range(min = 1, max = 22))]
+    level: Option<i32>,
+    #[validate(range(min = 1))]
+    thread: Option<u32>,
+    /// Write the zstd seekable format: independent frames followed by a seek table.
+    seekable: Option<bool>,
+    /// Uncompressed size of every frame in seekable mode.
+    #[validate(range(min = 1))]
+    frame_size: Option<u32>,
+}
+
+impl<W: Write> CompressorBuilder<W> for ZstdConfig {
+    fn build_compressor(&self, writer: W) -> Result<Compressor<W>> {
+        let level = self.level.unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+        if self.seekable.unwrap_or(false) {
+            return Ok(ZstdSeekableEncoder::new(
+                writer,
+                level,
+                self.frame_size.unwrap_or(DEFAULT_SEEKABLE_FRAME_SIZE),
+            )
+            .into());
+        }
+
+        let thread = self.thread.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(NonZero::get)
+                .map(|core| core / 2)
+                .map(|t| t.max(1))
+                .map(|t| t.min(DEFAULT_MAX_PARALLELIZATION) as u32)
+                .unwrap_or(1)
+        });
+        let mut encoder = zstd::Encoder::new(writer, level)?;
+        if thread > 1 {
+            encoder.multithread(thread)?;
+        }
+        Ok(encoder.into())
+    }
+}
+
+/// Encoder for the zstd seekable format, so readers can decompress an arbitrary range by only
+/// decoding the frames covering it.
+pub struct ZstdSeekableEncoder<W: Write> {
+    inner: W,
+    level: i32,
+    frame_size: usize,
+    buf: Vec<u8>,
+    /// Compressed and decompressed size of each written frame.
+    frames: Vec<(u32, u32)>,
+    /// Write frames as raw blocks instead of compressing them.
+    stored: bool,
+}
+
+impl<W: Write> ZstdSeekableEncoder<W> {
+    pub fn new(inner: W, level: i32, frame_size: u32) -> Self {
+        Self {
+            inner,
+            level,
+            frame_size: frame_size as usize,
+            buf: Vec::with_capacity(frame_size as usize),
+            frames: Vec::new(),
+            stored: false,
+        }
+    }
+
+    /// Store the following data uncompressed, starting a new frame.
+    pub fn set_stored(&mut self, stored: bool) -> std::io::Result<()> {
+        self.write_frame()?;
+        self.stored = stored;
+        Ok(())
+    }
+
+    fn write_frame(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let compressed = match self.stored {
+            true => stored_frame(&self.buf),
+            false => zstd::bulk::compress(&self.buf, self.level)?,
+        };
+        self.inner.write_all(&compressed)?;
+        self.frames
+            .push((compressed.len() as u32, self.buf.len() as u32));
+        self.buf.clear();
+        Ok(())
+    }
+
+    fn write_seek_table(&mut self) -> std::io::Result<()> {
+        let frame_content_size = self.frames.len() * 8 + 9;
+        self.inner.write_all(&SKIPPABLE_FRAME_MAGIC.to_le_bytes())?;
+        self.inner
+            .write_all(&(frame_content_size as u32).to_le_bytes())?;
+        for (compressed_size, decompressed_size) in self.frames.iter() {
+            self.inner.write_all(&compressed_size.to_le_bytes())?;
+            self.inner.write_all(&decompressed_size.to_le_bytes())?;
+        }
+        self.inner
+            .write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        // Seek table descriptor, no checksums
+        self.inner.write_all(&[0u8])?;
+        self.inner.write_all(&SEEKABLE_MAGIC.to_le_bytes())
+    }
+}
+
+impl<W: Write> Write for ZstdSeekableEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.frame_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        if self.buf.len() == self.frame_size {
+            self.write_frame()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Finish<W> for ZstdSeekableEncoder<W> {
+    fn finish(mut self) -> std::io::Result<W> {
+        self.write_frame()?;
+        self.write_seek_table()?;
+        Ok(self.inner)
+    }
+}
+
+/// Frame holding `data` in raw blocks, decodable by any zstd decoder.
+fn stored_frame(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_BLOCK_SIZE).max(1);
+    let mut frame = Vec::with_capacity(data.len() + blocks * 3 + 6);
+    frame.extend_from_slice(&FRAME_MAGIC.to_le_bytes());
+    // Frame header descriptor: no content size, no checksum, no dictionary
+    frame.push(0);
+    frame.push(STORED_WINDOW_DESCRIPTOR);
+    let mut chunks = data.chunks(MAX_BLOCK_SIZE).peekable();
+    if chunks.peek().is_none() {
+        // Last raw block of size 0
+        frame.extend_from_slice(&[1, 0, 0]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none() as u32;
+        // Block header: last block flag, raw block type 0, block size
+        let header = last | ((chunk.len() as u32) << 3);
+        frame.extend_from_slice(&header.to_le_bytes()[..3]);
+        frame.extend_from_slice(chunk);
+    }
+    frame
+}
+
+/// Writer of stored zstd frames, for data already compressed by its producer.
+pub struct StoredZstdEncoder<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> StoredZstdEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(MAX_BLOCK_SIZE),
+        }
+    }
+
+    fn write_frame(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_all(&stored_frame(&self.buf))?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for StoredZstdEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(MAX_BLOCK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        if self.buf.len() == MAX_BLOCK_SIZE {
+            self.write_frame()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_frame()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Finish<W> for StoredZstdEncoder<W> {
+    fn finish(mut self) -> std::io::Result<W> {
+        self.write_frame()?;
+        Ok(self.inner)
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+static POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+static DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// `NMMetered` values meaning the connection is metered.
+static NM_METERED_YES: [u32; 2] = [1, 3];
+
+/// Conditions a scheduled run waits for, e.g. so a laptop does not back up on battery.
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct RunConditionsConfig {
+    /// Skip while running on battery, i.e. batteries present and no external power online.
+    pub skip_on_battery: Option<bool>,
+    /// Skip while NetworkManager reports the primary connection as metered.
+    pub skip_on_metered: Option<bool>,
+    /// `host:port` targets that must accept a TCP connection, e.g. the storage server.
+    pub require_reachable: Option<Vec<Arc<str>>>,
+    #[serde(default, with = "humantime_serde")]
+    pub connect_timeout: Option<Duration>,
+    /// Check unmet conditions again this often rather than skipping the run until the next
+    /// scheduled one.
+    #[serde(default, with = "humantime_serde")]
+    pub recheck_interval: Option<Duration>,
+}
+
+impl RunConditionsConfig {
+    /// Reason the run should not happen now, `None` when every condition is met.
+    pub fn unmet_reason(&self) -> Option<String> {
+        if self.skip_on_battery.unwrap_or(false) && is_on_battery() {
+            return Some("running on battery".to_string());
+        }
+        if self.skip_on_metered.unwrap_or(false) && is_metered() {
+            return Some("network connection is metered".to_string());
+        }
+        let timeout = self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+        self.require_reachable
+            .iter()
+            .flatten()
+            .find(|target| !is_reachable(target, timeout))
+            .map(|target| format!("{target} is unreachable"))
+    }
+}
+
+fn read_power_supply_attr(supply: &Path, attr: &str) -> Option<String> {
+    std::fs::read_to_string(supply.join(attr))
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+fn is_on_battery() -> bool {
+    let Ok(read_dir) = std::fs::read_dir(POWER_SUPPLY_DIR) else {
+        return false;
+    };
+    let mut has_battery = false;
+    for supply in read_dir.filter_map(|r| r.ok()).map(|r| r.path()) {
+        match read_power_supply_attr(&supply, "type").as_deref() {
+            Some("Battery") => has_battery = true,
+            Some(_) if read_power_supply_attr(&supply, "online").as_deref() == Some("1") => {
+                return false
+            }
+            _ => {}
+        }
+    }
+    has_battery
+}
+
+fn is_metered() -> bool {
+    let output = Command::new("busctl")
+        .args([
+            "get-property",
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+            "Metered",
+        ])
+        .output();
+    match output {
+        // Printed as `u <value>`
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .nth(1)
+            .and_then(|v| v.parse().ok())
+            .is_some_and(|v: u32| NM_METERED_YES.contains(&v)),
+        Ok(output) => {
+            warn!(
+                "Failed to query NetworkManager metered state: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
+        Err(e) => {
+            warn!("Failed to query NetworkManager metered state: {e}");
+            false
+        }
+    }
+}
+
+fn is_reachable(target: &str, timeout: Duration) -> bool {
+    match target.to_socket_addrs() {
+        Ok(addrs) => addrs
+            .into_iter()
+            .any(|addr| TcpStream::connect_timeout(&addr, timeout).is_ok()),
+        Err(_) => false,
+    }
+}
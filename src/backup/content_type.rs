@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bytes read from the start of a file, enough for magic numbers and telling text apart.
+static SNIFF_LEN: usize = 512;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    Image,
+    Video,
+    Audio,
+    /// Compressed archives and streams, zip based formats included.
+    Compressed,
+    Document,
+    Sqlite,
+    Executable,
+    Text,
+    /// Anything else, e.g. encrypted or application specific data.
+    Binary,
+    Empty,
+}
+
+static CONTENT_TYPES: [ContentType; 10] = [
+    ContentType::Image,
+    ContentType::Video,
+    ContentType::Audio,
+    ContentType::Compressed,
+    ContentType::Document,
+    ContentType::Sqlite,
+    ContentType::Executable,
+    ContentType::Text,
+    ContentType::Binary,
+    ContentType::Empty,
+];
+
+impl ContentType {
+    /// Content type of a file starting with `head`, from magic numbers only.
+    pub fn detect(head: &[u8]) -> ContentType {
+        let starts = |magic: &[u8]| head.starts_with(magic);
+        let at =
+            |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+        if head.is_empty() {
+            ContentType::Empty
+        } else if starts(b"\x89PNG")
+            || starts(b"\xff\xd8\xff")
+            || starts(b"GIF8")
+            || starts(b"II*\0")
+            || starts(b"MM\0*")
+            || (starts(b"RIFF") && at(8, b"WEBP"))
+            || (at(4, b"ftyp") && (at(8, b"heic") || at(8, b"heix") || at(8, b"avif")))
+        {
+            ContentType::Image
+        } else if starts(b"ID3")
+            || starts(b"fLaC")
+            || starts(b"OggS")
+            || starts(b"\xff\xfb")
+            || starts(b"\xff\xf3")
+            || (starts(b"RIFF") && at(8, b"WAVE"))
+            || (at(4, b"ftyp") && at(8, b"M4A "))
+        {
+            ContentType::Audio
+        } else if starts(b"\x1a\x45\xdf\xa3")
+            || starts(b"\0\0\x01\xba")
+            || at(4, b"ftyp")
+            || (starts(b"RIFF") && at(8, b"AVI "))
+        {
+            ContentType::Video
+        } else if starts(b"\x1f\x8b")
+            || starts(b"\xfd7zXZ\0")
+            || starts(b"\x28\xb5\x2f\xfd")
+            || starts(b"BZh")
+            || starts(b"PK\x03\x04")
+            || starts(b"7z\xbc\xaf\x27\x1c")
+            || starts(b"Rar!")
+        {
+            ContentType::Compressed
+        } else if starts(b"%PDF") || starts(b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") {
+            ContentType::Document
+        } else if starts(b"SQLite format 3\0") {
+            ContentType::Sqlite
+        } else if starts(b"\x7fELF") || starts(b"MZ") || starts(b"\xcf\xfa\xed\xfe") {
+            ContentType::Executable
+        } else if is_text(head) {
+            ContentType::Text
+        } else {
+            ContentType::Binary
+        }
+    }
+
+    /// Content type of the file at `path`, reading its first bytes.
+    pub fn detect_file<P: AsRef<Path>>(path: P) -> std::io::Result<ContentType> {
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        File::open(path)?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)?;
+        Ok(Self::detect(&head))
+    }
+}
+
+impl Display for ContentType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ContentType::Image => "image",
+            ContentType::Video => "video",
+            ContentType::Audio => "audio",
+            ContentType::Compressed => "compressed",
+            ContentType::Document => "document",
+            ContentType::Sqlite => "sqlite",
+            ContentType::Executable => "executable",
+            ContentType::Text => "text",
+            ContentType::Binary => "binary",
+            ContentType::Empty => "empty",
+        })
+    }
+}
+
+/// UTF-8 without NUL bytes, a character cut at the end of `head` is fine.
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// Share of one content type among the archived files.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ContentTypeShare {
+    pub content_type: ContentType,
+    pub entries: u64,
+    pub bytes: u64,
+    /// Percentage of all archived file bytes.
+    pub percent: f64,
+}
+
+/// Entry and byte counters per content type, updated concurrently while entries are collected.
+#[derive(Default, Debug)]
+pub struct ContentTypeStats {
+    entries: [AtomicU64; CONTENT_TYPES.len()],
+    bytes: [AtomicU64; CONTENT_TYPES.len()],
+}
+
+impl ContentTypeStats {
+    pub fn record(&self, content_type: ContentType, size: u64) {
+        let idx = CONTENT_TYPES
+            .iter()
+            .position(|t| *t == content_type)
+            .unwrap();
+        self.entries[idx].fetch_add(1, Ordering::Relaxed);
+        self.bytes[idx].fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// Content types seen across all `stats`, largest share first.
+    pub fn to_shares<'a, I: IntoIterator<Item = &'a ContentTypeStats>>(
+        stats: I,
+    ) -> Vec<ContentTypeShare> {
+        let mut entries = [0u64; CONTENT_TYPES.len()];
+        let mut bytes = [0u64; CONTENT_TYPES.len()];
+        for stats in stats {
+            for idx in 0..CONTENT_TYPES.len() {
+                entries[idx] += stats.entries[idx].load(Ordering::Relaxed);
+                bytes[idx] += stats.bytes[idx].load(Ordering::Relaxed);
+            }
+        }
+        let total_bytes: u64 = bytes.iter().sum();
+        let mut shares: Vec<_> = CONTENT_TYPES
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| entries[*idx] > 0)
+            .map(|(idx, content_type)| ContentTypeShare {
+                content_type: *content_type,
+                entries: entries[idx],
+                bytes: bytes[idx],
+                percent: match total_bytes {
+                    0 => 0.0,
+                    total => bytes[idx] as f64 * 100.0 / total as f64,
+                },
+            })
+            .collect();
+        shares.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(b.entries.cmp(&a.entries)));
+        shares
+    }
+}
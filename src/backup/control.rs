@@ -0,0 +1,134 @@
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+static CONTROL_SOCKET_FILE: &str = "control.sock";
+static TRIGGER_REQUEST: &str = "run";
+/// Time a client has to send its request, a silent one would block later triggers.
+static REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What starts the backups of the daemon.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleMode {
+    /// The `cron` schedule.
+    #[default]
+    Cron,
+    /// Triggers sent to the control socket in the state directory, e.g. by `k_backup trigger`,
+    /// for orchestrators owning the schedule. Triggers are run one at a time.
+    External,
+}
+
+/// Unix socket of a daemon with `schedule: external` within its state directory.
+pub fn control_socket_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(CONTROL_SOCKET_FILE)
+}
+
+/// Outcome of a triggered backup, sent back as a single line.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TriggerResponse {
+    Created(PathBuf),
+    Skipped,
+    Failed(String),
+}
+
+impl Display for TriggerResponse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerResponse::Created(path) => write!(f, "created {}", path.display()),
+            TriggerResponse::Skipped => write!(f, "skipped"),
+            // Kept on one line, the response ends at the first newline
+            TriggerResponse::Failed(error) => write!(f, "failed {}", error.replace('\n', " ")),
+        }
+    }
+}
+
+impl TriggerResponse {
+    fn parse(line: &str) -> Option<Self> {
+        let (status, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match status {
+            "created" => Some(TriggerResponse::Created(rest.into())),
+            "skipped" => Some(TriggerResponse::Skipped),
+            "failed" => Some(TriggerResponse::Failed(rest.into())),
+            _ => None,
+        }
+    }
+}
+
+/// Listen on the control socket at `path`, replacing one left by a daemon that is gone. Only
+/// the owner may connect.
+pub fn bind_control_socket(path: &Path) -> Result<UnixListener> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // The daemon holds the backup lock, a socket file left behind is stale
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Read the request of a connection, calling `run` for a trigger and answering with its outcome.
+pub fn handle_connection<F: FnOnce() -> TriggerResponse>(stream: UnixStream, run: F) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_READ_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match line.trim() {
+        request if request == TRIGGER_REQUEST => run(),
+        request => TriggerResponse::Failed(format!("unknown request {request:?}")),
+    };
+    writeln!(&stream, "{response}")?;
+    Ok(())
+}
+
+/// Trigger a backup of the daemon listening at `path` and wait for its outcome.
+pub fn send_trigger(path: &Path) -> Result<TriggerResponse> {
+    let stream = UnixStream::connect(path).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("no daemon with schedule: external listening on {path:?}: {e}"),
+        )
+    })?;
+    writeln!(&stream, "{TRIGGER_REQUEST}")?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    TriggerResponse::parse(line.trim_end_matches('\n')).ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("unexpected response {line:?} from {path:?}"),
+        )
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trigger_is_answered_with_its_outcome() {
+        let (client, server) = UnixStream::pair().unwrap();
+        writeln!(&client, "{TRIGGER_REQUEST}").unwrap();
+        handle_connection(server, || TriggerResponse::Failed("a\nb".into())).unwrap();
+        let mut line = String::new();
+        BufReader::new(&client).read_line(&mut line).unwrap();
+        assert_eq!(
+            TriggerResponse::parse(line.trim_end_matches('\n')),
+            Some(TriggerResponse::Failed("a b".into()))
+        );
+    }
+
+    #[test]
+    fn silent_client_times_out_without_a_run() {
+        let (_client, server) = UnixStream::pair().unwrap();
+        assert!(handle_connection(server, || unreachable!()).is_err());
+    }
+}
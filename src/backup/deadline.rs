@@ -0,0 +1,76 @@
+use crate::backup::humanize::HumanDuration;
+use crate::backup::result_error::result::Result;
+use std::io::ErrorKind;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How long the collector may keep running once an archive timed out, it is left behind after.
+static COLLECTOR_GRACE: Duration = Duration::from_secs(30);
+static JOIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time an archive must be created by, see `max_duration`. Never expires without one.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    limit: Option<(Instant, Duration)>,
+}
+
+impl Deadline {
+    /// Deadline `max_duration` from now.
+    pub fn after(max_duration: Option<Duration>) -> Self {
+        Self {
+            limit: max_duration.map(|d| (Instant::now() + d, d)),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.limit.is_some_and(|(at, _)| Instant::now() >= at)
+    }
+
+    /// Fail with a timeout error once expired.
+    pub fn check(&self) -> Result<()> {
+        match self.limit.filter(|_| self.is_expired()) {
+            Some((_, max_duration)) => Err(timed_out(max_duration))?,
+            None => Ok(()),
+        }
+    }
+
+    /// Next value of `rx`, `None` once all senders are gone. Fails once expired, even while
+    /// waiting on a sender stuck on a slow source.
+    pub fn recv<T>(&self, rx: &Receiver<T>) -> Result<Option<T>> {
+        self.check()?;
+        let Some((at, _)) = self.limit else {
+            return Ok(rx.recv().ok());
+        };
+        match rx.recv_timeout(at.saturating_duration_since(Instant::now())) {
+            Ok(value) => Ok(Some(value)),
+            Err(RecvTimeoutError::Disconnected) => Ok(None),
+            Err(RecvTimeoutError::Timeout) => self.check().map(|_| None),
+        }
+    }
+
+    /// Join `handle`, giving it a grace period once expired. A thread still running after is
+    /// left behind, e.g. blocked reading a hung network mount, and a timeout error returned.
+    pub fn join<T>(&self, handle: JoinHandle<Result<T>>) -> Result<T> {
+        if let Some((_, max_duration)) = self.limit.filter(|_| self.is_expired()) {
+            let give_up_at = Instant::now() + COLLECTOR_GRACE;
+            while !handle.is_finished() {
+                if Instant::now() >= give_up_at {
+                    Err(timed_out(max_duration))?
+                }
+                std::thread::sleep(JOIN_POLL_INTERVAL);
+            }
+        }
+        handle.join().unwrap()
+    }
+}
+
+fn timed_out(max_duration: Duration) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::TimedOut,
+        format!(
+            "backup cancelled, it ran longer than max_duration of {}",
+            HumanDuration(max_duration)
+        ),
+    )
+}
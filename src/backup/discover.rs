@@ -0,0 +1,236 @@
+#[cfg(feature = "sqlite")]
+use crate::backup::archive::sqlite::SqliteDBSource;
+use crate::backup::archive::walkdir_globset::{CustomDeserializedGlob, WalkdirAndGlobsetSource};
+use crate::backup::archive::{ArchiveEntryConfig, ArchiveSourceConfig};
+use crate::backup::result_error::result::Result;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A source suggested for an application data layout found on the host.
+#[derive(Debug)]
+pub struct DiscoveredSource {
+    pub application: &'static str,
+    pub note: Option<&'static str>,
+    pub source: ArchiveSourceConfig,
+}
+
+fn join_root(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
+
+fn glob_source(src_dir: PathBuf, dst_dir: &Path, globset: &[&str]) -> WalkdirAndGlobsetSource {
+    WalkdirAndGlobsetSource::new(
+        src_dir,
+        Some(dst_dir.into()),
+        (!globset.is_empty()).then(|| {
+            globset
+                .iter()
+                .map(|g| CustomDeserializedGlob::new(g).expect("invalid built-in glob"))
+                .collect()
+        }),
+    )
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_source(db: PathBuf, dst: &Path) -> ArchiveEntryConfig {
+    SqliteDBSource::new(db, dst).into()
+}
+
+/// Plain copy of the database file, only consistent while the application is stopped.
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_source(db: PathBuf, dst: &Path) -> ArchiveEntryConfig {
+    let file_name = db
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    glob_source(
+        db.parent().map(Path::to_path_buf).unwrap_or_default(),
+        dst.parent().unwrap_or(Path::new("")),
+        &[file_name.as_str()],
+    )
+    .into()
+}
+
+fn source<N: Into<Arc<str>>, S: Into<ArchiveEntryConfig>>(
+    name: N,
+    depends_on: Option<&str>,
+    source: S,
+) -> ArchiveSourceConfig {
+    ArchiveSourceConfig {
+        name: Some(name.into()),
+        depends_on: depends_on.map(|d| vec![d.into()]),
+        ownership: None,
+        precompressed: None,
+        source: source.into(),
+    }
+}
+
+fn discover_vaultwarden(root: &Path, found: &mut Vec<DiscoveredSource>) {
+    for dir in ["/var/lib/vaultwarden", "/opt/vaultwarden/data", "/vw-data"] {
+        let dir = join_root(root, dir);
+        let db = dir.join("db.sqlite3");
+        if !db.is_file() {
+            continue;
+        }
+        found.push(DiscoveredSource {
+            application: "Vaultwarden",
+            note: None,
+            source: source(
+                "vaultwarden_db",
+                None,
+                sqlite_source(db, Path::new("vaultwarden/db.sqlite3")),
+            ),
+        });
+        found.push(DiscoveredSource {
+            application: "Vaultwarden",
+            note: None,
+            source: source(
+                "vaultwarden_files",
+                Some("vaultwarden_db"),
+                glob_source(
+                    dir,
+                    Path::new("vaultwarden"),
+                    &["sends/**/*", "attachments/**/*", "rsa_key*", "config.json"],
+                ),
+            ),
+        });
+        return;
+    }
+}
+
+fn discover_gitea(root: &Path, found: &mut Vec<DiscoveredSource>) {
+    let dir = join_root(root, "/var/lib/gitea");
+    let db = dir.join("data/gitea.db");
+    if !dir.is_dir() {
+        return;
+    }
+    if db.is_file() {
+        found.push(DiscoveredSource {
+            application: "Gitea",
+            note: None,
+            source: source(
+                "gitea_db",
+                None,
+                sqlite_source(db, Path::new("gitea/data/gitea.db")),
+            ),
+        });
+    }
+    found.push(DiscoveredSource {
+        application: "Gitea",
+        note: Some("repositories are only consistent while no push is in progress"),
+        source: source(
+            "gitea_files",
+            found
+                .iter()
+                .any(|d| d.source.name.as_deref() == Some("gitea_db"))
+                .then_some("gitea_db"),
+            glob_source(
+                dir,
+                Path::new("gitea"),
+                &[
+                    "data/gitea-repositories/**/*",
+                    "data/attachments/**/*",
+                    "data/lfs/**/*",
+                    "custom/**/*",
+                ],
+            ),
+        ),
+    });
+}
+
+fn discover_jellyfin(root: &Path, found: &mut Vec<DiscoveredSource>) {
+    let dir = join_root(root, "/var/lib/jellyfin/data");
+    for db in ["library.db", "jellyfin.db"] {
+        let path = dir.join(db);
+        if path.is_file() {
+            found.push(DiscoveredSource {
+                application: "Jellyfin",
+                note: None,
+                source: source(
+                    format!("jellyfin_{}", db.trim_end_matches(".db")),
+                    None,
+                    sqlite_source(path, &Path::new("jellyfin/data").join(db)),
+                ),
+            });
+        }
+    }
+}
+
+fn discover_postgresql(root: &Path, found: &mut Vec<DiscoveredSource>) {
+    let dir = join_root(root, "/var/lib/postgresql");
+    if dir.is_dir() {
+        found.push(DiscoveredSource {
+            application: "PostgreSQL",
+            note: Some(
+                "raw data files are only consistent while the server is stopped, \
+                 prefer backing up a pg_dump output",
+            ),
+            source: source(
+                "postgresql",
+                None,
+                glob_source(dir, Path::new("postgresql"), &[]),
+            ),
+        });
+    }
+}
+
+fn discover_docker_volumes(root: &Path, found: &mut Vec<DiscoveredSource>) {
+    let Ok(read_dir) = std::fs::read_dir(join_root(root, "/var/lib/docker/volumes")) else {
+        return;
+    };
+    let mut volumes: Vec<_> = read_dir
+        .filter_map(|r| r.ok())
+        .map(|r| r.path())
+        .filter(|p| p.join("_data").is_dir())
+        .collect();
+    volumes.sort();
+    for volume in volumes {
+        let Some(volume_name) = volume.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        found.push(DiscoveredSource {
+            application: "Docker volume",
+            note: Some("stop or quiesce the containers using this volume for consistency"),
+            source: source(
+                format!("docker_volume_{volume_name}"),
+                None,
+                glob_source(
+                    volume.join("_data"),
+                    &Path::new("docker/volumes").join(volume_name),
+                    &[],
+                ),
+            ),
+        });
+    }
+}
+
+/// Scan `root` for known application data layouts.
+pub fn discover<P: AsRef<Path>>(root: P) -> Vec<DiscoveredSource> {
+    let root = root.as_ref();
+    let mut found = Vec::new();
+    discover_vaultwarden(root, &mut found);
+    discover_gitea(root, &mut found);
+    discover_jellyfin(root, &mut found);
+    discover_postgresql(root, &mut found);
+    discover_docker_volumes(root, &mut found);
+    found
+}
+
+/// Render the discovered sources as a `files:` config snippet, for review before use.
+pub fn to_config_snippet(discovered: &[DiscoveredSource]) -> Result<String> {
+    let mut snippet = String::from("files:\n");
+    for d in discovered {
+        let _ = match d.note {
+            None => writeln!(snippet, "  # {}", d.application),
+            Some(note) => writeln!(snippet, "  # {}: {note}", d.application),
+        };
+        let yaml = serde_yml::to_string(&d.source)?;
+        for (idx, line) in yaml.lines().enumerate() {
+            let prefix = if idx == 0 { "  - " } else { "    " };
+            let _ = writeln!(snippet, "{prefix}{line}");
+        }
+    }
+    Ok(snippet)
+}
@@ -0,0 +1,93 @@
+use crate::backup::humanize::HumanSize;
+use crate::backup::result_error::result::Result;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::io::ErrorKind;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+static DEFAULT_FACTOR: f64 = 1.5;
+
+/// Check out_dir and the temp dir have room for the backup before starting it, failing right
+/// away instead of running out of space halfway through the archive.
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct DiskSpaceCheckConfig {
+    /// How the size of the archive is estimated, `last_archive` by default.
+    pub estimate: Option<SpaceEstimate>,
+    /// Multiplier of the estimate leaving room for growth, 1.5 by default.
+    pub factor: Option<f64>,
+}
+
+impl DiskSpaceCheckConfig {
+    pub fn factor(&self) -> f64 {
+        self.factor.unwrap_or(DEFAULT_FACTOR)
+    }
+
+    /// `size` with the headroom of `factor`.
+    pub fn required(&self, size: u64) -> u64 {
+        (size as f64 * self.factor()).ceil() as u64
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SpaceEstimate {
+    /// Size of the newest archive, the size of the sources until there is one.
+    #[default]
+    LastArchive,
+    /// Sum of the sizes of the source files, for sources that compress poorly or grow fast.
+    Sources,
+}
+
+/// Free space for unprivileged users on the filesystem holding `path`, or its nearest existing
+/// ancestor.
+pub fn available_space<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let path = existing_ancestor(path.as_ref());
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is a NUL terminated string and `stat` is written on success
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        Err(std::io::Error::last_os_error())?
+    }
+    let stat = unsafe { stat.assume_init() };
+    // Not u64 on every platform
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Fail unless every dir of `needs` has the bytes it needs free, dirs on the same filesystem
+/// adding up.
+pub fn check_free_space(needs: &[(PathBuf, u64)]) -> Result<()> {
+    let mut by_device: BTreeMap<u64, (Vec<&Path>, u64)> = BTreeMap::new();
+    for (dir, bytes) in needs.iter().filter(|(_, bytes)| *bytes > 0) {
+        let device = std::fs::metadata(existing_ancestor(dir))?.dev();
+        let (dirs, total) = by_device.entry(device).or_default();
+        dirs.push(dir);
+        *total += bytes;
+    }
+    for (dirs, required) in by_device.into_values() {
+        let available = available_space(dirs[0])?;
+        if available < required {
+            Err(std::io::Error::new(
+                ErrorKind::StorageFull,
+                format!(
+                    "not enough free space for the backup in {}: {} available, about {} needed",
+                    dirs.iter().map(|dir| format!("{dir:?}")).join(" and "),
+                    HumanSize(available),
+                    HumanSize(required)
+                ),
+            ))?
+        }
+    }
+    Ok(())
+}
+
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors().find(|p| p.exists()).unwrap_or(path)
+}
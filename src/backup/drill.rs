@@ -0,0 +1,95 @@
+#[cfg(feature = "sqlite")]
+use crate::backup::archive::sqlite::verify_restored;
+use crate::backup::hook::{CommandHook, DEFAULT_TIMEOUT};
+use crate::backup::restore::{
+    detect_pipeline, extract, open_archive, OwnerSpec, RestoreOptions, SecretSource,
+};
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::{convert_error_vec, Result};
+use crate::backup::result_error::WithMsg;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+#[cfg(feature = "sqlite")]
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Scheduled restore of the newest archive into a scratch directory, followed by user checks
+/// of the restored files. Results are sent to the notifications.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RestoreDrillConfig {
+    pub cron: Arc<str>,
+    /// Directory a fresh `drill-*` directory is restored into for every drill, the state dir by
+    /// default.
+    pub scratch_dir: Option<Arc<Path>>,
+    /// Commands run inside the restored directory that must all succeed, e.g. `sqlite3 app.db
+    /// "PRAGMA integrity_check"`.
+    pub checks: Option<Vec<CommandHook>>,
+    /// Age identity files for archives encrypted to recipients.
+    pub identity_files: Option<Vec<Arc<Path>>>,
+    /// Leave the restored files in place after the drill for inspection.
+    pub keep: Option<bool>,
+    /// Run `PRAGMA integrity_check` and count the table rows of the restored databases of
+    /// sqlite sources, the drill fails on integrity errors. On by default.
+    pub sqlite_checks: Option<bool>,
+}
+
+impl RestoreDrillConfig {
+    /// Restore `archive` into `target` owned by this process, then run every check in it. The
+    /// databases restored at `databases`, relative to `target`, are checked too, returning a
+    /// line of results for each.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    pub fn run<S: SecretSource>(
+        &self,
+        archive: &Path,
+        target: &Path,
+        secrets: &S,
+        databases: &[&Path],
+    ) -> Result<Vec<Arc<str>>> {
+        let process = std::fs::metadata("/proc/self")?;
+        let options = RestoreOptions {
+            chown: Some(OwnerSpec {
+                uid: Some(process.uid()),
+                gid: Some(process.gid()),
+            }),
+            ..Default::default()
+        };
+        let pipeline = detect_pipeline(archive)?;
+        let tar = open_archive(archive, &pipeline, secrets).with_msg("Open archive failed")?;
+        extract(tar, target, &options).with_msg("Restore failed")?;
+
+        #[cfg_attr(not(feature = "sqlite"), allow(unused_mut))]
+        let mut results = Vec::new();
+        let mut errors = Vec::new();
+        #[cfg(feature = "sqlite")]
+        for database in databases
+            .iter()
+            .filter(|_| self.sqlite_checks.unwrap_or(true))
+        {
+            let path = target.join(database.strip_prefix("/").unwrap_or(database));
+            match verify_restored(&path) {
+                Ok(verification) if verification.is_ok() => {
+                    results.push(format!("{database:?} {verification}").into())
+                }
+                Ok(verification) => errors.push(Error::from(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("restored database {database:?} failed checks: {verification}"),
+                ))),
+                Err(e) => errors.push(e.with_msg(format!("Check of database {database:?} failed"))),
+            }
+        }
+        errors.extend(self.checks.iter().flatten().filter_map(|check| {
+            check
+                .to_command()
+                .current_dir(target)
+                .spawn()
+                .map_err(Error::from)
+                .and_then(|mut child| check.wait(&mut child, DEFAULT_TIMEOUT))
+                .err()
+                .map(|e| e.with_msg(format!("Check {check:?} failed")))
+        }));
+        convert_error_vec(errors).map(|_| results)
+    }
+}
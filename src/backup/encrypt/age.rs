@@ -1,27 +1,86 @@
-use crate::backup::encrypt::{Encryptor, EncryptorBuilder};
+use crate::backup::encrypt::scrypt::{
+    ScryptIdentity, ScryptRecipient, MAX_SCRYPT_WORK_FACTOR, MIN_SCRYPT_WORK_FACTOR,
+    SCRYPT_STANZA_TAG,
+};
+use crate::backup::encrypt::ssh::{SshEd25519Identity, SshEd25519Recipient};
+use crate::backup::encrypt::threshold::{ThresholdIdentity, ThresholdRecipient};
+use crate::backup::encrypt::{Decryptor, DecryptorBuilder, Encryptor, EncryptorBuilder};
+use crate::backup::result_error::error::Error;
 use crate::backup::result_error::result::Result;
-use age::EncryptError;
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::secrecy::SecretString;
+use age::stream::{StreamReader, StreamWriter};
+use age::{x25519, DecryptError, EncryptError};
+use age_core::format::{FileKey, Stanza};
 use derive_more::From;
 use secrecy::{CloneableSecret, DebugSecret, ExposeSecret, Secret, SerializableSecret, Zeroize};
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_with::skip_serializing_none;
+use std::cell::{OnceCell, RefCell};
 use std::fmt::{Debug, Formatter};
-use std::io::Write;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
 use std::result;
-use validator::{Validate, ValidationErrors};
+use std::sync::Arc;
+use validator::{Validate, ValidationError, ValidationErrors};
 
 static REDACTED_PASSPHRASE: &str = "###REDACTED_PASSPHRASE###";
 
+/// Age output, armored or binary.
+pub type AgeStreamWriter<W> = StreamWriter<ArmoredWriter<W>>;
+/// Age input, armor is detected and removed transparently.
+pub type AgeStreamReader<R> = StreamReader<ArmoredReader<BufReader<R>>>;
+
+#[skip_serializing_none]
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct AgeEncryptorConfig {
+    #[serde(flatten)]
+    pub secret: AgeSecretConfig,
+    /// Write ASCII armored (PEM-like) output, for destinations mangling binary data.
+    pub armor: Option<bool>,
+}
+
 #[derive(From, Clone, Deserialize, Serialize, Debug)]
 #[serde(tag = "secret_type")]
 #[serde(rename_all = "snake_case")]
-pub enum AgeEncryptorConfig {
-    Passphrase { passphrase: Secret<RedactedString> },
+pub enum AgeSecretConfig {
+    Passphrase {
+        passphrase: Secret<RedactedString>,
+        /// log2 of the scrypt cost, 14 to 20. Each step doubles the time and memory to derive
+        /// the key, 20 takes 1 GiB. Default picks one taking about a second on this machine,
+        /// which may be too slow for frequent backups on small boards.
+        ///
+        /// Setting it drops compatibility with the age CLI: the key is wrapped in a custom
+        /// `k-backup-scrypt` stanza that only `k_backup restore` reads.
+        work_factor: Option<u8>,
+    },
+    /// Encrypt to several recipients, any one of them can decrypt. Recipients are age X25519
+    /// public keys (`age1...`) or SSH public keys (`ssh-ed25519 AAAA... comment`), taken from
+    /// `recipients` and `recipients_file`.
+    Recipients {
+        #[serde(default)]
+        recipients: Vec<Arc<str>>,
+        /// File of recipients, one per line with blank lines and `#` comments skipped, e.g. the
+        /// output of `age-keygen -y`. Read again at each backup, so keys rotated by configuration
+        /// management are used without restarting the daemon.
+        recipients_file: Option<Arc<Path>>,
+        /// Age identity files or unencrypted OpenSSH private keys used for decrypting, never
+        /// needed for creating backups.
+        identity_files: Option<Vec<Arc<Path>>>,
+    },
+    /// Encrypt so any `threshold` of the X25519 `recipients` together can decrypt.
+    Threshold {
+        threshold: u8,
+        recipients: Vec<Arc<str>>,
+        /// Identities of the key holders present, used for decrypting.
+        identity_files: Option<Vec<Arc<Path>>>,
+    },
 }
 
 #[derive(Validate, Clone, From)]
 pub struct RedactedString {
-    #[validate(length(min = 8))]
+    #[validate(length(min = 8, message = "passphrase must be at least 8 characters"))]
     inner: String,
 }
 
@@ -70,28 +129,384 @@ impl SerializableSecret for RedactedString {}
 impl DebugSecret for RedactedString {}
 impl CloneableSecret for RedactedString {}
 
-impl<W: Write> EncryptorBuilder<W> for AgeEncryptorConfig {
-    fn build_encryptor(&self, writer: W) -> Result<Encryptor<W>> {
+fn parse_recipients(recipients: &[Arc<str>]) -> result::Result<Vec<x25519::Recipient>, String> {
+    recipients
+        .iter()
+        .map(|r| {
+            r.parse::<x25519::Recipient>()
+                .map_err(|e| format!("invalid age recipient {r:?}: {e}"))
+        })
+        .collect()
+}
+
+/// Recipients listed in `recipients_file`, one per line.
+fn read_recipients_file(recipients_file: &Path) -> result::Result<Vec<Arc<str>>, String> {
+    let content = std::fs::read_to_string(recipients_file)
+        .map_err(|e| format!("failed to read recipients_file {recipients_file:?}: {e}"))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Into::into)
+        .collect())
+}
+
+/// Any recipient accepted in `Recipients`, age X25519 or SSH public keys.
+fn parse_any_recipients(
+    recipients: &[Arc<str>],
+) -> result::Result<Vec<Box<dyn age::Recipient + Send>>, String> {
+    recipients
+        .iter()
+        .map(
+            |r| -> result::Result<Box<dyn age::Recipient + Send>, String> {
+                if r.starts_with("ssh-") {
+                    Ok(Box::new(r.parse::<SshEd25519Recipient>().map_err(|e| {
+                        format!("invalid SSH recipient {r:?}: {e}")
+                    })?))
+                } else {
+                    Ok(Box::new(r.parse::<x25519::Recipient>().map_err(|e| {
+                        format!("invalid age recipient {r:?}: {e}")
+                    })?))
+                }
+            },
+        )
+        .collect()
+}
+
+/// Identity decrypting files encrypted to an X25519 or SSH recipient.
+#[derive(Clone, From)]
+pub enum AgeIdentity {
+    X25519(x25519::Identity),
+    SshEd25519(SshEd25519Identity),
+}
+
+impl age::Identity for AgeIdentity {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<result::Result<FileKey, DecryptError>> {
         match self {
-            AgeEncryptorConfig::Passphrase { passphrase } => {
-                Ok(age::Encryptor::with_user_passphrase(
-                    passphrase.expose_secret().inner.clone().into(),
+            AgeIdentity::X25519(identity) => identity.unwrap_stanza(stanza),
+            AgeIdentity::SshEd25519(identity) => identity.unwrap_stanza(stanza),
+        }
+    }
+}
+
+/// Read identities from age identity files, skipping comments and blank lines, or from
+/// unencrypted OpenSSH private key files.
+pub fn read_identities<P: AsRef<Path>>(identity_files: &[P]) -> Result<Vec<AgeIdentity>> {
+    let mut identities = Vec::new();
+    for path in identity_files.iter().map(AsRef::as_ref) {
+        let content = std::fs::read_to_string(path)?;
+        if SshEd25519Identity::is_openssh_private_key(&content) {
+            identities.push(
+                content
+                    .parse::<SshEd25519Identity>()
+                    .map_err(|e| {
+                        std::io::Error::new(ErrorKind::InvalidData, format!("{path:?}: {e}"))
+                    })?
+                    .into(),
+            );
+            continue;
+        }
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            identities.push(
+                line.parse::<x25519::Identity>()
+                    .map_err(|e| {
+                        std::io::Error::new(ErrorKind::InvalidData, format!("{path:?}: {e}"))
+                    })?
+                    .into(),
+            );
+        }
+    }
+    if identities.is_empty() {
+        Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "no age identity configured in identity_files",
+        ))?
+    }
+    Ok(identities)
+}
+
+impl AgeSecretConfig {
+    /// Recipients encrypted to, with those of `recipients_file` as it reads now. Empty with a
+    /// passphrase.
+    pub fn recipients(&self) -> result::Result<Vec<Arc<str>>, String> {
+        match self {
+            AgeSecretConfig::Passphrase { .. } => Ok(Vec::new()),
+            AgeSecretConfig::Recipients {
+                recipients,
+                recipients_file,
+                ..
+            } => {
+                let mut recipients = recipients.clone();
+                if let Some(recipients_file) = recipients_file {
+                    recipients.extend(read_recipients_file(recipients_file)?);
+                }
+                Ok(recipients)
+            }
+            AgeSecretConfig::Threshold { recipients, .. } => Ok(recipients.clone()),
+        }
+    }
+
+    fn build_age_encryptor(&self) -> result::Result<age::Encryptor, String> {
+        match self {
+            AgeSecretConfig::Passphrase {
+                passphrase,
+                work_factor: None,
+            } => Ok(age::Encryptor::with_user_passphrase(
+                passphrase.expose_secret().inner.clone().into(),
+            )),
+            AgeSecretConfig::Passphrase {
+                passphrase,
+                work_factor: Some(work_factor),
+            } => Ok(
+                age::Encryptor::with_recipients(vec![Box::new(ScryptRecipient {
+                    passphrase: Secret::new(passphrase.expose_secret().inner.clone()),
+                    work_factor: *work_factor,
+                })])
+                .unwrap(),
+            ),
+            AgeSecretConfig::Recipients { .. } => {
+                age::Encryptor::with_recipients(parse_any_recipients(&self.recipients()?)?)
+                    .ok_or_else(|| "no age recipient configured".to_string())
+            }
+            AgeSecretConfig::Threshold {
+                threshold,
+                recipients,
+                ..
+            } => {
+                let recipients = parse_recipients(recipients)?;
+                if recipients.len() > u8::MAX as usize {
+                    return Err(format!("at most {} recipients are supported", u8::MAX));
+                }
+                if *threshold == 0 || *threshold as usize > recipients.len() {
+                    return Err(format!(
+                        "threshold must be between 1 and the {} recipients",
+                        recipients.len()
+                    ));
+                }
+                Ok(
+                    age::Encryptor::with_recipients(vec![Box::new(ThresholdRecipient {
+                        threshold: *threshold,
+                        recipients,
+                    })])
+                    .unwrap(),
                 )
-                .wrap_output(writer)
-                .map_err(|e| match e {
-                    EncryptError::Io(e) => e,
-                    _ => panic!("Unexpected or supported error occurred: {e}"),
-                })?
-                .into())
             }
         }
     }
 }
 
+impl AgeEncryptorConfig {
+    pub fn is_armored(&self) -> bool {
+        self.armor.unwrap_or(false)
+    }
+
+    /// Configured passphrase, `None` when encrypting to recipients.
+    pub fn passphrase(&self) -> Option<SecretString> {
+        match &self.secret {
+            AgeSecretConfig::Passphrase { passphrase, .. } => {
+                Some(Secret::new(passphrase.expose_secret().inner.clone()))
+            }
+            AgeSecretConfig::Recipients { .. } | AgeSecretConfig::Threshold { .. } => None,
+        }
+    }
+
+    /// Configured identity files used for decrypting, empty with a passphrase.
+    pub fn identity_files(&self) -> &[Arc<Path>] {
+        match &self.secret {
+            AgeSecretConfig::Passphrase { .. } => &[],
+            AgeSecretConfig::Recipients { identity_files, .. }
+            | AgeSecretConfig::Threshold { identity_files, .. } => {
+                identity_files.as_deref().unwrap_or_default()
+            }
+        }
+    }
+}
+
+impl<W: Write> EncryptorBuilder<W> for AgeEncryptorConfig {
+    fn build_encryptor(&self, writer: W) -> Result<Encryptor<W>> {
+        let format = match self.is_armored() {
+            true => Format::AsciiArmor,
+            false => Format::Binary,
+        };
+        Ok(self
+            .secret
+            .build_age_encryptor()
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?
+            .wrap_output(ArmoredWriter::wrap_output(writer, format)?)
+            .map_err(|e| match e {
+                EncryptError::Io(e) => e,
+                _ => panic!("Unexpected or supported error occurred: {e}"),
+            })?
+            .into())
+    }
+}
+
+/// Decrypt age `reader` with the secret its header asks for, `passphrase` or `identities` is
+/// only called when needed. Identities are tried alone and as threshold key holders.
+pub fn decrypt_age<R, P, I>(reader: R, passphrase: P, identities: I) -> Result<AgeStreamReader<R>>
+where
+    R: Read,
+    P: FnOnce() -> Result<SecretString>,
+    I: FnOnce() -> Result<Vec<AgeIdentity>>,
+{
+    match age::Decryptor::new(ArmoredReader::new(reader))? {
+        age::Decryptor::Passphrase(decryptor) => Ok(decryptor.decrypt(&passphrase()?, None)?),
+        age::Decryptor::Recipients(decryptor) => {
+            let passphrase = LazyIdentities::new(
+                |s| s.tag == SCRYPT_STANZA_TAG,
+                || {
+                    let identity = ScryptIdentity {
+                        passphrase: passphrase()?,
+                    };
+                    Ok(vec![Box::new(identity) as Box<dyn age::Identity>])
+                },
+            );
+            let keys = LazyIdentities::new(
+                |s| s.tag != SCRYPT_STANZA_TAG,
+                || {
+                    let identities = identities()?;
+                    let threshold_identity = ThresholdIdentity {
+                        identities: identities.clone(),
+                    };
+                    Ok(
+                        std::iter::once(Box::new(threshold_identity) as Box<dyn age::Identity>)
+                            .chain(
+                                identities
+                                    .into_iter()
+                                    .map(|i| Box::new(i) as Box<dyn age::Identity>),
+                            )
+                            .collect(),
+                    )
+                },
+            );
+            let result = decryptor.decrypt([&passphrase as &dyn age::Identity, &keys].into_iter());
+            match result {
+                Ok(reader) => Ok(reader),
+                Err(e) => Err(passphrase
+                    .into_error()
+                    .or_else(|| keys.into_error())
+                    .unwrap_or_else(|| e.into())),
+            }
+        }
+    }
+}
+
+type BoxedIdentities = Vec<Box<dyn age::Identity>>;
+
+/// Identities made by `build` when a header has a stanza they are `for_stanza`, so secrets are
+/// only read or asked for when needed. A build error is kept to be reported instead of the
+/// decryption error.
+struct LazyIdentities<F> {
+    for_stanza: fn(&Stanza) -> bool,
+    build: RefCell<Option<F>>,
+    built: OnceCell<Result<BoxedIdentities>>,
+}
+
+impl<F: FnOnce() -> Result<BoxedIdentities>> LazyIdentities<F> {
+    fn new(for_stanza: fn(&Stanza) -> bool, build: F) -> Self {
+        Self {
+            for_stanza,
+            build: RefCell::new(Some(build)),
+            built: OnceCell::new(),
+        }
+    }
+
+    fn into_error(self) -> Option<Error> {
+        self.built.into_inner()?.err()
+    }
+}
+
+impl<F: FnOnce() -> Result<BoxedIdentities>> age::Identity for LazyIdentities<F> {
+    fn unwrap_stanza(&self, _stanza: &Stanza) -> Option<result::Result<FileKey, DecryptError>> {
+        None
+    }
+
+    fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<result::Result<FileKey, DecryptError>> {
+        if !stanzas.iter().any(self.for_stanza) {
+            return None;
+        }
+        let built = self
+            .built
+            .get_or_init(|| (self.build.borrow_mut().take().unwrap())());
+        match built {
+            Ok(identities) => identities.iter().find_map(|i| i.unwrap_stanzas(stanzas)),
+            Err(_) => Some(Err(DecryptError::NoMatchingKeys)),
+        }
+    }
+}
+
+impl<R: Read> DecryptorBuilder<R> for AgeEncryptorConfig {
+    fn build_decryptor(&self, reader: R) -> Result<Decryptor<R>> {
+        let stream_reader = match &self.secret {
+            AgeSecretConfig::Passphrase { passphrase, .. } => decrypt_age(
+                reader,
+                || Ok(Secret::new(passphrase.expose_secret().inner.clone())),
+                || {
+                    Err(std::io::Error::other(
+                        "input is encrypted to recipients, not a passphrase",
+                    ))?
+                },
+            ),
+            AgeSecretConfig::Recipients { identity_files, .. }
+            | AgeSecretConfig::Threshold { identity_files, .. } => decrypt_age(
+                reader,
+                || {
+                    Err(std::io::Error::other(
+                        "input is encrypted to a passphrase, not recipients",
+                    ))?
+                },
+                || read_identities(identity_files.as_deref().unwrap_or_default()),
+            ),
+        }?;
+        Ok(Box::new(stream_reader).into())
+    }
+}
+
 impl Validate for AgeEncryptorConfig {
+    fn validate(&self) -> result::Result<(), ValidationErrors> {
+        self.secret.validate()
+    }
+}
+
+impl Validate for AgeSecretConfig {
     fn validate(&self) -> result::Result<(), ValidationErrors> {
         match self {
-            AgeEncryptorConfig::Passphrase { passphrase } => passphrase.expose_secret().validate(),
+            AgeSecretConfig::Passphrase {
+                passphrase,
+                work_factor,
+            } => {
+                let mut result = passphrase.expose_secret().validate();
+                if let Some(work_factor) = work_factor
+                    .filter(|w| !(MIN_SCRYPT_WORK_FACTOR..=MAX_SCRYPT_WORK_FACTOR).contains(w))
+                {
+                    let mut errors = result.err().unwrap_or_default();
+                    errors.add(
+                        "work_factor",
+                        ValidationError::new("InvalidWorkFactor").with_message(
+                            format!(
+                                "work_factor {work_factor} is not between \
+                                 {MIN_SCRYPT_WORK_FACTOR} and {MAX_SCRYPT_WORK_FACTOR}"
+                            )
+                            .into(),
+                        ),
+                    );
+                    result = Err(errors);
+                }
+                result
+            }
+            AgeSecretConfig::Recipients { .. } | AgeSecretConfig::Threshold { .. } => {
+                self.build_age_encryptor().map(|_| ()).map_err(|e| {
+                    let mut errors = ValidationErrors::new();
+                    errors.add(
+                        "recipients",
+                        ValidationError::new("InvalidRecipients").with_message(e.into()),
+                    );
+                    errors
+                })
+            }
         }
     }
 }
@@ -0,0 +1,308 @@
+use crate::backup::encrypt::shamir::{combine, split};
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use age::secrecy::{ExposeSecret, SecretString};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::str::FromStr;
+
+static SHARE_PREFIX: &str = "KBACKUP-S1";
+static BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+static SET_ID_LEN: usize = 5;
+static CHECKSUM_LEN: usize = 4;
+
+/// One of the shares a passphrase is split into, any `threshold` shares of the same set recover
+/// it. Written as `KBACKUP-S1-<set>-<threshold>-<index>-<data>` in uppercase base32, so it can
+/// be typed back and fits the alphanumeric mode of QR codes, e.g. `qrencode -i`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PassphraseShare {
+    /// Random id of the split, shares of different splits cannot be combined.
+    pub set_id: [u8; SET_ID_LEN],
+    pub threshold: u8,
+    pub index: u8,
+    pub data: Vec<u8>,
+}
+
+impl PassphraseShare {
+    /// Catches typos when the share is typed back.
+    fn checksum(&self) -> [u8; CHECKSUM_LEN] {
+        let digest = Sha256::new()
+            .chain_update(SHARE_PREFIX)
+            .chain_update(self.set_id)
+            .chain_update([self.threshold, self.index])
+            .chain_update(&self.data)
+            .finalize();
+        digest[..CHECKSUM_LEN].try_into().unwrap()
+    }
+}
+
+impl Display for PassphraseShare {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut data = self.data.clone();
+        data.extend_from_slice(&self.checksum());
+        write!(
+            f,
+            "{SHARE_PREFIX}-{}-{}-{}-{}",
+            base32_encode(&self.set_id),
+            self.threshold,
+            self.index,
+            base32_encode(&data)
+        )
+    }
+}
+
+impl FromStr for PassphraseShare {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| -> Error {
+            std::io::Error::new(ErrorKind::InvalidInput, format!("invalid share: {reason}")).into()
+        };
+        let s: String = s
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_uppercase();
+        let rest = s
+            .strip_prefix(SHARE_PREFIX)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .ok_or_else(|| invalid(&format!("does not start with {SHARE_PREFIX}")))?;
+        let [set_id, threshold, index, data] = rest.split('-').collect::<Vec<_>>()[..] else {
+            return Err(invalid("expected 4 fields after the prefix"));
+        };
+        let set_id = base32_decode(set_id)
+            .and_then(|id| <[u8; SET_ID_LEN]>::try_from(id).ok())
+            .ok_or_else(|| invalid("bad set id"))?;
+        let threshold = threshold
+            .parse()
+            .ok()
+            .filter(|t| *t != 0)
+            .ok_or_else(|| invalid("bad threshold"))?;
+        // Index 0 would be the passphrase itself
+        let index = index
+            .parse()
+            .ok()
+            .filter(|i| *i != 0)
+            .ok_or_else(|| invalid("bad index"))?;
+        let mut data = base32_decode(data)
+            .filter(|d| d.len() > CHECKSUM_LEN)
+            .ok_or_else(|| invalid("bad data"))?;
+        let checksum = data.split_off(data.len() - CHECKSUM_LEN);
+        let share = Self {
+            set_id,
+            threshold,
+            index,
+            data,
+        };
+        if share.checksum()[..] != checksum[..] {
+            return Err(invalid("checksum mismatch, check for typos"));
+        }
+        Ok(share)
+    }
+}
+
+/// Split `passphrase` into `shares` shares of which any `threshold` recover it.
+pub fn split_passphrase(
+    passphrase: &SecretString,
+    threshold: u8,
+    shares: u8,
+) -> Result<Vec<PassphraseShare>> {
+    if threshold == 0 || threshold > shares {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("threshold must be between 1 and the {shares} shares"),
+        )
+        .into());
+    }
+    let mut set_id = [0u8; SET_ID_LEN];
+    OsRng.fill_bytes(&mut set_id);
+    Ok(
+        split(passphrase.expose_secret().as_bytes(), threshold, shares)
+            .into_iter()
+            .map(|(index, data)| PassphraseShare {
+                set_id,
+                threshold,
+                index,
+                data,
+            })
+            .collect(),
+    )
+}
+
+/// Passphrase recovered from `shares` of a single split, at least its threshold of them.
+pub fn combine_shares(shares: &[PassphraseShare]) -> Result<SecretString> {
+    let invalid =
+        |message: String| -> Error { std::io::Error::new(ErrorKind::InvalidInput, message).into() };
+    let first = shares
+        .first()
+        .ok_or_else(|| invalid("no share given".to_string()))?;
+    let mut distinct: Vec<&PassphraseShare> = Vec::new();
+    for share in shares {
+        if share.set_id != first.set_id || share.threshold != first.threshold {
+            return Err(invalid(format!(
+                "share {} belongs to another split",
+                share.index
+            )));
+        }
+        if share.data.len() != first.data.len() {
+            return Err(invalid(format!(
+                "share {} has a different length",
+                share.index
+            )));
+        }
+        if !distinct.iter().any(|s| s.index == share.index) {
+            distinct.push(share);
+        }
+    }
+    if distinct.len() < first.threshold as usize {
+        return Err(invalid(format!(
+            "{} distinct shares given, {} are needed",
+            distinct.len(),
+            first.threshold
+        )));
+    }
+    let points = distinct
+        .iter()
+        .take(first.threshold as usize)
+        .map(|s| (s.index, s.data.clone()))
+        .collect::<Vec<_>>();
+    String::from_utf8(combine(&points))
+        .map(SecretString::new)
+        .map_err(|_| invalid("recovered passphrase is not valid UTF-8".to_string()))
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in data {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1F) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1F) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    // Padding bits are zero, a typo in the last character must not go unnoticed
+    (buffer & ((1 << bits) - 1) == 0).then_some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shares 1 and 2 of `k-backup` split 2 of n with the coefficient 0x5a for every byte.
+    static FIXED_SHARES: [&str; 2] = [
+        "KBACKUP-S1-NNRGCY3L-2-1-GF3TQOZZGEXSVFD5DKIA",
+        "KBACKUP-S1-NNRGCY3L-2-2-36M5NVOX37A4IUI4542A",
+    ];
+
+    fn parse(shares: &[&str]) -> Vec<PassphraseShare> {
+        shares.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn error(share: &str) -> String {
+        share.parse::<PassphraseShare>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn fixed_shares_keep_their_format() {
+        let shares = parse(&FIXED_SHARES);
+        assert_eq!(shares[0].set_id, *b"kback");
+        assert_eq!(shares[0].data, b"k-backup".map(|b| b ^ 0x5a));
+        assert_eq!(shares[1].data, b"k-backup".map(|b| b ^ 0xb4));
+        for (share, text) in shares.iter().zip(FIXED_SHARES) {
+            assert_eq!(share.to_string(), text);
+        }
+        let passphrase = combine_shares(&shares).unwrap();
+        assert_eq!(passphrase.expose_secret(), "k-backup");
+    }
+
+    #[test]
+    fn every_subset_of_threshold_shares_recovers() {
+        let passphrase = SecretString::new("correct horse battery staple".to_string());
+        let shares = split_passphrase(&passphrase, 3, 5).unwrap();
+        for mask in 1..1u32 << shares.len() {
+            let subset = shares
+                .iter()
+                .filter(|share| mask & (1 << (share.index - 1)) != 0)
+                .map(|share| share.to_string().parse().unwrap())
+                .collect::<Vec<PassphraseShare>>();
+            match combine_shares(&subset) {
+                Ok(recovered) if subset.len() >= 3 => {
+                    assert_eq!(recovered.expose_secret(), passphrase.expose_secret())
+                }
+                Err(e) if subset.len() < 3 => {
+                    assert!(e.to_string().contains("are needed"), "{e}")
+                }
+                res => panic!("{mask:b}: {:?}", res.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn typed_back_shares_are_normalized() {
+        let typed = FIXED_SHARES[0].to_lowercase().replace('-', " -");
+        assert_eq!(
+            typed.parse::<PassphraseShare>().unwrap(),
+            parse(&FIXED_SHARES)[0]
+        );
+    }
+
+    #[test]
+    fn typos_are_rejected() {
+        let typo = FIXED_SHARES[0].replacen("GF3T", "GF3U", 1);
+        assert!(error(&typo).contains("checksum mismatch"));
+        let bad_checksum = FIXED_SHARES[0].replacen("DKIA", "DJIA", 1);
+        assert!(error(&bad_checksum).contains("checksum mismatch"));
+        let bad_padding = FIXED_SHARES[0].replacen("DKIA", "DKIB", 1);
+        assert!(error(&bad_padding).contains("bad data"));
+        let not_base32 = FIXED_SHARES[0].replacen("GF3T", "GF1T", 1);
+        assert!(error(&not_base32).contains("bad data"));
+        let wrong_index = FIXED_SHARES[0].replacen("-2-1-", "-2-3-", 1);
+        assert!(error(&wrong_index).contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn zero_threshold_and_index_are_rejected() {
+        let zero_threshold = FIXED_SHARES[0].replacen("-2-1-", "-0-1-", 1);
+        assert!(error(&zero_threshold).contains("bad threshold"));
+        let zero_index = FIXED_SHARES[0].replacen("-2-1-", "-2-0-", 1);
+        assert!(error(&zero_index).contains("bad index"));
+    }
+
+    #[test]
+    fn duplicate_shares_count_once() {
+        let share = parse(&FIXED_SHARES[..1]).remove(0);
+        let e = combine_shares(&[share.clone(), share]).unwrap_err();
+        assert!(e.to_string().contains("1 distinct shares given"), "{e}");
+    }
+
+    #[test]
+    fn shares_of_another_split_are_rejected() {
+        let mut shares = parse(&FIXED_SHARES);
+        shares[1].set_id = *b"other";
+        let e = combine_shares(&shares).unwrap_err();
+        assert!(e.to_string().contains("another split"), "{e}");
+    }
+}
@@ -1,23 +1,45 @@
+#[cfg(feature = "age")]
 pub mod age;
+#[cfg(feature = "age")]
+pub mod key_backup;
+pub mod policy;
+#[cfg(feature = "age")]
+pub mod scrypt;
+#[cfg(feature = "age")]
+pub mod shamir;
+#[cfg(feature = "age")]
+pub mod ssh;
+#[cfg(feature = "age")]
+pub mod threshold;
 
-use crate::backup::encrypt::age::AgeEncryptorConfig;
+#[cfg(feature = "age")]
+use crate::backup::encrypt::age::{AgeEncryptorConfig, AgeStreamReader, AgeStreamWriter};
 use crate::backup::file_ext::FileExtProvider;
 use crate::backup::finish::Finish;
 use crate::backup::result_error::result::Result;
 use crate::backup::result_error::WithDebugObjectAndFnName;
-use ::age::stream::StreamWriter;
 use derive_more::From;
-use io_enum::Write;
+use io_enum::{Read, Write};
 use serde::{Deserialize, Serialize};
-use std::io::{Error, Write};
+use std::io::{Error, Read, Write};
 use std::result;
-use std::sync::{Arc, OnceLock};
+use std::sync::Arc;
+#[cfg(feature = "age")]
+use std::sync::OnceLock;
 use validator::{Validate, ValidationErrors};
 
 #[derive(Write, From)]
 pub enum Encryptor<W: Write> {
     None(W),
-    AgeEncryptor(StreamWriter<W>),
+    #[cfg(feature = "age")]
+    AgeEncryptor(AgeStreamWriter<W>),
+}
+
+#[derive(Read, From)]
+pub enum Decryptor<R: Read> {
+    None(R),
+    #[cfg(feature = "age")]
+    AgeDecryptor(Box<AgeStreamReader<R>>),
 }
 
 #[derive(Clone, Default, From, Serialize, Deserialize, Debug)]
@@ -26,6 +48,7 @@ pub enum Encryptor<W: Write> {
 pub enum EncryptorConfig {
     #[default]
     None,
+    #[cfg(feature = "age")]
     Age(AgeEncryptorConfig),
 }
 
@@ -33,6 +56,7 @@ impl Validate for EncryptorConfig {
     fn validate(&self) -> result::Result<(), ValidationErrors> {
         match self {
             EncryptorConfig::None => Ok(()),
+            #[cfg(feature = "age")]
             EncryptorConfig::Age(inner) => inner.validate(),
         }
     }
@@ -42,11 +66,16 @@ pub trait EncryptorBuilder<W: Write> {
     fn build_encryptor(&self, writer: W) -> Result<Encryptor<W>>;
 }
 
+pub trait DecryptorBuilder<R: Read> {
+    fn build_decryptor(&self, reader: R) -> Result<Decryptor<R>>;
+}
+
 impl<W: Write> Finish<W> for Encryptor<W> {
     fn finish(self) -> result::Result<W, Error> {
         match self {
             Encryptor::None(w) => Ok(w),
-            Encryptor::AgeEncryptor(w) => w.finish(),
+            #[cfg(feature = "age")]
+            Encryptor::AgeEncryptor(w) => w.finish()?.finish(),
         }
     }
 }
@@ -55,17 +84,39 @@ impl<W: Write> EncryptorBuilder<W> for EncryptorConfig {
     fn build_encryptor(&self, writer: W) -> Result<Encryptor<W>> {
         match self {
             EncryptorConfig::None => Ok(writer.into()),
+            #[cfg(feature = "age")]
             EncryptorConfig::Age(age) => age.build_encryptor(writer),
         }
         .with_debug_object_and_fn_name(self.clone(), "build_encryptor")
     }
 }
 
+impl<R: Read> DecryptorBuilder<R> for EncryptorConfig {
+    fn build_decryptor(&self, reader: R) -> Result<Decryptor<R>> {
+        match self {
+            EncryptorConfig::None => Ok(reader.into()),
+            #[cfg(feature = "age")]
+            EncryptorConfig::Age(age) => age.build_decryptor(reader),
+        }
+        .with_debug_object_and_fn_name(self.clone(), "build_decryptor")
+    }
+}
+
+#[cfg(feature = "age")]
 static AGE_FILE_EXT: OnceLock<Arc<str>> = OnceLock::new();
+#[cfg(feature = "age")]
+static ARMORED_AGE_FILE_EXT: OnceLock<Arc<str>> = OnceLock::new();
 impl FileExtProvider for EncryptorConfig {
     fn file_ext(&self) -> Option<Arc<str>> {
         match self {
             EncryptorConfig::None => None,
+            #[cfg(feature = "age")]
+            EncryptorConfig::Age(age) if age.is_armored() => Some(
+                ARMORED_AGE_FILE_EXT
+                    .get_or_init(|| "age.asc".into())
+                    .clone(),
+            ),
+            #[cfg(feature = "age")]
             EncryptorConfig::Age(_) => Some(AGE_FILE_EXT.get_or_init(|| "age".into()).clone()),
         }
     }
@@ -0,0 +1,196 @@
+#[cfg(feature = "age")]
+use crate::backup::encrypt::age::AgeSecretConfig;
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::inspect::AgeHeader;
+use crate::backup::result_error::result::Result;
+use crate::backup::volume::open_archive_file;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Bytes read from the start of an archive for its age header.
+static HEADER_PEEK_LEN: u64 = 64 * 1024;
+static AGE_PAYLOAD: &str = "ChaCha20-Poly1305 STREAM in 64 KiB chunks, key from HKDF-SHA-256";
+static AGE_HEADER_MAC: &str = "HMAC-SHA-256, key from HKDF-SHA-256";
+static KEY_WRAP: &str = "ChaCha20-Poly1305";
+/// Algorithms of stanzas of types this crate does not know, e.g. from age plugins.
+static UNKNOWN: &str = "unknown";
+
+/// Algorithms archives may be encrypted with.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CryptoPolicy {
+    /// Anything the config sets up, including no encryption.
+    #[default]
+    Default,
+    /// Only the primitives of the age v1 specification: X25519 and ssh-ed25519 recipients or
+    /// scrypt passphrases, with HKDF-SHA-256, ChaCha20-Poly1305 and HMAC-SHA-256. Archives and
+    /// the copies for destinations must be encrypted, threshold encryption is not allowed.
+    Strict,
+}
+
+impl CryptoPolicy {
+    /// Policy in force with `configured`, always strict when built with the `strict-crypto`
+    /// feature.
+    pub fn effective(configured: Option<CryptoPolicy>) -> CryptoPolicy {
+        match cfg!(feature = "strict-crypto") {
+            true => CryptoPolicy::Strict,
+            false => configured.unwrap_or_default(),
+        }
+    }
+
+    /// Why `encryptor`, named `name` in the config, is not allowed by the policy.
+    pub fn violation(&self, name: &str, encryptor: &EncryptorConfig) -> Option<String> {
+        if *self == CryptoPolicy::Default {
+            return None;
+        }
+        match encryptor {
+            EncryptorConfig::None => Some(format!(
+                "{name} is not encrypted, crypto_policy strict requires encryption"
+            )),
+            #[cfg(feature = "age")]
+            EncryptorConfig::Age(age) => match age.secret {
+                AgeSecretConfig::Threshold { .. } => Some(format!(
+                    "{name} uses threshold encryption, which crypto_policy strict does not allow"
+                )),
+                AgeSecretConfig::Passphrase { .. } | AgeSecretConfig::Recipients { .. } => None,
+            },
+        }
+    }
+}
+
+/// Exact cryptographic parameters of an archive, read from its header, for compliance audits.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CryptoParameters {
+    pub policy: CryptoPolicy,
+    /// `age-v1` or `none`.
+    pub encryption: Arc<str>,
+    pub payload: Option<Arc<str>>,
+    pub header_mac: Option<Arc<str>>,
+    /// How the file key is wrapped for each recipient or passphrase, stanzas of unknown types
+    /// are reported with unknown algorithms.
+    pub recipients: Vec<RecipientParameters>,
+}
+
+/// Parameters of one stanza of an age header.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RecipientParameters {
+    pub stanza: Arc<str>,
+    pub key_agreement: Option<Arc<str>>,
+    pub kdf: Arc<str>,
+    /// log2 of the scrypt cost N, with r = 8 and p = 1.
+    pub scrypt_work_factor: Option<u8>,
+    pub key_wrap: Arc<str>,
+}
+
+impl CryptoParameters {
+    /// Parameters of the archive at `archive_path` created under `policy`.
+    pub fn read<P: AsRef<Path>>(archive_path: P, policy: CryptoPolicy) -> Result<Self> {
+        let mut head = Vec::new();
+        open_archive_file(archive_path)?
+            .take(HEADER_PEEK_LEN)
+            .read_to_end(&mut head)?;
+        let Some(header) = AgeHeader::detect(&head) else {
+            return Ok(Self {
+                policy,
+                encryption: "none".into(),
+                payload: None,
+                header_mac: None,
+                recipients: Vec::new(),
+            });
+        };
+        Ok(Self {
+            policy,
+            encryption: "age-v1".into(),
+            payload: Some(AGE_PAYLOAD.into()),
+            header_mac: Some(AGE_HEADER_MAC.into()),
+            recipients: header
+                .stanzas
+                .iter()
+                .map(|stanza| {
+                    let (key_agreement, kdf, key_wrap) = match stanza.tag.as_ref() {
+                        "scrypt" | "k-backup-scrypt" => (None, "scrypt", KEY_WRAP),
+                        "X25519" => (Some("X25519"), "HKDF-SHA-256", KEY_WRAP),
+                        "ssh-ed25519" => (
+                            Some("X25519 from the Ed25519 key"),
+                            "HKDF-SHA-256",
+                            KEY_WRAP,
+                        ),
+                        "k-backup-threshold" => (
+                            Some("X25519, Shamir shares of the file key"),
+                            "HKDF-SHA-256",
+                            KEY_WRAP,
+                        ),
+                        _ => (None, UNKNOWN, UNKNOWN),
+                    };
+                    RecipientParameters {
+                        stanza: stanza.tag.clone(),
+                        key_agreement: key_agreement.map(Into::into),
+                        kdf: kdf.into(),
+                        scrypt_work_factor: matches!(
+                            stanza.tag.as_ref(),
+                            "scrypt" | "k-backup-scrypt"
+                        )
+                        .then(|| stanza.args.get(1).and_then(|n| n.parse().ok()))
+                        .flatten(),
+                        key_wrap: key_wrap.into(),
+                    }
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn stanzas_are_reported_by_type() {
+        let mut archive = tempfile::NamedTempFile::new().unwrap();
+        archive
+            .write_all(
+                b"age-encryption.org/v1\n\
+                  -> X25519 cGs\nYm9keQ\n\
+                  -> scrypt c2FsdA 18\nYm9keQ\n\
+                  -> piv-p256 dGFn cGs\nYm9keQ\n\
+                  -> x-grease\n\n\
+                  --- bWFj\n",
+            )
+            .unwrap();
+        let parameters = CryptoParameters::read(archive.path(), CryptoPolicy::Default).unwrap();
+        assert_eq!(parameters.encryption.as_ref(), "age-v1");
+        let recipients = parameters
+            .recipients
+            .iter()
+            .map(|r| {
+                (
+                    r.stanza.as_ref(),
+                    r.key_agreement.as_deref(),
+                    r.kdf.as_ref(),
+                    r.scrypt_work_factor,
+                    r.key_wrap.as_ref(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recipients,
+            [
+                (
+                    "X25519",
+                    Some("X25519"),
+                    "HKDF-SHA-256",
+                    None,
+                    "ChaCha20-Poly1305"
+                ),
+                ("scrypt", None, "scrypt", Some(18), "ChaCha20-Poly1305"),
+                ("piv-p256", None, "unknown", None, "unknown"),
+            ]
+        );
+    }
+}
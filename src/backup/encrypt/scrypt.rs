@@ -0,0 +1,96 @@
+use age::secrecy::{ExposeSecret, SecretString};
+use age::{DecryptError, EncryptError};
+use age_core::format::{FileKey, Stanza};
+use age_core::primitives::{aead_decrypt, aead_encrypt};
+use base64::prelude::{Engine, BASE64_STANDARD_NO_PAD};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use scrypt::Params;
+
+pub static SCRYPT_STANZA_TAG: &str = "k-backup-scrypt";
+static SCRYPT_SALT_LABEL: &[u8] = b"age-encryption.org/v1/scrypt";
+static SALT_LEN: usize = 16;
+static FILE_KEY_BYTES: usize = 16;
+/// Lowest work factor accepted, about 16 MiB of memory.
+pub static MIN_SCRYPT_WORK_FACTOR: u8 = 14;
+/// Highest work factor accepted, about 1 GiB of memory.
+pub static MAX_SCRYPT_WORK_FACTOR: u8 = 20;
+
+/// Passphrase recipient with a fixed scrypt work factor, instead of the one age measures to
+/// take about a second on the machine. The stanza is the age `scrypt` one under another tag:
+/// age only accepts a `scrypt` stanza alone in the header, and custom recipients always get a
+/// grease stanza next to theirs. Archives are decrypted by k_backup only, not by the age CLI.
+pub struct ScryptRecipient {
+    pub passphrase: SecretString,
+    /// log2 of the scrypt cost N.
+    pub work_factor: u8,
+}
+
+/// Passphrase identity unwrapping [`ScryptRecipient`] stanzas.
+pub struct ScryptIdentity {
+    pub passphrase: SecretString,
+}
+
+fn derive_key(passphrase: &SecretString, salt: &[u8], work_factor: u8) -> Option<[u8; 32]> {
+    let mut label_salt = SCRYPT_SALT_LABEL.to_vec();
+    label_salt.extend_from_slice(salt);
+    let params = Params::new(work_factor, 8, 1, 32).ok()?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(
+        passphrase.expose_secret().as_bytes(),
+        &label_salt,
+        &params,
+        &mut key,
+    )
+    .ok()?;
+    Some(key)
+}
+
+impl age::Recipient for ScryptRecipient {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(&self.passphrase, &salt, self.work_factor).ok_or_else(|| {
+            std::io::Error::other(format!("invalid scrypt work factor {}", self.work_factor))
+        })?;
+        Ok(vec![Stanza {
+            tag: SCRYPT_STANZA_TAG.to_string(),
+            args: vec![
+                BASE64_STANDARD_NO_PAD.encode(salt),
+                self.work_factor.to_string(),
+            ],
+            body: aead_encrypt(&key, file_key.expose_secret()),
+        }])
+    }
+}
+
+impl age::Identity for ScryptIdentity {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        if stanza.tag != SCRYPT_STANZA_TAG {
+            return None;
+        }
+        let (Some(salt), Some(work_factor)) = (
+            stanza
+                .args
+                .first()
+                .and_then(|s| BASE64_STANDARD_NO_PAD.decode(s).ok())
+                .filter(|s| s.len() == SALT_LEN),
+            stanza.args.get(1).and_then(|n| n.parse::<u8>().ok()),
+        ) else {
+            return Some(Err(DecryptError::InvalidHeader));
+        };
+        // Bound the work a crafted header can ask for
+        if work_factor > MAX_SCRYPT_WORK_FACTOR {
+            return Some(Err(DecryptError::ExcessiveWork {
+                required: work_factor,
+                target: MAX_SCRYPT_WORK_FACTOR,
+            }));
+        }
+        let key = derive_key(&self.passphrase, &salt, work_factor)?;
+        Some(
+            aead_decrypt(&key, FILE_KEY_BYTES, &stanza.body)
+                .map(|file_key| FileKey::from(<[u8; 16]>::try_from(file_key.as_slice()).unwrap()))
+                .map_err(|_| DecryptError::DecryptionFailed),
+        )
+    }
+}
@@ -0,0 +1,117 @@
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+/// Multiplication in GF(2^8) with the AES polynomial.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+fn gf_inv(a: u8) -> u8 {
+    // a^254 == a^-1 since the multiplicative group has order 255
+    let mut result = 1;
+    for _ in 0..254 {
+        result = gf_mul(result, a);
+    }
+    result
+}
+
+/// Shamir split of `secret` byte-wise into `shares` shares of which `threshold` recover it.
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Vec<(u8, Vec<u8>)> {
+    let mut coefficients = vec![0u8; secret.len() * (threshold as usize - 1)];
+    OsRng.fill_bytes(&mut coefficients);
+    (1..=shares)
+        .map(|x| {
+            let share = secret
+                .iter()
+                .enumerate()
+                .map(|(i, s)| {
+                    let poly =
+                        &coefficients[i * (threshold as usize - 1)..][..threshold as usize - 1];
+                    poly.iter().rev().fold(0u8, |acc, c| gf_mul(acc ^ c, x)) ^ s
+                })
+                .collect();
+            (x, share)
+        })
+        .collect()
+}
+
+/// Lagrange interpolation at zero of byte-wise shares.
+pub fn combine(shares: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let len = shares[0].1.len();
+    (0..len)
+        .map(|i| {
+            shares.iter().fold(0u8, |acc, (xj, yj)| {
+                let basis = shares
+                    .iter()
+                    .filter(|(xm, _)| xm != xj)
+                    .fold(1u8, |b, (xm, _)| gf_mul(b, gf_mul(*xm, gf_inv(xm ^ xj))));
+                acc ^ gf_mul(yj[i], basis)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SECRET: &[u8] = b"correct horse battery staple";
+
+    /// Shares of `shares` picked by the bits of `mask`.
+    fn subset(shares: &[(u8, Vec<u8>)], mask: u32) -> Vec<(u8, Vec<u8>)> {
+        shares
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| mask & (1 << idx) != 0)
+            .map(|(_, share)| share.clone())
+            .collect()
+    }
+
+    #[test]
+    fn inverse_of_every_element() {
+        for a in 1..=255 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1, "{a}");
+        }
+    }
+
+    #[test]
+    fn every_subset_of_threshold_shares_recovers() {
+        for shares in 1..=5u8 {
+            for threshold in 1..=shares {
+                let split = split(SECRET, threshold, shares);
+                for mask in 1..1u32 << shares {
+                    let subset = subset(&split, mask);
+                    let recovered = combine(&subset);
+                    match subset.len() >= threshold as usize {
+                        true => assert_eq!(recovered, SECRET, "{threshold} of {shares}: {mask:b}"),
+                        false => {
+                            assert_ne!(recovered, SECRET, "{threshold} of {shares}: {mask:b}")
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_shares_recover() {
+        // f(x) = 0x53 + 0xca x
+        assert_eq!(combine(&[(1, vec![0x99]), (2, vec![0xdc])]), [0x53]);
+        assert_eq!(
+            combine(&[(3, vec![gf_mul(0xca, 3) ^ 0x53]), (2, vec![0xdc])]),
+            [0x53]
+        );
+    }
+}
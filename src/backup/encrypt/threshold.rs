@@ -0,0 +1,187 @@
+use crate::backup::encrypt::age::AgeIdentity;
+use crate::backup::encrypt::shamir::{combine, split};
+use age::secrecy::ExposeSecret;
+use age::{x25519, DecryptError, EncryptError};
+use age_core::format::{FileKey, Stanza};
+use std::collections::HashSet;
+
+static THRESHOLD_STANZA_TAG: &str = "k-backup-threshold";
+static X25519_STANZA_TAG: &str = "X25519";
+
+/// Recipient splitting the age file key into one Shamir share per recipient, any `threshold`
+/// of them recover it.
+///
+/// Each share is wrapped as a regular X25519 stanza retagged with the threshold and share
+/// index, so the whole scheme lives inside the standard age header.
+pub struct ThresholdRecipient {
+    pub threshold: u8,
+    pub recipients: Vec<x25519::Recipient>,
+}
+
+impl age::Recipient for ThresholdRecipient {
+    fn wrap_file_key(&self, file_key: &FileKey) -> Result<Vec<Stanza>, EncryptError> {
+        let shares = split(
+            file_key.expose_secret(),
+            self.threshold,
+            self.recipients.len() as u8,
+        );
+        let mut stanzas = Vec::with_capacity(shares.len());
+        for (recipient, (index, share)) in self.recipients.iter().zip(shares) {
+            let share_key = FileKey::from(<[u8; 16]>::try_from(share.as_slice()).unwrap());
+            for stanza in recipient.wrap_file_key(&share_key)? {
+                let mut args = vec![self.threshold.to_string(), index.to_string()];
+                args.extend(stanza.args);
+                stanzas.push(Stanza {
+                    tag: THRESHOLD_STANZA_TAG.to_string(),
+                    args,
+                    body: stanza.body,
+                });
+            }
+        }
+        Ok(stanzas)
+    }
+}
+
+/// Identity holding the identities of the key holders present, recovering the file key once
+/// enough shares are unwrapped.
+pub struct ThresholdIdentity {
+    pub identities: Vec<AgeIdentity>,
+}
+
+impl age::Identity for ThresholdIdentity {
+    fn unwrap_stanza(&self, _stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        None
+    }
+
+    fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<Result<FileKey, DecryptError>> {
+        let mut threshold = None;
+        let mut indices = HashSet::new();
+        let mut shares = Vec::new();
+        for stanza in stanzas.iter().filter(|s| s.tag == THRESHOLD_STANZA_TAG) {
+            let (Some(t), Some(index)) = (
+                stanza.args.first().and_then(|a| a.parse::<u8>().ok()),
+                stanza.args.get(1).and_then(|a| a.parse::<u8>().ok()),
+            ) else {
+                return Some(Err(DecryptError::InvalidHeader));
+            };
+            // Index 0 would be the file key itself, repeated indices break recombination
+            if t == 0 || index == 0 || threshold.is_some_and(|th| th != t) || !indices.insert(index)
+            {
+                return Some(Err(DecryptError::InvalidHeader));
+            }
+            threshold = Some(t);
+            let inner = Stanza {
+                tag: X25519_STANZA_TAG.to_string(),
+                args: stanza.args[2..].to_vec(),
+                body: stanza.body.clone(),
+            };
+            let share = self
+                .identities
+                .iter()
+                .find_map(|identity| age::Identity::unwrap_stanza(identity, &inner)?.ok());
+            if let Some(share) = share {
+                shares.push((index, share.expose_secret().to_vec()));
+            }
+        }
+
+        let threshold = threshold?;
+        if shares.len() < threshold as usize {
+            return Some(Err(DecryptError::NoMatchingKeys));
+        }
+        let file_key = combine(&shares[..threshold as usize]);
+        Some(Ok(FileKey::from(
+            <[u8; 16]>::try_from(file_key.as_slice()).unwrap(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::Identity;
+
+    /// Stanzas of a random file key wrapped `threshold` of `identities.len()`.
+    fn wrap(threshold: u8, identities: &[x25519::Identity]) -> (FileKey, Vec<Stanza>) {
+        let recipient = ThresholdRecipient {
+            threshold,
+            recipients: identities.iter().map(|i| i.to_public()).collect(),
+        };
+        let file_key = FileKey::from(rand::random::<[u8; 16]>());
+        let stanzas = age::Recipient::wrap_file_key(&recipient, &file_key).unwrap();
+        (file_key, stanzas)
+    }
+
+    fn unwrap(
+        identities: &[&x25519::Identity],
+        stanzas: &[Stanza],
+    ) -> Option<Result<FileKey, DecryptError>> {
+        ThresholdIdentity {
+            identities: identities.iter().map(|i| (*i).clone().into()).collect(),
+        }
+        .unwrap_stanzas(stanzas)
+    }
+
+    #[test]
+    fn every_subset_of_key_holders_at_the_threshold_decrypts() {
+        let identities = (0..4)
+            .map(|_| x25519::Identity::generate())
+            .collect::<Vec<_>>();
+        for threshold in 1..=4 {
+            let (file_key, stanzas) = wrap(threshold, &identities);
+            for mask in 1..1u32 << identities.len() {
+                let present = identities
+                    .iter()
+                    .enumerate()
+                    .filter(|(idx, _)| mask & (1 << idx) != 0)
+                    .map(|(_, identity)| identity)
+                    .collect::<Vec<_>>();
+                match unwrap(&present, &stanzas) {
+                    Some(Ok(unwrapped)) if present.len() >= threshold as usize => {
+                        assert_eq!(unwrapped.expose_secret(), file_key.expose_secret())
+                    }
+                    Some(Err(DecryptError::NoMatchingKeys))
+                        if present.len() < threshold as usize => {}
+                    res => panic!("{threshold} of {mask:b}: {:?}", res.map(|r| r.is_ok())),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn other_stanzas_are_not_for_threshold_identities() {
+        let identity = x25519::Identity::generate();
+        let stanzas =
+            age::Recipient::wrap_file_key(&identity.to_public(), &FileKey::from([1; 16])).unwrap();
+        assert!(unwrap(&[&identity], &stanzas).is_none());
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let identities = (0..3)
+            .map(|_| x25519::Identity::generate())
+            .collect::<Vec<_>>();
+        let (_, stanzas) = wrap(2, &identities);
+        let all = identities.iter().collect::<Vec<_>>();
+        let with_args = |edit: &dyn Fn(&mut Vec<Stanza>)| {
+            let mut stanzas = stanzas
+                .iter()
+                .map(|s| Stanza {
+                    tag: s.tag.clone(),
+                    args: s.args.clone(),
+                    body: s.body.clone(),
+                })
+                .collect::<Vec<_>>();
+            edit(&mut stanzas);
+            unwrap(&all, &stanzas)
+        };
+        for res in [
+            with_args(&|s| s.iter_mut().for_each(|s| s.args[0] = "0".to_string())),
+            with_args(&|s| s[0].args[1] = "0".to_string()),
+            with_args(&|s| s[1].args[1] = s[0].args[1].clone()),
+            with_args(&|s| s[1].args[0] = "3".to_string()),
+            with_args(&|s| s[0].args[0] = "two".to_string()),
+        ] {
+            assert!(matches!(res, Some(Err(DecryptError::InvalidHeader))));
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use crate::backup::finish::Finish;
+use std::io::{Error, Write};
+
+/// Writer duplicating every write to all inner writers.
+pub struct FanOutWriter<W: Write>(Vec<W>);
+
+impl<W: Write> FanOutWriter<W> {
+    pub fn new(writers: Vec<W>) -> Self {
+        Self(writers)
+    }
+}
+
+impl<W: Write> Write for FanOutWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for writer in self.0.iter_mut() {
+            writer.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        for writer in self.0.iter_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<O, W: Write + Finish<O>> Finish<Vec<O>> for FanOutWriter<W> {
+    fn finish(self) -> Result<Vec<O>, Error> {
+        self.0.into_iter().map(Finish::finish).collect()
+    }
+}
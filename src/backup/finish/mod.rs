@@ -1,8 +1,9 @@
-use age::stream::StreamWriter;
-use liblzma::write::XzEncoder;
-use std::io::{Error, Write};
+use std::io::Error;
+#[cfg(any(feature = "age", feature = "xz", feature = "zstd"))]
+use std::io::Write;
 
-impl<W: Write> Finish<W> for StreamWriter<W> {
+#[cfg(feature = "age")]
+impl<W: Write> Finish<W> for age::stream::StreamWriter<W> {
     fn finish(self) -> Result<W, Error> {
         self.finish()
     }
@@ -12,7 +13,15 @@ pub trait Finish<O> {
     fn finish(self) -> Result<O, Error>;
 }
 
-impl<W: Write> Finish<W> for XzEncoder<W> {
+#[cfg(feature = "xz")]
+impl<W: Write> Finish<W> for liblzma::write::XzEncoder<W> {
+    fn finish(self) -> Result<W, Error> {
+        self.finish()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> Finish<W> for zstd::Encoder<'static, W> {
     fn finish(self) -> Result<W, Error> {
         self.finish()
     }
@@ -0,0 +1,91 @@
+use crate::backup::retention::ItemWithDateTime;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::ops::RangeBounds;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Archive of a [`BackupHistory`], with its creation time.
+pub type HistoryEntry = Arc<ItemWithDateTime<PathBuf, Utc>>;
+
+type ArchivesByTime = BTreeMap<DateTime<Utc>, Vec<HistoryEntry>>;
+
+/// Archives of a job in its out dir, kept up to date by the schedule as backups are created and
+/// pruned. Clones share the history, so other threads can query it while the schedule runs.
+#[derive(Clone, Default)]
+pub struct BackupHistory {
+    archives: Arc<RwLock<ArchivesByTime>>,
+}
+
+impl BackupHistory {
+    pub fn new<I: IntoIterator<Item = (PathBuf, DateTime<Utc>)>>(archives: I) -> Self {
+        let history = Self::default();
+        for (path, date_time) in archives {
+            history.insert(path, date_time);
+        }
+        history
+    }
+
+    /// Replace the archives with `archives`, e.g. read again from the out dir.
+    pub fn replace<I: IntoIterator<Item = (PathBuf, DateTime<Utc>)>>(&self, archives: I) {
+        let replacement = Self::new(archives);
+        let archives = std::mem::take(&mut *replacement.write());
+        *self.write() = archives;
+    }
+
+    pub fn insert(&self, path: PathBuf, date_time: DateTime<Utc>) {
+        self.write()
+            .entry(date_time)
+            .or_default()
+            .push(Arc::new(ItemWithDateTime::from((path, date_time))));
+    }
+
+    /// Forget `entry`, returning whether it was known.
+    pub fn remove(&self, entry: &ItemWithDateTime<PathBuf, Utc>) -> bool {
+        let mut archives = self.write();
+        let Some(entries) = archives.get_mut(&entry.date_time) else {
+            return false;
+        };
+        let count = entries.len();
+        entries.retain(|e| e.as_ref() != entry);
+        let removed = entries.len() < count;
+        if entries.is_empty() {
+            archives.remove(&entry.date_time);
+        }
+        removed
+    }
+
+    pub fn count(&self) -> usize {
+        self.read().values().map(Vec::len).sum()
+    }
+
+    /// All archives, newest first.
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        self.range(..).into_iter().rev().collect()
+    }
+
+    /// Newest archive.
+    pub fn latest(&self) -> Option<HistoryEntry> {
+        self.read()
+            .last_key_value()
+            .and_then(|(_, entries)| entries.last().cloned())
+    }
+
+    /// Archives created within `range`, oldest first.
+    pub fn range<R: RangeBounds<DateTime<Utc>>>(&self, range: R) -> Vec<HistoryEntry> {
+        self.read()
+            .range(range)
+            .flat_map(|(_, entries)| entries.iter().cloned())
+            .collect()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, ArchivesByTime> {
+        self.archives.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, ArchivesByTime> {
+        self.archives
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,71 @@
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::process::{Child, Command};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub static DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+static POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CommandHook {
+    pub command: Arc<str>,
+    pub args: Option<Vec<Arc<str>>>,
+    /// Kill the command after this long, 5 minutes by default for hooks and checks and 1 minute
+    /// for command sources. Storage commands are not limited.
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+}
+
+impl CommandHook {
+    pub fn to_command(&self) -> Command {
+        let mut command = Command::new(self.command.as_ref());
+        command.args(self.args.iter().flatten().map(AsRef::<str>::as_ref));
+        command
+    }
+
+    pub fn run(&self) -> Result<()> {
+        let mut child = self.to_command().spawn()?;
+        self.wait(&mut child, DEFAULT_TIMEOUT)
+    }
+
+    /// Wait for `child` spawned from this hook to exit successfully, killing it once the timeout,
+    /// `default_timeout` if unset, has passed.
+    pub fn wait(&self, child: &mut Child, default_timeout: Duration) -> Result<()> {
+        let timeout = self.timeout.unwrap_or(default_timeout);
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= timeout {
+                child.kill()?;
+                child.wait()?;
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("{self:?} timed out after {timeout:?}"),
+                )
+                .into());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::CommandExitStatus {
+                command: format!("{self:?}"),
+                status,
+            })
+        }
+    }
+}
+
+/// Commands that pause (`start`) and resume (`stop`) applications around source capture.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct QuiesceConfig {
+    pub start: CommandHook,
+    pub stop: CommandHook,
+}
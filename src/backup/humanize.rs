@@ -0,0 +1,81 @@
+use chrono::{DateTime, Local, Utc};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+static SIZE_UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+static DURATION_UNITS: [(&str, u64); 4] = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+static RELATIVE_UNITS: [(&str, u64); 3] = [("day", 86400), ("hour", 3600), ("minute", 60)];
+
+/// Byte count with binary units, e.g. `1.4 GiB`.
+#[derive(Clone, Copy, Debug)]
+pub struct HumanSize(pub u64);
+
+impl Display for HumanSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        match size < 10.0 {
+            true => write!(f, "{size:.1} {}", SIZE_UNITS[unit]),
+            false => write!(f, "{size:.0} {}", SIZE_UNITS[unit]),
+        }
+    }
+}
+
+/// Duration in its two largest units, e.g. `2h 13m`, or milliseconds below a second.
+#[derive(Clone, Copy, Debug)]
+pub struct HumanDuration(pub Duration);
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let secs = self.0.as_secs();
+        if secs == 0 {
+            return write!(f, "{}ms", self.0.as_millis());
+        }
+        let Some(idx) = DURATION_UNITS.iter().position(|(_, len)| secs >= *len) else {
+            return Ok(());
+        };
+        let (unit, len) = DURATION_UNITS[idx];
+        write!(f, "{}{unit}", secs / len)?;
+        if let Some((next_unit, next_len)) = DURATION_UNITS.get(idx + 1) {
+            let value = secs % len / next_len;
+            if value > 0 {
+                write!(f, " {value}{next_unit}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Upcoming time relative to `now`, e.g. `in 6 hours, at 01:00 local`.
+#[derive(Clone, Copy, Debug)]
+pub struct HumanNextRun {
+    pub at: DateTime<Utc>,
+    pub now: DateTime<Utc>,
+}
+
+impl Display for HumanNextRun {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let secs = (self.at - self.now).num_seconds().max(0) as u64;
+        match RELATIVE_UNITS.iter().find(|(_, len)| secs >= *len) {
+            Some((unit, len)) => {
+                // Rounded to the nearest unit, 5h 50m reads as in 6 hours
+                let value = (secs + len / 2) / len;
+                let plural = if value == 1 { "" } else { "s" };
+                write!(f, "in {value} {unit}{plural}")?;
+            }
+            None => write!(f, "in less than a minute")?,
+        }
+        let at = self.at.with_timezone(&Local);
+        match at.date_naive() == self.now.with_timezone(&Local).date_naive() {
+            true => write!(f, ", at {} local", at.format("%H:%M")),
+            false => write!(f, ", at {} local", at.format("%a %d %b %H:%M")),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::metadata::{read_metadata, write_metadata};
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+static INDEX_FILE_SUFFIX: &str = ".index.json";
+static TAR_BLOCK_SIZE: u64 = 512;
+
+/// Location of an entry within the decompressed tar stream.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ArchiveIndexEntry {
+    pub path: Arc<Path>,
+    /// Offset of the first header block of the entry (including extension headers).
+    pub header_offset: u64,
+    pub data_offset: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct ArchiveIndex {
+    pub entries: Vec<ArchiveIndexEntry>,
+}
+
+impl ArchiveIndex {
+    /// Record an entry appended between tar stream positions `start` and `end`.
+    pub fn push(&mut self, path: Arc<Path>, start: u64, end: u64, size: u64) {
+        let padded_size = size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+        self.entries.push(ArchiveIndexEntry {
+            path,
+            header_offset: start,
+            data_offset: end - padded_size,
+            size,
+        })
+    }
+
+    pub fn find<P: AsRef<Path>>(&self, path: P) -> Option<&ArchiveIndexEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.path.as_ref() == path.as_ref())
+    }
+
+    pub fn index_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+        let archive_path = archive_path.as_ref();
+        let mut file_name = archive_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(INDEX_FILE_SUFFIX);
+        archive_path.with_file_name(file_name)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P, encryptor: &EncryptorConfig) -> Result<PathBuf> {
+        write_metadata(path, encryptor, |w| Ok(serde_json::to_writer(w, self)?))
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P, encryptor: &EncryptorConfig) -> Result<Self> {
+        read_metadata(path, encryptor)
+    }
+}
@@ -0,0 +1,419 @@
+use crate::backup::compress::Decompressor;
+#[cfg(feature = "age")]
+use crate::backup::encrypt::age::decrypt_age;
+use crate::backup::humanize::HumanSize;
+use crate::backup::restore::SecretSource;
+use crate::backup::result_error::result::Result;
+use crate::backup::volume::{archive_file_size, open_archive_file};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::io::{copy, sink, BufReader, Cursor, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+static AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+static AGE_ARMOR_MAGIC: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+static XZ_MAGIC: &[u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+static XZ_FOOTER_MAGIC: &[u8] = b"YZ";
+static XZ_HEADER_LEN: usize = 12;
+/// Gzip member header with the deflate method, the only one defined.
+static GZIP_MAGIC: &[u8] = &[0x1F, 0x8B, 0x08];
+static ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+static TAR_MAGIC: &[u8] = b"ustar";
+static TAR_MAGIC_OFFSET: usize = 257;
+/// Bytes of every layer looked at to detect its format, enough for any age header.
+static PEEK_LEN: usize = 64 * 1024;
+/// Bytes kept from the end of an xz layer to read its indexes.
+static XZ_TAIL_LEN: usize = 4 * 1024 * 1024;
+
+/// What an archive is made of, from its outermost layer in, read without its config or
+/// sidecar files.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct ArchiveInspection {
+    pub archive_size: u64,
+    /// Formats of the layers, outermost first, as far as they could be read.
+    pub layers: Vec<Arc<str>>,
+    pub age: Option<AgeHeader>,
+    pub xz: Option<XzInfo>,
+    pub tar: Option<TarSummary>,
+    /// Why the inner layers could not be read, e.g. no secret to decrypt with.
+    pub stopped: Option<Arc<str>>,
+}
+
+/// Stanzas of an age header, naming the key types able to decrypt without decrypting.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct AgeHeader {
+    pub armored: bool,
+    pub stanzas: Vec<AgeStanza>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AgeStanza {
+    pub tag: Arc<str>,
+    pub args: Vec<Arc<str>>,
+}
+
+/// Stream structure of an xz layer, from its headers and indexes.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct XzInfo {
+    /// Integrity checks used by the streams, e.g. `none` for stored precompressed data.
+    pub checks: Vec<Arc<str>>,
+    pub streams: u64,
+    pub blocks: u64,
+    /// Whether every stream was counted, indexes beyond the kept tail are not.
+    pub complete: bool,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct TarSummary {
+    pub entries: u64,
+    /// Bytes of entry data, headers and padding excluded.
+    pub data_size: u64,
+}
+
+/// Detect the layers of the archive at `path` and describe each. Encrypted layers are only
+/// read through with `secrets`, everything else needs no secret.
+pub fn inspect_archive<S: SecretSource>(
+    path: &Path,
+    secrets: Option<&S>,
+) -> Result<ArchiveInspection> {
+    let mut inspection = ArchiveInspection {
+        archive_size: archive_file_size(path)?,
+        ..Default::default()
+    };
+    let mut reader: Box<dyn Read> = Box::new(BufReader::new(open_archive_file(path)?));
+    let mut xz_tail = None;
+    loop {
+        let (head, rest) = peek(reader)?;
+        reader = rest;
+        let format = detect_format(&head);
+        inspection.layers.push(format.into());
+        match format {
+            "age" => {
+                inspection.age = Some(AgeHeader::parse(&head));
+                let Some(secrets) = secrets else {
+                    inspection.stopped = Some(
+                        "encrypted, inner layers need --identity, --config or --decrypt".into(),
+                    );
+                    break;
+                };
+                reader = decrypt(reader, secrets)?;
+            }
+            "xz" => {
+                let tail = Arc::new(Mutex::new(Tail::default()));
+                inspection.xz = Some(XzInfo {
+                    checks: vec![xz_check_name(head[7] & 0x0F).into()],
+                    streams: 1,
+                    blocks: 0,
+                    complete: false,
+                });
+                xz_tail = Some(tail.clone());
+                reader = Box::new(Decompressor::from_format(
+                    format,
+                    TailReader {
+                        inner: reader,
+                        tail,
+                    },
+                )?);
+            }
+            "gzip" | "zstd" => reader = Box::new(Decompressor::from_format(format, reader)?),
+            "tar" => {
+                let mut summary = TarSummary::default();
+                let mut archive = tar::Archive::new(reader);
+                for entry in archive.entries()? {
+                    summary.entries += 1;
+                    summary.data_size += entry?.header().size()?;
+                }
+                copy(&mut archive.into_inner(), &mut sink())?;
+                inspection.tar = Some(summary);
+                break;
+            }
+            _ => {
+                inspection.stopped = Some("unknown format".into());
+                break;
+            }
+        }
+    }
+
+    if let (Some(xz), Some(tail)) = (inspection.xz.as_mut(), xz_tail) {
+        let tail = tail.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(walked) = xz_streams(&tail.data, tail.is_whole()) {
+            xz.streams = walked.streams;
+            xz.blocks = walked.blocks;
+            xz.complete = walked.complete;
+            for check in walked.checks.into_iter().rev() {
+                if !xz.checks.contains(&check) {
+                    xz.checks.push(check);
+                }
+            }
+        }
+    }
+    Ok(inspection)
+}
+
+/// First bytes of `reader`, with a reader yielding them again followed by the rest.
+fn peek(mut reader: Box<dyn Read>) -> Result<(Vec<u8>, Box<dyn Read>)> {
+    let mut head = Vec::with_capacity(PEEK_LEN);
+    reader
+        .by_ref()
+        .take(PEEK_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok((head.clone(), Box::new(Cursor::new(head).chain(reader))))
+}
+
+fn detect_format(head: &[u8]) -> &'static str {
+    if head.starts_with(AGE_MAGIC) || head.starts_with(AGE_ARMOR_MAGIC) {
+        "age"
+    } else if head.starts_with(XZ_MAGIC) && head.len() >= XZ_HEADER_LEN {
+        "xz"
+    } else if head.starts_with(ZSTD_MAGIC) {
+        "zstd"
+    } else if head.starts_with(GZIP_MAGIC) {
+        "gzip"
+    } else if head.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC) {
+        "tar"
+    } else {
+        "unknown"
+    }
+}
+
+#[cfg(feature = "age")]
+fn decrypt<S: SecretSource>(reader: Box<dyn Read>, secrets: &S) -> Result<Box<dyn Read>> {
+    Ok(Box::new(decrypt_age(
+        reader,
+        || secrets.passphrase(),
+        || secrets.identities(),
+    )?))
+}
+
+#[cfg(not(feature = "age"))]
+fn decrypt<S: SecretSource>(_reader: Box<dyn Read>, _secrets: &S) -> Result<Box<dyn Read>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "age support is disabled",
+    ))?
+}
+
+impl AgeHeader {
+    /// Header at the start of `head`, `None` when it is not age encrypted.
+    pub fn detect(head: &[u8]) -> Option<Self> {
+        (head.starts_with(AGE_MAGIC) || head.starts_with(AGE_ARMOR_MAGIC))
+            .then(|| Self::parse(head))
+    }
+
+    /// Stanzas of the header at the start of `head`, the ones past it are left out.
+    fn parse(head: &[u8]) -> Self {
+        let armored = head.starts_with(AGE_ARMOR_MAGIC);
+        let header = match armored {
+            true => dearmor(head),
+            false => head.to_vec(),
+        };
+        let stanzas = String::from_utf8_lossy(&header)
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.starts_with("---"))
+            .filter_map(|line| line.strip_prefix("-> "))
+            .filter_map(|line| {
+                let mut parts = line.split(' ');
+                Some(AgeStanza {
+                    tag: parts.next()?.into(),
+                    args: parts.map(Into::into).collect(),
+                })
+            })
+            // Random stanzas age adds so parsers tolerate unknown ones
+            .filter(|stanza| !stanza.tag.ends_with("-grease"))
+            .collect();
+        Self { armored, stanzas }
+    }
+}
+
+/// As much of the armored file in `head` as decodes, empty without age support.
+#[cfg(feature = "age")]
+fn dearmor(head: &[u8]) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut reader = age::armor::ArmoredReader::new(head);
+    let mut buf = [0u8; 4096];
+    // The armor of a truncated file fails at the cut, everything before it is kept
+    while let Ok(n) = reader.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        header.extend_from_slice(&buf[..n]);
+    }
+    header
+}
+
+#[cfg(not(feature = "age"))]
+fn dearmor(_head: &[u8]) -> Vec<u8> {
+    Vec::new()
+}
+
+impl Display for AgeStanza {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let arg = |idx: usize| self.args.get(idx).map(|a| a.as_ref()).unwrap_or("?");
+        match self.tag.as_ref() {
+            // The argument is an ephemeral key, recipients are not named in the header
+            "X25519" => write!(f, "X25519 recipient"),
+            "scrypt" | "k-backup-scrypt" => {
+                write!(f, "passphrase, scrypt work factor 2^{}", arg(1))
+            }
+            "ssh-ed25519" => write!(f, "ssh-ed25519 recipient with key tag {}", arg(0)),
+            "ssh-rsa" => write!(f, "ssh-rsa recipient with key tag {}", arg(0)),
+            "k-backup-threshold" => write!(
+                f,
+                "threshold share {} of {} needed, X25519 recipient",
+                arg(1),
+                arg(0)
+            ),
+            tag => write!(f, "{tag} {}", self.args.join(" ")),
+        }
+    }
+}
+
+fn xz_check_name(check: u8) -> String {
+    match check {
+        0x00 => "none".to_string(),
+        0x01 => "CRC32".to_string(),
+        0x04 => "CRC64".to_string(),
+        0x0A => "SHA-256".to_string(),
+        check => format!("unknown ({check:#x})"),
+    }
+}
+
+/// Last bytes read through a reader and how many it read overall.
+#[derive(Default)]
+struct Tail {
+    data: Vec<u8>,
+    total: u64,
+}
+
+impl Tail {
+    fn is_whole(&self) -> bool {
+        self.total == self.data.len() as u64
+    }
+}
+
+struct TailReader<R: Read> {
+    inner: R,
+    tail: Arc<Mutex<Tail>>,
+}
+
+impl<R: Read> Read for TailReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let mut tail = self.tail.lock().unwrap_or_else(PoisonError::into_inner);
+        tail.total += n as u64;
+        tail.data.extend_from_slice(&buf[..n]);
+        if tail.data.len() > 2 * XZ_TAIL_LEN {
+            let excess = tail.data.len() - XZ_TAIL_LEN;
+            tail.data.drain(..excess);
+        }
+        Ok(n)
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..63).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Streams of an xz layer walked from its end.
+#[derive(Default)]
+struct XzStreams {
+    streams: u64,
+    blocks: u64,
+    /// Checks of the walked streams, last stream first.
+    checks: Vec<Arc<str>>,
+    complete: bool,
+}
+
+/// Streams and blocks of the xz data ending with `tail`, walking the stream footers and indexes
+/// backwards. Counts are complete when `whole` and every stream was walked through.
+fn xz_streams(tail: &[u8], whole: bool) -> Option<XzStreams> {
+    let mut walked = XzStreams::default();
+    let mut end = tail.len();
+    loop {
+        // Stream padding is a multiple of four null bytes
+        while end >= 4 && tail[end - 4..end] == [0; 4] {
+            end -= 4;
+        }
+        if end == 0 {
+            walked.complete = whole;
+            return (walked.streams > 0).then_some(walked);
+        }
+        let footer = tail.get(end.checked_sub(XZ_HEADER_LEN)?..end)?;
+        if &footer[10..] != XZ_FOOTER_MAGIC {
+            return None;
+        }
+        let backward_size = u32::from_le_bytes(footer[4..8].try_into().ok()?);
+        let index_len = (backward_size as usize + 1) * 4;
+        let Some(index_start) = (end - XZ_HEADER_LEN).checked_sub(index_len) else {
+            return (walked.streams > 0).then_some(walked);
+        };
+        let index = &tail[index_start..end - XZ_HEADER_LEN];
+        let mut pos = 1;
+        let records = read_varint(index, &mut pos)?;
+        let mut blocks_len = 0;
+        for _ in 0..records {
+            let unpadded = read_varint(index, &mut pos)?;
+            read_varint(index, &mut pos)?;
+            blocks_len += unpadded.div_ceil(4) * 4;
+        }
+        walked.streams += 1;
+        walked.blocks += records;
+        // The footer repeats the stream flags of the header
+        walked.checks.push(xz_check_name(footer[9] & 0x0F).into());
+        match index_start.checked_sub(blocks_len as usize + XZ_HEADER_LEN) {
+            Some(start) if tail[start..].starts_with(XZ_MAGIC) => end = start,
+            _ => return Some(walked),
+        }
+    }
+}
+
+impl Display for ArchiveInspection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Size: {}", HumanSize(self.archive_size))?;
+        writeln!(f, "Layers: {}", self.layers.join(" > "))?;
+        if let Some(age) = &self.age {
+            writeln!(
+                f,
+                "Age header{}: {} stanza(s)",
+                if age.armored { " (armored)" } else { "" },
+                age.stanzas.len()
+            )?;
+            for stanza in age.stanzas.iter() {
+                writeln!(f, "  {stanza}")?;
+            }
+        }
+        if let Some(xz) = &self.xz {
+            writeln!(
+                f,
+                "XZ: check {}, {}{} stream(s), {} block(s)",
+                xz.checks.join(", "),
+                if xz.complete { "" } else { "at least " },
+                xz.streams,
+                xz.blocks
+            )?;
+        }
+        if let Some(tar) = &self.tar {
+            writeln!(
+                f,
+                "Tar: {} entries, {} of data",
+                tar.entries,
+                HumanSize(tar.data_size)
+            )?;
+        }
+        if let Some(stopped) = &self.stopped {
+            writeln!(f, "Stopped: {stopped}")?;
+        }
+        Ok(())
+    }
+}
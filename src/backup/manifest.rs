@@ -0,0 +1,60 @@
+use crate::backup::encrypt::policy::CryptoParameters;
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::metadata::{read_metadata, write_metadata};
+use crate::backup::result_error::result::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+static MANIFEST_FILE_SUFFIX: &str = ".manifest.json";
+
+/// File archived under `path`, as written in its tar header.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ArchiveManifestEntry {
+    pub path: Arc<Path>,
+    pub size: u64,
+    pub mtime: DateTime<Utc>,
+}
+
+/// Checksum of an archive and the files it holds, written next to it by `manifest` so its
+/// content can be audited without downloading or decrypting it. Packed small files are listed
+/// with their own paths.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct ArchiveManifest {
+    pub archive_size: u64,
+    pub archive_sha256: String,
+    /// Missing in manifests written before crypto parameters were recorded.
+    #[serde(default)]
+    pub crypto: Option<CryptoParameters>,
+    pub entries: Vec<ArchiveManifestEntry>,
+}
+
+impl ArchiveManifest {
+    /// Record an entry of `size` bytes last modified at `mtime` in seconds since the epoch.
+    pub fn push(&mut self, path: Arc<Path>, size: u64, mtime: u64) {
+        self.entries.push(ArchiveManifestEntry {
+            path,
+            size,
+            mtime: DateTime::from_timestamp(mtime.min(i64::MAX as u64) as i64, 0)
+                .unwrap_or_default(),
+        })
+    }
+
+    pub fn manifest_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+        let archive_path = archive_path.as_ref();
+        let mut file_name = archive_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(MANIFEST_FILE_SUFFIX);
+        archive_path.with_file_name(file_name)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P, encryptor: &EncryptorConfig) -> Result<PathBuf> {
+        write_metadata(path, encryptor, |w| {
+            Ok(serde_json::to_writer_pretty(w, self)?)
+        })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P, encryptor: &EncryptorConfig) -> Result<Self> {
+        read_metadata(path, encryptor)
+    }
+}
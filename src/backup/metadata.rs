@@ -0,0 +1,64 @@
+use crate::backup::encrypt::{DecryptorBuilder, EncryptorBuilder, EncryptorConfig};
+use crate::backup::file_ext::FileExtProvider;
+use crate::backup::finish::Finish;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use serde::de::DeserializeOwned;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, IntoInnerError, Write};
+use std::path::{Path, PathBuf};
+
+/// Location of the metadata file at `path` once encrypted with `encryptor`.
+pub fn encrypted_path<P: AsRef<Path>>(path: P, encryptor: &EncryptorConfig) -> PathBuf {
+    let path = path.as_ref();
+    match encryptor.file_ext() {
+        None => path.to_path_buf(),
+        Some(ext) => {
+            let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+            file_name.push(".");
+            file_name.push(ext.as_ref());
+            path.with_file_name(file_name)
+        }
+    }
+}
+
+/// Write a metadata file next to an archive, encrypted with `encryptor` so it does not leak
+/// file names and sizes. Returns the written path.
+pub fn write_metadata<P, F>(path: P, encryptor: &EncryptorConfig, write_fn: F) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let path = encrypted_path(path, encryptor);
+    let mut writer = encryptor.build_encryptor(BufWriter::new(File::create(&path)?))?;
+    write_fn(&mut writer)?;
+    writer
+        .finish()?
+        .into_inner()
+        .map_err(IntoInnerError::into_error)?;
+    Ok(path)
+}
+
+/// Read a JSON metadata file, decrypting it with `encryptor` when its file name carries the
+/// encryptor extension.
+pub fn read_metadata<T: DeserializeOwned, P: AsRef<Path>>(
+    path: P,
+    encryptor: &EncryptorConfig,
+) -> Result<T> {
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path)?);
+    let is_encrypted = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext != "json");
+    if !is_encrypted {
+        return serde_json::from_reader(reader).map_err(Error::from);
+    }
+    if encryptor.file_ext().is_none() {
+        return Err(std::io::Error::other(format!(
+            "{path:?} is encrypted, an encryptor with credentials is required"
+        ))
+        .into());
+    }
+    serde_json::from_reader(encryptor.build_decryptor(reader)?).map_err(Error::from)
+}
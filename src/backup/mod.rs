@@ -1,8 +1,52 @@
 pub mod archive;
+pub mod archive_group;
 pub mod backup_config;
+pub mod catalog;
+pub mod checksum;
+pub mod clock;
+pub mod collect;
 pub mod compress;
+pub mod conditions;
+pub mod content_type;
+pub mod control;
+pub mod counting_writer;
+pub mod deadline;
+pub mod discover;
+pub mod disk_space;
+pub mod drill;
 pub mod encrypt;
+pub mod fan_out;
 pub mod file_ext;
 pub mod finish;
+pub mod history;
+pub mod hook;
+pub mod humanize;
+pub mod index;
+pub mod inspect;
+pub mod manifest;
+pub mod metadata;
+pub mod notification;
+pub mod pack;
+pub mod path_expand;
+pub mod permission_scan;
+pub mod pipeline;
+pub mod reconcile;
+pub mod recovery;
+pub mod reload;
+pub mod report;
+pub mod report_sink;
+pub mod restore;
 pub mod result_error;
 pub mod retention;
+pub mod roundtrip;
+pub mod sanity;
+pub mod span;
+pub mod stat_cache;
+pub mod status;
+pub mod storage;
+pub mod systemd;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod timings;
+pub mod verify;
+pub mod volume;
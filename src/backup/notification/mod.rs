@@ -0,0 +1,224 @@
+pub mod syslog;
+
+use crate::backup::humanize::{HumanDuration, HumanSize};
+use crate::backup::notification::syslog::SyslogConfig;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithDebugObjectAndFnName;
+use derive_more::From;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Kinds of events a notification destination can be limited to with `notify_on`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventCategory {
+    /// Backups and restore drills that passed without any error.
+    Success,
+    /// Backups created with non fatal errors and catalog drift.
+    Warning,
+    /// Failed backups and restore drills.
+    Failure,
+    /// Archives removed by retention.
+    Retention,
+    /// Scheduled runs skipped, e.g. by the hold file or run conditions.
+    Skipped,
+}
+
+#[derive(Clone, Debug)]
+pub enum BackupEvent {
+    BackupSkipped {
+        reason: Arc<str>,
+    },
+    BackupCreated {
+        file_path: Arc<Path>,
+        archive_size: u64,
+        /// Time taken creating and uploading the archive.
+        duration: Duration,
+        non_fatal_error: Option<Arc<str>>,
+    },
+    BackupFailed {
+        error: Arc<str>,
+    },
+    RetentionDeleted {
+        file_path: Arc<Path>,
+    },
+    RestoreDrillPassed {
+        file_path: Arc<Path>,
+        duration: Duration,
+        /// Results of the checks of restored databases.
+        checks: Vec<Arc<str>>,
+    },
+    RestoreDrillFailed {
+        file_path: Option<Arc<Path>>,
+        error: Arc<str>,
+    },
+    /// Differences found between the catalog and the out dir or destinations.
+    CatalogDrift {
+        drifts: Vec<Arc<str>>,
+        repaired: usize,
+    },
+}
+
+impl BackupEvent {
+    pub fn severity(&self) -> Severity {
+        match self {
+            BackupEvent::BackupSkipped { .. } => Severity::Info,
+            BackupEvent::BackupCreated {
+                non_fatal_error: Some(_),
+                ..
+            } => Severity::Warning,
+            BackupEvent::BackupCreated { .. } => Severity::Info,
+            BackupEvent::BackupFailed { .. } => Severity::Error,
+            BackupEvent::RetentionDeleted { .. } => Severity::Info,
+            BackupEvent::RestoreDrillPassed { .. } => Severity::Info,
+            BackupEvent::RestoreDrillFailed { .. } => Severity::Error,
+            BackupEvent::CatalogDrift { .. } => Severity::Warning,
+        }
+    }
+
+    pub fn category(&self) -> EventCategory {
+        match self {
+            BackupEvent::BackupSkipped { .. } => EventCategory::Skipped,
+            BackupEvent::BackupCreated {
+                non_fatal_error: Some(_),
+                ..
+            } => EventCategory::Warning,
+            BackupEvent::BackupCreated { .. } => EventCategory::Success,
+            BackupEvent::BackupFailed { .. } => EventCategory::Failure,
+            BackupEvent::RetentionDeleted { .. } => EventCategory::Retention,
+            BackupEvent::RestoreDrillPassed { .. } => EventCategory::Success,
+            BackupEvent::RestoreDrillFailed { .. } => EventCategory::Failure,
+            BackupEvent::CatalogDrift { .. } => EventCategory::Warning,
+        }
+    }
+
+    /// Stable identifier of the event kind, e.g. used as syslog MSGID.
+    pub fn id(&self) -> &'static str {
+        match self {
+            BackupEvent::BackupSkipped { .. } => "BACKUP_SKIPPED",
+            BackupEvent::BackupCreated { .. } => "BACKUP_CREATED",
+            BackupEvent::BackupFailed { .. } => "BACKUP_FAILED",
+            BackupEvent::RetentionDeleted { .. } => "RETENTION_DELETED",
+            BackupEvent::RestoreDrillPassed { .. } => "RESTORE_DRILL_PASSED",
+            BackupEvent::RestoreDrillFailed { .. } => "RESTORE_DRILL_FAILED",
+            BackupEvent::CatalogDrift { .. } => "CATALOG_DRIFT",
+        }
+    }
+}
+
+impl Display for BackupEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupEvent::BackupSkipped { reason } => write!(f, "Backup skipped: {reason}"),
+            BackupEvent::BackupCreated {
+                file_path,
+                archive_size,
+                duration,
+                non_fatal_error,
+            } => {
+                write!(
+                    f,
+                    "Created backup file: {file_path:?} ({} in {})",
+                    HumanSize(*archive_size),
+                    HumanDuration(*duration)
+                )?;
+                match non_fatal_error {
+                    Some(e) => write!(f, " with non fatal error: {e}"),
+                    None => Ok(()),
+                }
+            }
+            BackupEvent::BackupFailed { error } => write!(f, "Backup failed: {error}"),
+            BackupEvent::RetentionDeleted { file_path } => {
+                write!(f, "Removed out of retention file {file_path:?}")
+            }
+            BackupEvent::RestoreDrillPassed {
+                file_path,
+                duration,
+                checks,
+            } => {
+                write!(
+                    f,
+                    "Restore drill of {file_path:?} passed in {}",
+                    HumanDuration(*duration)
+                )?;
+                match checks.is_empty() {
+                    true => Ok(()),
+                    false => write!(f, ": {}", checks.join("; ")),
+                }
+            }
+            BackupEvent::RestoreDrillFailed {
+                file_path: Some(file_path),
+                error,
+            } => write!(f, "Restore drill of {file_path:?} failed: {error}"),
+            BackupEvent::RestoreDrillFailed {
+                file_path: None,
+                error,
+            } => write!(f, "Restore drill failed: {error}"),
+            BackupEvent::CatalogDrift { drifts, repaired } => write!(
+                f,
+                "Catalog drift, {} found and {repaired} repaired: {}",
+                drifts.len(),
+                drifts.join("; ")
+            ),
+        }
+    }
+}
+
+pub trait Notifier {
+    fn notify(&self, event: &BackupEvent) -> Result<()>;
+}
+
+#[derive(Clone, From, Serialize, Deserialize, Debug)]
+#[serde(tag = "notification_type")]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationConfig {
+    Syslog(SyslogConfig),
+}
+
+impl Notifier for NotificationConfig {
+    fn notify(&self, event: &BackupEvent) -> Result<()> {
+        match self {
+            NotificationConfig::Syslog(c) => c.notify(event),
+        }
+        .with_debug_object_and_fn_name(self.clone(), "notify")
+    }
+}
+
+/// A notification with the events it is sent.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct NotificationDestinationConfig {
+    /// Categories of events sent, e.g. `[failure, warning]`. Every event by default.
+    pub notify_on: Option<Vec<EventCategory>>,
+    #[serde(flatten)]
+    pub notification: NotificationConfig,
+}
+
+impl NotificationDestinationConfig {
+    pub fn accepts(&self, event: &BackupEvent) -> bool {
+        self.notify_on
+            .as_ref()
+            .is_none_or(|categories| categories.contains(&event.category()))
+    }
+}
+
+/// Events outside of `notify_on` are dropped.
+impl Notifier for NotificationDestinationConfig {
+    fn notify(&self, event: &BackupEvent) -> Result<()> {
+        match self.accepts(event) {
+            true => self.notification.notify(event),
+            false => Ok(()),
+        }
+    }
+}
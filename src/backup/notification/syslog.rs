@@ -0,0 +1,144 @@
+use crate::backup::notification::{BackupEvent, Notifier, Severity};
+use crate::backup::result_error::result::Result;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+static DEFAULT_APP_NAME: &str = "k_backup";
+static NIL_VALUE: &str = "-";
+/// Connect and write timeout of TCP transport, an unreachable server must not stall backups.
+static TCP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(tag = "transport")]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogTransport {
+    Udp { address: Arc<str> },
+    Tcp { address: Arc<str> },
+    Unix { path: Arc<Path> },
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogFacility {
+    User,
+    #[default]
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    fn code(self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// RFC 5424 syslog sink.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SyslogConfig {
+    #[serde(flatten)]
+    transport: SyslogTransport,
+    facility: Option<SyslogFacility>,
+    app_name: Option<Arc<str>>,
+    hostname: Option<Arc<str>>,
+}
+
+fn severity_code(severity: Severity) -> u8 {
+    match severity {
+        Severity::Info => 6,
+        Severity::Warning => 4,
+        Severity::Error => 3,
+    }
+}
+
+impl SyslogConfig {
+    fn format_message(&self, event: &BackupEvent) -> String {
+        let pri = self.facility.unwrap_or_default().code() * 8 + severity_code(event.severity());
+        let hostname = self
+            .hostname
+            .as_ref()
+            .map(|h| h.to_string())
+            .or_else(|| {
+                std::fs::read_to_string("/etc/hostname")
+                    .ok()
+                    .map(|h| h.trim().to_string())
+                    .filter(|h| !h.is_empty())
+            })
+            .unwrap_or(NIL_VALUE.to_string());
+        format!(
+            "<{pri}>1 {} {hostname} {} {} {} {NIL_VALUE} {event}",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.app_name.as_deref().unwrap_or(DEFAULT_APP_NAME),
+            std::process::id(),
+            event.id(),
+        )
+    }
+}
+
+impl Notifier for SyslogConfig {
+    fn notify(&self, event: &BackupEvent) -> Result<()> {
+        let message = self.format_message(event);
+        match &self.transport {
+            SyslogTransport::Udp { address } => {
+                let target = address.to_socket_addrs()?.next().ok_or_else(|| {
+                    std::io::Error::other(format!("cannot resolve syslog address {address:?}"))
+                })?;
+                let bind_address = if target.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                UdpSocket::bind(bind_address)?.send_to(message.as_bytes(), target)?;
+            }
+            SyslogTransport::Tcp { address } => {
+                let mut last_error = None;
+                let mut stream = address
+                    .to_socket_addrs()?
+                    .find_map(|addr| {
+                        TcpStream::connect_timeout(&addr, TCP_TIMEOUT)
+                            .map_err(|e| last_error = Some(e))
+                            .ok()
+                    })
+                    .ok_or_else(|| {
+                        last_error.unwrap_or_else(|| {
+                            std::io::Error::other(format!(
+                                "cannot resolve syslog address {address:?}"
+                            ))
+                        })
+                    })?;
+                stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+                // RFC 6587 octet counting framing
+                write!(stream, "{} {message}", message.len())?;
+                stream.flush()?;
+            }
+            SyslogTransport::Unix { path } => {
+                std::os::unix::net::UnixDatagram::unbound()?.send_to(message.as_bytes(), path)?;
+            }
+        }
+        Ok(())
+    }
+}
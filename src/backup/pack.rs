@@ -0,0 +1,315 @@
+use crate::backup::archive::{ArchiveEntry, EntryStat};
+use crate::backup::counting_writer::CountingWriter;
+use crate::backup::index::{ArchiveIndex, ArchiveIndexEntry};
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory inside the archive holding packs, restore unpacks them transparently.
+pub static PACK_DIR: &str = ".k_backup_packs";
+pub static PACK_INDEX_EXT: &str = "json";
+pub static PACK_BLOB_EXT: &str = "bin";
+static DEFAULT_MAX_FILE_SIZE: u64 = 16 * 1024;
+static DEFAULT_BLOB_SIZE: u64 = 4 * 1024 * 1024;
+static TAR_BLOCK_SIZE: u64 = 512;
+
+/// Pack tiny files into larger blobs, saving a tar header, padding and a separate write per
+/// file when archiving millions of them.
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct PackConfig {
+    /// Files up to this size are packed, 16KiB by default.
+    pub max_file_size: Option<u64>,
+    /// Pack data written per blob, 4MiB by default.
+    pub blob_size: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PackedFile {
+    pub path: Arc<Path>,
+    pub offset: u64,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: u64,
+    pub uid: u64,
+    pub gid: u64,
+}
+
+/// Index stored in the archive right before the blob it describes.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct PackIndex {
+    pub files: Vec<PackedFile>,
+}
+
+impl PackIndex {
+    /// Location of pack `id` within the archive with the given extension.
+    pub fn entry_path(id: usize, ext: &str) -> PathBuf {
+        Path::new(PACK_DIR).join(format!("pack-{id:06}.{ext}"))
+    }
+}
+
+pub struct PackWriter {
+    max_file_size: u64,
+    blob_size: u64,
+    next_id: usize,
+    data: Vec<u8>,
+    index: PackIndex,
+}
+
+impl PackWriter {
+    pub fn new(config: &PackConfig) -> Self {
+        Self {
+            max_file_size: config.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE),
+            blob_size: config.blob_size.unwrap_or(DEFAULT_BLOB_SIZE),
+            next_id: 0,
+            data: Vec::new(),
+            index: PackIndex::default(),
+        }
+    }
+
+    /// Add `entry` to the current pack, `None` when it is not a file small enough.
+    pub fn try_add(&mut self, entry: &ArchiveEntry) -> Result<Option<EntryStat>> {
+        if let Some(data) = &entry.data {
+            if data.len() as u64 > self.max_file_size {
+                return Ok(None);
+            }
+            let header = entry.captured_header()?;
+            let stat = EntryStat {
+                size: data.len() as u64,
+                mtime: header.mtime()?,
+            };
+            self.index.files.push(PackedFile {
+                path: entry.dst.clone(),
+                offset: self.data.len() as u64,
+                size: stat.size,
+                mode: header.mode()?,
+                mtime: stat.mtime,
+                uid: header.uid()?,
+                gid: header.gid()?,
+            });
+            self.data.extend_from_slice(data);
+            return Ok(Some(stat));
+        }
+        let metadata = std::fs::metadata(&entry.src)?;
+        if !metadata.is_file() || metadata.len() > self.max_file_size {
+            return Ok(None);
+        }
+        let offset = self.data.len() as u64;
+        let size = File::open(&entry.src)?
+            .take(metadata.len())
+            .read_to_end(&mut self.data)? as u64;
+        let (uid, gid) = match &entry.ownership {
+            None => (metadata.uid() as u64, metadata.gid() as u64),
+            Some(ownership) => (
+                ownership.map_uid(metadata.uid() as u64),
+                ownership.map_gid(metadata.gid() as u64),
+            ),
+        };
+        let stat = EntryStat {
+            size,
+            mtime: metadata.mtime().max(0) as u64,
+        };
+        self.index.files.push(PackedFile {
+            path: entry.dst.clone(),
+            offset,
+            size,
+            mode: metadata.mode(),
+            mtime: stat.mtime,
+            uid,
+            gid,
+        });
+        Ok(Some(stat))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.data.len() as u64 >= self.blob_size
+    }
+
+    /// Write the pending pack index and blob, recording packed files in `archive_index`.
+    pub fn flush<W: Write>(
+        &mut self,
+        builder: &mut tar::Builder<CountingWriter<W>>,
+        archive_index: Option<&mut ArchiveIndex>,
+    ) -> Result<()> {
+        if self.index.files.is_empty() {
+            return Ok(());
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let index_json = serde_json::to_vec(&self.index)?;
+        builder.append_data(
+            &mut pack_header(index_json.len() as u64),
+            PackIndex::entry_path(id, PACK_INDEX_EXT),
+            index_json.as_slice(),
+        )?;
+
+        let header_offset = builder.get_ref().count();
+        builder.append_data(
+            &mut pack_header(self.data.len() as u64),
+            PackIndex::entry_path(id, PACK_BLOB_EXT),
+            self.data.as_slice(),
+        )?;
+        let padded_size = (self.data.len() as u64).div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+        let data_offset = builder.get_ref().count() - padded_size;
+
+        let index = std::mem::take(&mut self.index);
+        if let Some(archive_index) = archive_index {
+            archive_index
+                .entries
+                .extend(index.files.into_iter().map(|f| ArchiveIndexEntry {
+                    path: f.path,
+                    header_offset,
+                    data_offset: data_offset + f.offset,
+                    size: f.size,
+                }));
+        }
+        self.data.clear();
+        Ok(())
+    }
+}
+
+fn pack_header(size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_mode(0o600);
+    header.set_size(size);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    );
+    header
+}
+
+/// Pack index or blob id of an archive entry path, `None` for regular entries.
+pub fn pack_entry_id(path: &Path, ext: &str) -> Option<usize> {
+    let mut components = path.components();
+    if components.next()?.as_os_str() != PACK_DIR {
+        return None;
+    }
+    let file_name = components.next()?.as_os_str().to_str()?;
+    if components.next().is_some() {
+        return None;
+    }
+    file_name
+        .strip_prefix("pack-")?
+        .strip_suffix(ext)?
+        .strip_suffix('.')?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn writer(blob_size: u64) -> PackWriter {
+        PackWriter::new(&PackConfig {
+            max_file_size: Some(100),
+            blob_size: Some(blob_size),
+        })
+    }
+
+    #[test]
+    fn only_small_files_are_packed() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("small");
+        let large = dir.path().join("large");
+        std::fs::write(&small, [1; 100]).unwrap();
+        std::fs::write(&large, [1; 101]).unwrap();
+        let mut writer = writer(1000);
+
+        let stat = writer
+            .try_add(&ArchiveEntry::keep_src(small.as_path(), Path::new("small")))
+            .unwrap();
+        assert_eq!(stat.map(|s| s.size), Some(100));
+        let entry = ArchiveEntry::keep_src(large.as_path(), Path::new("large"));
+        assert!(writer.try_add(&entry).unwrap().is_none());
+        let entry = ArchiveEntry::keep_src(dir.path(), Path::new("dir"));
+        assert!(writer.try_add(&entry).unwrap().is_none());
+        let entry = ArchiveEntry::captured(Path::new("out"), Path::new("out"), vec![2; 101]);
+        assert!(writer.try_add(&entry).unwrap().is_none());
+        let entry = ArchiveEntry::captured(Path::new("out"), Path::new("out"), vec![2; 10]);
+        assert_eq!(writer.try_add(&entry).unwrap().map(|s| s.size), Some(10));
+
+        let paths = writer
+            .index
+            .files
+            .iter()
+            .map(|f| (f.path.to_path_buf(), f.offset, f.size))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [("small".into(), 0, 100), ("out".into(), 100, 10)]);
+    }
+
+    #[test]
+    fn flush_writes_index_then_blob_and_records_offsets() {
+        let mut writer = writer(64);
+        for (idx, len) in [40, 30].into_iter().enumerate() {
+            let entry = ArchiveEntry::captured(
+                Path::new("cmd"),
+                PathBuf::from(format!("file{idx}")),
+                vec![idx as u8 + 1; len],
+            );
+            assert!(!writer.is_full());
+            writer.try_add(&entry).unwrap();
+        }
+        assert!(writer.is_full());
+
+        let mut builder = tar::Builder::new(CountingWriter::new(Vec::new()));
+        let mut archive_index = ArchiveIndex::default();
+        writer
+            .flush(&mut builder, Some(&mut archive_index))
+            .unwrap();
+        // Nothing pending, nothing written
+        writer.flush(&mut builder, None).unwrap();
+        let tar = builder.into_inner().unwrap().into_inner();
+
+        let mut archive = tar::Archive::new(tar.as_slice());
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                PackIndex::entry_path(0, PACK_INDEX_EXT),
+                PackIndex::entry_path(0, PACK_BLOB_EXT)
+            ]
+        );
+        for (idx, entry) in archive_index.entries.iter().enumerate() {
+            let start = entry.data_offset as usize;
+            let content = &tar[start..start + entry.size as usize];
+            assert!(content.iter().all(|b| *b == idx as u8 + 1), "{entry:?}");
+        }
+        assert_eq!(archive_index.entries.len(), 2);
+        assert!(!writer.is_full() && writer.index.files.is_empty());
+    }
+
+    #[test]
+    fn pack_entry_ids_are_parsed_from_pack_paths_only() {
+        let index = PackIndex::entry_path(42, PACK_INDEX_EXT);
+        assert_eq!(pack_entry_id(&index, PACK_INDEX_EXT), Some(42));
+        assert_eq!(pack_entry_id(&index, PACK_BLOB_EXT), None);
+        for path in [
+            "pack-000042.json",
+            "other/pack-000042.json",
+            ".k_backup_packs/sub/pack-000042.json",
+            ".k_backup_packs/pack-x.json",
+            ".k_backup_packs/pack-000042json",
+        ] {
+            assert_eq!(
+                pack_entry_id(Path::new(path), PACK_INDEX_EXT),
+                None,
+                "{path}"
+            );
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::backup::result_error::result::Result;
+use serde_yml::{Mapping, Value};
+use std::io::ErrorKind;
+
+/// Keys of the source path of each source type, with the key of its destination in the archive.
+static SOURCE_PATH_KEYS: [(&str, &str); 2] = [("src_dir", "dst_dir"), ("src", "dst")];
+
+/// Expand `~`, environment variables and braces in the `src_dir` and `src` paths of the `files`
+/// sources of the YAML config, so one config fits users with different home directories.
+///
+/// A source whose path expands to several, e.g. `/srv/{gitea,nextcloud}/data`, is replaced by a
+/// copy per path. Its destination may use the same braces to get one per path, e.g.
+/// `{gitea,nextcloud}`, otherwise all copies share it. Named sources must expand to one path,
+/// their name is referenced by `depends_on` and `archives`.
+pub fn expand_source_paths(config: &mut Value) -> Result<()> {
+    let Some(files) = config.get_mut("files").and_then(Value::as_sequence_mut) else {
+        return Ok(());
+    };
+    let mut expanded = Vec::with_capacity(files.len());
+    for source in files.drain(..) {
+        expanded.extend(expand_source(source)?);
+    }
+    *files = expanded;
+    Ok(())
+}
+
+fn expand_source(source: Value) -> Result<Vec<Value>> {
+    let Value::Mapping(mapping) = source else {
+        return Ok(vec![source]);
+    };
+    let Some((path_key, dst_key)) = SOURCE_PATH_KEYS
+        .iter()
+        .find(|(key, _)| mapping.get(key).is_some_and(Value::is_string))
+    else {
+        return Ok(vec![Value::Mapping(mapping)]);
+    };
+    let paths = expand_path(
+        mapping
+            .get(path_key)
+            .and_then(Value::as_str)
+            .unwrap_or_default(),
+    )?;
+    if paths.len() > 1 {
+        if let Some(name) = mapping.get("name").and_then(Value::as_str) {
+            Err(invalid_input(format!(
+                "source {name:?} expands to {} paths, named sources must expand to one",
+                paths.len()
+            )))?
+        }
+    }
+    let dsts = match mapping.get(dst_key).and_then(Value::as_str) {
+        Some(dst) => {
+            let dsts = expand_braces(dst);
+            if dsts.len() > 1 && dsts.len() != paths.len() {
+                Err(invalid_input(format!(
+                    "{dst_key} {dst:?} expands to {} paths but {path_key} to {}",
+                    dsts.len(),
+                    paths.len()
+                )))?
+            }
+            Some(dsts)
+        }
+        None => None,
+    };
+    Ok(paths
+        .into_iter()
+        .enumerate()
+        .map(|(idx, path)| {
+            let mut copy: Mapping = mapping.clone();
+            copy.insert(Value::from(*path_key), Value::from(path));
+            if let Some(dsts) = dsts.as_ref().filter(|d| d.len() > 1) {
+                copy.insert(Value::from(*dst_key), Value::from(dsts[idx].as_str()));
+            }
+            Value::Mapping(copy)
+        })
+        .collect())
+}
+
+/// Paths `path` expands to, braces first as in a shell, then a leading `~` and `$NAME` or
+/// `${NAME}` environment variables in each. Unset variables are an error.
+pub fn expand_path(path: &str) -> Result<Vec<String>> {
+    expand_braces(path)
+        .iter()
+        .map(|p| expand_env(&expand_tilde(p)?))
+        .collect()
+}
+
+/// Brace expansion of a shell, `a{b,c{d,e}}f` gives `abf`, `acdf` and `acef`. Braces without a
+/// comma, e.g. `{}`, are kept as is.
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some((start, end, alternatives)) = find_brace_group(pattern) else {
+        return vec![pattern.to_string()];
+    };
+    alternatives
+        .iter()
+        .flat_map(|alternative| {
+            expand_braces(&format!(
+                "{}{alternative}{}",
+                &pattern[..start],
+                &pattern[end + 1..]
+            ))
+        })
+        .collect()
+}
+
+/// Byte offsets of the first braces holding a comma at their level, with the alternatives
+/// between them.
+fn find_brace_group(pattern: &str) -> Option<(usize, usize, Vec<&str>)> {
+    for (start, _) in pattern.char_indices().filter(|(_, c)| *c == '{') {
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        for (offset, c) in pattern[start..].char_indices() {
+            let idx = start + offset;
+            match c {
+                '{' => depth += 1,
+                ',' if depth == 1 => commas.push(idx),
+                '}' => {
+                    depth -= 1;
+                    if depth > 0 {
+                        continue;
+                    }
+                    if commas.is_empty() {
+                        break;
+                    }
+                    let bounds = std::iter::once(start)
+                        .chain(commas.iter().copied())
+                        .zip(commas.iter().copied().chain(std::iter::once(idx)));
+                    return Some((
+                        start,
+                        idx,
+                        bounds.map(|(from, to)| &pattern[from + 1..to]).collect(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Replace `~` alone or followed by `/` with `$HOME`, `~user` is kept as is.
+fn expand_tilde(path: &str) -> Result<String> {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            Ok(format!("{}{rest}", env_var("HOME")?))
+        }
+        _ => Ok(path.to_string()),
+    }
+}
+
+fn expand_env(path: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+        let (name, remainder) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) if end > 0 => (&braced[..end], &braced[end + 1..]),
+                _ => Err(invalid_input(format!("invalid ${{...}} in {path:?}")))?,
+            },
+            None => {
+                let end = after
+                    .char_indices()
+                    .find(|(i, c)| {
+                        !(c.is_ascii_alphanumeric() || *c == '_') || (*i == 0 && c.is_ascii_digit())
+                    })
+                    .map_or(after.len(), |(i, _)| i);
+                after.split_at(end)
+            }
+        };
+        match name.is_empty() {
+            // Not a variable, e.g. a trailing `$`
+            true => expanded.push('$'),
+            false => expanded.push_str(&env_var(name)?),
+        }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn env_var(name: &str) -> Result<String> {
+    std::env::var(name)
+        .map_err(|_| invalid_input(format!("environment variable {name} is not set")).into())
+}
+
+fn invalid_input(msg: String) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Never set, tests only read the environment so they can run in parallel.
+    static UNSET: &str = "K_BACKUP_TEST_UNSET_VARIABLE";
+
+    #[test]
+    fn braces_expand_like_a_shell() {
+        assert_eq!(expand_braces("a{b,c{d,e}}f"), ["abf", "acdf", "acef"]);
+        assert_eq!(expand_braces("{x,y}/{1,2}"), ["x/1", "x/2", "y/1", "y/2"]);
+        assert_eq!(expand_braces("a{,b}"), ["a", "ab"]);
+        assert_eq!(expand_braces("{}/{a}/{b,"), ["{}/{a}/{b,"]);
+        assert_eq!(expand_braces("{a}{b,c}"), ["{a}b", "{a}c"]);
+    }
+
+    #[test]
+    fn tilde_and_variables_expand_from_the_environment() {
+        let home = std::env::var("HOME").unwrap();
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(expand_path("~").unwrap(), [home.as_str()]);
+        assert_eq!(expand_path("~/a").unwrap(), [format!("{home}/a")]);
+        assert_eq!(expand_path("~user/a").unwrap(), ["~user/a"]);
+        assert_eq!(
+            expand_path("$HOME/${PATH}x$").unwrap(),
+            [format!("{home}/{path}x$")]
+        );
+        assert_eq!(expand_path("a$1b/$-").unwrap(), ["a$1b/$-"]);
+        assert_eq!(
+            expand_path("~/{a,b}").unwrap(),
+            [format!("{home}/a"), format!("{home}/b")]
+        );
+        assert!(expand_path(&format!("/${UNSET}")).is_err());
+        assert!(expand_path(&format!("/${{{UNSET}}}")).is_err());
+        assert!(expand_path("/${}").is_err());
+        assert!(expand_path("/${HOME").is_err());
+    }
+
+    fn expand(yaml: &str) -> Result<Value> {
+        let mut config: Value = serde_yml::from_str(yaml).unwrap();
+        expand_source_paths(&mut config)?;
+        Ok(config)
+    }
+
+    #[test]
+    fn sources_are_copied_per_expanded_path() {
+        let config = expand(
+            "files: [{type: glob, src_dir: '/srv/{a,b}/data', dst_dir: '{a,b}'}, \
+             {type: glob, src_dir: '/srv/{c,d}', dst_dir: shared}, \
+             {type: command, command: 'true', dst: out}]",
+        )
+        .unwrap();
+        let expected: Value = serde_yml::from_str(
+            "files: [{type: glob, src_dir: /srv/a/data, dst_dir: a}, \
+             {type: glob, src_dir: /srv/b/data, dst_dir: b}, \
+             {type: glob, src_dir: /srv/c, dst_dir: shared}, \
+             {type: glob, src_dir: /srv/d, dst_dir: shared}, \
+             {type: command, command: 'true', dst: out}]",
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn ambiguous_expansions_are_rejected() {
+        assert!(expand("files: [{name: x, type: glob, src_dir: '/{a,b}'}]").is_err());
+        assert!(expand("files: [{name: x, type: glob, src_dir: '/{a}'}]").is_ok());
+        assert!(expand("files: [{type: glob, src_dir: '/{a,b}', dst_dir: '{x,y,z}'}]").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::backup::result_error::error::Error;
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Unreadable paths listed in a warning, the rest only counted.
+static MAX_LISTED_PATHS: usize = 20;
+
+/// Source paths the process cannot read, found from their permissions without reading them,
+/// see [`crate::backup::backup_config::BackupConfig::permission_scan`].
+#[derive(Debug, Default)]
+pub struct PermissionScan {
+    /// Files checked.
+    pub scanned: usize,
+    pub unreadable_files: Vec<Arc<Path>>,
+    /// Sources or directories that cannot be listed, e.g. without execute permission.
+    pub errors: Vec<Error>,
+}
+
+impl PermissionScan {
+    pub fn is_clean(&self) -> bool {
+        self.unreadable_files.is_empty() && self.errors.is_empty()
+    }
+
+    pub fn unreadable_count(&self) -> usize {
+        self.unreadable_files.len() + self.errors.len()
+    }
+}
+
+impl Display for PermissionScan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} paths unreadable",
+            self.unreadable_count(),
+            self.scanned + self.errors.len()
+        )?;
+        let listed = self
+            .unreadable_files
+            .iter()
+            .map(|path| format!("{path:?}"))
+            .chain(self.errors.iter().map(ToString::to_string))
+            .take(MAX_LISTED_PATHS);
+        for path in listed {
+            write!(f, "\n  {path}")?;
+        }
+        if self.unreadable_count() > MAX_LISTED_PATHS {
+            write!(
+                f,
+                "\n  and {} more",
+                self.unreadable_count() - MAX_LISTED_PATHS
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the process may read the file at `path`, judged from its permissions with the
+/// effective user and groups.
+pub fn is_readable(path: &Path) -> bool {
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `c_path` is a NUL terminated string
+    unsafe {
+        libc::faccessat(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            libc::R_OK,
+            libc::AT_EACCESS,
+        ) == 0
+    }
+}
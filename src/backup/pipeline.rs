@@ -0,0 +1,146 @@
+use crate::backup::compress::CompressorConfig;
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::file_ext::FileExtProvider;
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+/// Bumped whenever the meaning of existing stage fields changes.
+pub static PIPELINE_DESCRIPTOR_VERSION: u32 = 1;
+static TAR_FORMAT: &str = "tar";
+/// Suffix of ASCII armored age output, `tar.age.asc`.
+static ARMOR_EXTENSION: &str = "asc";
+
+/// Extensions restore recognizes, with the stage and format they stand for.
+static KNOWN_EXTENSIONS: [(&str, StageKind, &str); 4] = [
+    ("gz", StageKind::Compress, "gzip"),
+    ("xz", StageKind::Compress, "xz"),
+    ("zst", StageKind::Compress, "zstd"),
+    ("age", StageKind::Encrypt, "age"),
+];
+
+/// Parameters never recorded as they are secrets or local to the creating host.
+static PRIVATE_PARAMETERS: [&str; 2] = ["passphrase", "identity_files"];
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StageKind {
+    Archive,
+    Compress,
+    Encrypt,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct PipelineStage {
+    pub kind: StageKind,
+    pub format: Arc<str>,
+    pub extension: Option<Arc<str>>,
+    pub parameters: Map<String, Value>,
+}
+
+/// Stable description of how an archive was written, in writing order, so it can be reversed
+/// without the config that created it.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct PipelineDescriptor {
+    pub version: u32,
+    pub stages: Vec<PipelineStage>,
+}
+
+impl PipelineDescriptor {
+    pub fn new(compressor: &CompressorConfig, encryptor: &EncryptorConfig) -> Result<Self> {
+        let mut stages = vec![PipelineStage {
+            kind: StageKind::Archive,
+            format: TAR_FORMAT.into(),
+            extension: Some(TAR_FORMAT.into()),
+            parameters: Map::new(),
+        }];
+        if let Some(extension) = compressor.file_ext() {
+            stages.push(Self::stage(
+                StageKind::Compress,
+                serde_json::to_value(compressor)?,
+                "compressor_type",
+                extension,
+            ));
+        }
+        if let Some(extension) = encryptor.file_ext() {
+            stages.push(Self::stage(
+                StageKind::Encrypt,
+                serde_json::to_value(encryptor)?,
+                "encryptor_type",
+                extension,
+            ));
+        }
+        Ok(Self {
+            version: PIPELINE_DESCRIPTOR_VERSION,
+            stages,
+        })
+    }
+
+    fn stage(kind: StageKind, config: Value, tag: &str, extension: Arc<str>) -> PipelineStage {
+        let mut parameters = match config {
+            Value::Object(parameters) => parameters,
+            _ => Map::new(),
+        };
+        let format = match parameters.remove(tag) {
+            Some(Value::String(format)) => format.into(),
+            _ => extension.clone(),
+        };
+        for private in PRIVATE_PARAMETERS {
+            parameters.remove(private);
+        }
+        PipelineStage {
+            kind,
+            format,
+            extension: Some(extension),
+            parameters,
+        }
+    }
+
+    /// Infer the pipeline from an archive file name such as `backup.<time>.tar.xz.age`, stages
+    /// carry no parameters. `None` when the name does not end with a known pipeline extension.
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let components = file_name.split('.').collect::<Vec<_>>();
+        let tar_idx = components.iter().rposition(|c| *c == TAR_FORMAT)?;
+        let mut stages = vec![PipelineStage {
+            kind: StageKind::Archive,
+            format: TAR_FORMAT.into(),
+            extension: Some(TAR_FORMAT.into()),
+            parameters: Map::new(),
+        }];
+        for extension in &components[tar_idx + 1..] {
+            if *extension == ARMOR_EXTENSION {
+                let armored = stages.last_mut().filter(|s| {
+                    s.kind == StageKind::Encrypt && s.extension.as_deref() == Some("age")
+                })?;
+                armored.extension = Some(format!("age.{ARMOR_EXTENSION}").into());
+                armored.parameters.insert("armor".into(), Value::Bool(true));
+                continue;
+            }
+            let (_, kind, format) = KNOWN_EXTENSIONS.iter().find(|(e, _, _)| e == extension)?;
+            // Compression always happens before encryption and each at most once
+            if stages.last().is_some_and(|s| s.kind as u8 >= *kind as u8) {
+                return None;
+            }
+            stages.push(PipelineStage {
+                kind: *kind,
+                format: (*format).into(),
+                extension: Some((*extension).into()),
+                parameters: Map::new(),
+            });
+        }
+        Some(Self {
+            version: PIPELINE_DESCRIPTOR_VERSION,
+            stages,
+        })
+    }
+
+    /// File extension produced by the pipeline, e.g. `tar.xz.age`.
+    pub fn file_ext(&self) -> String {
+        self.stages
+            .iter()
+            .filter_map(|s| s.extension.as_deref())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Scheduled comparison of what the catalog (local archives, their sidecar files and upload
+/// receipts) says exists against the local directory and the destination listings.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ReconcileConfig {
+    pub cron: Arc<str>,
+    /// Fix the catalog where no data is touched: remove sidecar files of archives that are gone
+    /// and forget receipts of copies missing remotely, so `sync` uploads them again.
+    pub repair: Option<bool>,
+    /// Pause between listing destinations, 1s by default.
+    #[serde(default, with = "humantime_serde")]
+    pub destination_interval: Option<Duration>,
+}
+
+/// Difference between the catalog and reality.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(tag = "drift_type", rename_all = "snake_case")]
+pub enum Drift {
+    /// Sidecar file left behind by an archive deleted out of band.
+    OrphanedSidecar { path: Arc<Path> },
+    /// Copy recorded by an upload receipt that the destination does not list.
+    MissingCopy {
+        destination: usize,
+        file_name: Arc<str>,
+    },
+    /// Copy listed by a destination mirroring local retention (`prune` without `retention`)
+    /// of no local archive and not queued for deletion.
+    UnexpectedCopy {
+        destination: usize,
+        file_name: Arc<str>,
+    },
+}
+
+impl Drift {
+    /// Whether repairing the catalog resolves this drift.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, Drift::UnexpectedCopy { .. })
+    }
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Drift::OrphanedSidecar { path } => write!(f, "{path:?} has no archive"),
+            Drift::MissingCopy {
+                destination,
+                file_name,
+            } => write!(
+                f,
+                "{file_name:?} is recorded as uploaded but missing on destination {destination}"
+            ),
+            Drift::UnexpectedCopy {
+                destination,
+                file_name,
+            } => write!(
+                f,
+                "{file_name:?} on destination {destination} has no local archive"
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct ReconcileReport {
+    pub drifts: Vec<Drift>,
+    /// Drifts of `drifts` resolved by repairing the catalog.
+    pub repaired: usize,
+    /// Destinations that could not be listed, with the error.
+    pub unchecked_destinations: Vec<(usize, Arc<str>)>,
+}
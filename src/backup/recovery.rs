@@ -0,0 +1,324 @@
+#[cfg(feature = "age")]
+use crate::backup::encrypt::age::AgeSecretConfig;
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::pipeline::{PipelineDescriptor, StageKind};
+use crate::backup::result_error::result::Result;
+use crate::backup::volume::volume_paths;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, IntoInnerError};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+static RECOVERY_JSON_SUFFIX: &str = ".recovery.json";
+static RECOVERY_MD_SUFFIX: &str = ".RECOVERY.md";
+static K_BACKUP_COMMAND: &str = "k_backup";
+
+/// Key able to decrypt an archive, identified without revealing any secret.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RecoveryKey {
+    /// `passphrase`, `age-x25519` or `ssh-ed25519`.
+    pub key_type: Arc<str>,
+    /// Public key of the recipient, empty for a passphrase.
+    pub public_key: Arc<str>,
+}
+
+/// How to restore an archive with standard tools only, written next to the archive so whoever
+/// restores needs neither k-backup nor its config.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RecoveryInstructions {
+    pub archive_file: Arc<str>,
+    pub backup_time: DateTime<Utc>,
+    pub pipeline: PipelineDescriptor,
+    /// Any one of these decrypts the archive, or `threshold` of them together.
+    pub keys: Vec<RecoveryKey>,
+    pub threshold: Option<u8>,
+    pub archive_sha256: Option<String>,
+    /// Shell command extracting the archive into the current directory, with standard tools
+    /// unless the encryption needs `k_backup`.
+    pub command: Option<String>,
+    pub notes: Vec<String>,
+}
+
+impl RecoveryInstructions {
+    pub fn new(
+        archive_path: &Path,
+        backup_time: DateTime<Utc>,
+        pipeline: PipelineDescriptor,
+        encryptor: &EncryptorConfig,
+        archive_sha256: Option<String>,
+        packed: bool,
+    ) -> Self {
+        let archive_file: Arc<str> = archive_path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default()
+            .into();
+        let (keys, threshold, age_compatible) = recovery_keys(encryptor);
+        let mut notes = vec![
+            "Every archive is self-contained, no other archive is needed to restore it."
+                .to_string(),
+            "The config that created the archive is stored inside it as .k-backup/config.yml, \
+             with secrets redacted."
+                .to_string(),
+        ];
+        let split = !volume_paths(archive_path).is_empty();
+        if split {
+            notes.push(format!(
+                "The archive is split into volumes {archive_file}.000, .001 and so on, together \
+                 in that order they form the archive."
+            ));
+        }
+        if packed {
+            notes.push(
+                "Small files are packed into .k_backup_packs/pack-*.bin blobs, described by the \
+                 pack-*.json index next to each blob (path, offset and size of every file). \
+                 `k_backup restore` unpacks them."
+                    .to_string(),
+            );
+        }
+        let command = match (threshold, age_compatible) {
+            (Some(threshold), _) => {
+                notes.push(format!(
+                    "Threshold encryption cannot be decrypted by the age CLI. Pass the identity \
+                     files of any {threshold} of the {} key holders to `k_backup restore`, one \
+                     `--identity` each.",
+                    keys.len()
+                ));
+                k_backup_restore_command(&archive_file, threshold)
+            }
+            (None, false) => {
+                notes.push(
+                    "The passphrase is stretched with a custom scrypt work factor, stored in a \
+                     k-backup-scrypt stanza the age CLI cannot read. `k_backup restore` asks for \
+                     the passphrase, or takes it from K_BACKUP_PASSPHRASE."
+                        .to_string(),
+                );
+                k_backup_restore_command(&archive_file, 0)
+            }
+            (None, true) => restore_command(&archive_file, split, &pipeline, &keys),
+        };
+        Self {
+            archive_file,
+            backup_time,
+            pipeline,
+            keys,
+            threshold,
+            archive_sha256,
+            command: Some(command),
+            notes,
+        }
+    }
+
+    pub fn json_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+        with_suffix(archive_path.as_ref(), RECOVERY_JSON_SUFFIX)
+    }
+
+    pub fn markdown_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+        with_suffix(archive_path.as_ref(), RECOVERY_MD_SUFFIX)
+    }
+
+    /// Write the instructions as JSON and Markdown next to `archive_path`.
+    pub fn write<P: AsRef<Path>>(&self, archive_path: P) -> Result<()> {
+        let archive_path = archive_path.as_ref();
+        let mut writer = BufWriter::new(File::create(Self::json_path(archive_path))?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.into_inner().map_err(IntoInnerError::into_error)?;
+        std::fs::write(Self::markdown_path(archive_path), self.to_markdown())?;
+        Ok(())
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(md, "# Recovering `{}`\n", self.archive_file);
+        let _ = writeln!(
+            md,
+            "Backup taken {}. Stages in writing order: {}.\n",
+            self.backup_time.to_rfc3339(),
+            self.pipeline
+                .stages
+                .iter()
+                .map(|s| s.format.as_ref())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        if let Some(sha256) = &self.archive_sha256 {
+            let _ = writeln!(md, "## Check the archive\n");
+            let _ = writeln!(
+                md,
+                "```sh\necho \"{sha256}  {}\" | sha256sum -c -\n```\n",
+                self.archive_file
+            );
+        }
+        let _ = writeln!(md, "## Keys\n");
+        if self.keys.is_empty() {
+            let _ = writeln!(md, "The archive is not encrypted.\n");
+        }
+        if let Some(threshold) = self.threshold {
+            let _ = writeln!(md, "Any {threshold} of these keys together decrypt it:\n");
+        } else if !self.keys.is_empty() {
+            let _ = writeln!(md, "Any one of these decrypts it:\n");
+        }
+        for key in self.keys.iter() {
+            match key.public_key.is_empty() {
+                true => {
+                    let _ = writeln!(md, "- the {} of the backup job", key.key_type);
+                }
+                false => {
+                    let _ = writeln!(md, "- {} private key of `{}`", key.key_type, key.public_key);
+                }
+            }
+        }
+        if !self.keys.is_empty() {
+            md.push('\n');
+        }
+        if let Some(command) = &self.command {
+            let _ = writeln!(md, "## Restore\n");
+            let _ = writeln!(
+                md,
+                "Run in an empty directory, needs {}:\n",
+                self.tools().join(", ")
+            );
+            let _ = writeln!(md, "```sh\n{command}\n```\n");
+        }
+        let _ = writeln!(md, "## Notes\n");
+        for note in self.notes.iter() {
+            let _ = writeln!(md, "- {note}");
+        }
+        md
+    }
+
+    /// Command line tools used by `command`.
+    fn tools(&self) -> Vec<&str> {
+        if self
+            .command
+            .as_deref()
+            .is_some_and(|c| c.starts_with(K_BACKUP_COMMAND))
+        {
+            return vec![K_BACKUP_COMMAND];
+        }
+        self.pipeline
+            .stages
+            .iter()
+            .rev()
+            .map(|s| match s.format.as_ref() {
+                "gzip" => "gzip",
+                "zstd" => "zstd",
+                "xz" => "xz",
+                "age" => "age",
+                _ => "tar",
+            })
+            .collect()
+    }
+}
+
+fn with_suffix(archive_path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = archive_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    archive_path.with_file_name(file_name)
+}
+
+/// Keys of `encryptor`, the threshold of them needed, and whether the age CLI can decrypt.
+#[cfg_attr(not(feature = "age"), allow(unused_variables))]
+fn recovery_keys(encryptor: &EncryptorConfig) -> (Vec<RecoveryKey>, Option<u8>, bool) {
+    match encryptor {
+        EncryptorConfig::None => (vec![], None, true),
+        #[cfg(feature = "age")]
+        EncryptorConfig::Age(age) => {
+            let recipient_keys = |recipients: &[Arc<str>]| {
+                recipients
+                    .iter()
+                    .map(|r| RecoveryKey {
+                        key_type: match r.starts_with("ssh-") {
+                            true => "ssh-ed25519".into(),
+                            false => "age-x25519".into(),
+                        },
+                        public_key: r.trim().into(),
+                    })
+                    .collect()
+            };
+            match &age.secret {
+                AgeSecretConfig::Passphrase { work_factor, .. } => (
+                    vec![RecoveryKey {
+                        key_type: "passphrase".into(),
+                        public_key: "".into(),
+                    }],
+                    None,
+                    work_factor.is_none(),
+                ),
+                AgeSecretConfig::Recipients { recipients, .. } => {
+                    let recipients = age.secret.recipients().unwrap_or(recipients.clone());
+                    (recipient_keys(&recipients), None, true)
+                }
+                AgeSecretConfig::Threshold {
+                    threshold,
+                    recipients,
+                    ..
+                } => (recipient_keys(recipients), Some(*threshold), false),
+            }
+        }
+    }
+}
+
+/// `k_backup restore` of `archive_file` into the current directory, with `identities` identity
+/// file placeholders.
+fn k_backup_restore_command(archive_file: &str, identities: u8) -> String {
+    let mut command = format!("{K_BACKUP_COMMAND} restore --target .");
+    for idx in 1..=identities {
+        let _ = write!(command, " --identity <private key file {idx}>");
+    }
+    let _ = write!(command, " '{}'", archive_file.replace('\'', r"'\''"));
+    command
+}
+
+/// Pipe reversing `pipeline` over `archive_file`, or its volumes when `split`, with standard
+/// tools.
+fn restore_command(
+    archive_file: &str,
+    split: bool,
+    pipeline: &PipelineDescriptor,
+    keys: &[RecoveryKey],
+) -> String {
+    let mut commands = Vec::new();
+    for stage in pipeline.stages.iter().rev() {
+        let command = match (stage.kind, stage.format.as_ref()) {
+            (StageKind::Encrypt, "age") => match keys.iter().any(|k| !k.public_key.is_empty()) {
+                true => "age --decrypt --identity <private key file>".to_string(),
+                false => "age --decrypt".to_string(),
+            },
+            (StageKind::Compress, "gzip") => "gzip --decompress --stdout".to_string(),
+            (StageKind::Compress, "zstd") => "zstd --decompress --stdout".to_string(),
+            (StageKind::Compress, "xz") => "xz --decompress --stdout".to_string(),
+            (_, format) => format!("{format} --extract --preserve-permissions --file"),
+        };
+        commands.push(command);
+    }
+    let quoted = format!("'{}'", archive_file.replace('\'', r"'\''"));
+    if split {
+        // Every stage reads the volumes from stdin, the glob lists them in order
+        if let Some(last) = commands.last_mut() {
+            last.push_str(" -");
+        }
+        commands.insert(0, format!("cat {quoted}.[0-9][0-9][0-9]"));
+        return commands.join(" | ");
+    }
+    match commands.split_first_mut() {
+        Some((first, rest)) if !rest.is_empty() => {
+            first.push(' ');
+            first.push_str(&quoted);
+            if let Some(last) = rest.last_mut() {
+                last.push_str(" -");
+            }
+        }
+        Some((first, _)) => {
+            first.push(' ');
+            first.push_str(&quoted);
+        }
+        None => {}
+    }
+    commands.join(" | ")
+}
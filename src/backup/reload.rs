@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// SIGHUPs received since [`listen_for_sighup`].
+static SIGHUP_COUNT: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn on_sighup(_signal: libc::c_int) {
+    SIGHUP_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Count SIGHUPs for [`ReloadWatch`] instead of being terminated by them.
+pub fn listen_for_sighup() {
+    // SAFETY: the handler only increments an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            on_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// Tells the daemon loops to return so the config at `path` is reloaded, once SIGHUP is
+/// received or, when watched, the file is modified. Changes made while loading the config are
+/// caught by creating the watch first.
+#[derive(Debug)]
+pub struct ReloadWatch {
+    path: PathBuf,
+    sighups: u64,
+    /// Modification time of the watched file when the watch was created.
+    modified: Option<Option<SystemTime>>,
+    reloaded: bool,
+}
+
+impl ReloadWatch {
+    /// Watch for a reload of the config at `path`, also checking its modification time when
+    /// `watch_file` is set. `reloaded` tells the loops they run a reloaded config.
+    pub fn new<P: AsRef<Path>>(path: P, watch_file: bool, reloaded: bool) -> Self {
+        let path = path.as_ref().to_path_buf();
+        Self {
+            sighups: SIGHUP_COUNT.load(Ordering::SeqCst),
+            modified: watch_file.then(|| modified(&path)),
+            path,
+            reloaded,
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        SIGHUP_COUNT.load(Ordering::SeqCst) != self.sighups
+            || self
+                .modified
+                .is_some_and(|modified_at| modified(&self.path) != modified_at)
+    }
+
+    /// Whether the config is a reloaded one, not the one the daemon started with.
+    pub fn is_reloaded(&self) -> bool {
+        self.reloaded
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
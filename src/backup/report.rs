@@ -0,0 +1,318 @@
+use crate::backup::archive::{ArchiveEntry, ArchiveSourceConfig};
+use crate::backup::content_type::{ContentType, ContentTypeShare, ContentTypeStats};
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::humanize::{HumanDuration, HumanSize};
+use crate::backup::metadata::{read_metadata, write_metadata};
+use crate::backup::pipeline::PipelineDescriptor;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::FileType;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+static REPORT_FILE_SUFFIX: &str = ".report.json";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SourceReport {
+    pub name: Option<Arc<str>>,
+    pub source_type: Arc<str>,
+    pub entries: u64,
+    pub bytes: u64,
+    pub skipped_entries: u64,
+    /// Missing in reports written before special files were counted.
+    #[serde(default)]
+    pub skipped_special_files: SpecialFileCounts,
+    /// Entries of files already archived by the run, see `duplicate_entries`. Missing in reports
+    /// written before duplicates were skipped.
+    #[serde(default)]
+    pub duplicate_entries: u64,
+}
+
+/// Non-regular files matched by a source but never archived, by type.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct SpecialFileCounts {
+    pub sockets: u64,
+    pub fifos: u64,
+    pub block_devices: u64,
+    pub char_devices: u64,
+    /// Broken symlinks and anything else neither file nor directory.
+    pub other: u64,
+}
+
+impl SpecialFileCounts {
+    pub fn total(&self) -> u64 {
+        self.sockets + self.fifos + self.block_devices + self.char_devices + self.other
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BackupReport {
+    pub archive_file: Arc<Path>,
+    pub backup_time: DateTime<Utc>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    #[serde(with = "humantime_serde")]
+    pub duration: std::time::Duration,
+    pub archive_size: u64,
+    /// Bytes read from the sources per second of `duration`. Missing in reports written before
+    /// throughput was recorded.
+    #[serde(default)]
+    pub read_bytes_per_second: u64,
+    pub description: Option<Arc<str>>,
+    /// Created by a manual run, missing in reports written before runs were tagged.
+    #[serde(default)]
+    pub manual: bool,
+    /// Missing in reports written before pipelines were recorded.
+    pub pipeline: Option<PipelineDescriptor>,
+    pub sources: Vec<SourceReport>,
+    /// Only present when the stat cache is enabled.
+    pub changes: Option<ChangeSummary>,
+    /// Breakdown of the archived files by content type, only present with `content_types`.
+    pub content_types: Option<Vec<ContentTypeShare>>,
+    /// Completed uploads to the storage destinations, only present with `storage`.
+    #[serde(default)]
+    pub uploads: Option<Vec<UploadReport>>,
+    /// Bytes read from the sources per second from `started_at` until the last upload is done,
+    /// only present with `storage`.
+    #[serde(default)]
+    pub end_to_end_bytes_per_second: Option<u64>,
+    pub non_fatal_errors: Vec<String>,
+}
+
+/// Upload of the archive to a storage destination.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UploadReport {
+    pub destination: usize,
+    pub bytes: u64,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    pub bytes_per_second: u64,
+}
+
+impl UploadReport {
+    pub fn new(destination: usize, bytes: u64, duration: Duration) -> Self {
+        Self {
+            destination,
+            bytes,
+            duration,
+            bytes_per_second: bytes_per_second(bytes, duration),
+        }
+    }
+}
+
+pub fn bytes_per_second(bytes: u64, duration: Duration) -> u64 {
+    match duration.as_secs_f64() {
+        secs if secs > 0.0 => (bytes as f64 / secs) as u64,
+        _ => 0,
+    }
+}
+
+/// Messages of `error` for `non_fatal_errors`, one per chained error.
+pub fn error_messages(error: &Error) -> Vec<String> {
+    match error {
+        Error::LotsOfError(errors) => errors.iter().map(Error::to_string).collect(),
+        e => vec![e.to_string()],
+    }
+}
+
+/// Files changed since the previous run, from the stat cache.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct ChangeSummary {
+    pub new: u64,
+    pub modified: u64,
+    pub unchanged: u64,
+    pub removed: u64,
+}
+
+/// Counters for a single source, updated concurrently while entries are collected.
+#[derive(Default, Debug)]
+pub struct SourceStats {
+    entries: AtomicU64,
+    /// Added to by the archive writer, which knows the size without another stat.
+    bytes: Arc<AtomicU64>,
+    skipped_entries: AtomicU64,
+    duplicate_entries: AtomicU64,
+    special_files: Arc<SpecialFileStats>,
+    content_types: Option<ContentTypeStats>,
+}
+
+/// Special file counters shared with the source walking the tree.
+#[derive(Default, Debug)]
+pub struct SpecialFileStats {
+    sockets: AtomicU64,
+    fifos: AtomicU64,
+    block_devices: AtomicU64,
+    char_devices: AtomicU64,
+    other: AtomicU64,
+}
+
+impl SpecialFileStats {
+    pub fn record(&self, file_type: &FileType) {
+        let counter = if file_type.is_socket() {
+            &self.sockets
+        } else if file_type.is_fifo() {
+            &self.fifos
+        } else if file_type.is_block_device() {
+            &self.block_devices
+        } else if file_type.is_char_device() {
+            &self.char_devices
+        } else {
+            &self.other
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn to_counts(&self) -> SpecialFileCounts {
+        SpecialFileCounts {
+            sockets: self.sockets.load(Ordering::Relaxed),
+            fifos: self.fifos.load(Ordering::Relaxed),
+            block_devices: self.block_devices.load(Ordering::Relaxed),
+            char_devices: self.char_devices.load(Ordering::Relaxed),
+            other: self.other.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl SourceStats {
+    /// Stats also detecting the content type of every file when `content_types`.
+    pub fn new(content_types: bool) -> Self {
+        Self {
+            content_types: content_types.then(ContentTypeStats::default),
+            ..Default::default()
+        }
+    }
+
+    /// Count `entry`, only files detected for `content_types` are read here.
+    pub fn record_entry(&self, entry: &ArchiveEntry) {
+        self.entries.fetch_add(1, Ordering::Relaxed);
+        let Some(content_types) = self.content_types.as_ref() else {
+            return;
+        };
+        if let Some(data) = &entry.data {
+            content_types.record(ContentType::detect(data), data.len() as u64);
+            return;
+        }
+        if let Some(metadata) = std::fs::metadata(&entry.src).ok().filter(|m| m.is_file()) {
+            let content_type = ContentType::detect_file(&entry.src).unwrap_or(ContentType::Binary);
+            content_types.record(content_type, metadata.len());
+        }
+    }
+
+    /// Byte counter of archived entries, see [`ArchiveEntry::record_archived`].
+    pub fn archived_bytes(&self) -> Arc<AtomicU64> {
+        self.bytes.clone()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn content_types(&self) -> Option<&ContentTypeStats> {
+        self.content_types.as_ref()
+    }
+
+    pub fn record_skipped(&self) {
+        self.skipped_entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_duplicate(&self) {
+        self.duplicate_entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn special_files(&self) -> Arc<SpecialFileStats> {
+        self.special_files.clone()
+    }
+
+    pub fn to_report(&self, source: &ArchiveSourceConfig) -> SourceReport {
+        SourceReport {
+            name: source.name.clone(),
+            source_type: source.source.type_name().into(),
+            entries: self.entries.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            skipped_entries: self.skipped_entries.load(Ordering::Relaxed),
+            skipped_special_files: self.special_files.to_counts(),
+            duplicate_entries: self.duplicate_entries.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl BackupReport {
+    pub fn report_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+        let archive_path = archive_path.as_ref();
+        let mut file_name = archive_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(REPORT_FILE_SUFFIX);
+        archive_path.with_file_name(file_name)
+    }
+
+    pub fn write(&self, encryptor: &EncryptorConfig) -> Result<PathBuf> {
+        write_metadata(Self::report_path(&self.archive_file), encryptor, |w| {
+            Ok(serde_json::to_writer_pretty(w, self)?)
+        })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P, encryptor: &EncryptorConfig) -> Result<Self> {
+        read_metadata(path, encryptor)
+    }
+
+    /// Bytes read from all sources.
+    pub fn bytes_read(&self) -> u64 {
+        self.sources.iter().map(|s| s.bytes).sum()
+    }
+
+    /// Record the uploads of the archive that ended at `finished_at`, `error` telling which
+    /// destinations failed.
+    pub fn record_uploads(
+        &mut self,
+        uploads: Vec<UploadReport>,
+        finished_at: DateTime<Utc>,
+        error: Option<&Error>,
+    ) {
+        let duration = (finished_at - self.started_at).to_std().unwrap_or_default();
+        self.end_to_end_bytes_per_second = Some(bytes_per_second(self.bytes_read(), duration));
+        self.uploads = Some(uploads);
+        self.non_fatal_errors
+            .extend(error.into_iter().flat_map(error_messages));
+    }
+}
+
+/// One line summary with humanized sizes and durations, for logs and notifications.
+impl Display for BackupReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let entries: u64 = self.sources.iter().map(|s| s.entries).sum();
+        write!(
+            f,
+            "{} entries ({}) from {} source{} archived to {} in {} ({}/s)",
+            entries,
+            HumanSize(self.bytes_read()),
+            self.sources.len(),
+            if self.sources.len() == 1 { "" } else { "s" },
+            HumanSize(self.archive_size),
+            HumanDuration(self.duration),
+            HumanSize(self.read_bytes_per_second)
+        )?;
+        if let Some(end_to_end) = self.end_to_end_bytes_per_second {
+            write!(f, ", {}/s end to end", HumanSize(end_to_end))?;
+        }
+        for share in self.content_types.iter().flatten().take(3) {
+            write!(f, ", {:.0}% {}", share.percent, share.content_type)?;
+        }
+        let skipped: u64 = self.sources.iter().map(|s| s.skipped_entries).sum();
+        if skipped > 0 {
+            write!(f, ", {skipped} skipped")?;
+        }
+        let duplicates: u64 = self.sources.iter().map(|s| s.duplicate_entries).sum();
+        if duplicates > 0 {
+            write!(f, ", {duplicates} duplicates skipped")?;
+        }
+        if !self.non_fatal_errors.is_empty() {
+            write!(f, ", {} non fatal error(s)", self.non_fatal_errors.len())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,546 @@
+use crate::backup::checksum::ArchiveChecksums;
+use crate::backup::compress::Decompressor;
+#[cfg(feature = "age")]
+use crate::backup::encrypt::age::{decrypt_age, read_identities, AgeIdentity};
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::pack::{pack_entry_id, PackIndex, PACK_BLOB_EXT, PACK_INDEX_EXT};
+use crate::backup::pipeline::{PipelineDescriptor, StageKind};
+use crate::backup::report::BackupReport;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use crate::backup::volume::open_archive_file;
+use secrecy::SecretString;
+use serde::Serialize;
+use std::fs::{OpenOptions, Permissions};
+use std::io::{BufReader, ErrorKind, Read};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+pub static PASSPHRASE_ENV: &str = "K_BACKUP_PASSPHRASE";
+static PASSWD_PATH: &str = "/etc/passwd";
+static GROUP_PATH: &str = "/etc/group";
+
+/// Owner to force on every restored entry, parsed from `user:group`, `user`, or `:group`.
+///
+/// Both parts accept a numeric id or a name looked up in the local `/etc/passwd` and
+/// `/etc/group`, so the owner is resolved on the host the archive is extracted to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct OwnerSpec {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FromStr for OwnerSpec {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, group),
+            None => (s, ""),
+        };
+        if user.is_empty() && group.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("owner {s:?} has neither user nor group"),
+            ));
+        }
+        let uid = (!user.is_empty())
+            .then(|| resolve_id(user, PASSWD_PATH))
+            .transpose()?;
+        let gid = (!group.is_empty())
+            .then(|| resolve_id(group, GROUP_PATH))
+            .transpose()?;
+        Ok(OwnerSpec { uid, gid })
+    }
+}
+
+/// Resolve a numeric id or name through a `name:password:id:...` database file.
+fn resolve_id(name_or_id: &str, database: &str) -> std::io::Result<u32> {
+    if let Ok(id) = name_or_id.parse() {
+        return Ok(id);
+    }
+    std::fs::read_to_string(database)?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?;
+            (name == name_or_id).then(|| id.parse().ok()).flatten()
+        })
+        .next()
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("{name_or_id:?} not found in {database}"),
+            )
+        })
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct RestoreOptions {
+    /// Owner of restored entries, the archived owner is restored otherwise which requires root.
+    pub chown: Option<OwnerSpec>,
+    /// Leading path components removed from entry names, shorter entries are skipped.
+    pub strip_components: usize,
+    /// Permission bits cleared from the archived mode of every restored entry.
+    pub umask: Option<u32>,
+}
+
+impl RestoreOptions {
+    /// Path of an entry below `target_dir`, `None` when it is stripped away entirely.
+    ///
+    /// Entry names escaping `target_dir` (absolute or `..`) are rejected.
+    pub fn target_path(&self, target_dir: &Path, entry_path: &Path) -> Result<Option<PathBuf>> {
+        let mut components = Vec::new();
+        for component in entry_path.components() {
+            match component {
+                Component::Normal(c) => components.push(c),
+                Component::CurDir => {}
+                _ => Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("entry {entry_path:?} escapes restore target"),
+                ))?,
+            }
+        }
+        if components.len() <= self.strip_components {
+            return Ok(None);
+        }
+        Ok(Some(
+            components[self.strip_components..]
+                .iter()
+                .fold(target_dir.to_path_buf(), |p, c| p.join(c)),
+        ))
+    }
+}
+
+/// Extract every entry of `archive` into `target_dir` applying `options`.
+///
+/// Like [`tar::Archive::unpack`], nothing is written outside `target_dir`, be it through `..`,
+/// symlinks already extracted or link targets, and modes of directories are applied last so
+/// read-only directories can still be filled.
+pub fn extract<R: Read>(
+    mut archive: tar::Archive<R>,
+    target_dir: &Path,
+    options: &RestoreOptions,
+) -> Result<()> {
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(options.chown.is_none());
+    archive.set_preserve_mtime(true);
+    std::fs::create_dir_all(target_dir)?;
+    let target_dir = target_dir.canonicalize()?;
+
+    let mut pack_index = None;
+    let mut dirs = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_pax_global_extensions() {
+            continue;
+        }
+        let entry_path = entry.path()?.into_owned();
+        if pack_entry_id(&entry_path, PACK_INDEX_EXT).is_some() {
+            pack_index = Some(serde_json::from_reader::<_, PackIndex>(&mut entry)?);
+            continue;
+        }
+        if pack_entry_id(&entry_path, PACK_BLOB_EXT).is_some() {
+            let index = pack_index.take().ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("pack {entry_path:?} has no preceding index"),
+                )
+            })?;
+            extract_pack(&mut entry, &index, &target_dir, options)
+                .with_msg(format!("Failed to unpack {entry_path:?}"))?;
+            continue;
+        }
+        let Some(dst) = options.target_path(&target_dir, &entry_path)? else {
+            continue;
+        };
+        create_parent_within(&target_dir, &dst)?;
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        if entry_type.is_dir() {
+            reject_symlink(&dst)?;
+            std::fs::create_dir_all(&dst)?;
+            dirs.push(DeferredDir {
+                mode: header.mode()?,
+                mtime: header.mtime()?,
+                uid: header.uid()? as u32,
+                gid: header.gid()? as u32,
+                path: dst,
+            });
+            continue;
+        }
+        let mode = header.mode()?;
+        let is_symlink = entry_type.is_symlink();
+        let link = match is_symlink || entry_type.is_hard_link() {
+            true => Some(entry.link_name()?.map(|l| l.into_owned()).ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("link {entry_path:?} has no target"),
+                )
+            })?),
+            false => None,
+        };
+        // Hard link targets are archive paths, `unpack` would resolve them from the working
+        // directory
+        let hard_link_src = match &link {
+            Some(link) if !is_symlink => options
+                .target_path(&target_dir, link)?
+                .filter(|src| is_within(&target_dir, src.parent().unwrap_or(&target_dir))),
+            _ => None,
+        };
+        if let Some(link) = &link {
+            let escapes = match is_symlink {
+                true => symlink_escapes(&target_dir, &dst, link),
+                false => hard_link_src.is_none(),
+            };
+            if escapes {
+                Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("link {entry_path:?} to {link:?} escapes restore target"),
+                ))?;
+            }
+        }
+        match &hard_link_src {
+            Some(src) => std::fs::hard_link(src, &dst).map_err(Error::from),
+            None => entry.unpack(&dst).map(|_| ()).map_err(Error::from),
+        }
+        .with_msg(format!("Failed to restore {entry_path:?} to {dst:?}"))?;
+
+        if let Some(umask) = options.umask.filter(|_| !is_symlink) {
+            std::fs::set_permissions(&dst, Permissions::from_mode(mode & !umask))?;
+        }
+        if let Some(owner) = &options.chown {
+            std::os::unix::fs::lchown(&dst, owner.uid, owner.gid)
+                .map_err(Error::from)
+                .with_msg(format!("Failed to change owner of {dst:?}"))?;
+        }
+    }
+    // Decoders and volume manifests only check trailing integrity data once read to the end
+    std::io::copy(&mut archive.into_inner(), &mut std::io::sink())
+        .map_err(Error::from)
+        .with_msg("Read end of archive failed")?;
+
+    // Deepest first, so restoring the mtime of a directory is not undone by its children
+    for dir in dirs.iter().rev() {
+        dir.apply(options)
+            .with_msg(format!("Failed to restore attributes of {:?}", dir.path))?;
+    }
+    Ok(())
+}
+
+/// Directory whose attributes are applied once every entry is extracted.
+struct DeferredDir {
+    path: PathBuf,
+    mode: u32,
+    mtime: u64,
+    uid: u32,
+    gid: u32,
+}
+
+impl DeferredDir {
+    /// Applied through the directory itself, never through a symlink replacing it meanwhile.
+    fn apply(&self, options: &RestoreOptions) -> Result<()> {
+        let (uid, gid) = match &options.chown {
+            Some(owner) => (owner.uid, owner.gid),
+            None => (Some(self.uid), Some(self.gid)),
+        };
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_DIRECTORY)
+            .open(&self.path)?;
+        std::os::unix::fs::fchown(&dir, uid, gid)?;
+        dir.set_modified(UNIX_EPOCH + Duration::from_secs(self.mtime))?;
+        let mode = self.mode & 0o7777 & !options.umask.unwrap_or(0);
+        dir.set_permissions(Permissions::from_mode(mode))?;
+        Ok(())
+    }
+}
+
+/// Create the parent directories of `dst`, failing when a symlink among them leads outside of
+/// `target_dir`, which must be canonical.
+fn create_parent_within(target_dir: &Path, dst: &Path) -> Result<()> {
+    let Some(parent) = dst.parent() else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(parent)?;
+    if !is_within(target_dir, parent) {
+        Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{dst:?} is outside of restore target through a symlink"),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Whether `path`, which must exist, resolves within `target_dir` once every symlink is
+/// followed.
+fn is_within(target_dir: &Path, path: &Path) -> bool {
+    path.canonicalize()
+        .is_ok_and(|path| path.starts_with(target_dir))
+}
+
+/// Fail when `dst` is a symlink, which writing to it would follow.
+fn reject_symlink(dst: &Path) -> Result<()> {
+    if dst
+        .symlink_metadata()
+        .is_ok_and(|m| m.file_type().is_symlink())
+    {
+        Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{dst:?} is a symlink, restoring over it could write outside restore target"),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Whether a symlink at `dst` pointing to `link` resolves outside of `target_dir`. Symlinks
+/// already extracted are followed as the kernel would, the path of parts not extracted yet is
+/// taken as written, and no step may leave `target_dir`.
+fn symlink_escapes(target_dir: &Path, dst: &Path, link: &Path) -> bool {
+    let Ok(mut resolved) = dst.parent().unwrap_or(target_dir).canonicalize() else {
+        return true;
+    };
+    for component in link.components() {
+        match component {
+            Component::Normal(c) => {
+                resolved.push(c);
+                // Dangling links cannot be resolved, an entry could later fill their target
+                if resolved.symlink_metadata().is_ok() {
+                    match resolved.canonicalize() {
+                        Ok(canonical) => resolved = canonical,
+                        Err(_) => return true,
+                    }
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::RootDir | Component::Prefix(_) => return true,
+        }
+        if !resolved.starts_with(target_dir) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Write the files of a pack blob, which are stored in index order.
+fn extract_pack<R: Read>(
+    blob: &mut R,
+    index: &PackIndex,
+    target_dir: &Path,
+    options: &RestoreOptions,
+) -> Result<()> {
+    let mut position = 0;
+    for file in index.files.iter() {
+        std::io::copy(&mut blob.take(file.offset - position), &mut std::io::sink())?;
+        position = file.offset + file.size;
+        let Some(dst) = options.target_path(target_dir, &file.path)? else {
+            std::io::copy(&mut blob.take(file.size), &mut std::io::sink())?;
+            continue;
+        };
+        create_parent_within(target_dir, &dst)?;
+        reject_symlink(&dst)?;
+        // Not following a symlink planted meanwhile either
+        let mut out = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&dst)?;
+        std::io::copy(&mut blob.take(file.size), &mut out)?;
+        out.set_modified(UNIX_EPOCH + Duration::from_secs(file.mtime))?;
+        let mode = file.mode & 0o7777 & !options.umask.unwrap_or(0);
+        out.set_permissions(Permissions::from_mode(mode))?;
+        let (uid, gid) = match &options.chown {
+            Some(owner) => (owner.uid, owner.gid),
+            None => (Some(file.uid as u32), Some(file.gid as u32)),
+        };
+        std::os::unix::fs::fchown(&out, uid, gid)
+            .map_err(Error::from)
+            .with_msg(format!("Failed to change owner of {dst:?}"))?;
+    }
+    Ok(())
+}
+
+/// Entry of an archive as listed by [`list_entries`].
+#[derive(Clone, Serialize, Debug)]
+pub struct ListedEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Modification time in seconds since the epoch.
+    pub mtime: u64,
+    pub is_dir: bool,
+}
+
+/// Read every entry header of `archive` in order, calling `f` with each, without writing
+/// anything. Files of packs are listed by their own path instead of the pack blob.
+pub fn list_entries<R: Read, F: FnMut(ListedEntry) -> Result<()>>(
+    mut archive: tar::Archive<R>,
+    mut f: F,
+) -> Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_pax_global_extensions() {
+            continue;
+        }
+        let entry_path = entry.path()?.into_owned();
+        if pack_entry_id(&entry_path, PACK_INDEX_EXT).is_some() {
+            let index = serde_json::from_reader::<_, PackIndex>(&mut entry)?;
+            for file in index.files {
+                f(ListedEntry {
+                    path: file.path.to_path_buf(),
+                    size: file.size,
+                    mtime: file.mtime,
+                    is_dir: false,
+                })?;
+            }
+            continue;
+        }
+        if pack_entry_id(&entry_path, PACK_BLOB_EXT).is_some() {
+            continue;
+        }
+        f(ListedEntry {
+            path: entry_path,
+            size: entry.header().size()?,
+            mtime: entry.header().mtime()?,
+            is_dir: entry.header().entry_type().is_dir(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Secrets needed to decrypt an archive, only asked for once the archive header shows which.
+pub trait SecretSource {
+    fn passphrase(&self) -> Result<SecretString>;
+    #[cfg(feature = "age")]
+    fn identities(&self) -> Result<Vec<AgeIdentity>>;
+}
+
+/// Takes the passphrase from `K_BACKUP_PASSPHRASE` or prompts on the terminal, identities
+/// from the given age identity files.
+#[derive(Clone, Default, Debug)]
+pub struct PromptSecretSource {
+    pub identity_files: Vec<PathBuf>,
+}
+
+impl SecretSource for PromptSecretSource {
+    fn passphrase(&self) -> Result<SecretString> {
+        match std::env::var(PASSPHRASE_ENV) {
+            Ok(passphrase) => Ok(SecretString::new(passphrase)),
+            Err(_) => Ok(SecretString::new(rpassword::prompt_password(
+                "Archive passphrase: ",
+            )?)),
+        }
+    }
+
+    #[cfg(feature = "age")]
+    fn identities(&self) -> Result<Vec<AgeIdentity>> {
+        read_identities(&self.identity_files)
+            .with_msg("Archive is encrypted to age recipients, identity files are required")
+    }
+}
+
+/// Takes secrets from the encryptor of a backup config, falling back to `fallback` for the
+/// ones it does not configure.
+#[derive(Clone, Debug)]
+pub struct ConfigSecretSource {
+    pub encryptor: Arc<EncryptorConfig>,
+    pub fallback: PromptSecretSource,
+}
+
+impl SecretSource for ConfigSecretSource {
+    fn passphrase(&self) -> Result<SecretString> {
+        match self.encryptor.as_ref() {
+            #[cfg(feature = "age")]
+            EncryptorConfig::Age(age) => match age.passphrase() {
+                Some(passphrase) => Ok(passphrase),
+                None => self.fallback.passphrase(),
+            },
+            _ => self.fallback.passphrase(),
+        }
+    }
+
+    #[cfg(feature = "age")]
+    fn identities(&self) -> Result<Vec<AgeIdentity>> {
+        match self.encryptor.as_ref() {
+            EncryptorConfig::Age(age) if !age.identity_files().is_empty() => {
+                read_identities(age.identity_files())
+            }
+            _ => self.fallback.identities(),
+        }
+    }
+}
+
+/// Pipeline recorded in the plain report or checksums next to the archive, falling back to the
+/// archive file name so the creating config is never needed.
+pub fn detect_pipeline<P: AsRef<Path>>(archive_path: P) -> Result<PipelineDescriptor> {
+    let archive_path = archive_path.as_ref();
+    let report_path = BackupReport::report_path(archive_path);
+    if let Ok(BackupReport {
+        pipeline: Some(pipeline),
+        ..
+    }) = BackupReport::read(&report_path, &EncryptorConfig::None)
+    {
+        return Ok(pipeline);
+    }
+    if let Ok(checksums) = ArchiveChecksums::read(ArchiveChecksums::checksums_path(archive_path)) {
+        return Ok(checksums.pipeline);
+    }
+    archive_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .and_then(PipelineDescriptor::from_file_name)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot infer archive pipeline from {archive_path:?}"),
+            )
+            .into()
+        })
+}
+
+/// Reverse `pipeline` over the archive file, yielding the tar stream.
+pub fn open_archive<P: AsRef<Path>, S: SecretSource>(
+    archive_path: P,
+    pipeline: &PipelineDescriptor,
+    secrets: &S,
+) -> Result<tar::Archive<Box<dyn Read>>> {
+    open_archive_reader(
+        Box::new(BufReader::new(open_archive_file(archive_path)?)),
+        pipeline,
+        secrets,
+    )
+}
+
+/// Reverse `pipeline` over archive bytes read from `reader`, yielding the tar stream.
+#[cfg_attr(not(feature = "age"), allow(unused_variables))]
+pub fn open_archive_reader<S: SecretSource>(
+    mut reader: Box<dyn Read>,
+    pipeline: &PipelineDescriptor,
+    secrets: &S,
+) -> Result<tar::Archive<Box<dyn Read>>> {
+    for stage in pipeline.stages.iter().rev() {
+        reader = match (stage.kind, stage.format.as_ref()) {
+            #[cfg(feature = "age")]
+            (StageKind::Encrypt, "age") => Box::new(decrypt_age(
+                reader,
+                || secrets.passphrase(),
+                || secrets.identities(),
+            )?),
+            (StageKind::Compress, format) => Box::new(Decompressor::from_format(format, reader)?),
+            (StageKind::Archive, "tar") => reader,
+            (kind, format) => Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!("unsupported {kind:?} stage {format:?}"),
+            ))?,
+        };
+    }
+    Ok(tar::Archive::new(reader))
+}
@@ -8,10 +8,15 @@ use thiserror::Error;
 pub enum Error {
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    #[cfg(feature = "sqlite")]
     #[error(transparent)]
     Rusqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "xz")]
     #[error(transparent)]
     LiblzmaStream(#[from] liblzma::stream::Error),
+    #[cfg(feature = "age")]
+    #[error(transparent)]
+    AgeDecrypt(#[from] age::DecryptError),
     #[error(transparent)]
     ValidationError(#[from] validator::ValidationErrors),
     #[error(transparent)]
@@ -19,9 +24,16 @@ pub enum Error {
     #[error(transparent)]
     SerdeYml(#[from] serde_yml::Error),
     #[error(transparent)]
-    WalkDir(#[from] walkdir::Error),
+    SerdeJson(#[from] serde_json::Error),
+    #[error(transparent)]
+    JWalk(#[from] jwalk::Error),
     #[error("{0}")]
     ChannelSendError(String),
+    #[error("{command} exited with {status}")]
+    CommandExitStatus {
+        command: String,
+        status: std::process::ExitStatus,
+    },
     #[error("{}:\n{}", msg, indent::indent_all_with("  ", error.to_string()))]
     WithMsg { msg: String, error: Box<Error> },
     #[error("{:?} {} failed:\n{}", obj_debug, fn_name, indent::indent_all_with("  ", error.to_string()))]
@@ -64,20 +76,15 @@ impl From<Vec<Error>> for Error {
         if errors.is_empty() {
             panic!("Should not create lots of errors when error is empty")
         }
-        Self::LotsOfError(
-            errors
-                .into_iter()
-                .map(|e| e.into_iter())
-                .flatten()
-                .collect_vec(),
-        )
+        Self::LotsOfError(errors.into_iter().flat_map(|e| e.into_iter()).collect_vec())
     }
 }
 
 impl Error {
+    #[allow(clippy::should_implement_trait)]
     pub fn into_iter(self) -> Box<dyn Iterator<Item = Error>> {
         match self {
-            Error::LotsOfError(v) => Box::new(v.into_iter().map(|e| e.into_iter()).flatten()),
+            Error::LotsOfError(v) => Box::new(v.into_iter().flat_map(|e| e.into_iter())),
             e => Box::new(std::iter::once(e)),
         }
     }
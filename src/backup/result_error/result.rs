@@ -23,3 +23,10 @@ pub fn convert_error_vec(errors: Vec<Error>) -> Result<()> {
         Err(errors.into())
     }
 }
+
+pub fn chain_optional_error(error: Option<Error>, other: Error) -> Error {
+    match error {
+        None => other,
+        Some(e) => e.chain(other),
+    }
+}
@@ -1,10 +1,10 @@
-use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::cmp::Reverse;
-use std::fmt::{Debug, Formatter};
-use std::rc::Rc;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
 use validator::Validate;
 
 #[skip_serializing_none]
@@ -14,20 +14,123 @@ pub struct RetentionConfig {
     pub default_retention: std::time::Duration,
     #[serde(with = "humantime_serde")]
     pub daily_retention: Option<std::time::Duration>,
+    /// Keeps the newest backup of each ISO week, weeks starting on Monday.
+    #[serde(default, with = "humantime_serde")]
+    pub weekly_retention: Option<std::time::Duration>,
     #[serde(with = "humantime_serde")]
     pub monthly_retention: Option<std::time::Duration>,
     #[serde(with = "humantime_serde")]
     pub yearly_retention: Option<std::time::Duration>,
+    /// Newest backups counting toward this minimum are never deleted, whatever their age.
+    pub min_backups: Option<usize>,
+}
+
+/// Tier of [`RetentionConfig`] keeping the newest backup of each of its buckets.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetentionTier {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl RetentionTier {
+    /// Config key of the tier retention.
+    pub fn key(&self) -> &'static str {
+        match self {
+            RetentionTier::Daily => "daily_retention",
+            RetentionTier::Weekly => "weekly_retention",
+            RetentionTier::Monthly => "monthly_retention",
+            RetentionTier::Yearly => "yearly_retention",
+        }
+    }
+
+    /// Calendar period a single backup is kept for.
+    pub fn bucket(&self) -> &'static str {
+        match self {
+            RetentionTier::Daily => "day",
+            RetentionTier::Weekly => "ISO week",
+            RetentionTier::Monthly => "month",
+            RetentionTier::Yearly => "year",
+        }
+    }
+}
+
+/// Whether a backup is kept or deleted and why.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetentionVerdict {
+    /// Younger than `default_retention`.
+    Recent,
+    /// Newest of its bucket within the retention of the tier.
+    Tier(RetentionTier),
+    /// Among the newest `min_backups`, out of retention otherwise.
+    MinBackups,
+    /// No retention is configured for its kind of backup.
+    NoRetention,
+    /// Out of retention, kept until its off-site copies are confirmed.
+    AwaitingOffsite,
+    /// Out of retention, deleted.
+    Expired,
+}
+
+impl RetentionVerdict {
+    pub fn is_kept(&self) -> bool {
+        *self != RetentionVerdict::Expired
+    }
+}
+
+impl Display for RetentionVerdict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetentionVerdict::Recent => write!(f, "younger than default_retention"),
+            RetentionVerdict::Tier(tier) => {
+                write!(f, "newest of its {} within {}", tier.bucket(), tier.key())
+            }
+            RetentionVerdict::MinBackups => write!(f, "among the newest min_backups"),
+            RetentionVerdict::NoRetention => write!(f, "no retention applies to it"),
+            RetentionVerdict::AwaitingOffsite => {
+                write!(f, "its off-site copies are not confirmed yet")
+            }
+            RetentionVerdict::Expired => {
+                write!(f, "older than default_retention and kept by no tier")
+            }
+        }
+    }
 }
 
 impl RetentionConfig {
-    pub fn get_delete<R, T, I, II>(
+    /// Items of `iter` that are out of retention at `now`, see [`Self::classify`].
+    pub fn get_delete<R, T, I, II, F>(
         &self,
         iter: I,
         now: DateTime<Utc>,
+        counts_toward_min: F,
     ) -> Box<dyn Iterator<Item = II>>
     where
         R: 'static,
+        F: Fn(&R) -> bool + 'static,
+        T: TimeZone + 'static,
+        II: AsRef<ItemWithDateTime<R, T>> + 'static,
+        I: IntoIterator<Item = II>,
+    {
+        Box::new(
+            self.classify(iter, now, counts_toward_min)
+                .filter(|(_, verdict)| !verdict.is_kept())
+                .map(|(item, _)| item),
+        )
+    }
+
+    /// Items of `iter` with their verdict at `now`, newest first. Only items for which
+    /// `counts_toward_min` holds are protected by `min_backups`.
+    pub fn classify<R, T, I, II, F>(
+        &self,
+        iter: I,
+        now: DateTime<Utc>,
+        counts_toward_min: F,
+    ) -> Box<dyn Iterator<Item = (II, RetentionVerdict)>>
+    where
+        R: 'static,
+        F: Fn(&R) -> bool + 'static,
         T: TimeZone + 'static,
         II: AsRef<ItemWithDateTime<R, T>> + 'static,
         I: IntoIterator<Item = II>,
@@ -37,6 +140,10 @@ impl RetentionConfig {
             .daily_retention
             .map(Duration::from_std)
             .map(Result::unwrap);
+        let weekly_retention = self
+            .weekly_retention
+            .map(Duration::from_std)
+            .map(Result::unwrap);
         let monthly_retention = self
             .monthly_retention
             .map(Duration::from_std)
@@ -46,84 +153,86 @@ impl RetentionConfig {
             .map(Duration::from_std)
             .map(Result::unwrap);
         let mut last_keep = None;
+        let mut remaining_min_backups = self.min_backups.unwrap_or(0);
 
         let iter = iter
             .into_iter()
             .sorted_unstable_by_key(|r| Reverse(r.as_ref().date_time.clone()))
-            .filter(move |r| {
+            .map(move |r| {
+                let protected = remaining_min_backups > 0 && counts_toward_min(&r.as_ref().item);
+                if protected {
+                    remaining_min_backups -= 1;
+                }
                 let utc_date_time = r.as_ref().date_time.to_utc();
-                println!("{:?}", utc_date_time);
                 let age = now.signed_duration_since(utc_date_time);
                 if age < default_retention {
-                    println!();
-                    return false;
+                    return (r, RetentionVerdict::Recent);
                 }
 
-                let should_keep = should_keep(
-                    &utc_date_time,
-                    age,
-                    &mut last_keep,
-                    yearly_retention,
-                    DateTime::year,
-                ) || should_keep(
-                    &utc_date_time,
-                    age,
-                    &mut last_keep,
-                    monthly_retention,
-                    DateTime::month,
-                ) || should_keep(
-                    &utc_date_time,
-                    age,
-                    &mut last_keep,
-                    daily_retention,
-                    DateTime::day,
-                );
-
-                println!();
-                return !should_keep;
+                // Bucketed by calendar date once, each tier compares a prefix of it
+                let date = utc_date_time.date_naive();
+                let tier = if should_keep(date, age, &mut last_keep, yearly_retention, |d| d.year())
+                {
+                    Some(RetentionTier::Yearly)
+                } else if should_keep(date, age, &mut last_keep, monthly_retention, |d| {
+                    (d.year(), d.month())
+                }) {
+                    Some(RetentionTier::Monthly)
+                } else if should_keep(date, age, &mut last_keep, weekly_retention, |d| {
+                    let week = d.iso_week();
+                    (week.year(), week.week())
+                }) {
+                    Some(RetentionTier::Weekly)
+                } else if should_keep(date, age, &mut last_keep, daily_retention, |d| d) {
+                    Some(RetentionTier::Daily)
+                } else {
+                    None
+                };
+
+                let verdict = match tier {
+                    Some(tier) => RetentionVerdict::Tier(tier),
+                    None if protected => RetentionVerdict::MinBackups,
+                    None => RetentionVerdict::Expired,
+                };
+                (r, verdict)
             });
 
         Box::new(iter)
     }
 }
 
-fn should_keep<O: Copy, T: TimeZone<Offset = O>, R: Ord, F: Fn(&DateTime<T>) -> R>(
-    to_check: &DateTime<T>,
+/// Whether the backup of `date` is the newest of its bucket older than the last kept backup,
+/// buckets being the calendar year, month, ISO week or day given by `bucket`.
+fn should_keep<K: Ord, F: Fn(NaiveDate) -> K>(
+    date: NaiveDate,
     age: Duration,
-    last_keep: &mut Option<DateTime<T>>,
+    last_keep: &mut Option<NaiveDate>,
     retention: Option<Duration>,
-    cmp_value_extract_fn: F,
+    bucket: F,
 ) -> bool {
-    println!("last keep {:?}", last_keep);
     match retention {
-        None => false,
-        Some(retention) => {
-            if age < retention {
-                match last_keep {
-                    None => {
-                        *last_keep = Some(*to_check);
-                        true
-                    }
-                    Some(last_keep_val) => {
-                        if cmp_value_extract_fn(&to_check) < cmp_value_extract_fn(last_keep_val) {
-                            *last_keep = Some(*to_check);
-                            true
-                        } else {
-                            false
-                        }
-                    }
+        Some(retention) if age < retention => match last_keep {
+            None => {
+                *last_keep = Some(date);
+                true
+            }
+            Some(last_keep_val) => {
+                if bucket(date) < bucket(*last_keep_val) {
+                    *last_keep = Some(date);
+                    true
+                } else {
+                    false
                 }
-            } else {
-                false
             }
-        }
+        },
+        _ => false,
     }
 }
 
 #[derive(Clone, Hash, Eq, PartialEq)]
 pub struct ItemWithDateTime<R, T: TimeZone> {
     pub item: R,
-    pub date_time: Rc<DateTime<T>>,
+    pub date_time: Arc<DateTime<T>>,
 }
 
 impl<T: TimeZone> ItemWithDateTime<(), T> {
@@ -145,7 +254,7 @@ impl<R, T: TimeZone> From<(R, DateTime<T>)> for ItemWithDateTime<R, T> {
     fn from(value: (R, DateTime<T>)) -> Self {
         Self {
             item: value.0,
-            date_time: Rc::new(value.1),
+            date_time: Arc::new(value.1),
         }
     }
 }
@@ -155,3 +264,118 @@ impl<T: TimeZone> Debug for ItemWithDateTime<(), T> {
         self.date_time.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    static DAY: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+    /// Calendar bucket of a date, as a sortable string.
+    type Bucket = fn(NaiveDate) -> String;
+
+    /// Tiers of the newest backup of each bucket, among the backups older than the default
+    /// retention, within the retention of the tier.
+    fn expected_tiers(
+        config: &RetentionConfig,
+        backups: &[DateTime<Utc>],
+        now: DateTime<Utc>,
+    ) -> Vec<(DateTime<Utc>, RetentionVerdict)> {
+        let default_retention = Duration::from_std(config.default_retention).unwrap();
+        let tiers: [(RetentionTier, Option<std::time::Duration>, Bucket); 4] = [
+            (RetentionTier::Yearly, config.yearly_retention, |d| {
+                d.format("%Y").to_string()
+            }),
+            (RetentionTier::Monthly, config.monthly_retention, |d| {
+                d.format("%Y-%m").to_string()
+            }),
+            (RetentionTier::Weekly, config.weekly_retention, |d| {
+                d.format("%G-%V").to_string()
+            }),
+            (RetentionTier::Daily, config.daily_retention, |d| {
+                d.format("%F").to_string()
+            }),
+        ];
+        let mut seen = tiers.map(|_| HashSet::new());
+        backups
+            .iter()
+            .sorted_by_key(|date_time| Reverse(**date_time))
+            .map(|date_time| {
+                let age = now - date_time;
+                if age < default_retention {
+                    return (*date_time, RetentionVerdict::Recent);
+                }
+                let mut verdict = RetentionVerdict::Expired;
+                for ((tier, retention, bucket), seen) in tiers.iter().zip(seen.iter_mut()) {
+                    let newest = seen.insert(bucket(date_time.date_naive()));
+                    let within = retention.is_some_and(|r| age < Duration::from_std(r).unwrap());
+                    if newest && within && verdict == RetentionVerdict::Expired {
+                        verdict = RetentionVerdict::Tier(*tier);
+                    }
+                }
+                (*date_time, verdict)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hourly_history_of_years_is_bucketed_per_tier() {
+        let first = Utc.with_ymd_and_hms(2020, 11, 3, 0, 0, 0).unwrap();
+        let backups = (0..4 * 365 * 24)
+            .map(|hours| first + Duration::hours(hours))
+            .collect::<Vec<_>>();
+        let now = *backups.last().unwrap() + Duration::minutes(30);
+        let config = RetentionConfig {
+            default_retention: 2 * DAY,
+            daily_retention: Some(40 * DAY),
+            weekly_retention: Some(120 * DAY),
+            monthly_retention: Some(400 * DAY),
+            yearly_retention: Some(3 * 365 * DAY),
+            min_backups: None,
+        };
+        let verdicts = config
+            .classify(
+                backups.iter().map(|b| Arc::new(ItemWithDateTime::from(*b))),
+                now,
+                |_: &()| true,
+            )
+            .map(|(backup, verdict)| (*backup.date_time, verdict))
+            .collect::<Vec<_>>();
+        assert_eq!(verdicts, expected_tiers(&config, &backups, now));
+
+        let count = |tier| verdicts.iter().filter(|(_, v)| *v == tier).count();
+        assert_eq!(count(RetentionVerdict::Recent), 48);
+        // 2021 to 2024, the newest backup outside the default retention is the newest of 2024
+        assert_eq!(count(RetentionVerdict::Tier(RetentionTier::Yearly)), 4);
+    }
+
+    #[test]
+    fn min_backups_protect_the_newest_counted_backups_only() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+        let config = RetentionConfig {
+            default_retention: DAY,
+            min_backups: Some(2),
+            ..Default::default()
+        };
+        let backups = (1..=5).map(|days| ((days % 2 == 0), now - Duration::days(days) * 10));
+        let verdicts = config
+            .classify(
+                backups.map(|b| Arc::new(ItemWithDateTime::from(b))),
+                now,
+                |counted: &bool| *counted,
+            )
+            .map(|(backup, verdict)| (backup.item, verdict))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            verdicts,
+            [
+                (false, RetentionVerdict::Expired),
+                (true, RetentionVerdict::MinBackups),
+                (false, RetentionVerdict::Expired),
+                (true, RetentionVerdict::MinBackups),
+                (false, RetentionVerdict::Expired),
+            ]
+        );
+    }
+}
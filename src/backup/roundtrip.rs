@@ -0,0 +1,108 @@
+use crate::backup::compress::{CompressorBuilder, CompressorConfig};
+use crate::backup::encrypt::{EncryptorBuilder, EncryptorConfig};
+use crate::backup::finish::Finish;
+use crate::backup::pipeline::PipelineDescriptor;
+use crate::backup::restore::{open_archive_reader, ConfigSecretSource, PromptSecretSource};
+use crate::backup::result_error::result::Result;
+use std::io::{BufWriter, Cursor, IntoInnerError, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Archive entry held in memory, a directory when `data` is `None`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RoundtripEntry {
+    pub path: PathBuf,
+    pub data: Option<Vec<u8>>,
+}
+
+/// Write `entries` through the tar, compress and encrypt stages in the order backups stack
+/// them, returning the archive bytes.
+pub fn write_archive(
+    entries: &[RoundtripEntry],
+    compressor: &CompressorConfig,
+    encryptor: &EncryptorConfig,
+) -> Result<Vec<u8>> {
+    let writer = BufWriter::new(encryptor.build_encryptor(Vec::new())?);
+    let mut builder = compressor
+        .build_compressor(writer)
+        .map(BufWriter::new)
+        .map(tar::Builder::new)?;
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        match &entry.data {
+            Some(data) => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(0o644);
+                header.set_size(data.len() as u64);
+                builder.append_data(&mut header, &entry.path, data.as_slice())?;
+            }
+            None => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_size(0);
+                builder.append_data(&mut header, &entry.path, std::io::empty())?;
+            }
+        }
+    }
+    let archive = builder
+        .into_inner()?
+        .into_inner()
+        .map_err(IntoInnerError::into_error)?
+        .finish()?
+        .into_inner()
+        .map_err(IntoInnerError::into_error)?
+        .finish()?;
+    Ok(archive)
+}
+
+/// Reverse the pipeline of `compressor` and `encryptor` over `archive` the way restore does,
+/// returning the entries read back. Secrets are taken from `encryptor`.
+pub fn read_archive(
+    archive: Vec<u8>,
+    compressor: &CompressorConfig,
+    encryptor: &EncryptorConfig,
+) -> Result<Vec<RoundtripEntry>> {
+    let pipeline = PipelineDescriptor::new(compressor, encryptor)?;
+    let secrets = ConfigSecretSource {
+        encryptor: Arc::new(encryptor.clone()),
+        fallback: PromptSecretSource::default(),
+    };
+    read_entries(open_archive_reader(
+        Box::new(Cursor::new(archive)),
+        &pipeline,
+        &secrets,
+    )?)
+}
+
+/// Entries of an opened tar stream read into memory.
+pub fn read_entries(mut archive: tar::Archive<Box<dyn Read>>) -> Result<Vec<RoundtripEntry>> {
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let data = match entry.header().entry_type() {
+            tar::EntryType::Directory => None,
+            _ => {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                Some(data)
+            }
+        };
+        entries.push(RoundtripEntry { path, data });
+    }
+    Ok(entries)
+}
+
+/// Archive `entries` in memory and restore them again, the result equals `entries` for any
+/// pipeline restoring faithfully. Meant for checking new stages against arbitrary inputs.
+pub fn roundtrip(
+    entries: &[RoundtripEntry],
+    compressor: &CompressorConfig,
+    encryptor: &EncryptorConfig,
+) -> Result<Vec<RoundtripEntry>> {
+    read_archive(
+        write_archive(entries, compressor, encryptor)?,
+        compressor,
+        encryptor,
+    )
+}
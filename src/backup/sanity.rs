@@ -0,0 +1,142 @@
+use crate::backup::backup_config::BackupConfig;
+use crate::backup::compress::CompressorConfig;
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::humanize::HumanDuration;
+use crate::backup::restore::PASSPHRASE_ENV;
+use crate::backup::retention::RetentionConfig;
+
+/// Settings that are valid but likely a mistake, reported at startup and by `check-config`.
+pub fn sanity_warnings(config: &BackupConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let unencrypted = matches!(config.encryptor.as_ref(), EncryptorConfig::None);
+    if unencrypted {
+        for (idx, destination) in config.storage.iter().flat_map(|s| s.iter()).enumerate() {
+            if destination.encryptor.is_none() {
+                warnings.push(format!(
+                    "archives are uploaded unencrypted to destination {idx}, set an encryptor"
+                ));
+            }
+        }
+        if std::env::var_os(PASSPHRASE_ENV).is_some() {
+            warnings.push(format!(
+                "{PASSPHRASE_ENV} is set but encryptor_type is none, archives are not encrypted"
+            ));
+        }
+    }
+
+    if matches!(config.compressor.as_ref(), CompressorConfig::None)
+        && config
+            .files
+            .iter()
+            .any(|f| !f.precompressed.unwrap_or(false))
+    {
+        warnings.push(
+            "compressor_type is none, sources that are not precompressed are stored at full size"
+                .into(),
+        );
+    }
+
+    let retentions = [
+        ("retention".to_string(), config.retention.as_deref()),
+        (
+            "manual_retention".to_string(),
+            config.manual_retention.as_deref(),
+        ),
+    ]
+    .into_iter()
+    .chain(config.archives.iter().flat_map(|g| g.iter()).flat_map(|g| {
+        [
+            (
+                format!("archives.{}.retention", g.name),
+                g.retention.as_deref(),
+            ),
+            (
+                format!("archives.{}.manual_retention", g.name),
+                g.manual_retention.as_deref(),
+            ),
+        ]
+    }))
+    .chain(
+        config
+            .storage
+            .iter()
+            .flat_map(|s| s.iter())
+            .enumerate()
+            .map(|(idx, d)| {
+                (
+                    format!("storage.{idx}.prune.retention"),
+                    d.prune.as_ref().and_then(|p| p.retention.as_ref()),
+                )
+            }),
+    );
+    for (name, retention) in retentions {
+        if let Some(retention) = retention {
+            warnings.extend(
+                retention_warnings(retention)
+                    .into_iter()
+                    .map(|w| format!("{name}: {w}")),
+            );
+        }
+    }
+    warnings
+}
+
+/// Secrets configured under an `encryptor` of the YAML config whose `encryptor_type` is none, they
+/// are ignored and archives are not encrypted.
+pub fn unused_secret_warnings(config: &serde_yml::Value) -> Vec<String> {
+    let destinations = config
+        .get("storage")
+        .and_then(|s| s.as_sequence())
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(|(idx, d)| (format!("storage.{idx}.encryptor"), d.get("encryptor")));
+    std::iter::once(("encryptor".to_string(), config.get("encryptor")))
+        .chain(destinations)
+        .filter_map(|(name, encryptor)| {
+            let encryptor = encryptor?;
+            let unencrypted =
+                encryptor.get("encryptor_type").and_then(|t| t.as_str()) == Some("none");
+            let secret = ["passphrase", "recipients", "identity_files"]
+                .into_iter()
+                .find(|key| encryptor.get(key).is_some())?;
+            unencrypted
+                .then(|| format!("{name} has a {secret} but encryptor_type is none, it is ignored"))
+        })
+        .collect()
+}
+
+fn retention_warnings(retention: &RetentionConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if retention.min_backups.unwrap_or(0) == 0 {
+        warnings.push(
+            "min_backups is 0, every backup is deleted once out of retention, e.g. when runs \
+             keep failing"
+                .to_string(),
+        );
+    }
+    let tiers = [
+        ("default_retention", Some(retention.default_retention)),
+        ("daily_retention", retention.daily_retention),
+        ("weekly_retention", retention.weekly_retention),
+        ("monthly_retention", retention.monthly_retention),
+        ("yearly_retention", retention.yearly_retention),
+    ];
+    for (idx, (name, duration)) in tiers.iter().enumerate() {
+        let Some(duration) = duration else {
+            continue;
+        };
+        if let Some((longer_name, longer)) = tiers[..idx]
+            .iter()
+            .filter_map(|(n, d)| d.map(|d| (n, d)))
+            .find(|(_, d)| d > duration)
+        {
+            warnings.push(format!(
+                "{name} of {} is shorter than {longer_name} of {}, it never keeps anything more",
+                HumanDuration(*duration),
+                HumanDuration(longer)
+            ));
+        }
+    }
+    warnings
+}
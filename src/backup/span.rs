@@ -0,0 +1,65 @@
+use crate::backup::archive::ArchiveSourceConfig;
+use crate::backup::report::SourceStats;
+use serde::{Deserialize, Serialize};
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+/// Phase of a backup run, each traced in a `stage` span. Collecting and writing run
+/// concurrently, entries are written as they are collected.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Deleting archives out of retention before the run.
+    Retention,
+    /// Reading entries from the sources, with a `source` span per source.
+    Collect,
+    /// Writing the entries through the compressor and encryptors into the archive files.
+    Write,
+    /// Uploading the archive to the storage destinations.
+    Upload,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Retention => "retention",
+            Stage::Collect => "collect",
+            Stage::Write => "write",
+            Stage::Upload => "upload",
+        }
+    }
+}
+
+/// Span of a whole run creating an archive of `archive`.
+pub fn backup_span(archive: &str, manual: bool) -> Span {
+    info_span!("backup", archive, manual)
+}
+
+/// Span of a phase of the current run.
+pub fn stage_span(stage: Stage) -> Span {
+    info_span!("stage", stage = stage.as_str())
+}
+
+/// Span of the collection of a single source, entered on the collecting threads so `parent` is
+/// explicit. Entry counts are filled in by [`record_source_stats`], bytes are only known once
+/// archived and are part of the run report instead.
+pub fn source_span(parent: &Span, source: &ArchiveSourceConfig) -> Span {
+    info_span!(
+        parent: parent,
+        "source",
+        source_type = source.source.type_name(),
+        source_name = source.name.as_deref(),
+        dst_prefix = %source.source.dst_prefix().display(),
+        entries = Empty,
+        skipped = Empty,
+        duplicates = Empty,
+    )
+}
+
+/// Record the entry counts of `stats` on the `source` span, shown when the span closes.
+pub fn record_source_stats(span: &Span, source: &ArchiveSourceConfig, stats: &SourceStats) {
+    let report = stats.to_report(source);
+    span.record("entries", report.entries);
+    span.record("skipped", report.skipped_entries);
+    span.record("duplicates", report.duplicate_entries);
+}
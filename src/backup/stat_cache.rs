@@ -0,0 +1,158 @@
+use crate::backup::checksum::sha256_file;
+use crate::backup::report::ChangeSummary;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, Metadata};
+use std::io::{BufReader, BufWriter, IntoInnerError};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+static STAT_CACHE_FILE_NAME: &str = "stat_cache.json";
+static DEFAULT_RECHECK_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(30 * 24 * 60 * 60);
+
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct StatCacheConfig {
+    /// Content of unchanged files is hashed again after this long, catching silent corruption
+    /// and writers preserving mtime. 30 days by default.
+    #[serde(default, with = "humantime_serde")]
+    pub recheck_interval: Option<std::time::Duration>,
+}
+
+/// Identity of a file version, content is assumed unchanged while it stays equal.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct StatKey {
+    pub dev: u64,
+    pub ino: u64,
+    pub mtime_ns: i128,
+    pub size: u64,
+}
+
+impl From<&Metadata> for StatKey {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            mtime_ns: metadata.mtime() as i128 * 1_000_000_000 + metadata.mtime_nsec() as i128,
+            size: metadata.len(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CachedStat {
+    pub key: StatKey,
+    pub sha256: Arc<str>,
+    pub hashed_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryChange {
+    New,
+    Modified,
+    Unchanged,
+}
+
+/// Stat info and content hash of archived files across runs, keyed by source path.
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct StatCache {
+    entries: HashMap<Arc<Path>, CachedStat>,
+    #[serde(skip)]
+    seen: HashSet<Arc<Path>>,
+    #[serde(skip)]
+    summary: ChangeSummary,
+}
+
+impl StatCache {
+    pub fn cache_path<P: AsRef<Path>>(state_dir: P) -> PathBuf {
+        state_dir.as_ref().join(STAT_CACHE_FILE_NAME)
+    }
+
+    /// Load the cache at `path`, starting empty when it does not exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match File::open(path) {
+            Ok(f) => serde_json::from_reader(BufReader::new(f)).map_err(Error::from),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Drop files not observed since loading, then write the cache atomically.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let seen = std::mem::take(&mut self.seen);
+        let before = self.entries.len();
+        self.entries.retain(|p, _| seen.contains(p));
+        self.summary.removed = (before - self.entries.len()) as u64;
+        self.seen = seen;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer
+            .into_inner()
+            .map_err(IntoInnerError::into_error)?
+            .sync_all()?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Hash of `src`, reusing the cached one while its stat key is unchanged and it was hashed
+    /// within `recheck_interval`.
+    pub fn observe(
+        &mut self,
+        src: &Path,
+        now: DateTime<Utc>,
+        recheck_interval: Option<std::time::Duration>,
+    ) -> Result<(Arc<str>, EntryChange)> {
+        let metadata = std::fs::metadata(src)?;
+        let key = StatKey::from(&metadata);
+        let src: Arc<Path> = src.into();
+        self.seen.insert(src.clone());
+        let recheck_interval =
+            chrono::Duration::from_std(recheck_interval.unwrap_or(DEFAULT_RECHECK_INTERVAL))
+                .unwrap_or(chrono::Duration::max_value());
+
+        let cached = self.entries.get(&src);
+        if let Some(cached) = cached {
+            if cached.key == key && now - cached.hashed_at < recheck_interval {
+                self.summary.unchanged += 1;
+                return Ok((cached.sha256.clone(), EntryChange::Unchanged));
+            }
+        }
+        let sha256: Arc<str> = sha256_file(&src)?.into();
+        let change = match cached {
+            None => EntryChange::New,
+            Some(cached) if cached.sha256 == sha256 => EntryChange::Unchanged,
+            Some(_) => EntryChange::Modified,
+        };
+        match change {
+            EntryChange::New => self.summary.new += 1,
+            EntryChange::Modified => self.summary.modified += 1,
+            EntryChange::Unchanged => self.summary.unchanged += 1,
+        }
+        self.entries.insert(
+            src,
+            CachedStat {
+                key,
+                sha256: sha256.clone(),
+                hashed_at: now,
+            },
+        );
+        Ok((sha256, change))
+    }
+
+    /// Changes observed so far, `removed` is only known after `save`.
+    pub fn summary(&self) -> &ChangeSummary {
+        &self.summary
+    }
+}
@@ -0,0 +1,141 @@
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::span::Stage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, IntoInnerError};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::warn;
+
+static STATUS_FILE_NAME: &str = "status.json";
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulerState {
+    /// Waiting for the next scheduled run.
+    #[default]
+    Idle,
+    Running,
+    /// The schedule stopped on an error, `last_error` tells which.
+    Stopped,
+}
+
+/// State of the schedule of a job, kept in `status.json` of its state dir so monitors can poll
+/// it without talking to the daemon. `updated_at` is refreshed at least every minute while the
+/// daemon runs, an older one means the daemon is gone.
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct SchedulerStatus {
+    pub state: SchedulerState,
+    /// Stage of the current run.
+    pub phase: Option<Stage>,
+    pub pid: Option<u32>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub run_started_at: Option<DateTime<Utc>>,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_archive: Option<Arc<Path>>,
+    pub last_error: Option<Arc<str>>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+impl SchedulerStatus {
+    pub fn status_path<P: AsRef<Path>>(state_dir: P) -> PathBuf {
+        state_dir.as_ref().join(STATUS_FILE_NAME)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        serde_json::from_reader(BufReader::new(File::open(path)?)).map_err(Error::from)
+    }
+
+    /// Write the status atomically, readers never see a partial file.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.into_inner().map_err(IntoInnerError::into_error)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Status file of a running schedule, rewritten on every change. Write failures are only
+/// logged, monitoring must never stop backups.
+#[derive(Debug)]
+pub struct StatusFile {
+    path: PathBuf,
+    status: Mutex<SchedulerStatus>,
+}
+
+impl StatusFile {
+    /// Status of the schedule starting now, keeping the last success and error of a previous
+    /// daemon.
+    pub fn open<P: AsRef<Path>>(state_dir: P) -> Self {
+        let path = SchedulerStatus::status_path(state_dir);
+        let previous = SchedulerStatus::read(&path).unwrap_or_default();
+        let status_file = Self {
+            path,
+            status: Mutex::new(SchedulerStatus {
+                pid: Some(std::process::id()),
+                last_success: previous.last_success,
+                last_archive: previous.last_archive,
+                last_error: previous.last_error,
+                last_error_at: previous.last_error_at,
+                ..Default::default()
+            }),
+        };
+        status_file.update(|_| {});
+        status_file
+    }
+
+    /// Apply `change` and rewrite the file. `updated_at` is wall clock time, whatever clock
+    /// drives the schedule.
+    pub fn update<F: FnOnce(&mut SchedulerStatus)>(&self, change: F) {
+        let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+        change(&mut status);
+        status.updated_at = Some(Utc::now());
+        if let Err(e) = status.write(&self.path) {
+            warn!("Failed to write status file {:?}: {e}", self.path)
+        }
+    }
+
+    pub fn start_run(&self, now: DateTime<Utc>) {
+        self.update(|s| {
+            s.state = SchedulerState::Running;
+            s.run_started_at = Some(now);
+            s.next_run = None;
+        })
+    }
+
+    pub fn set_phase(&self, phase: Stage) {
+        self.update(|s| s.phase = Some(phase))
+    }
+
+    /// Record the end of the run started by [`Self::start_run`] at `now`, which created `archive`
+    /// or failed with an error.
+    pub fn finish_run(&self, now: DateTime<Utc>, result: std::result::Result<&Path, &Error>) {
+        self.update(|s| {
+            s.phase = None;
+            s.run_started_at = None;
+            match result {
+                Ok(archive) => {
+                    s.state = SchedulerState::Idle;
+                    s.last_success = Some(now);
+                    s.last_archive = Some(archive.into());
+                }
+                Err(e) => {
+                    s.state = SchedulerState::Stopped;
+                    s.last_error = Some(e.to_string().into());
+                    s.last_error_at = Some(now);
+                }
+            }
+        })
+    }
+}
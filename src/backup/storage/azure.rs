@@ -0,0 +1,260 @@
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use crate::backup::storage::http::{curl, download, output, uri_encode, xml_values, Body, Request};
+use crate::backup::storage::resumable::{ResumableStorageBackend, UploadPart};
+use crate::backup::storage::{check_stored_size, StorageBackend};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::process::Output;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static API_VERSION: &str = "2021-08-06";
+static DEFAULT_PART_SIZE: u64 = 64 * 1024 * 1024;
+/// Largest block accepted by Put Block.
+static MAX_PART_SIZE: u64 = 4000 * 1024 * 1024;
+
+/// Azure Blob Storage container, blobs are named `{prefix}{file_name}`. Requests are authorized
+/// with a SAS token and sent with `curl`, archives go through resumable block uploads.
+///
+/// The SAS token is part of the URL, which is passed to curl on stdin and never in its process
+/// arguments. Prefer a token scoped to the container with only the needed permissions (read,
+/// write, list and delete when pruning) and an expiry.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct AzureStorageConfig {
+    pub account: Arc<str>,
+    pub container: Arc<str>,
+    /// Prepended to the file names to form blob names, e.g. `host-a/`.
+    pub prefix: Option<Arc<str>>,
+    /// Shared access signature query string, e.g. `sv=...&sig=...`. Never serialized back.
+    #[serde(default, skip_serializing)]
+    pub sas_token: Option<SecretString>,
+    /// `https://{account}.blob.core.windows.net` by default, e.g. Azurite at
+    /// `http://127.0.0.1:10000/devstoreaccount1`.
+    pub endpoint: Option<Arc<str>>,
+    /// Block size of resumable uploads, 64MiB by default and 4000MiB at most.
+    pub part_size: Option<u64>,
+    /// Path to the curl binary.
+    pub command: Option<Arc<str>>,
+}
+
+impl AzureStorageConfig {
+    fn blob_name(&self, file_name: &str) -> String {
+        format!("{}{file_name}", self.prefix.as_deref().unwrap_or_default())
+    }
+
+    /// URL of the blob `blob_name`, or the container when `None`, with the SAS token appended.
+    fn url(&self, blob_name: Option<&str>, query: &[(&str, &str)]) -> Result<String> {
+        let sas_token = self.sas_token.as_ref().ok_or_else(|| {
+            std::io::Error::new(ErrorKind::InvalidInput, "sas_token is not configured")
+        })?;
+        let mut url = match &self.endpoint {
+            Some(endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None => format!("https://{}.blob.core.windows.net", self.account),
+        };
+        let _ = write!(url, "/{}", uri_encode(&self.container, false));
+        if let Some(blob_name) = blob_name {
+            let _ = write!(url, "/{}", uri_encode(blob_name, true));
+        }
+        url.push('?');
+        for (k, v) in query {
+            let _ = write!(url, "{}={}&", uri_encode(k, false), uri_encode(v, false));
+        }
+        url.push_str(sas_token.expose_secret().trim_start_matches('?'));
+        Ok(url)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        blob_name: Option<&str>,
+        query: &[(&str, &str)],
+        headers: &[&str],
+    ) -> Result<Request> {
+        let mut all_headers = vec![format!("x-ms-version: {API_VERSION}")];
+        all_headers.extend(headers.iter().map(|h| h.to_string()));
+        let mut request = curl(self.command.as_deref(), method, &all_headers);
+        request.config("url", &self.url(blob_name, query)?);
+        Ok(request)
+    }
+
+    fn error(&self, output: &Output) -> Error {
+        std::io::Error::other(format!(
+            "Azure request to container {:?} of {:?} failed with {}: {}",
+            self.container,
+            self.account,
+            output.status,
+            String::from_utf8_lossy(&output.stdout).trim()
+        ))
+        .into()
+    }
+
+    /// Send `request`, returning its stdout. The response body is part of the error on failure.
+    fn run(&self, request: Request, body: &Body) -> Result<Vec<u8>> {
+        let output = output(request, body)?;
+        if !output.status.success() {
+            return Err(self.error(&output));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl StorageBackend for AzureStorageConfig {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        let file_name = archive_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| std::io::Error::other("archive path has no valid file name"))?;
+        let body = Body::File(archive_path);
+        let request = self.request(
+            "PUT",
+            Some(&self.blob_name(file_name)),
+            &[],
+            &["x-ms-blob-type: BlockBlob"],
+        )?;
+        self.run(request, &body)?;
+        Ok(())
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        download(
+            self.request("GET", Some(&self.blob_name(file_name)), &[], &[])?,
+            writer,
+            &format!("Azure download of {file_name:?}"),
+        )
+    }
+
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        let mut names = Vec::new();
+        let mut marker = None;
+        loop {
+            let mut query = vec![("restype", "container"), ("comp", "list")];
+            if !prefix.is_empty() {
+                query.push(("prefix", prefix));
+            }
+            if let Some(marker) = marker.as_deref() {
+                query.push(("marker", marker));
+            }
+            let response = self.run(self.request("GET", None, &query, &[])?, &Body::None)?;
+            let response = String::from_utf8_lossy(&response);
+            names.extend(
+                xml_values(&response, "Name")
+                    .into_iter()
+                    .filter_map(|name| name.strip_prefix(prefix).map(Arc::<str>::from))
+                    .filter(|name| !name.contains('/')),
+            );
+            marker = xml_values(&response, "NextMarker")
+                .into_iter()
+                .find(|m| !m.is_empty());
+            if marker.is_none() {
+                return Ok(names);
+            }
+        }
+    }
+
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        for file_name in file_names {
+            let request = self.request("DELETE", Some(&self.blob_name(file_name)), &[], &[])?;
+            let output = output(request, &Body::None)?;
+            let gone = xml_values(&String::from_utf8_lossy(&output.stdout), "Code")
+                .iter()
+                .any(|code| code == "BlobNotFound");
+            if !output.status.success() && !gone {
+                return Err(self.error(&output));
+            }
+        }
+        Ok(())
+    }
+
+    /// The size comes from listing the blob name, each listed blob has one `Name` and
+    /// `Content-Length`.
+    fn confirm_upload(&self, file_name: &str, size: u64, _sha256: &str) -> Result<()> {
+        let blob_name = self.blob_name(file_name);
+        let query = [
+            ("restype", "container"),
+            ("comp", "list"),
+            ("prefix", blob_name.as_str()),
+        ];
+        let response = self.run(self.request("GET", None, &query, &[])?, &Body::None)?;
+        let response = String::from_utf8_lossy(&response);
+        let stored = xml_values(&response, "Name")
+            .into_iter()
+            .zip(xml_values(&response, "Content-Length"))
+            .find(|(listed, _)| *listed == blob_name)
+            .and_then(|(_, size)| size.parse().ok());
+        check_stored_size(file_name, size, stored)
+    }
+
+    fn as_resumable(&self) -> Option<&dyn ResumableStorageBackend> {
+        Some(self)
+    }
+}
+
+impl ResumableStorageBackend for AzureStorageConfig {
+    fn part_size(&self) -> u64 {
+        self.part_size
+            .unwrap_or(DEFAULT_PART_SIZE)
+            .min(MAX_PART_SIZE)
+    }
+
+    /// Blocks are staged on the blob itself, the upload id only keeps block ids of different
+    /// uploads apart.
+    fn create_upload(&self, _file_name: &str) -> Result<Arc<str>> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(std::io::Error::other)?
+            .as_nanos();
+        Ok(format!("{:016x}", nanos as u64).into())
+    }
+
+    fn upload_part(
+        &self,
+        file_name: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: &[u8],
+    ) -> Result<Arc<str>> {
+        // Block ids of a blob must all have the same length
+        let block_id = BASE64_STANDARD.encode(format!("{upload_id}-{part_number:06}"));
+        let body = Body::Data(data);
+        let request = self.request(
+            "PUT",
+            Some(&self.blob_name(file_name)),
+            &[("comp", "block"), ("blockid", &block_id)],
+            &[],
+        )?;
+        self.run(request, &body)?;
+        Ok(block_id.into())
+    }
+
+    fn complete_upload(
+        &self,
+        file_name: &str,
+        _upload_id: &str,
+        parts: &[UploadPart],
+    ) -> Result<()> {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>");
+        for part in parts {
+            let _ = write!(xml, "<Latest>{}</Latest>", part.tag);
+        }
+        xml.push_str("</BlockList>");
+        let body = Body::Data(xml.as_bytes());
+        let request = self.request(
+            "PUT",
+            Some(&self.blob_name(file_name)),
+            &[("comp", "blocklist")],
+            &[],
+        )?;
+        self.run(request, &body)
+            .with_msg(format!("Commit blocks of {file_name:?} failed"))?;
+        Ok(())
+    }
+}
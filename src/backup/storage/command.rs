@@ -0,0 +1,169 @@
+use crate::backup::hook::CommandHook;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use crate::backup::storage::{check_listed, StorageBackend};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fs::File;
+use std::io::{copy, ErrorKind, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+static FILE_NAME_PLACEHOLDER: &str = "{file_name}";
+static DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Stream the archive to the stdin of an external command, e.g. `aws s3 cp - s3://...`.
+/// `{file_name}` in args is replaced with the archive file name.
+///
+/// The optional `download` command writes a stored archive to stdout, e.g.
+/// `aws s3 cp s3://.../{file_name} -`, enabling remote verification. The optional `list`
+/// command prints the stored files one per line as `ls -1` or `aws s3 ls` do, the file name of
+/// the last whitespace separated field is used, enabling sync of missing archives. The
+/// optional `delete` command gets the file names to delete on stdin, one per line, e.g.
+/// `xargs -I{} aws s3 rm s3://.../{}`.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct CommandStorageConfig {
+    #[serde(flatten)]
+    command: CommandHook,
+    retry: Option<u32>,
+    #[serde(default, with = "humantime_serde")]
+    retry_delay: Option<Duration>,
+    download: Option<CommandHook>,
+    list: Option<CommandHook>,
+    delete: Option<CommandHook>,
+}
+
+impl CommandStorageConfig {
+    fn build_command(hook: &CommandHook, file_name: &str) -> Command {
+        let mut command = Command::new(hook.command.as_ref());
+        command.args(
+            hook.args
+                .iter()
+                .flatten()
+                .map(|arg| arg.replace(FILE_NAME_PLACEHOLDER, file_name)),
+        );
+        command
+    }
+
+    fn upload_once(&self, archive_path: &Path, file_name: &str) -> Result<()> {
+        let mut child = Self::build_command(&self.command, file_name)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let copy_res = child
+            .stdin
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdin is not piped"))
+            .and_then(|mut stdin| copy(&mut File::open(archive_path)?, &mut stdin));
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(Error::CommandExitStatus {
+                command: format!("{:?}", self.command),
+                status,
+            });
+        }
+        copy_res?;
+        Ok(())
+    }
+}
+
+impl StorageBackend for CommandStorageConfig {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        let file_name = archive_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| std::io::Error::other("archive path has no valid file name"))?;
+
+        let mut attempt = 0;
+        loop {
+            match self.upload_once(archive_path, file_name) {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.retry.unwrap_or(0) => {
+                    attempt += 1;
+                    warn!("Upload attempt {attempt} failed, retrying: {e}");
+                    std::thread::sleep(self.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY));
+                }
+                Err(e) => return Err(e.with_msg(format!("Upload failed after {attempt} retries"))),
+            }
+        }
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        let download = self.download.as_ref().ok_or_else(|| {
+            std::io::Error::new(ErrorKind::Unsupported, "no download command configured")
+        })?;
+        let mut child = Self::build_command(download, file_name)
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let copy_res = child
+            .stdout
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdout is not piped"))
+            .and_then(|mut stdout| copy(&mut stdout, writer));
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(Error::CommandExitStatus {
+                command: format!("{download:?}"),
+                status,
+            });
+        }
+        copy_res?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        let list = self.list.as_ref().ok_or_else(|| {
+            std::io::Error::new(ErrorKind::Unsupported, "no list command configured")
+        })?;
+        let output = list.to_command().stderr(Stdio::inherit()).output()?;
+        if !output.status.success() {
+            return Err(Error::CommandExitStatus {
+                command: format!("{list:?}"),
+                status: output.status,
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .map(|name| name.rsplit('/').next().unwrap_or(name).into())
+            .collect())
+    }
+
+    /// The copy is confirmed listed when a `list` command is configured, trusting the upload
+    /// command otherwise.
+    fn confirm_upload(&self, file_name: &str, _size: u64, _sha256: &str) -> Result<()> {
+        match self.list {
+            Some(_) => check_listed(file_name, &self.list()?),
+            None => Ok(()),
+        }
+    }
+
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        let delete = self.delete.as_ref().ok_or_else(|| {
+            std::io::Error::new(ErrorKind::Unsupported, "no delete command configured")
+        })?;
+        let mut child = delete.to_command().stdin(Stdio::piped()).spawn()?;
+        let write_res = child
+            .stdin
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdin is not piped"))
+            .and_then(|mut stdin| {
+                file_names
+                    .iter()
+                    .try_for_each(|name| writeln!(stdin, "{name}"))
+            });
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(Error::CommandExitStatus {
+                command: format!("{delete:?}"),
+                status,
+            });
+        }
+        write_res?;
+        Ok(())
+    }
+}
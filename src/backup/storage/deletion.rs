@@ -0,0 +1,135 @@
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use crate::backup::retention::RetentionConfig;
+use crate::backup::storage::StorageBackend;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, IntoInnerError};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+static DEFAULT_BATCH_SIZE: usize = 100;
+static DEFAULT_BATCH_INTERVAL: Duration = Duration::from_secs(1);
+static DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Delete the copies of archives removed by retention from the destination, in rate limited
+/// batches. Deletions failing after the retries are kept pending and retried next cycle.
+///
+/// With `retention`, the stored archives are instead listed and pruned against it every cycle,
+/// so `out_dir` can be kept as a short lived staging area.
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct RemoteDeletionConfig {
+    /// Files deleted per storage call, 100 by default.
+    pub batch_size: Option<usize>,
+    /// Pause between batches, 1s by default.
+    #[serde(default, with = "humantime_serde")]
+    pub batch_interval: Option<Duration>,
+    pub retry: Option<u32>,
+    #[serde(default, with = "humantime_serde")]
+    pub retry_delay: Option<Duration>,
+    /// Retention of the scheduled archives stored on the destination, independent of the local
+    /// one. Manual archives are left alone.
+    pub retention: Option<RetentionConfig>,
+}
+
+/// Stored file names still to delete from a destination, persisted in the state directory.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct PendingDeletions {
+    pub file_names: Vec<Arc<str>>,
+}
+
+impl PendingDeletions {
+    /// State file of the destination at `idx` within `state_dir`.
+    pub fn pending_path(state_dir: &Path, idx: usize) -> PathBuf {
+        state_dir
+            .join("deletions")
+            .join(format!("destination-{idx}.json"))
+    }
+
+    /// Pending deletions at `path`, empty when there is none.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match File::open(path) {
+            Ok(f) => Ok(serde_json::from_reader(BufReader::new(f))?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Persist to `path`, removing it once nothing is pending.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if self.file_names.is_empty() {
+            return match std::fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.into_inner().map_err(IntoInnerError::into_error)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn extend<I: IntoIterator<Item = Arc<str>>>(&mut self, file_names: I) {
+        for file_name in file_names {
+            if !self.file_names.contains(&file_name) {
+                self.file_names.push(file_name);
+            }
+        }
+    }
+}
+
+impl RemoteDeletionConfig {
+    fn delete_batch<B: StorageBackend + ?Sized>(
+        &self,
+        backend: &B,
+        file_names: &[Arc<str>],
+    ) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match backend.delete(file_names) {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.retry.unwrap_or(0) => {
+                    attempt += 1;
+                    warn!("Delete attempt {attempt} failed, retrying: {e}");
+                    std::thread::sleep(self.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY));
+                }
+                Err(e) => return Err(e.with_msg(format!("Delete failed after {attempt} retries"))),
+            }
+        }
+    }
+
+    /// Delete `pending` from `backend` batch by batch, saving progress to `pending_path` after
+    /// each batch. Stops at the first batch failing.
+    pub fn delete_pending<B: StorageBackend + ?Sized>(
+        &self,
+        backend: &B,
+        pending: &mut PendingDeletions,
+        pending_path: &Path,
+    ) -> Result<()> {
+        let batch_size = self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+        let mut first = true;
+        while !pending.file_names.is_empty() {
+            if !first {
+                std::thread::sleep(self.batch_interval.unwrap_or(DEFAULT_BATCH_INTERVAL));
+            }
+            first = false;
+            let len = batch_size.min(pending.file_names.len());
+            self.delete_batch(backend, &pending.file_names[..len])?;
+            for file_name in pending.file_names.drain(..len) {
+                info!("Removed out of retention remote file {file_name:?}");
+            }
+            pending.write(pending_path)?;
+        }
+        Ok(())
+    }
+}
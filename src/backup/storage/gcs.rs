@@ -0,0 +1,324 @@
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use crate::backup::storage::http::{curl, download, output, uri_encode, Body, Request};
+use crate::backup::storage::{check_stored_size, StorageBackend};
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+static DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+static DEFAULT_OPENSSL_COMMAND: &str = "openssl";
+static SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+static TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+/// Tokens are renewed this long before they expire.
+static TOKEN_RENEW_MARGIN: Duration = Duration::from_secs(300);
+
+/// Google Cloud Storage bucket, objects are named `{prefix}{file_name}`. Requests are authorized
+/// with OAuth tokens of a service account and sent with `curl`.
+///
+/// Tokens are obtained with a JWT signed by `openssl`, the private key of the service account
+/// is passed to it on stdin. Tokens are passed to curl on stdin too, never in its process
+/// arguments.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct GcsStorageConfig {
+    pub bucket: Arc<str>,
+    /// Prepended to the file names to form object names, e.g. `host-a/`.
+    pub prefix: Option<Arc<str>>,
+    /// Service account key JSON file, read whenever a token is needed.
+    pub credentials_file: Arc<Path>,
+    /// `https://storage.googleapis.com` by default.
+    pub endpoint: Option<Arc<str>>,
+    /// Path to the curl binary.
+    pub command: Option<Arc<str>>,
+    /// Path to the openssl binary.
+    pub openssl_command: Option<Arc<str>>,
+    #[serde(skip)]
+    token: Arc<Mutex<Option<(SecretString, Instant)>>>,
+}
+
+/// Fields of a service account key file used for authorization, the private key is a PEM.
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: SecretString,
+    token_uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: SecretString,
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ListResponse {
+    #[serde(default)]
+    items: Vec<ListItem>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListItem {
+    name: String,
+    /// Decimal string, only requested when confirming uploads.
+    size: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    code: u16,
+}
+
+impl GcsStorageConfig {
+    fn object_name(&self, file_name: &str) -> String {
+        format!("{}{file_name}", self.prefix.as_deref().unwrap_or_default())
+    }
+
+    fn endpoint(&self) -> &str {
+        self.endpoint
+            .as_deref()
+            .unwrap_or(DEFAULT_ENDPOINT)
+            .trim_end_matches('/')
+    }
+
+    /// Cached access token, a new one is requested when it is about to expire.
+    fn access_token(&self) -> Result<SecretString> {
+        let mut token = self
+            .token
+            .lock()
+            .map_err(|_| std::io::Error::other("token cache is poisoned"))?;
+        if let Some((token, expires_at)) = token.as_ref() {
+            if Instant::now() + TOKEN_RENEW_MARGIN < *expires_at {
+                return Ok(token.clone());
+            }
+        }
+        let requested_at = Instant::now();
+        let response = self.request_token().with_msg(format!(
+            "Get GCS token for {:?} failed",
+            self.credentials_file
+        ))?;
+        let lifetime = response
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(TOKEN_LIFETIME);
+        *token = Some((response.access_token.clone(), requested_at + lifetime));
+        Ok(response.access_token)
+    }
+
+    fn request_token(&self) -> Result<TokenResponse> {
+        let key: ServiceAccountKey =
+            serde_json::from_reader(BufReader::new(File::open(&self.credentials_file)?))?;
+        let token_uri = key.token_uri.as_deref().unwrap_or(DEFAULT_TOKEN_URI);
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(std::io::Error::other)?
+            .as_secs();
+        let header = BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+        let claims = BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&serde_json::json!({
+            "iss": key.client_email,
+            "scope": SCOPE,
+            "aud": token_uri,
+            "iat": iat,
+            "exp": iat + TOKEN_LIFETIME.as_secs(),
+        }))?);
+        let signing_input = format!("{header}.{claims}");
+        let signature = BASE64_URL_SAFE_NO_PAD.encode(self.sign(&key, &signing_input)?);
+        let form = format!(
+            "grant_type={}&assertion={signing_input}.{signature}",
+            uri_encode("urn:ietf:params:oauth:grant-type:jwt-bearer", false)
+        );
+        let body = Body::Data(form.as_bytes());
+        let mut request = curl(self.command.as_deref(), "POST", &[]);
+        request.command.arg(token_uri);
+        let output = output(request, &body)?;
+        if !output.status.success() {
+            return Err(self.error(&output));
+        }
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+
+    /// RS256 signature of `signing_input` by the service account key.
+    fn sign(&self, key: &ServiceAccountKey, signing_input: &str) -> Result<Vec<u8>> {
+        // The signing input is not secret, the key stays off the disk
+        let mut input = tempfile::NamedTempFile::new()?;
+        input.write_all(signing_input.as_bytes())?;
+        input.flush()?;
+        let mut child = Command::new(
+            self.openssl_command
+                .as_deref()
+                .unwrap_or(DEFAULT_OPENSSL_COMMAND),
+        )
+        .args(["dgst", "-sha256", "-binary", "-sign", "/dev/stdin"])
+        .arg(input.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+        let write_res = child
+            .stdin
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdin is not piped"))
+            .and_then(|mut stdin| stdin.write_all(key.private_key.expose_secret().as_bytes()));
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "signing token request failed with {}",
+                output.status
+            ))
+            .into());
+        }
+        write_res?;
+        Ok(output.stdout)
+    }
+
+    /// Authorized curl request for `method` on `path` below the endpoint.
+    fn request(&self, method: &str, path: &str, query: &[(&str, &str)]) -> Result<Request> {
+        let mut url = format!("{}{path}", self.endpoint());
+        for (idx, (k, v)) in query.iter().enumerate() {
+            url.push(if idx == 0 { '?' } else { '&' });
+            url.push_str(&format!(
+                "{}={}",
+                uri_encode(k, false),
+                uri_encode(v, false)
+            ));
+        }
+        let mut request = curl(self.command.as_deref(), method, &[]);
+        request.command.arg(url);
+        request.config(
+            "header",
+            &format!(
+                "Authorization: Bearer {}",
+                self.access_token()?.expose_secret()
+            ),
+        );
+        Ok(request)
+    }
+
+    /// Path of the object `file_name` in the JSON API.
+    fn object_path(&self, file_name: &str) -> String {
+        format!(
+            "/storage/v1/b/{}/o/{}",
+            uri_encode(&self.bucket, false),
+            uri_encode(&self.object_name(file_name), false)
+        )
+    }
+
+    fn error(&self, output: &Output) -> Error {
+        std::io::Error::other(format!(
+            "GCS request for bucket {:?} failed with {}: {}",
+            self.bucket,
+            output.status,
+            String::from_utf8_lossy(&output.stdout).trim()
+        ))
+        .into()
+    }
+
+    /// Send `request`, returning its stdout. The response body is part of the error on failure.
+    fn run(&self, request: Request, body: &Body) -> Result<Vec<u8>> {
+        let output = output(request, body)?;
+        if !output.status.success() {
+            return Err(self.error(&output));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl StorageBackend for GcsStorageConfig {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        let file_name = archive_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| std::io::Error::other("archive path has no valid file name"))?;
+        let body = Body::File(archive_path);
+        let object_name = self.object_name(file_name);
+        let mut request = self.request(
+            "POST",
+            &format!("/upload/storage/v1/b/{}/o", uri_encode(&self.bucket, false)),
+            &[("uploadType", "media"), ("name", &object_name)],
+        )?;
+        request
+            .command
+            .args(["--header", "Content-Type: application/octet-stream"]);
+        self.run(request, &body)?;
+        Ok(())
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        download(
+            self.request("GET", &self.object_path(file_name), &[("alt", "media")])?,
+            writer,
+            &format!("GCS download of {file_name:?}"),
+        )
+    }
+
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        let path = format!("/storage/v1/b/{}/o", uri_encode(&self.bucket, false));
+        let mut names = Vec::new();
+        let mut page_token = None;
+        loop {
+            let mut query = vec![("prefix", prefix), ("fields", "items(name),nextPageToken")];
+            if let Some(token) = page_token.as_deref() {
+                query.push(("pageToken", token));
+            }
+            let response = self.run(self.request("GET", &path, &query)?, &Body::None)?;
+            let response: ListResponse = serde_json::from_slice(&response)?;
+            names.extend(
+                response
+                    .items
+                    .into_iter()
+                    .filter_map(|item| item.name.strip_prefix(prefix).map(Arc::<str>::from))
+                    .filter(|name| !name.contains('/')),
+            );
+            page_token = response.next_page_token;
+            if page_token.is_none() {
+                return Ok(names);
+            }
+        }
+    }
+
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        for file_name in file_names {
+            let request = self.request("DELETE", &self.object_path(file_name), &[])?;
+            let output = output(request, &Body::None)?;
+            let gone = serde_json::from_slice::<ErrorResponse>(&output.stdout)
+                .is_ok_and(|e| e.error.code == 404);
+            if !output.status.success() && !gone {
+                return Err(self.error(&output));
+            }
+        }
+        Ok(())
+    }
+
+    fn confirm_upload(&self, file_name: &str, size: u64, _sha256: &str) -> Result<()> {
+        let object_name = self.object_name(file_name);
+        let path = format!("/storage/v1/b/{}/o", uri_encode(&self.bucket, false));
+        let query = [
+            ("prefix", object_name.as_str()),
+            ("fields", "items(name,size)"),
+        ];
+        let response = self.run(self.request("GET", &path, &query)?, &Body::None)?;
+        let response: ListResponse = serde_json::from_slice(&response)?;
+        let stored = response
+            .items
+            .into_iter()
+            .find(|item| item.name == object_name)
+            .and_then(|item| item.size?.parse().ok());
+        check_stored_size(file_name, size, stored)
+    }
+}
@@ -0,0 +1,206 @@
+use crate::backup::result_error::result::Result;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::io::{copy, Write};
+use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+
+pub static DEFAULT_COMMAND: &str = "curl";
+
+/// Body sent with a request.
+pub enum Body<'a> {
+    None,
+    Data(&'a [u8]),
+    File(&'a Path),
+}
+
+/// Percent encode unreserved characters only, keeping `/` when encoding a path.
+pub fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            b => {
+                let _ = write!(encoded, "%{b:02X}");
+            }
+        }
+    }
+    encoded
+}
+
+/// Text content of every `<tag>` element in `xml`, entities decoded.
+pub fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    xml.split(open.as_str())
+        .skip(1)
+        .filter_map(|rest| {
+            rest.split_once(close.as_str()).map(|(value, _)| {
+                value
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&")
+            })
+        })
+        .collect()
+}
+
+/// Quote `value` as a string of a curl config file.
+pub fn config_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Curl command with the config it reads from stdin, anything secret goes in the config so it
+/// never shows up in the process list.
+pub struct Request {
+    pub command: Command,
+    pub config: String,
+}
+
+impl Request {
+    /// Set the curl option `name` to `value` in the config.
+    pub fn config(&mut self, name: &str, value: &str) -> &mut Self {
+        let _ = writeln!(self.config, "{name} = {}", config_quote(value));
+        self
+    }
+}
+
+/// Curl request sending `method`, failing on HTTP errors with the response body on stdout.
+pub fn curl(program: Option<&str>, method: &str, headers: &[String]) -> Request {
+    let mut command = Command::new(program.unwrap_or(DEFAULT_COMMAND));
+    command
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail-with-body",
+            "--config",
+            "-",
+            "--request",
+            method,
+        ])
+        .args(["--header", "Expect:"]);
+    for header in headers {
+        command.args(["--header", header]);
+    }
+    Request {
+        command,
+        config: String::new(),
+    }
+}
+
+/// Start `command` and write `config` to its stdin.
+pub fn spawn(mut command: Command, config: &str) -> Result<Child> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // Dropping stdin closes it, curl only starts once the config is read
+    let write_res = child
+        .stdin
+        .take()
+        .ok_or_else(|| std::io::Error::other("child stdin is not piped"))
+        .and_then(|mut stdin| stdin.write_all(config.as_bytes()));
+    if let Err(e) = write_res {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(e.into());
+    }
+    Ok(child)
+}
+
+/// Send `request` with `body`, the status is left to the caller. Stdin carries the config, data
+/// is spooled to a temporary file curl reads it from.
+pub fn output(request: Request, body: &Body) -> Result<Output> {
+    let Request {
+        mut command,
+        config,
+    } = request;
+    let mut spooled = None;
+    match body {
+        Body::None => {}
+        Body::Data(data) => {
+            let mut file = tempfile::NamedTempFile::new()?;
+            file.write_all(data)?;
+            file.flush()?;
+            let mut arg = OsString::from("@");
+            arg.push(file.path());
+            command.arg("--data-binary").arg(arg);
+            spooled = Some(file);
+        }
+        Body::File(path) => {
+            command.arg("--upload-file").arg(path);
+        }
+    }
+    let output = spawn(command, &config)?.wait_with_output()?;
+    drop(spooled);
+    Ok(output)
+}
+
+/// Send `request` streaming its stdout into `writer`.
+pub fn download(request: Request, writer: &mut dyn Write, what: &str) -> Result<()> {
+    let mut child = spawn(request.command, &request.config)?;
+    let copy_res = child
+        .stdout
+        .take()
+        .ok_or_else(|| std::io::Error::other("child stdout is not piped"))
+        .and_then(|mut stdout| copy(&mut stdout, writer));
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("{what} failed with {status}")).into());
+    }
+    copy_res?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_encode_keeps_unreserved_characters_only() {
+        assert_eq!(uri_encode("AZaz09-_.~", false), "AZaz09-_.~");
+        assert_eq!(
+            uri_encode("host a/b+c=d&é", false),
+            "host%20a%2Fb%2Bc%3Dd%26%C3%A9"
+        );
+        assert_eq!(uri_encode("dir/file name", true), "dir/file%20name");
+    }
+
+    #[test]
+    fn xml_values_of_every_element_decoded() {
+        let xml = "<List><Name>a&amp;b</Name><Other>x</Other><Name>&lt;c&gt; &quot;d&apos;\
+                   </Name><Name>unclosed</List>";
+        assert_eq!(xml_values(xml, "Name"), ["a&b", "<c> \"d'"]);
+        assert_eq!(xml_values(xml, "Other"), ["x"]);
+        assert!(xml_values(xml, "Missing").is_empty());
+        // `&amp;` is decoded last so escaped entities stay literal
+        assert_eq!(xml_values("<a>&amp;lt;</a>", "a"), ["&lt;"]);
+    }
+
+    #[test]
+    fn config_quote_escapes_what_curl_unescapes() {
+        assert_eq!(config_quote("plain"), "\"plain\"");
+        assert_eq!(
+            config_quote("a\"b\\c\nd\re\tf"),
+            "\"a\\\"b\\\\c\\nd\\re\\tf\""
+        );
+    }
+}
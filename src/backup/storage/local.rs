@@ -0,0 +1,63 @@
+use crate::backup::result_error::result::Result;
+use crate::backup::storage::{check_stored_size, StorageBackend};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{copy, ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Copy archives into a directory, e.g. a mounted network share or removable drive.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct LocalStorageConfig {
+    pub dir: Arc<Path>,
+}
+
+impl StorageBackend for LocalStorageConfig {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        let file_name = archive_path
+            .file_name()
+            .ok_or_else(|| std::io::Error::other("archive path has no valid file name"))?;
+        std::fs::create_dir_all(&self.dir)?;
+        let dst = self.dir.join(file_name);
+        // Never leave a truncated copy under the final name
+        let mut tmp_name = file_name.to_os_string();
+        tmp_name.push(".tmp");
+        let tmp = self.dir.join(tmp_name);
+        std::fs::copy(archive_path, &tmp)?;
+        File::open(&tmp)?.sync_all()?;
+        std::fs::rename(tmp, dst)?;
+        Ok(())
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        copy(&mut File::open(self.dir.join(file_name))?, writer)?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        Ok(std::fs::read_dir(&self.dir)?
+            .filter_map(|r| r.ok())
+            .filter(|r| r.file_type().is_ok_and(|t| t.is_file()))
+            .filter_map(|r| r.file_name().to_str().map(Arc::from))
+            .collect())
+    }
+
+    fn confirm_upload(&self, file_name: &str, size: u64, _sha256: &str) -> Result<()> {
+        let stored = match std::fs::metadata(self.dir.join(file_name)) {
+            Ok(metadata) => Some(metadata.len()),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        check_stored_size(file_name, size, stored)
+    }
+
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        for file_name in file_names {
+            match std::fs::remove_file(self.dir.join(file_name.as_ref())) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
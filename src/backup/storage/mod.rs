@@ -0,0 +1,306 @@
+#[cfg(feature = "azure")]
+pub mod azure;
+pub mod command;
+pub mod deletion;
+#[cfg(feature = "gcs")]
+pub mod gcs;
+pub mod http;
+pub mod local;
+#[cfg(feature = "oci")]
+pub mod oci;
+pub mod receipt;
+pub mod resumable;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+pub mod verify;
+
+use crate::backup::checksum::sha256_hex;
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithDebugObjectAndFnName;
+#[cfg(feature = "azure")]
+use crate::backup::storage::azure::AzureStorageConfig;
+use crate::backup::storage::command::CommandStorageConfig;
+use crate::backup::storage::deletion::RemoteDeletionConfig;
+#[cfg(feature = "gcs")]
+use crate::backup::storage::gcs::GcsStorageConfig;
+use crate::backup::storage::local::LocalStorageConfig;
+#[cfg(feature = "oci")]
+use crate::backup::storage::oci::OciStorageConfig;
+use crate::backup::storage::resumable::ResumableStorageBackend;
+#[cfg(feature = "s3")]
+use crate::backup::storage::s3::S3StorageConfig;
+#[cfg(feature = "sftp")]
+use crate::backup::storage::sftp::SftpStorageConfig;
+#[cfg(feature = "testing")]
+use crate::backup::testing::MemoryStorage;
+use derive_more::From;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Fail unless the `stored` size of `file_name`, `None` when missing, is the `size` uploaded.
+pub fn check_stored_size(file_name: &str, size: u64, stored: Option<u64>) -> Result<()> {
+    match stored {
+        Some(stored) if stored == size => Ok(()),
+        Some(stored) => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("stored {file_name:?} has {stored} bytes instead of {size}"),
+        ))?,
+        None => Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("{file_name:?} is not stored after upload"),
+        ))?,
+    }
+}
+
+/// Fail unless `file_name` is among the `listed` stored files, for storage without sizes.
+pub fn check_listed(file_name: &str, listed: &[Arc<str>]) -> Result<()> {
+    if listed.iter().any(|name| name.as_ref() == file_name) {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        ErrorKind::NotFound,
+        format!("{file_name:?} is not stored after upload"),
+    ))?
+}
+
+pub trait StorageBackend {
+    /// Copy the finished archive at `archive_path` to the storage.
+    fn upload(&self, archive_path: &Path) -> Result<()>;
+
+    /// Stream the stored `file_name` into `writer`, for verifying remote copies.
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        let _ = writer;
+        Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!("storage does not support downloading {file_name:?}"),
+        ))?
+    }
+
+    /// Names of the files stored, for finding archives missing remotely.
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            "storage does not support listing",
+        ))?
+    }
+
+    /// Delete the stored `file_names`, files already gone are not an error.
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!("storage does not support deleting {file_names:?}"),
+        ))?
+    }
+
+    /// Check the stored `file_name` is the `size` bytes with hex SHA-256 `sha256` just uploaded,
+    /// before the upload is recorded. Storage unable to tell trusts the upload.
+    fn confirm_upload(&self, file_name: &str, size: u64, sha256: &str) -> Result<()> {
+        let _ = (file_name, size, sha256);
+        Ok(())
+    }
+
+    /// Storage supporting resumable uploads, preferred over [`StorageBackend::upload`].
+    fn as_resumable(&self) -> Option<&dyn ResumableStorageBackend> {
+        None
+    }
+}
+
+#[derive(Clone, From, Serialize, Deserialize, Debug)]
+#[serde(tag = "storage_type")]
+#[serde(rename_all = "snake_case")]
+pub enum StorageConfig {
+    Command(Box<CommandStorageConfig>),
+    Local(LocalStorageConfig),
+    #[cfg(feature = "s3")]
+    S3(S3StorageConfig),
+    #[cfg(feature = "azure")]
+    Azure(AzureStorageConfig),
+    #[cfg(feature = "gcs")]
+    Gcs(GcsStorageConfig),
+    #[cfg(feature = "sftp")]
+    Sftp(SftpStorageConfig),
+    #[cfg(feature = "oci")]
+    Oci(OciStorageConfig),
+    /// Files kept in memory, only set up from code.
+    #[cfg(feature = "testing")]
+    #[serde(skip)]
+    Memory(MemoryStorage),
+}
+
+impl StorageBackend for StorageConfig {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        match self {
+            StorageConfig::Command(c) => c.upload(archive_path),
+            StorageConfig::Local(c) => c.upload(archive_path),
+            #[cfg(feature = "s3")]
+            StorageConfig::S3(c) => c.upload(archive_path),
+            #[cfg(feature = "azure")]
+            StorageConfig::Azure(c) => c.upload(archive_path),
+            #[cfg(feature = "gcs")]
+            StorageConfig::Gcs(c) => c.upload(archive_path),
+            #[cfg(feature = "sftp")]
+            StorageConfig::Sftp(c) => c.upload(archive_path),
+            #[cfg(feature = "oci")]
+            StorageConfig::Oci(c) => c.upload(archive_path),
+            #[cfg(feature = "testing")]
+            StorageConfig::Memory(c) => c.upload(archive_path),
+        }
+        .with_debug_object_and_fn_name(self.clone(), "upload")
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        match self {
+            StorageConfig::Command(c) => c.download(file_name, writer),
+            StorageConfig::Local(c) => c.download(file_name, writer),
+            #[cfg(feature = "s3")]
+            StorageConfig::S3(c) => c.download(file_name, writer),
+            #[cfg(feature = "azure")]
+            StorageConfig::Azure(c) => c.download(file_name, writer),
+            #[cfg(feature = "gcs")]
+            StorageConfig::Gcs(c) => c.download(file_name, writer),
+            #[cfg(feature = "sftp")]
+            StorageConfig::Sftp(c) => c.download(file_name, writer),
+            #[cfg(feature = "oci")]
+            StorageConfig::Oci(c) => c.download(file_name, writer),
+            #[cfg(feature = "testing")]
+            StorageConfig::Memory(c) => c.download(file_name, writer),
+        }
+        .with_debug_object_and_fn_name(self.clone(), "download")
+    }
+
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        match self {
+            StorageConfig::Command(c) => c.list(),
+            StorageConfig::Local(c) => c.list(),
+            #[cfg(feature = "s3")]
+            StorageConfig::S3(c) => c.list(),
+            #[cfg(feature = "azure")]
+            StorageConfig::Azure(c) => c.list(),
+            #[cfg(feature = "gcs")]
+            StorageConfig::Gcs(c) => c.list(),
+            #[cfg(feature = "sftp")]
+            StorageConfig::Sftp(c) => c.list(),
+            #[cfg(feature = "oci")]
+            StorageConfig::Oci(c) => c.list(),
+            #[cfg(feature = "testing")]
+            StorageConfig::Memory(c) => c.list(),
+        }
+        .with_debug_object_and_fn_name(self.clone(), "list")
+    }
+
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        match self {
+            StorageConfig::Command(c) => c.delete(file_names),
+            StorageConfig::Local(c) => c.delete(file_names),
+            #[cfg(feature = "s3")]
+            StorageConfig::S3(c) => c.delete(file_names),
+            #[cfg(feature = "azure")]
+            StorageConfig::Azure(c) => c.delete(file_names),
+            #[cfg(feature = "gcs")]
+            StorageConfig::Gcs(c) => c.delete(file_names),
+            #[cfg(feature = "sftp")]
+            StorageConfig::Sftp(c) => c.delete(file_names),
+            #[cfg(feature = "oci")]
+            StorageConfig::Oci(c) => c.delete(file_names),
+            #[cfg(feature = "testing")]
+            StorageConfig::Memory(c) => c.delete(file_names),
+        }
+        .with_debug_object_and_fn_name(self.clone(), "delete")
+    }
+
+    fn confirm_upload(&self, file_name: &str, size: u64, sha256: &str) -> Result<()> {
+        match self {
+            StorageConfig::Command(c) => c.confirm_upload(file_name, size, sha256),
+            StorageConfig::Local(c) => c.confirm_upload(file_name, size, sha256),
+            #[cfg(feature = "s3")]
+            StorageConfig::S3(c) => c.confirm_upload(file_name, size, sha256),
+            #[cfg(feature = "azure")]
+            StorageConfig::Azure(c) => c.confirm_upload(file_name, size, sha256),
+            #[cfg(feature = "gcs")]
+            StorageConfig::Gcs(c) => c.confirm_upload(file_name, size, sha256),
+            #[cfg(feature = "sftp")]
+            StorageConfig::Sftp(c) => c.confirm_upload(file_name, size, sha256),
+            #[cfg(feature = "oci")]
+            StorageConfig::Oci(c) => c.confirm_upload(file_name, size, sha256),
+            #[cfg(feature = "testing")]
+            StorageConfig::Memory(c) => c.confirm_upload(file_name, size, sha256),
+        }
+        .with_debug_object_and_fn_name(self.clone(), "confirm_upload")
+    }
+
+    fn as_resumable(&self) -> Option<&dyn ResumableStorageBackend> {
+        match self {
+            StorageConfig::Command(c) => c.as_resumable(),
+            StorageConfig::Local(c) => c.as_resumable(),
+            #[cfg(feature = "s3")]
+            StorageConfig::S3(c) => c.as_resumable(),
+            #[cfg(feature = "azure")]
+            StorageConfig::Azure(c) => c.as_resumable(),
+            #[cfg(feature = "gcs")]
+            StorageConfig::Gcs(c) => c.as_resumable(),
+            #[cfg(feature = "sftp")]
+            StorageConfig::Sftp(c) => c.as_resumable(),
+            #[cfg(feature = "oci")]
+            StorageConfig::Oci(c) => c.as_resumable(),
+            #[cfg(feature = "testing")]
+            StorageConfig::Memory(c) => c.as_resumable(),
+        }
+    }
+}
+
+/// A storage with optional destination specific settings.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct StorageDestinationConfig {
+    /// Identifies the destination in upload receipts, a digest of the storage settings by
+    /// default. Set it to keep receipts valid when changing those settings.
+    pub name: Option<Arc<str>>,
+    /// Encrypt the copy sent to this destination differently than the local archive.
+    pub encryptor: Option<Arc<EncryptorConfig>>,
+    /// Delete the copies of archives removed by retention, remote copies are kept otherwise.
+    pub prune: Option<Arc<RemoteDeletionConfig>>,
+    #[serde(flatten)]
+    pub storage: StorageConfig,
+}
+
+impl StorageDestinationConfig {
+    /// Stable identifier of the destination, unchanged when destinations are reordered.
+    pub fn id(&self) -> Arc<str> {
+        self.name.clone().unwrap_or_else(|| {
+            let settings = serde_json::to_vec(&self.storage).unwrap_or_default();
+            sha256_hex(&settings)[..16].into()
+        })
+    }
+}
+
+impl StorageBackend for StorageDestinationConfig {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        self.storage.upload(archive_path)
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        self.storage.download(file_name, writer)
+    }
+
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        self.storage.list()
+    }
+
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        self.storage.delete(file_names)
+    }
+
+    fn confirm_upload(&self, file_name: &str, size: u64, sha256: &str) -> Result<()> {
+        self.storage.confirm_upload(file_name, size, sha256)
+    }
+
+    fn as_resumable(&self) -> Option<&dyn ResumableStorageBackend> {
+        self.storage.as_resumable()
+    }
+}
@@ -0,0 +1,569 @@
+use crate::backup::checksum::sha256_file;
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use crate::backup::storage::http::{
+    config_quote, output, spawn, uri_encode, Body, Request, DEFAULT_COMMAND,
+};
+use crate::backup::storage::{check_stored_size, StorageBackend};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::{SecondsFormat, Utc};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{copy, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+static MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+static ARTIFACT_TYPE: &str = "application/vnd.k-backup.archive.v1";
+static LAYER_MEDIA_TYPE: &str = "application/vnd.k-backup.archive.layer.v1";
+static EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+static EMPTY_CONFIG: &[u8] = b"{}";
+/// Annotation holding the file name, also used by `oras pull` to name the file.
+static TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+static CREATED_ANNOTATION: &str = "org.opencontainers.image.created";
+static MAX_TAG_LEN: usize = 128;
+static TAG_PAGE_SIZE: usize = 1000;
+/// Lifetime of tokens whose response has none, as the token auth spec defines.
+static DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+/// Tokens are renewed this long before they expire.
+static TOKEN_RENEW_MARGIN: Duration = Duration::from_secs(10);
+/// Error codes of the distribution spec telling the manifest or repository does not exist.
+static NOT_FOUND_CODES: [&str; 2] = ["MANIFEST_UNKNOWN", "NAME_UNKNOWN"];
+
+/// Repository of an OCI registry, every archive is pushed as an artifact with a single layer
+/// (ORAS-style) tagged with its file name, characters not allowed in tags replaced by `_`.
+/// Requests are sent with `curl`.
+///
+/// Registries with token auth, e.g. ghcr.io or Docker Hub, get a token for `username` and
+/// `password`, registries with basic auth get the credentials directly. Both are passed to curl
+/// on stdin, never in its process arguments. Deleting needs a registry allowing manifest deletes,
+/// blobs are left to its garbage collection.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct OciStorageConfig {
+    /// Host of the registry with an optional port, e.g. `ghcr.io` or `localhost:5000`.
+    pub registry: Arc<str>,
+    /// Repository the artifacts are pushed to, e.g. `acme/backups`.
+    pub repository: Arc<str>,
+    pub username: Option<Arc<str>>,
+    /// Password or access token of `username`. Never serialized back.
+    #[serde(default, skip_serializing)]
+    pub password: Option<SecretString>,
+    /// Use HTTP instead of HTTPS, for a registry on a trusted network only.
+    pub plain_http: Option<bool>,
+    /// Path to the curl binary.
+    pub command: Option<Arc<str>>,
+    #[serde(skip)]
+    authorization: Arc<Mutex<Option<Authorization>>>,
+}
+
+/// `Authorization` header value and when it must be renewed, no value when the registry needs no
+/// auth.
+type Authorization = (Option<SecretString>, Option<Instant>);
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<SecretString>,
+    access_token: Option<SecretString>,
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TagList {
+    tags: Option<Vec<String>>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    schema_version: u32,
+    media_type: Option<String>,
+    artifact_type: Option<String>,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+    annotations: Option<HashMap<String, String>>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: u64,
+    annotations: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    errors: Vec<ErrorDetails>,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    code: String,
+}
+
+/// Exit status and stdout of curl, the body is the error document of the registry when the
+/// request failed.
+struct Response {
+    status: ExitStatus,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn is_not_found(&self) -> bool {
+        serde_json::from_slice::<ErrorResponse>(&self.body)
+            .is_ok_and(|r| r.errors.iter().any(|e| NOT_FOUND_CODES.contains(&&*e.code)))
+    }
+}
+
+/// Challenge of the `WWW-Authenticate` header of the registry.
+enum Challenge {
+    None,
+    Basic,
+    Bearer(HashMap<String, String>),
+}
+
+impl Challenge {
+    /// Challenge of raw response `headers`, the last response counts when redirected.
+    fn parse(headers: &str) -> Self {
+        let header = headers.lines().rev().find_map(|line| {
+            line.split_once(':')
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("www-authenticate"))
+                .map(|(_, value)| value.trim())
+        });
+        let Some(header) = header else {
+            return Challenge::None;
+        };
+        let (scheme, params) = header.split_once(' ').unwrap_or((header, ""));
+        match scheme.to_ascii_lowercase().as_str() {
+            "bearer" => Challenge::Bearer(auth_params(params)),
+            _ => Challenge::Basic,
+        }
+    }
+}
+
+/// `key="value"` pairs of an auth challenge, values may hold commas.
+fn auth_params(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key
+            .trim()
+            .trim_start_matches(',')
+            .trim()
+            .to_ascii_lowercase();
+        let value = value.trim_start();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        parsed.insert(key, value.to_string());
+        rest = next.trim_start_matches(',').trim();
+    }
+    parsed
+}
+
+/// Tag of `file_name`, valid tags are `[A-Za-z0-9_][A-Za-z0-9._-]{0,127}`.
+fn tag(file_name: &str) -> String {
+    let mut tag = file_name
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    if !tag.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+        tag.insert(0, '_');
+    }
+    tag.truncate(MAX_TAG_LEN);
+    tag
+}
+
+impl OciStorageConfig {
+    fn base_url(&self) -> String {
+        let scheme = match self.plain_http.unwrap_or(false) {
+            true => "http",
+            false => "https",
+        };
+        format!("{scheme}://{}", self.registry.trim_end_matches('/'))
+    }
+
+    /// URL of `path` below the repository.
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/v2/{}/{path}",
+            self.base_url(),
+            uri_encode(self.repository.trim_matches('/'), true)
+        )
+    }
+
+    /// Curl config with `username` and `password`, read from stdin.
+    fn credentials_config(&self) -> String {
+        match &self.username {
+            None => String::new(),
+            Some(username) => {
+                let password = self.password.as_ref().map(|p| p.expose_secret().as_str());
+                format!(
+                    "user = {}\n",
+                    config_quote(&format!("{username}:{}", password.unwrap_or_default()))
+                )
+            }
+        }
+    }
+
+    /// Curl config with the `Authorization` header, read from stdin. A token is requested when
+    /// the cached one is about to expire.
+    fn auth_config(&self) -> Result<String> {
+        let mut authorization = self
+            .authorization
+            .lock()
+            .map_err(|_| std::io::Error::other("authorization cache is poisoned"))?;
+        let cached = authorization.as_ref().filter(|(_, renew_at)| {
+            renew_at.is_none_or(|renew_at| Instant::now() + TOKEN_RENEW_MARGIN < renew_at)
+        });
+        let (value, renew_at) = match cached {
+            Some(cached) => cached.clone(),
+            None => self.authorize().with_msg(format!(
+                "Authorize to OCI registry {:?} failed",
+                self.registry
+            ))?,
+        };
+        let config = value
+            .as_ref()
+            .map(|v| {
+                format!(
+                    "header = {}\n",
+                    config_quote(&format!("Authorization: {}", v.expose_secret()))
+                )
+            })
+            .unwrap_or_default();
+        *authorization = Some((value, renew_at));
+        Ok(config)
+    }
+
+    /// `Authorization` header value asked for by the registry and when it must be renewed.
+    fn authorize(&self) -> Result<Authorization> {
+        let mut command = self.command();
+        command
+            .args(["--output", "/dev/null", "--dump-header", "-"])
+            .arg(format!("{}/v2/", self.base_url()));
+        let response = self.send(command, "", &Body::None)?;
+        let headers = String::from_utf8_lossy(&response.body);
+        match Challenge::parse(&headers) {
+            Challenge::None => Ok((None, None)),
+            Challenge::Basic => {
+                let username = self.username.as_deref().ok_or_else(|| {
+                    std::io::Error::other("registry asks for basic auth but username is unset")
+                })?;
+                let password = self.password.as_ref().map(|p| p.expose_secret().as_str());
+                let credentials =
+                    BASE64_STANDARD.encode(format!("{username}:{}", password.unwrap_or_default()));
+                Ok((Some(format!("Basic {credentials}").into()), None))
+            }
+            Challenge::Bearer(params) => {
+                let requested_at = Instant::now();
+                let token = self.request_token(&params)?;
+                let lifetime = token
+                    .expires_in
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+                let value = token.token.or(token.access_token).ok_or_else(|| {
+                    std::io::Error::other("token response of the registry holds no token")
+                })?;
+                Ok((
+                    Some(format!("Bearer {}", value.expose_secret()).into()),
+                    Some(requested_at + lifetime),
+                ))
+            }
+        }
+    }
+
+    fn request_token(&self, params: &HashMap<String, String>) -> Result<TokenResponse> {
+        let realm = params.get("realm").ok_or_else(|| {
+            std::io::Error::other("bearer challenge of the registry has no realm")
+        })?;
+        let scope = format!(
+            "repository:{}:pull,push,delete",
+            self.repository.trim_matches('/')
+        );
+        let mut url = format!("{realm}?scope={}", uri_encode(&scope, false));
+        if let Some(service) = params.get("service") {
+            url.push_str(&format!("&service={}", uri_encode(service, false)));
+        }
+        let mut command = self.command();
+        command.args(["--fail-with-body", "--location"]).arg(url);
+        let body = self.check(
+            self.send(command, &self.credentials_config(), &Body::None)?,
+            "token request",
+        )?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(self.command.as_deref().unwrap_or(DEFAULT_COMMAND));
+        command.args(["--silent", "--show-error", "--config", "-"]);
+        command
+    }
+
+    /// Authorized curl command for `method` on `url`, failing on HTTP errors with the response
+    /// body on stdout.
+    fn request(&self, method: &str, url: &str) -> Command {
+        let mut command = self.command();
+        command
+            .args(["--fail-with-body", "--request", method])
+            .args(["--header", "Expect:"])
+            .arg(url);
+        command
+    }
+
+    /// Run `command` with `config` on stdin and `body` attached, the status is left to the
+    /// caller.
+    fn send(&self, command: Command, config: &str, body: &Body) -> Result<Response> {
+        let request = Request {
+            command,
+            config: config.to_string(),
+        };
+        let output = output(request, body)?;
+        Ok(Response {
+            status: output.status,
+            body: output.stdout,
+        })
+    }
+
+    /// Run the authorized `command` with `body` attached, returning the response body.
+    fn fetch(&self, command: Command, body: &Body, what: &str) -> Result<Vec<u8>> {
+        self.check(self.send(command, &self.auth_config()?, body)?, what)
+    }
+
+    fn check(&self, response: Response, what: &str) -> Result<Vec<u8>> {
+        if !response.status.success() {
+            return Err(self.error(&response, what));
+        }
+        Ok(response.body)
+    }
+
+    fn error(&self, response: &Response, what: &str) -> Error {
+        std::io::Error::other(format!(
+            "OCI {what} on {}/{} failed with {}: {}",
+            self.registry,
+            self.repository,
+            response.status,
+            String::from_utf8_lossy(&response.body).trim()
+        ))
+        .into()
+    }
+
+    /// Push a blob of `digest`, read from the file at `path` or taken from `data`.
+    fn push_blob(&self, digest: &str, path: Option<&Path>, data: &[u8]) -> Result<()> {
+        let mut command = self.command();
+        command
+            .args(["--fail-with-body", "--request", "POST"])
+            .args(["--output", "/dev/null", "--dump-header", "-"])
+            .arg(self.url("blobs/uploads/"));
+        let headers = self.fetch(command, &Body::None, "blob upload start")?;
+        let headers = String::from_utf8_lossy(&headers);
+        let location = headers
+            .lines()
+            .rev()
+            .find_map(|line| {
+                line.split_once(':')
+                    .filter(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+                    .map(|(_, value)| value.trim())
+            })
+            .ok_or_else(|| std::io::Error::other("registry returned no upload location"))?;
+        let location = match location.starts_with('/') {
+            true => format!("{}{location}", self.base_url()),
+            false => location.to_string(),
+        };
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let url = format!("{location}{separator}digest={}", uri_encode(digest, false));
+        let mut command = self.request("PUT", &url);
+        command.args(["--header", "Content-Type: application/octet-stream"]);
+        let body = match path {
+            Some(path) => Body::File(path),
+            None => Body::Data(data),
+        };
+        self.fetch(command, &body, &format!("blob upload of {digest}"))?;
+        Ok(())
+    }
+
+    /// Raw manifest tagged for `file_name`, `None` when there is none.
+    fn manifest(&self, file_name: &str) -> Result<Option<Vec<u8>>> {
+        let mut command = self.request("GET", &self.url(&format!("manifests/{}", tag(file_name))));
+        command.args(["--header", &format!("Accept: {MANIFEST_MEDIA_TYPE}")]);
+        let response = self.send(command, &self.auth_config()?, &Body::None)?;
+        if response.is_not_found() {
+            return Ok(None);
+        }
+        self.check(response, &format!("manifest download of {file_name:?}"))
+            .map(Some)
+    }
+
+    /// Layer of a manifest pushed by [`OciStorageConfig::upload`], with the file name as title.
+    fn archive_layer(manifest: &Manifest) -> Option<&Descriptor> {
+        if manifest.artifact_type.as_deref() != Some(ARTIFACT_TYPE) {
+            return None;
+        }
+        manifest.layers.first()
+    }
+}
+
+impl StorageBackend for OciStorageConfig {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        let file_name = archive_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| std::io::Error::other("archive path has no valid file name"))?;
+        let digest = format!("sha256:{}", sha256_file(archive_path)?);
+        let size = std::fs::metadata(archive_path)?.len();
+        self.push_blob(&digest, Some(archive_path), &[])?;
+        let config_digest = format!("sha256:{:x}", Sha256::digest(EMPTY_CONFIG));
+        self.push_blob(&config_digest, None, EMPTY_CONFIG)?;
+
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(MANIFEST_MEDIA_TYPE.into()),
+            artifact_type: Some(ARTIFACT_TYPE.into()),
+            config: Descriptor {
+                media_type: EMPTY_MEDIA_TYPE.into(),
+                digest: config_digest,
+                size: EMPTY_CONFIG.len() as u64,
+                annotations: None,
+            },
+            layers: vec![Descriptor {
+                media_type: LAYER_MEDIA_TYPE.into(),
+                digest,
+                size,
+                annotations: Some(HashMap::from([(TITLE_ANNOTATION.into(), file_name.into())])),
+            }],
+            annotations: Some(HashMap::from([(
+                CREATED_ANNOTATION.into(),
+                Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            )])),
+        };
+        let manifest = serde_json::to_string(&manifest)?;
+        let mut command = self.request("PUT", &self.url(&format!("manifests/{}", tag(file_name))));
+        command.args(["--header", &format!("Content-Type: {MANIFEST_MEDIA_TYPE}")]);
+        self.fetch(
+            command,
+            &Body::Data(manifest.as_bytes()),
+            &format!("manifest upload of {file_name:?}"),
+        )?;
+        Ok(())
+    }
+
+    /// The artifact layer records the size and digest of the stored archive.
+    fn confirm_upload(&self, file_name: &str, size: u64, sha256: &str) -> Result<()> {
+        let manifest = self
+            .manifest(file_name)?
+            .map(|manifest| serde_json::from_slice::<Manifest>(&manifest))
+            .transpose()?;
+        let layer = manifest.as_ref().and_then(Self::archive_layer);
+        check_stored_size(file_name, size, layer.map(|layer| layer.size))?;
+        if layer.is_some_and(|layer| layer.digest != format!("sha256:{sha256}")) {
+            return Err(std::io::Error::other(format!(
+                "stored {file_name:?} does not match the uploaded digest"
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        let manifest = self.manifest(file_name)?.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no artifact tagged for {file_name:?}"),
+            )
+        })?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)?;
+        let layer = Self::archive_layer(&manifest).ok_or_else(|| {
+            std::io::Error::other(format!(
+                "artifact of {file_name:?} is not a k_backup archive"
+            ))
+        })?;
+        // Blobs are often served by redirecting to object storage, curl drops the
+        // authorization header when the host changes
+        let mut command = self.request("GET", &self.url(&format!("blobs/{}", layer.digest)));
+        command.arg("--location");
+        let mut child = spawn(command, &self.auth_config()?)?;
+        let copy_res = child
+            .stdout
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdout is not piped"))
+            .and_then(|mut stdout| copy(&mut stdout, writer));
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "OCI download of {file_name:?} failed with {status}"
+            ))
+            .into());
+        }
+        copy_res?;
+        Ok(())
+    }
+
+    /// Titles of the k_backup artifacts of the repository, a manifest is fetched per tag.
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        let mut tags: Vec<String> = Vec::new();
+        loop {
+            let mut url = self.url(&format!("tags/list?n={TAG_PAGE_SIZE}"));
+            if let Some(last) = tags.last() {
+                url.push_str(&format!("&last={}", uri_encode(last, false)));
+            }
+            let response =
+                self.send(self.request("GET", &url), &self.auth_config()?, &Body::None)?;
+            // Repositories are created by the first push
+            if response.is_not_found() {
+                break;
+            }
+            let page: TagList = serde_json::from_slice(&self.check(response, "tag listing")?)?;
+            let page = page.tags.unwrap_or_default();
+            let full = page.len() >= TAG_PAGE_SIZE;
+            tags.extend(page);
+            if !full {
+                break;
+            }
+        }
+        let mut names = Vec::new();
+        for tag in tags {
+            let Some(manifest) = self.manifest(&tag)? else {
+                continue;
+            };
+            let Ok(manifest) = serde_json::from_slice::<Manifest>(&manifest) else {
+                continue;
+            };
+            names.extend(
+                Self::archive_layer(&manifest)
+                    .and_then(|l| l.annotations.as_ref()?.get(TITLE_ANNOTATION))
+                    .map(|title| Arc::from(title.as_str())),
+            );
+        }
+        Ok(names)
+    }
+
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        for file_name in file_names {
+            let Some(manifest) = self.manifest(file_name)? else {
+                continue;
+            };
+            // Manifests are deleted by digest, deleting a tag is optional in the spec
+            let digest = format!("sha256:{:x}", Sha256::digest(&manifest));
+            let command = self.request("DELETE", &self.url(&format!("manifests/{digest}")));
+            let response = self.send(command, &self.auth_config()?, &Body::None)?;
+            if !response.is_not_found() {
+                self.check(response, &format!("deletion of {file_name:?}"))?;
+            }
+        }
+        Ok(())
+    }
+}
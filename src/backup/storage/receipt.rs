@@ -0,0 +1,82 @@
+use crate::backup::result_error::result::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, IntoInnerError};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+static RECEIPTS_FILE_SUFFIX: &str = ".uploads.json";
+
+/// Confirmed upload of an archive to a destination of `storage`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UploadReceipt {
+    /// [`StorageDestinationConfig::id`](crate::backup::storage::StorageDestinationConfig::id).
+    pub destination: Arc<str>,
+    /// Name of the stored file, differs from the archive with a destination encryptor.
+    pub file_name: Arc<str>,
+    pub size: u64,
+    pub sha256: String,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+/// Plain JSON stored next to an archive, recording which destinations hold a copy of it.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct UploadReceipts {
+    pub receipts: Vec<UploadReceipt>,
+}
+
+impl UploadReceipts {
+    pub fn receipts_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+        let archive_path = archive_path.as_ref();
+        let mut file_name = archive_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(RECEIPTS_FILE_SUFFIX);
+        archive_path.with_file_name(file_name)
+    }
+
+    /// Receipts of `archive_path`, empty when nothing was uploaded yet.
+    pub fn read<P: AsRef<Path>>(archive_path: P) -> Result<Self> {
+        match File::open(Self::receipts_path(archive_path)) {
+            Ok(f) => Ok(serde_json::from_reader(BufReader::new(f))?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Add `receipt` to the receipts of `archive_path`, replacing any of the same destination.
+    pub fn record<P: AsRef<Path>>(archive_path: P, receipt: UploadReceipt) -> Result<()> {
+        let archive_path = archive_path.as_ref();
+        let mut receipts = Self::read(archive_path)?;
+        receipts
+            .receipts
+            .retain(|r| r.destination != receipt.destination);
+        receipts.receipts.push(receipt);
+        receipts.write(archive_path)
+    }
+
+    /// Drop the receipt of `destination` from the receipts of `archive_path`.
+    pub fn forget<P: AsRef<Path>>(archive_path: P, destination: &str) -> Result<()> {
+        let archive_path = archive_path.as_ref();
+        let mut receipts = Self::read(archive_path)?;
+        receipts
+            .receipts
+            .retain(|r| r.destination.as_ref() != destination);
+        receipts.write(archive_path)
+    }
+
+    fn write(&self, archive_path: &Path) -> Result<()> {
+        let path = Self::receipts_path(archive_path);
+        let tmp_path = path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.into_inner().map_err(IntoInnerError::into_error)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn has_destination(&self, destination: &str) -> bool {
+        self.receipts
+            .iter()
+            .any(|r| r.destination.as_ref() == destination)
+    }
+}
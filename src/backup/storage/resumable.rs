@@ -0,0 +1,154 @@
+use crate::backup::checksum::{sha256_file, sha256_hex};
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Storage that can receive an archive in parts and resume an upload left unfinished.
+pub trait ResumableStorageBackend {
+    fn part_size(&self) -> u64;
+
+    /// Start a new upload of `file_name`, returning the backend upload id.
+    fn create_upload(&self, file_name: &str) -> Result<Arc<str>>;
+
+    /// Upload a single part, returning the backend part tag.
+    fn upload_part(
+        &self,
+        file_name: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: &[u8],
+    ) -> Result<Arc<str>>;
+
+    fn complete_upload(&self, file_name: &str, upload_id: &str, parts: &[UploadPart])
+        -> Result<()>;
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UploadPart {
+    pub part_number: u32,
+    pub tag: Arc<str>,
+    pub sha256: Arc<str>,
+}
+
+/// Persisted progress of an upload, only resumed if the archive still has the same checksum.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UploadState {
+    pub archive_path: Arc<Path>,
+    pub file_name: Arc<str>,
+    pub archive_size: u64,
+    pub archive_sha256: Arc<str>,
+    pub part_size: u64,
+    pub upload_id: Arc<str>,
+    pub parts: Vec<UploadPart>,
+}
+
+impl UploadState {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        serde_json::from_reader(File::open(path)?).map_err(Error::from)
+    }
+
+    fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// State file tracking the upload of `archive_path` within `state_dir`.
+pub fn upload_state_path<P: AsRef<Path>>(state_dir: &Path, archive_path: P) -> PathBuf {
+    let mut file_name = archive_path
+        .as_ref()
+        .file_name()
+        .unwrap_or_default()
+        .to_os_string();
+    file_name.push(".upload.json");
+    state_dir.join(file_name)
+}
+
+/// Upload `archive_path` in parts, persisting progress to `state_path` after each part so that
+/// an interrupted upload continues where it stopped instead of starting over.
+pub fn resumable_upload<B: ResumableStorageBackend + ?Sized>(
+    backend: &B,
+    archive_path: &Path,
+    state_path: &Path,
+) -> Result<()> {
+    let file_name: Arc<str> = archive_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| std::io::Error::other("archive path has no valid file name"))?
+        .into();
+    let archive_size = std::fs::metadata(archive_path)?.len();
+    let archive_sha256: Arc<str> = sha256_file(archive_path)?.into();
+    let part_size = backend.part_size().max(1);
+
+    let previous = if state_path.exists() {
+        UploadState::read(state_path)
+            .map_err(|e| warn!("Ignoring unreadable upload state {state_path:?}: {e}"))
+            .ok()
+            .filter(|s| {
+                s.file_name == file_name
+                    && s.archive_size == archive_size
+                    && s.archive_sha256 == archive_sha256
+                    && s.part_size == part_size
+            })
+    } else {
+        None
+    };
+
+    let mut state = match previous {
+        Some(state) => {
+            info!(
+                "Resuming upload of {file_name:?} from part {}",
+                state.parts.len() + 1
+            );
+            state
+        }
+        None => UploadState {
+            archive_path: archive_path.into(),
+            file_name: file_name.clone(),
+            archive_size,
+            archive_sha256,
+            part_size,
+            upload_id: backend.create_upload(&file_name)?,
+            parts: Vec::new(),
+        },
+    };
+    if let Some(parent) = state_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    state.write(state_path)?;
+
+    let mut file = File::open(archive_path)?;
+    let part_count = archive_size.div_ceil(part_size).max(1);
+    let mut buf = vec![0u8; part_size as usize];
+    for part_idx in state.parts.len() as u64..part_count {
+        let offset = part_idx * part_size;
+        let len = part_size.min(archive_size - offset) as usize;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf[..len])?;
+        let part_number = part_idx as u32 + 1;
+        let tag = backend
+            .upload_part(&file_name, &state.upload_id, part_number, &buf[..len])
+            .with_msg(format!("Upload part {part_number}/{part_count} failed"))?;
+        state.parts.push(UploadPart {
+            part_number,
+            tag,
+            sha256: sha256_hex(&buf[..len]).into(),
+        });
+        state.write(state_path)?;
+    }
+
+    backend.complete_upload(&file_name, &state.upload_id, &state.parts)?;
+    std::fs::remove_file(state_path)?;
+    Ok(())
+}
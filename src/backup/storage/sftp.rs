@@ -0,0 +1,211 @@
+use crate::backup::result_error::result::Result;
+use crate::backup::storage::http::{config_quote, uri_encode, DEFAULT_COMMAND};
+use crate::backup::storage::{check_listed, StorageBackend};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::io::{copy, ErrorKind, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+static DEFAULT_PORT: u16 = 22;
+
+/// Directory on a server reached over SSH, archives are stored as `{dir}/{file_name}`. Requests
+/// are sent with `curl`, which must be built with SFTP support (`curl -V` lists `sftp`).
+///
+/// Logging in uses `private_key_file` or `password`. Secrets are passed to curl on stdin, never
+/// in its process arguments. The host key is checked against `~/.ssh/known_hosts` unless
+/// `host_key_sha256` pins it.
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SftpStorageConfig {
+    pub host: Arc<str>,
+    /// 22 by default.
+    pub port: Option<u16>,
+    pub user: Arc<str>,
+    /// Absolute path of the directory on the server, created when missing.
+    pub dir: Arc<str>,
+    /// Private key file, e.g. `/root/.ssh/id_ed25519`, its public key is looked up next to it.
+    pub private_key_file: Option<Arc<Path>>,
+    /// Passphrase of `private_key_file`. Never serialized back.
+    #[serde(default, skip_serializing)]
+    pub key_passphrase: Option<SecretString>,
+    /// Password of `user`, when the server allows password logins. Never serialized back.
+    #[serde(default, skip_serializing)]
+    pub password: Option<SecretString>,
+    /// Base64 SHA-256 fingerprint of the host key, as printed by `ssh-keygen -lf` without the
+    /// `SHA256:` prefix.
+    pub host_key_sha256: Option<Arc<str>>,
+    /// Path to the curl binary.
+    pub command: Option<Arc<str>>,
+}
+
+impl SftpStorageConfig {
+    fn dir(&self) -> Result<&str> {
+        match self.dir.starts_with('/') {
+            true => Ok(self.dir.trim_end_matches('/')),
+            false => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("sftp dir {:?} is not an absolute path", self.dir),
+            ))?,
+        }
+    }
+
+    /// Path of `file_name` on the server, quoted for curl quote commands.
+    fn quoted_path(&self, file_name: &str) -> Result<String> {
+        Ok(format!("\"{}/{file_name}\"", self.dir()?))
+    }
+
+    /// URL of `file_name` in the directory, or the directory itself when `None`.
+    fn url(&self, file_name: Option<&str>) -> Result<String> {
+        Ok(format!(
+            "sftp://{}:{}{}/{}",
+            self.host,
+            self.port.unwrap_or(DEFAULT_PORT),
+            uri_encode(self.dir()?, true),
+            file_name.map(|n| uri_encode(n, false)).unwrap_or_default()
+        ))
+    }
+
+    /// Curl config read from stdin, holding the login secrets.
+    fn secrets_config(&self) -> String {
+        // Without a password after the colon curl would prompt for one
+        let password = self.password.as_ref().map(|p| p.expose_secret().as_str());
+        let mut config = format!(
+            "user = {}\n",
+            config_quote(&format!("{}:{}", self.user, password.unwrap_or_default()))
+        );
+        if let Some(key_passphrase) = &self.key_passphrase {
+            config.push_str(&format!(
+                "pass = {}\n",
+                config_quote(key_passphrase.expose_secret())
+            ));
+        }
+        config
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(self.command.as_deref().unwrap_or(DEFAULT_COMMAND));
+        command.args(["--silent", "--show-error", "--config", "-"]);
+        if let Some(private_key_file) = &self.private_key_file {
+            command.arg("--key").arg(private_key_file.as_ref());
+        }
+        if let Some(host_key_sha256) = &self.host_key_sha256 {
+            command.args(["--hostpubsha256", host_key_sha256]);
+        }
+        command
+    }
+
+    /// Run `command` with the secrets on stdin, streaming its stdout into `writer`.
+    fn run(&self, mut command: Command, writer: &mut dyn Write, what: &str) -> Result<()> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let write_res = child
+            .stdin
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdin is not piped"))
+            .and_then(|mut stdin| stdin.write_all(self.secrets_config().as_bytes()));
+        let copy_res = child
+            .stdout
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdout is not piped"))
+            .and_then(|mut stdout| copy(&mut stdout, writer));
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "SFTP {what} on {:?} failed with {status}",
+                self.host
+            ))
+            .into());
+        }
+        write_res?;
+        copy_res?;
+        Ok(())
+    }
+}
+
+impl StorageBackend for SftpStorageConfig {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        let file_name = archive_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| std::io::Error::other("archive path has no valid file name"))?;
+        // Never leave a truncated copy under the final name, the rename of SFTP fails when the
+        // target exists
+        let tmp_name = format!("{file_name}.tmp");
+        let mut command = self.command();
+        command
+            .arg("--ftp-create-dirs")
+            .arg("--upload-file")
+            .arg(archive_path)
+            .args(["--quote", &format!("*rm {}", self.quoted_path(file_name)?)])
+            .args([
+                "--quote",
+                &format!(
+                    "-rename {} {}",
+                    self.quoted_path(&tmp_name)?,
+                    self.quoted_path(file_name)?
+                ),
+            ])
+            .arg(self.url(Some(&tmp_name))?);
+        self.run(
+            command,
+            &mut std::io::sink(),
+            &format!("upload of {file_name:?}"),
+        )
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        let mut command = self.command();
+        command.arg(self.url(Some(file_name))?);
+        self.run(command, writer, &format!("download of {file_name:?}"))
+    }
+
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        let mut command = self.command();
+        command.arg("--list-only").arg(self.url(None)?);
+        let mut listing = Vec::new();
+        self.run(command, &mut listing, "listing")?;
+        Ok(String::from_utf8_lossy(&listing)
+            .lines()
+            .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+            .map(Arc::from)
+            .collect())
+    }
+
+    /// The listing has names only, the copy is confirmed present.
+    fn confirm_upload(&self, file_name: &str, _size: u64, _sha256: &str) -> Result<()> {
+        check_listed(file_name, &self.list()?)
+    }
+
+    /// Removals ignore failures so files already gone pass, the listing taken afterwards in the
+    /// same session tells whether everything is gone.
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        if file_names.is_empty() {
+            return Ok(());
+        }
+        let mut command = self.command();
+        for file_name in file_names {
+            command.args(["--quote", &format!("*rm {}", self.quoted_path(file_name)?)]);
+        }
+        command.arg("--list-only").arg(self.url(None)?);
+        let mut listing = Vec::new();
+        self.run(command, &mut listing, "deletion")?;
+        let listing = String::from_utf8_lossy(&listing);
+        let left = file_names
+            .iter()
+            .filter(|name| listing.lines().any(|line| line == name.as_ref()))
+            .collect::<Vec<_>>();
+        if !left.is_empty() {
+            return Err(std::io::Error::other(format!(
+                "SFTP deletion on {:?} left {left:?}",
+                self.host
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
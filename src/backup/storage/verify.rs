@@ -0,0 +1,135 @@
+use crate::backup::checksum::{ArchiveChecksums, HashingWriter};
+use crate::backup::compress::Decompressor;
+use crate::backup::counting_writer::CountingWriter;
+use crate::backup::encrypt::{DecryptorBuilder, EncryptorConfig};
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use crate::backup::storage::StorageBackend;
+use std::io::{BufReader, ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Default, Debug)]
+pub struct RemoteVerifyOptions {
+    /// Verify only this many archives per destination, the newest and a random pick of the
+    /// others. Every archive is verified otherwise.
+    pub sample: Option<usize>,
+    /// Download speed limit in bytes per second.
+    pub bandwidth_limit: Option<u64>,
+    /// Only verify the destination at this index of `storage`.
+    pub destination: Option<usize>,
+}
+
+/// Outcome of verifying one remote copy, `Ok` holds the downloaded size.
+#[derive(Debug)]
+pub struct RemoteVerification {
+    pub destination: usize,
+    pub archive_path: PathBuf,
+    pub file_name: String,
+    pub result: Result<u64>,
+}
+
+/// Writer sleeping whenever it gets ahead of `bytes_per_sec`.
+pub struct ThrottledWriter<W: Write> {
+    inner: W,
+    bytes_per_sec: Option<u64>,
+    started_at: Instant,
+    written: u64,
+}
+
+impl<W: Write> ThrottledWriter<W> {
+    pub fn new(inner: W, bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            inner,
+            bytes_per_sec: bytes_per_sec.filter(|b| *b > 0),
+            started_at: Instant::now(),
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Some(bytes_per_sec) = self.bytes_per_sec else {
+            return self.inner.write(buf);
+        };
+        // Small writes keep the rate even, instead of bursts followed by long sleeps
+        let max_len = (bytes_per_sec / 10).max(1) as usize;
+        let written = self.inner.write(&buf[..buf.len().min(max_len)])?;
+        self.written += written as u64;
+        let due = Duration::from_secs_f64(self.written as f64 / bytes_per_sec as f64);
+        if let Some(wait) = due.checked_sub(self.started_at.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Download `file_name` from `backend` and check it decrypts and decompresses, comparing the
+/// tar stream to `checksums` when known. The archive checksum is only compared when
+/// `same_archive`, i.e. the remote copy is the local archive rather than encrypted for the
+/// destination. Nothing is written to disk.
+pub fn verify_download<B: StorageBackend + Sync + ?Sized>(
+    backend: &B,
+    file_name: &str,
+    encryptor: &EncryptorConfig,
+    compression: &str,
+    checksums: Option<&ArchiveChecksums>,
+    same_archive: bool,
+    bandwidth_limit: Option<u64>,
+) -> Result<u64> {
+    let (reader, writer) = std::io::pipe()?;
+    let (download_res, verify_res) = std::thread::scope(|scope| {
+        let download_handle = scope.spawn(move || -> Result<_> {
+            let mut writer = HashingWriter::new(
+                CountingWriter::new(ThrottledWriter::new(writer, bandwidth_limit)),
+                same_archive && checksums.is_some(),
+            );
+            backend.download(file_name, &mut writer)?;
+            writer.flush()?;
+            let (writer, digest) = writer.into_parts();
+            Ok((writer.count(), digest))
+        });
+        let verify_res = encryptor
+            .build_decryptor(BufReader::new(reader))
+            .and_then(|decryptor| Decompressor::from_format(compression, decryptor))
+            .and_then(|mut plaintext| match checksums {
+                Some(checksums) => checksums.verify_plaintext(plaintext),
+                None => Ok(std::io::copy(&mut plaintext, &mut std::io::sink()).map(|_| ())?),
+            });
+        (download_handle.join().unwrap(), verify_res)
+    });
+
+    let (size, digest) = match (download_res, verify_res) {
+        (Ok(downloaded), Ok(())) => downloaded,
+        (Ok(_), Err(e)) => return Err(e.with_msg("Remote archive is not restorable")),
+        (Err(e), Ok(())) => return Err(e.with_msg("Download failed")),
+        // Either one failing breaks the pipe for the other, so keep both
+        (Err(e1), Err(e2)) => {
+            return Err(e2
+                .with_msg("Remote archive is not restorable")
+                .chain(e1.with_msg("Download failed")))
+        }
+    };
+    if let (Some(checksums), Some(digest)) = (checksums, digest) {
+        if size != checksums.archive_size {
+            return Err(invalid_data(format!(
+                "remote archive is {size} bytes, expected {}",
+                checksums.archive_size
+            )));
+        }
+        if digest != checksums.archive_sha256 {
+            return Err(invalid_data("remote archive checksum mismatch".to_string()));
+        }
+    }
+    Ok(size)
+}
+
+fn invalid_data(msg: String) -> Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg).into()
+}
@@ -0,0 +1,228 @@
+use crate::backup::backup_config::BackupConfig;
+use crate::backup::result_error::result::Result;
+use itertools::Itertools;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+static HARDENING: [&str; 6] = [
+    "ProtectSystem=strict",
+    "ProtectHome=read-only",
+    "PrivateTmp=true",
+    "NoNewPrivileges=true",
+    "ProtectKernelTunables=true",
+    "ProtectControlGroups=true",
+];
+
+/// Service manager instance units are installed for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SystemdScope {
+    User,
+    System,
+}
+
+impl SystemdScope {
+    /// Directory units of the scope are installed in, `~/.config/systemd/user` or
+    /// `/etc/systemd/system`.
+    pub fn unit_dir(&self) -> Result<PathBuf> {
+        match self {
+            SystemdScope::System => Ok(PathBuf::from("/etc/systemd/system")),
+            SystemdScope::User => {
+                let config_home = std::env::var_os("XDG_CONFIG_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| {
+                        std::env::var_os("HOME").map(|home| Path::new(&home).join(".config"))
+                    })
+                    .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "HOME is not set"))?;
+                Ok(config_home.join("systemd").join("user"))
+            }
+        }
+    }
+
+    fn systemctl(&self) -> &'static str {
+        match self {
+            SystemdScope::User => "systemctl --user",
+            SystemdScope::System => "systemctl",
+        }
+    }
+
+    fn wanted_by(&self) -> &'static str {
+        match self {
+            SystemdScope::User => "default.target",
+            SystemdScope::System => "multi-user.target",
+        }
+    }
+}
+
+/// How the installed units run backups.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SystemdRunMode {
+    /// The daemon runs the schedule of the config, `extra_args` are passed to it, e.g.
+    /// `--watch-config`.
+    Daemon { extra_args: Vec<String> },
+    /// A timer starts a single `run --scheduled` at systemd calendar events, e.g. `daily`, which
+    /// applies retention as the daemon does. The cron of the config is not used.
+    Timer { on_calendar: String },
+}
+
+/// Service unit, and timer unit when run by a timer, generated for a config.
+#[derive(Clone, Debug)]
+pub struct SystemdUnits {
+    /// Unit name without suffix, e.g. `k-backup-home`.
+    pub name: String,
+    pub service: String,
+    pub timer: Option<String>,
+}
+
+impl SystemdUnits {
+    /// Units running `executable` on the config at `config_path`, loaded as `config`. The
+    /// service may only write to the directories the config writes to.
+    pub fn new(
+        executable: &Path,
+        config_path: &Path,
+        config: &BackupConfig,
+        scope: SystemdScope,
+        mode: &SystemdRunMode,
+    ) -> Self {
+        let stem = config_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        let name = format!(
+            "k-backup-{}",
+            stem.replace(
+                |c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_',
+                "-"
+            )
+        );
+        let exec_start = format!("{} --config {}", quote(executable), quote(config_path));
+
+        let mut service = vec![
+            "[Unit]".to_string(),
+            format!("Description=k_backup backups of {config_path:?}"),
+            "Wants=network-online.target".into(),
+            "After=network-online.target".into(),
+            "".into(),
+            "[Service]".into(),
+        ];
+        match mode {
+            SystemdRunMode::Daemon { extra_args } => service.extend([
+                "Type=exec".into(),
+                format!("ExecStart={exec_start} {}", extra_args.join(" "))
+                    .trim_end()
+                    .into(),
+                "ExecReload=/bin/kill -HUP $MAINPID".into(),
+                "Restart=on-failure".into(),
+                "RestartSec=30s".into(),
+            ]),
+            SystemdRunMode::Timer { .. } => service.extend([
+                "Type=oneshot".into(),
+                format!("ExecStart={exec_start} run --scheduled"),
+            ]),
+        }
+        // Read-only system, sources are read and only the dirs of the config written
+        service.extend(HARDENING.iter().map(|line| line.to_string()));
+        service.extend(
+            writable_dirs(config)
+                .iter()
+                .map(|dir| format!("ReadWritePaths={}", quote(dir))),
+        );
+        if let SystemdRunMode::Daemon { .. } = mode {
+            service.extend([
+                "".into(),
+                "[Install]".into(),
+                format!("WantedBy={}", scope.wanted_by()),
+            ]);
+        }
+
+        let timer = match mode {
+            SystemdRunMode::Daemon { .. } => None,
+            SystemdRunMode::Timer { on_calendar } => Some(vec![
+                "[Unit]".to_string(),
+                format!("Description=Timer of k_backup backups of {config_path:?}"),
+                "".into(),
+                "[Timer]".into(),
+                format!("OnCalendar={on_calendar}"),
+                "Persistent=true".into(),
+                format!("Unit={name}.service"),
+                "".into(),
+                "[Install]".into(),
+                "WantedBy=timers.target".into(),
+            ]),
+        };
+        let to_unit = |lines: Vec<String>| lines.into_iter().map(|line| line + "\n").collect();
+        Self {
+            name,
+            service: to_unit(service),
+            timer: timer.map(to_unit),
+        }
+    }
+
+    /// Write the units to the unit dir of `scope`, returning the files written. Existing units
+    /// of the same name are replaced.
+    pub fn install(&self, scope: SystemdScope) -> Result<Vec<PathBuf>> {
+        let unit_dir = scope.unit_dir()?;
+        std::fs::create_dir_all(&unit_dir)?;
+        let mut written = Vec::new();
+        let units = [
+            ("service", Some(&self.service)),
+            ("timer", self.timer.as_ref()),
+        ];
+        for (suffix, content) in units {
+            if let Some(content) = content {
+                let path = unit_dir.join(format!("{}.{suffix}", self.name));
+                std::fs::write(&path, content)?;
+                written.push(path);
+            }
+        }
+        Ok(written)
+    }
+
+    /// Command enabling and starting the installed units.
+    pub fn enable_command(&self, scope: SystemdScope) -> String {
+        let unit = match self.timer {
+            Some(_) => format!("{}.timer", self.name),
+            None => format!("{}.service", self.name),
+        };
+        format!(
+            "{0} daemon-reload && {0} enable --now {unit}",
+            scope.systemctl()
+        )
+    }
+}
+
+/// Directories the jobs of `config` write to, outermost only.
+fn writable_dirs(config: &BackupConfig) -> Vec<PathBuf> {
+    let dirs = config
+        .archive_jobs()
+        .iter()
+        .flat_map(|job| {
+            [job.out_dir.to_path_buf(), job.state_dir_path()]
+                .into_iter()
+                .chain(
+                    job.restore_drill
+                        .as_ref()
+                        .and_then(|drill| drill.scratch_dir.as_ref())
+                        .map(|dir| dir.to_path_buf()),
+                )
+        })
+        .sorted()
+        .dedup()
+        .collect_vec();
+    dirs.iter()
+        .filter(|dir| {
+            !dirs
+                .iter()
+                .any(|other| other != *dir && dir.starts_with(other))
+        })
+        .cloned()
+        .collect()
+}
+
+/// `path` double quoted for unit files when it contains spaces.
+fn quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    match path.contains(char::is_whitespace) {
+        true => format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => path.into_owned(),
+    }
+}
@@ -0,0 +1,156 @@
+pub use crate::backup::clock::FakeClock;
+
+use crate::backup::clock::Clock;
+use crate::backup::encrypt::EncryptorConfig;
+use crate::backup::pipeline::PipelineDescriptor;
+use crate::backup::restore::{
+    detect_pipeline, open_archive, open_archive_reader, ConfigSecretSource, PromptSecretSource,
+};
+use crate::backup::result_error::result::Result;
+use crate::backup::retention::{ItemWithDateTime, RetentionConfig};
+use crate::backup::roundtrip::{read_entries, RoundtripEntry};
+use crate::backup::storage::{check_stored_size, StorageBackend};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::io::{Cursor, ErrorKind, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+type MemoryFiles = BTreeMap<Arc<str>, Arc<[u8]>>;
+
+/// Storage keeping uploaded files in memory, clones share the same files. Used as a storage
+/// destination with `StorageConfig::Memory`, which cannot be configured from YAML.
+#[derive(Clone, Default, Debug)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<MemoryFiles>>,
+}
+
+impl MemoryStorage {
+    /// Names of the stored files, sorted.
+    pub fn file_names(&self) -> Vec<Arc<str>> {
+        self.files().keys().cloned().collect()
+    }
+
+    pub fn get(&self, file_name: &str) -> Option<Arc<[u8]>> {
+        self.files().get(file_name).cloned()
+    }
+
+    /// Store `data` as `file_name`, e.g. a copy left by a previous run.
+    pub fn insert(&self, file_name: &str, data: Vec<u8>) {
+        self.files().insert(file_name.into(), data.into());
+    }
+
+    /// Entries of the stored archive `file_name`, its pipeline taken from the file name and its
+    /// secrets from `encryptor`.
+    pub fn archive_entries(
+        &self,
+        file_name: &str,
+        encryptor: &EncryptorConfig,
+    ) -> Result<Vec<RoundtripEntry>> {
+        let data = self.get(file_name).ok_or_else(|| {
+            std::io::Error::new(ErrorKind::NotFound, format!("{file_name:?} is not stored"))
+        })?;
+        let pipeline = PipelineDescriptor::from_file_name(file_name).ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot infer archive pipeline from {file_name:?}"),
+            )
+        })?;
+        read_entries(open_archive_reader(
+            Box::new(Cursor::new(data)),
+            &pipeline,
+            &config_secrets(encryptor),
+        )?)
+    }
+
+    fn files(&self) -> MutexGuard<'_, MemoryFiles> {
+        self.files.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn upload(&self, archive_path: &Path) -> Result<()> {
+        let file_name = archive_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| std::io::Error::other("archive path has no valid file name"))?;
+        self.insert(file_name, std::fs::read(archive_path)?);
+        Ok(())
+    }
+
+    fn download(&self, file_name: &str, writer: &mut dyn Write) -> Result<()> {
+        let data = self.get(file_name).ok_or_else(|| {
+            std::io::Error::new(ErrorKind::NotFound, format!("{file_name:?} is not stored"))
+        })?;
+        writer.write_all(&data)?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<Arc<str>>> {
+        Ok(self.file_names())
+    }
+
+    fn confirm_upload(&self, file_name: &str, size: u64, _sha256: &str) -> Result<()> {
+        check_stored_size(file_name, size, self.get(file_name).map(|d| d.len() as u64))
+    }
+
+    fn delete(&self, file_names: &[Arc<str>]) -> Result<()> {
+        let mut files = self.files();
+        for file_name in file_names {
+            files.remove(file_name);
+        }
+        Ok(())
+    }
+}
+
+/// Entries of the archive file at `archive_path`, its pipeline detected as restore does and its
+/// secrets taken from `encryptor`.
+pub fn archive_entries<P: AsRef<Path>>(
+    archive_path: P,
+    encryptor: &EncryptorConfig,
+) -> Result<Vec<RoundtripEntry>> {
+    let pipeline = detect_pipeline(&archive_path)?;
+    read_entries(open_archive(
+        archive_path,
+        &pipeline,
+        &config_secrets(encryptor),
+    )?)
+}
+
+/// Creation times of the backups left when `cron` runs from the time of `clock` to `until`,
+/// `retention` applied before every run as the daemon does. Nothing is written and `clock` is
+/// only advanced, so years of schedule take milliseconds.
+pub fn simulate_retention(
+    cron: &str,
+    retention: &RetentionConfig,
+    clock: &FakeClock,
+    until: DateTime<Utc>,
+) -> Result<Vec<DateTime<Utc>>> {
+    let mut backups: Vec<Rc<ItemWithDateTime<(), Utc>>> = Vec::new();
+    loop {
+        let next = cron_parser::parse(cron, &clock.now()).map_err(|e| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid cron {cron:?}: {e}"),
+            )
+        })?;
+        if next > until {
+            break;
+        }
+        clock.sleep_until(next);
+        let deleted = retention
+            .get_delete(backups.clone(), next, |_: &()| true)
+            .collect::<Vec<_>>();
+        backups.retain(|b| !deleted.iter().any(|d| Rc::ptr_eq(b, d)));
+        backups.push(Rc::new(ItemWithDateTime::from(next)));
+    }
+    Ok(backups.iter().map(|b| *b.date_time).collect())
+}
+
+fn config_secrets(encryptor: &EncryptorConfig) -> ConfigSecretSource {
+    ConfigSecretSource {
+        encryptor: Arc::new(encryptor.clone()),
+        fallback: PromptSecretSource::default(),
+    }
+}
@@ -0,0 +1,112 @@
+use crate::backup::humanize::HumanDuration;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Time spent in a phase of a backup cycle, shared by the threads running it. Time spent in a
+/// nested phase is taken out with [`Self::exclude`], so each phase only counts its own work.
+#[derive(Clone, Default, Debug)]
+pub struct PhaseTimer {
+    // Wrapping, a nested phase may be taken out before the time around it is added
+    nanos: Arc<AtomicU64>,
+}
+
+impl PhaseTimer {
+    pub fn add(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    /// Run `f`, counting the time it takes.
+    pub fn time<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let started_at = Instant::now();
+        let res = f();
+        self.add(started_at.elapsed());
+        res
+    }
+
+    /// Run `f` within the phase, without counting the time it takes, e.g. waiting for entries or
+    /// work of another phase.
+    pub fn exclude<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let started_at = Instant::now();
+        let res = f();
+        self.nanos
+            .fetch_sub(started_at.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        res
+    }
+}
+
+/// Time spent in each phase of a backup cycle, logged as a single line once it ends. Entries
+/// are collected while the archive is written, so `collect` overlaps `tar` and the phases under
+/// it.
+#[derive(Clone, Default, Debug)]
+pub struct PhaseTimings {
+    /// Reading sources into entries.
+    pub collect: PhaseTimer,
+    /// Writing entries into the tar stream, not counting the wait for entries.
+    pub tar: PhaseTimer,
+    pub compress_encrypt: PhaseTimer,
+    /// Writing archive files, then renaming them and writing their sidecars.
+    pub persist: PhaseTimer,
+    pub upload: PhaseTimer,
+    pub retention: PhaseTimer,
+}
+
+impl Display for PhaseTimings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "collect={} tar={} compress_encrypt={} persist={} upload={} retention={}",
+            HumanDuration(self.collect.elapsed()),
+            HumanDuration(self.tar.elapsed()),
+            HumanDuration(self.compress_encrypt.elapsed()),
+            HumanDuration(self.persist.elapsed()),
+            HumanDuration(self.upload.elapsed()),
+            HumanDuration(self.retention.elapsed()),
+        )
+    }
+}
+
+/// Writer counting the time spent writing to `inner` to `timer`, taken out of `within`, the
+/// phase of the writers above.
+pub struct TimedWriter<W: Write> {
+    inner: W,
+    timer: PhaseTimer,
+    within: PhaseTimer,
+}
+
+impl<W: Write> TimedWriter<W> {
+    pub fn new(inner: W, timer: PhaseTimer, within: PhaseTimer) -> Self {
+        Self {
+            inner,
+            timer,
+            within,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for TimedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.within
+            .exclude(|| self.timer.time(|| self.inner.write(buf)))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.within
+            .exclude(|| self.timer.time(|| self.inner.flush()))
+    }
+}
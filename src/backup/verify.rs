@@ -0,0 +1,136 @@
+use crate::backup::checksum::{sha256_archive, ArchiveChecksums};
+use crate::backup::pipeline::PipelineDescriptor;
+use crate::backup::restore::{open_archive, SecretSource};
+use crate::backup::result_error::error::Error;
+use crate::backup::result_error::result::Result;
+use crate::backup::result_error::WithMsg;
+use crate::backup::volume::archive_file_size;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{copy, sink, ErrorKind, Read};
+use std::path::Path;
+
+/// Size of the two zero blocks ending a tar stream.
+static END_OF_ARCHIVE_LEN: u64 = 1024;
+static BLOCK_LEN: u64 = 512;
+
+/// What was checked while reading an archive end to end.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ArchiveVerification {
+    pub archive_size: u64,
+    pub entries: u64,
+    /// Bytes of entry data read, headers and padding excluded.
+    pub data_size: u64,
+    /// Size of the tar stream after decryption and decompression.
+    pub plaintext_size: u64,
+    /// Whether the archive and tar stream matched stored checksums.
+    pub checksums_verified: bool,
+}
+
+/// Archive file digest to check against, besides the decoded stream itself.
+#[derive(Clone, Default, Debug)]
+pub struct VerifyOptions {
+    /// Checksums stored next to the archive by `checksums`, also checking the tar stream.
+    pub checksums: Option<ArchiveChecksums>,
+    /// Hex SHA-256 of the archive file from elsewhere, e.g. a storage listing.
+    pub archive_sha256: Option<String>,
+}
+
+/// Tar stream reader keeping the size and digest of everything read through it.
+struct PlaintextReader<R: Read> {
+    inner: R,
+    size: u64,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for PlaintextReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.size += read as u64;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Decrypt and decompress `archive_path` in one streaming pass, reading every tar entry to find
+/// corruption or truncation, then compare the stored checksums of `options`.
+pub fn verify_archive<P: AsRef<Path>, S: SecretSource>(
+    archive_path: P,
+    pipeline: &PipelineDescriptor,
+    secrets: &S,
+    options: &VerifyOptions,
+) -> Result<ArchiveVerification> {
+    let archive_path = archive_path.as_ref();
+    let archive_size = archive_file_size(archive_path)?;
+    if let Some(checksums) = &options.checksums {
+        checksums
+            .verify_archive(archive_path)
+            .with_msg("Archive does not match its checksums")?;
+    }
+    if let Some(expected) = &options.archive_sha256 {
+        if !sha256_archive(archive_path)?.eq_ignore_ascii_case(expected) {
+            return Err(invalid_data(format!(
+                "{archive_path:?} checksum mismatch, expected {expected}"
+            )));
+        }
+    }
+
+    let reader = open_archive(archive_path, pipeline, secrets)
+        .with_msg("Open archive failed")?
+        .into_inner();
+    let mut tar = tar::Archive::new(PlaintextReader {
+        inner: reader,
+        size: 0,
+        hasher: Sha256::new(),
+    });
+    let mut entries = 0;
+    let mut data_size = 0;
+    let mut entries_end = 0;
+    for entry in tar
+        .entries()
+        .map_err(Error::from)
+        .with_msg("Read tar stream failed")?
+    {
+        let mut entry = entry
+            .map_err(Error::from)
+            .with_msg(format!("Read entry {} failed", entries + 1))?;
+        let path = entry.path()?.into_owned();
+        let size = entry.size();
+        let read = copy(&mut entry, &mut sink())
+            .map_err(Error::from)
+            .with_msg(format!("Read {path:?} failed"))?;
+        if read != size {
+            return Err(invalid_data(format!(
+                "{path:?} has {read} of {size} bytes, the archive is truncated"
+            )));
+        }
+        entries += 1;
+        data_size += size;
+        entries_end = entry.raw_file_position() + size.div_ceil(BLOCK_LEN) * BLOCK_LEN;
+    }
+    // Decoders only check trailing integrity data once read to the end
+    let mut plaintext = tar.into_inner();
+    copy(&mut plaintext, &mut sink())
+        .map_err(Error::from)
+        .with_msg("Read end of archive failed")?;
+    if plaintext.size < entries_end + END_OF_ARCHIVE_LEN {
+        return Err(invalid_data(
+            "tar stream ends without end-of-archive marker, the archive is truncated".to_string(),
+        ));
+    }
+    let plaintext_sha256 = format!("{:x}", plaintext.hasher.finalize());
+    if let Some(checksums) = &options.checksums {
+        checksums.check_plaintext(plaintext.size, &plaintext_sha256)?;
+    }
+    Ok(ArchiveVerification {
+        archive_size,
+        entries,
+        data_size,
+        plaintext_size: plaintext.size,
+        checksums_verified: options.checksums.is_some(),
+    })
+}
+
+fn invalid_data(msg: String) -> Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg).into()
+}
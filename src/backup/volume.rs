@@ -0,0 +1,408 @@
+use crate::backup::result_error::result::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+static VOLUMES_FILE_SUFFIX: &str = ".volumes.json";
+
+/// Path of the volume at `idx` of the archive at `archive_path`, e.g. `archive.tar.xz.age.000`.
+pub fn volume_path<P: AsRef<Path>>(archive_path: P, idx: usize) -> PathBuf {
+    let mut path = archive_path.as_ref().as_os_str().to_os_string();
+    path.push(format!(".{idx:03}"));
+    path.into()
+}
+
+/// Existing volumes of the archive at `archive_path`, in order, none for a whole archive.
+pub fn volume_paths<P: AsRef<Path>>(archive_path: P) -> Vec<PathBuf> {
+    (0..)
+        .map(|idx| volume_path(&archive_path, idx))
+        .take_while(|path| path.exists())
+        .collect()
+}
+
+/// Archive path of `path` when it is the first volume of an archive.
+pub fn first_volume_archive_path<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    let file_name = path.as_ref().file_name()?.to_str()?;
+    file_name
+        .strip_suffix(".000")
+        .map(|archive| path.as_ref().with_file_name(archive))
+}
+
+/// Whether the archive at `archive_path` exists, whole or split.
+pub fn archive_exists<P: AsRef<Path>>(archive_path: P) -> bool {
+    archive_path.as_ref().exists() || volume_path(archive_path, 0).exists()
+}
+
+/// Size of the archive at `archive_path`, whole or split.
+pub fn archive_file_size<P: AsRef<Path>>(archive_path: P) -> Result<u64> {
+    let archive_path = archive_path.as_ref();
+    let volumes = volume_paths(archive_path);
+    if volumes.is_empty() {
+        return Ok(std::fs::metadata(archive_path)?.len());
+    }
+    volumes
+        .iter()
+        .map(|path| Ok(std::fs::metadata(path)?.len()))
+        .sum()
+}
+
+/// Reader of the archive at `archive_path`, the volumes read one after the other when it is
+/// split. Fails when volumes listed in its [`VolumeManifest`] are missing, or once a volume is
+/// read whose size or SHA-256 differ from the manifest.
+pub fn open_archive_file<P: AsRef<Path>>(archive_path: P) -> Result<Box<dyn Read + Send>> {
+    let archive_path = archive_path.as_ref();
+    let volumes = volume_paths(archive_path);
+    if volumes.is_empty() {
+        return Ok(Box::new(File::open(archive_path)?));
+    }
+    let expected = match VolumeManifest::read(VolumeManifest::volumes_path(archive_path)) {
+        Ok(manifest) if manifest.volumes.len() != volumes.len() => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{archive_path:?} has {} of its {} volumes",
+                volumes.len(),
+                manifest.volumes.len()
+            ),
+        ))?,
+        Ok(manifest) => manifest.volumes.into_iter().map(Some).collect(),
+        Err(_) => vec![None; volumes.len()],
+    };
+    Ok(Box::new(VolumeReader {
+        volumes: volumes.into_iter().zip(expected).collect(),
+        current: None,
+    }))
+}
+
+/// Rename the archive at `from`, whole or split, to `to`, along with its [`VolumeManifest`].
+pub fn rename_archive<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
+    let volumes = volume_paths(&from);
+    if volumes.is_empty() {
+        return Ok(std::fs::rename(from, to)?);
+    }
+    for (idx, path) in volumes.iter().enumerate() {
+        std::fs::rename(path, volume_path(&to, idx))?;
+    }
+    let volumes_path = VolumeManifest::volumes_path(&from);
+    if volumes_path.exists() {
+        std::fs::rename(volumes_path, VolumeManifest::volumes_path(&to))?;
+    }
+    Ok(())
+}
+
+/// Remove the archive at `archive_path`, whole or split.
+pub fn remove_archive<P: AsRef<Path>>(archive_path: P) -> Result<()> {
+    let volumes = volume_paths(&archive_path);
+    if volumes.is_empty() {
+        return Ok(std::fs::remove_file(archive_path)?);
+    }
+    for path in volumes.iter() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+struct VolumeReader {
+    volumes: VecDeque<(PathBuf, Option<VolumeInfo>)>,
+    current: Option<OpenVolume>,
+}
+
+/// Volume being read, hashed when the manifest lists it.
+struct OpenVolume {
+    file: File,
+    path: PathBuf,
+    expected: Option<(VolumeInfo, Sha256)>,
+    size: u64,
+}
+
+impl OpenVolume {
+    /// Check the fully read volume against the manifest.
+    fn check(self) -> std::io::Result<()> {
+        let Some((expected, hasher)) = self.expected else {
+            return Ok(());
+        };
+        if self.size != expected.size || format!("{:x}", hasher.finalize()) != expected.sha256 {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "volume {:?} does not match the size and SHA-256 of its manifest",
+                    self.path
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let volume = match self.current.as_mut() {
+                Some(volume) => volume,
+                None => match self.volumes.pop_front() {
+                    Some((path, expected)) => self.current.insert(OpenVolume {
+                        file: File::open(&path)?,
+                        path,
+                        expected: expected.map(|info| (info, Sha256::new())),
+                        size: 0,
+                    }),
+                    None => return Ok(0),
+                },
+            };
+            match volume.file.read(buf)? {
+                0 if !buf.is_empty() => {
+                    if let Some(volume) = self.current.take() {
+                        volume.check()?;
+                    }
+                }
+                read => {
+                    if let Some((_, hasher)) = volume.expected.as_mut() {
+                        hasher.update(&buf[..read]);
+                    }
+                    volume.size += read as u64;
+                    return Ok(read);
+                }
+            }
+        }
+    }
+}
+
+/// Writer of an archive file, split into volumes of at most `split_size` bytes when given.
+pub struct VolumeWriter {
+    archive_path: PathBuf,
+    split_size: Option<u64>,
+    current: Option<(BufWriter<File>, Sha256)>,
+    current_size: u64,
+    volumes: Vec<VolumeInfo>,
+}
+
+impl VolumeWriter {
+    /// Create the archive at `archive_path`, or its first volume when split.
+    pub fn create<P: AsRef<Path>>(archive_path: P, split_size: Option<u64>) -> Result<Self> {
+        let mut writer = Self {
+            archive_path: archive_path.as_ref().to_path_buf(),
+            split_size,
+            current: None,
+            current_size: 0,
+            volumes: Vec::new(),
+        };
+        writer.next_volume()?;
+        Ok(writer)
+    }
+
+    /// Bytes written to all volumes.
+    pub fn size(&self) -> u64 {
+        self.volumes.iter().map(|v| v.size).sum::<u64>() + self.current_size
+    }
+
+    /// Flush the last volume, returning the volumes when split.
+    pub fn finish(mut self) -> std::io::Result<Option<VolumeManifest>> {
+        self.close_volume()?;
+        Ok(self.split_size.map(|split_size| VolumeManifest {
+            split_size,
+            volumes: self.volumes,
+        }))
+    }
+
+    fn next_volume(&mut self) -> std::io::Result<()> {
+        self.close_volume()?;
+        let path = match self.split_size {
+            Some(_) => volume_path(&self.archive_path, self.volumes.len()),
+            None => self.archive_path.clone(),
+        };
+        self.current = Some((BufWriter::new(File::create_new(path)?), Sha256::new()));
+        self.current_size = 0;
+        Ok(())
+    }
+
+    fn close_volume(&mut self) -> std::io::Result<()> {
+        if let Some((mut file, hasher)) = self.current.take() {
+            file.flush()?;
+            self.volumes.push(VolumeInfo {
+                size: self.current_size,
+                sha256: format!("{:x}", hasher.finalize()),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut len = buf.len();
+        if let Some(split_size) = self.split_size {
+            if self.current_size >= split_size {
+                self.next_volume()?;
+            }
+            len = len.min((split_size - self.current_size) as usize);
+        }
+        let (file, hasher) = self
+            .current
+            .as_mut()
+            .ok_or_else(|| std::io::Error::other("archive volume is closed"))?;
+        let written = file.write(&buf[..len])?;
+        if self.split_size.is_some() {
+            hasher.update(&buf[..written]);
+        }
+        self.current_size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.current.as_mut() {
+            Some((file, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Volumes of a split archive, written next to them so missing or damaged volumes are found
+/// before restoring.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VolumeManifest {
+    pub split_size: u64,
+    pub volumes: Vec<VolumeInfo>,
+}
+
+/// Size and hex SHA-256 of a volume, checked as the volume is read.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct VolumeInfo {
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl VolumeManifest {
+    pub fn volumes_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+        let mut path = archive_path.as_ref().as_os_str().to_os_string();
+        path.push(VOLUMES_FILE_SUFFIX);
+        path.into()
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7919 % 251) as u8).collect()
+    }
+
+    /// Write `data` to an archive split at `split_size`, in uneven chunks.
+    fn write(archive_path: &Path, split_size: Option<u64>, data: &[u8]) -> Option<VolumeManifest> {
+        let mut writer = VolumeWriter::create(archive_path, split_size).unwrap();
+        for chunk in data.chunks(3001) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.size(), data.len() as u64);
+        let manifest = writer.finish().unwrap();
+        if let Some(manifest) = &manifest {
+            manifest
+                .write(VolumeManifest::volumes_path(archive_path))
+                .unwrap();
+        }
+        manifest
+    }
+
+    fn read(archive_path: &Path) -> Result<Vec<u8>> {
+        let mut read = Vec::new();
+        open_archive_file(archive_path)?.read_to_end(&mut read)?;
+        Ok(read)
+    }
+
+    #[test]
+    fn whole_archive_has_no_volumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.tar");
+        let data = data(20_000);
+        assert!(write(&archive_path, None, &data).is_none());
+        assert!(volume_paths(&archive_path).is_empty());
+        assert_eq!(archive_file_size(&archive_path).unwrap(), 20_000);
+        assert_eq!(read(&archive_path).unwrap(), data);
+    }
+
+    #[test]
+    fn split_archive_reads_back_across_volumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.tar");
+        let data = data(20_000);
+        let manifest = write(&archive_path, Some(8000), &data).unwrap();
+        let sizes = manifest.volumes.iter().map(|v| v.size).collect::<Vec<_>>();
+        assert_eq!(sizes, [8000, 8000, 4000]);
+        assert_eq!(volume_paths(&archive_path).len(), 3);
+        assert!(!archive_path.exists() && archive_exists(&archive_path));
+        assert_eq!(
+            first_volume_archive_path(volume_path(&archive_path, 0)),
+            Some(archive_path.clone())
+        );
+        assert_eq!(
+            manifest.volumes[2].sha256,
+            format!("{:x}", Sha256::digest(&data[16_000..]))
+        );
+        assert_eq!(archive_file_size(&archive_path).unwrap(), 20_000);
+        assert_eq!(read(&archive_path).unwrap(), data);
+    }
+
+    #[test]
+    fn exact_multiple_of_the_split_size_leaves_no_empty_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.tar");
+        let manifest = write(&archive_path, Some(5000), &data(15_000)).unwrap();
+        assert_eq!(manifest.volumes.len(), 3);
+        assert_eq!(volume_paths(&archive_path).len(), 3);
+    }
+
+    #[test]
+    fn damaged_or_missing_volumes_fail_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.tar");
+        write(&archive_path, Some(8000), &data(20_000));
+
+        let second = volume_path(&archive_path, 1);
+        let mut damaged = std::fs::read(&second).unwrap();
+        damaged[100] ^= 1;
+        std::fs::write(&second, &damaged).unwrap();
+        let err = read(&archive_path).unwrap_err();
+        assert!(format!("{err:?}").contains("does not match"), "{err:?}");
+
+        std::fs::remove_file(volume_path(&archive_path, 2)).unwrap();
+        let err = read(&archive_path).unwrap_err();
+        assert!(
+            format!("{err:?}").contains("has 2 of its 3 volumes"),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn rename_and_remove_move_every_volume_and_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("archive.tar");
+        let renamed = dir.path().join("renamed.tar");
+        let data = data(20_000);
+        write(&archive_path, Some(8000), &data);
+
+        rename_archive(&archive_path, &renamed).unwrap();
+        assert!(!archive_exists(&archive_path));
+        assert!(!VolumeManifest::volumes_path(&archive_path).exists());
+        assert!(VolumeManifest::volumes_path(&renamed).exists());
+        assert_eq!(read(&renamed).unwrap(), data);
+
+        remove_archive(&renamed).unwrap();
+        assert!(!archive_exists(&renamed));
+    }
+}
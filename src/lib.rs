@@ -1 +1,5 @@
+// Ownership, permissions, sockets and signals of the unix APIs are used throughout
+#[cfg(not(unix))]
+compile_error!("k_backup supports unix targets only");
+
 pub mod backup;
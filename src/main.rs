@@ -1,48 +1,887 @@
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use itertools::Itertools;
 use k_backup::backup::backup_config::BackupConfig;
+use k_backup::backup::catalog::CatalogStatus;
+use k_backup::backup::checksum::ArchiveChecksums;
+use k_backup::backup::control::{control_socket_path, send_trigger, TriggerResponse};
+use k_backup::backup::discover::{discover, to_config_snippet};
+#[cfg(feature = "age")]
+use k_backup::backup::encrypt::key_backup::{combine_shares, split_passphrase, PassphraseShare};
+#[cfg(feature = "age")]
+use k_backup::backup::encrypt::EncryptorConfig;
+use k_backup::backup::humanize::{HumanDuration, HumanSize};
+use k_backup::backup::inspect::inspect_archive;
+use k_backup::backup::path_expand::expand_source_paths;
+use k_backup::backup::reload::{listen_for_sighup, ReloadWatch};
+use k_backup::backup::restore::{
+    detect_pipeline, extract, list_entries, open_archive, ConfigSecretSource, OwnerSpec,
+    PromptSecretSource, RestoreOptions,
+};
 use k_backup::backup::result_error::error::Error;
+use k_backup::backup::result_error::result::Result;
 use k_backup::backup::result_error::WithMsg;
-use rayon::ThreadPoolBuilder;
+use k_backup::backup::sanity::{sanity_warnings, unused_secret_warnings};
+use k_backup::backup::storage::verify::RemoteVerifyOptions;
+use k_backup::backup::systemd::{SystemdRunMode, SystemdScope, SystemdUnits};
+use k_backup::backup::verify::{verify_archive, VerifyOptions};
+use k_backup::backup::volume::first_volume_archive_path;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use tracing::error;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use tracing_subscriber::fmt::format::FmtSpan;
 use validator::Validate;
 
 /// Simple(?) program to create backup and delete old backup
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
     /// Location of config file
-    #[arg(short, long)]
-    config: PathBuf,
+    #[arg(short, long, required = true)]
+    config: Option<PathBuf>,
+    /// Let retention delete archives of an out dir that held files not created by k_backup on
+    /// the first run
+    #[arg(long)]
+    accept_existing_files: bool,
+    /// Reload the config when the file is modified, as on SIGHUP
+    #[arg(long)]
+    watch_config: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() {
-    tracing_subscriber::fmt::init();
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a single backup now using the config file, then exit
+    Run {
+        /// Description stored with this backup, overriding the config `description`
+        #[arg(long)]
+        comment: Option<String>,
+        /// List the entries that would be archived and the backups retention would delete,
+        /// without writing or removing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Run as a scheduled backup instead of a manual one: apply `retention` and create an
+        /// untagged archive, for external schedulers such as systemd timers
+        #[arg(long, conflicts_with = "comment")]
+        scheduled: bool,
+    },
+    /// Trigger a backup of the daemon running the config with `schedule: external` and wait for
+    /// its outcome
+    Trigger,
+    /// Print the backups recorded in the catalog, oldest first
+    List {
+        /// Print the catalog entries as JSON, by archive base name
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete the archives of the out dir that are out of retention now, outside the schedule
+    Prune {
+        /// Print every archive with whether retention keeps or deletes it and why, without
+        /// removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Extract an archive into a directory, taking secrets from the config when given
+    Restore {
+        /// Archive file to restore
+        archive: PathBuf,
+        /// Directory the archive is extracted into
+        #[arg(long)]
+        target: PathBuf,
+        /// Age identity file for archives encrypted to recipients, may be repeated
+        #[arg(long = "identity")]
+        identity_files: Vec<PathBuf>,
+        /// Owner of restored entries as `user:group`, `user` or `:group`
+        #[arg(long)]
+        chown: Option<OwnerSpec>,
+        /// Leading path components removed from entry names
+        #[arg(long, default_value_t = 0)]
+        strip_components: usize,
+        /// Octal permission bits cleared from restored entries
+        #[arg(long, value_parser = parse_umask)]
+        umask: Option<u32>,
+    },
+    /// Read an archive end to end and report corruption or truncation, taking secrets from the
+    /// config when given
+    Verify {
+        /// Archive file to verify
+        archive: PathBuf,
+        /// Age identity file for archives encrypted to recipients, may be repeated
+        #[arg(long = "identity")]
+        identity_files: Vec<PathBuf>,
+        /// Checksums file to compare against, the one next to the archive when it exists
+        #[arg(long)]
+        checksums: Option<PathBuf>,
+        /// Expected hex SHA-256 of the archive file
+        #[arg(long)]
+        sha256: Option<String>,
+    },
+    /// Print the layers of an archive, its age header stanzas and xz streams, and count its
+    /// entries when it can be decrypted
+    Inspect {
+        /// Archive file to inspect
+        archive: PathBuf,
+        /// Age identity file for archives encrypted to recipients, may be repeated
+        #[arg(long = "identity")]
+        identity_files: Vec<PathBuf>,
+        /// Decrypt without identities or config, prompting for a passphrase
+        #[arg(long)]
+        decrypt: bool,
+    },
+    /// Print the path, size and modification time of every entry of an archive, decrypting and
+    /// decompressing it as a stream without extracting anything
+    Ls {
+        /// Archive file to list
+        archive: PathBuf,
+        /// Age identity file for archives encrypted to recipients, may be repeated
+        #[arg(long = "identity")]
+        identity_files: Vec<PathBuf>,
+        /// Print one JSON object per entry
+        #[arg(long)]
+        json: bool,
+    },
+    /// Split the archive passphrase into shares of which a threshold recover it, or recover it
+    /// from shares
+    #[cfg(feature = "age")]
+    KeyBackup {
+        #[command(subcommand)]
+        action: KeyBackupAction,
+    },
+    /// Upload local archives missing on the storage destinations
+    Sync,
+    /// Download archives from the storage destinations and check they are restorable
+    VerifyRemote {
+        /// Verify the newest and a random pick of this many archives in total per destination
+        #[arg(long)]
+        sample: Option<usize>,
+        /// Download speed limit in bytes per second
+        #[arg(long)]
+        bandwidth_limit: Option<u64>,
+        /// Only verify the destination at this index of `storage`
+        #[arg(long)]
+        destination: Option<usize>,
+    },
+    /// Restore the newest archive into a scratch directory and run the `restore_drill` checks
+    Drill,
+    /// Compare the catalog of local archives and upload receipts against the out dir and the
+    /// destination listings, failing on any drift left
+    Reconcile {
+        /// Remove orphaned sidecar files and forget receipts of missing copies, also enabled by
+        /// `reconcile.repair`
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Load and validate the config, reporting settings that are likely a mistake
+    CheckConfig {
+        /// Fail when there are warnings
+        #[arg(long)]
+        strict: bool,
+    },
+    /// List the source files the current user cannot read, from their permissions without
+    /// reading them, failing when there are any
+    ScanPermissions,
+    /// Write a systemd service running the daemon on the config, or a timer running single
+    /// backups, only allowed to write to the directories of the config
+    InstallSystemd {
+        /// Install for the service manager of the current user
+        #[arg(long, conflicts_with = "system", required_unless_present = "system")]
+        user: bool,
+        /// Install system wide
+        #[arg(long)]
+        system: bool,
+        /// Run single backups from a timer at this systemd calendar event, e.g. `daily`, instead
+        /// of the daemon
+        #[arg(long)]
+        on_calendar: Option<String>,
+        /// Print the units instead of writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Scan the host for known application data and print suggested sources config
+    Discover {
+        /// Root directory to scan
+        #[arg(long, default_value = "/")]
+        root: PathBuf,
+    },
+}
 
-    let thread_pool = ThreadPoolBuilder::new().build().unwrap();
+#[cfg(feature = "age")]
+#[derive(Subcommand, Debug)]
+enum KeyBackupAction {
+    /// Print shares of the passphrase of the config, or of the passphrase prompted for without
+    /// one
+    Split {
+        /// Number of shares to print
+        #[arg(long)]
+        shares: u8,
+        /// Number of shares needed to recover the passphrase
+        #[arg(long)]
+        threshold: u8,
+    },
+    /// Print the passphrase recovered from shares, given as arguments or on stdin one per line
+    Combine { shares: Vec<String> },
+}
+
+/// Archive given by its path, or by its first volume when split.
+fn archive_path(path: PathBuf) -> PathBuf {
+    first_volume_archive_path(&path).unwrap_or(path)
+}
+
+fn parse_umask(s: &str) -> std::result::Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(s, 8)
+}
+
+fn restore(
+    config: Option<PathBuf>,
+    archive: &Path,
+    target: &Path,
+    identity_files: Vec<PathBuf>,
+    options: &RestoreOptions,
+) -> Result<()> {
+    let pipeline = detect_pipeline(archive)?;
+    let prompt = PromptSecretSource { identity_files };
+    let tar = match config {
+        Some(config) => open_archive(
+            archive,
+            &pipeline,
+            &ConfigSecretSource {
+                encryptor: load_config(&config)?.encryptor,
+                fallback: prompt,
+            },
+        ),
+        None => open_archive(archive, &pipeline, &prompt),
+    }
+    .with_msg(format!("Open {archive:?} failed"))?;
+    extract(tar, target, options).with_msg(format!("Restore {archive:?} failed"))?;
+    info!("Restored {archive:?} to {target:?}");
+    Ok(())
+}
+
+fn verify(
+    config: Option<PathBuf>,
+    archive: &Path,
+    identity_files: Vec<PathBuf>,
+    checksums: Option<PathBuf>,
+    sha256: Option<String>,
+) -> Result<()> {
+    let checksums = match checksums {
+        Some(path) => {
+            Some(ArchiveChecksums::read(&path).with_msg(format!("Read {path:?} failed"))?)
+        }
+        None => ArchiveChecksums::read(ArchiveChecksums::checksums_path(archive)).ok(),
+    };
+    let pipeline = match &checksums {
+        Some(checksums) => checksums.pipeline.clone(),
+        None => detect_pipeline(archive)?,
+    };
+    let options = VerifyOptions {
+        checksums,
+        archive_sha256: sha256,
+    };
+    let prompt = PromptSecretSource { identity_files };
+    let verification = match config {
+        Some(config) => verify_archive(
+            archive,
+            &pipeline,
+            &ConfigSecretSource {
+                encryptor: load_config(&config)?.encryptor,
+                fallback: prompt,
+            },
+            &options,
+        ),
+        None => verify_archive(archive, &pipeline, &prompt, &options),
+    }
+    .with_msg(format!("Verify {archive:?} failed"))?;
+    info!(
+        "{archive:?}: OK, {} entries, {} of data{}",
+        verification.entries,
+        HumanSize(verification.data_size),
+        match verification.checksums_verified {
+            true => ", checksums match",
+            false => ", no checksums to compare",
+        }
+    );
+    Ok(())
+}
+
+fn inspect(
+    config: Option<PathBuf>,
+    archive: &Path,
+    identity_files: Vec<PathBuf>,
+    decrypt: bool,
+) -> Result<()> {
+    let decrypt = decrypt || !identity_files.is_empty();
+    let prompt = PromptSecretSource { identity_files };
+    let inspection = match config {
+        Some(config) => inspect_archive(
+            archive,
+            Some(&ConfigSecretSource {
+                encryptor: load_config(&config)?.encryptor,
+                fallback: prompt,
+            }),
+        ),
+        None => inspect_archive(archive, decrypt.then_some(&prompt)),
+    }
+    .with_msg(format!("Inspect {archive:?} failed"))?;
+    println!("{archive:?}");
+    print!("{inspection}");
+    Ok(())
+}
+
+fn ls(
+    config: Option<PathBuf>,
+    archive: &Path,
+    identity_files: Vec<PathBuf>,
+    json: bool,
+) -> Result<()> {
+    let pipeline = detect_pipeline(archive)?;
+    let prompt = PromptSecretSource { identity_files };
+    let tar = match config {
+        Some(config) => open_archive(
+            archive,
+            &pipeline,
+            &ConfigSecretSource {
+                encryptor: load_config(&config)?.encryptor,
+                fallback: prompt,
+            },
+        ),
+        None => open_archive(archive, &pipeline, &prompt),
+    }
+    .with_msg(format!("Open {archive:?} failed"))?;
+    list_entries(tar, |entry| {
+        match json {
+            true => println!("{}", serde_json::to_string(&entry)?),
+            false => println!(
+                "{:>12}  {}  {}{}",
+                entry.size,
+                DateTime::from_timestamp(entry.mtime as i64, 0)
+                    .unwrap_or_default()
+                    .format("%Y-%m-%d %H:%M:%S"),
+                entry.path.display(),
+                if entry.is_dir { "/" } else { "" }
+            ),
+        }
+        Ok(())
+    })
+    .with_msg(format!("List {archive:?} failed"))
+}
+
+#[cfg(feature = "age")]
+fn key_backup(config: Option<PathBuf>, action: KeyBackupAction) -> Result<()> {
+    use k_backup::backup::restore::SecretSource;
+    use secrecy::ExposeSecret;
+    use std::io::BufRead;
+
+    match action {
+        KeyBackupAction::Split { shares, threshold } => {
+            let passphrase = match config {
+                Some(config) => match load_config(&config)?.encryptor.as_ref() {
+                    EncryptorConfig::Age(age) => age.passphrase().ok_or_else(|| {
+                        std::io::Error::other(
+                            "config encrypts to recipients, back up their identity files instead",
+                        )
+                    })?,
+                    EncryptorConfig::None => {
+                        Err(std::io::Error::other("config does not encrypt archives"))?
+                    }
+                },
+                None => PromptSecretSource::default().passphrase()?,
+            };
+            let shares = split_passphrase(&passphrase, threshold, shares)?;
+            println!(
+                "Any {threshold} of these {} shares recover the passphrase with \
+                 `k_backup key-backup combine`. Store them apart.",
+                shares.len()
+            );
+            for share in shares.iter() {
+                println!("\nShare {} of {}:\n{share}", share.index, shares.len());
+            }
+            Ok(())
+        }
+        KeyBackupAction::Combine { shares } => {
+            let lines = match shares.is_empty() {
+                true => std::io::stdin()
+                    .lock()
+                    .lines()
+                    .collect::<std::io::Result<Vec<_>>>()?,
+                false => shares,
+            };
+            let shares = lines
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.parse::<PassphraseShare>())
+                .collect::<Result<Vec<_>>>()?;
+            println!("{}", combine_shares(&shares)?.expose_secret());
+            Ok(())
+        }
+    }
+}
+
+fn print_dry_run(config: &BackupConfig) -> Result<()> {
+    let dry_run = config.dry_run(Utc::now())?;
+    println!("Archive {:?} would include:", config.archive_base_name);
+    for entry in dry_run.entries.iter() {
+        match entry.size {
+            Some(size) => println!("  {:?} ({})", entry.dst, HumanSize(size)),
+            None => println!("  {:?} (output of {:?})", entry.dst, entry.src),
+        }
+    }
+    let total: u64 = dry_run.entries.iter().filter_map(|e| e.size).sum();
+    println!(
+        "{} entries, {} before compression",
+        dry_run.entries.len(),
+        HumanSize(total)
+    );
+    for e in dry_run.errors.iter() {
+        println!("Would skip: {e}");
+    }
+    match dry_run.out_of_retention.is_empty() {
+        true => println!("Retention would delete nothing"),
+        false => println!("Retention would delete:"),
+    }
+    for archive in dry_run.out_of_retention.iter() {
+        println!("  {archive:?}");
+    }
+    Ok(())
+}
+
+/// Print the unreadable source paths of `config`, returning how many.
+fn print_permission_scan(config: &BackupConfig) -> usize {
+    let scan = config.permission_scan();
+    println!("Permissions of {:?}:", config.archive_base_name);
+    for path in scan.unreadable_files.iter() {
+        println!("  unreadable {path:?}");
+    }
+    for e in scan.errors.iter() {
+        println!("  unreadable: {e}");
+    }
+    println!(
+        "{} files scanned, {} paths unreadable",
+        scan.scanned,
+        scan.unreadable_count()
+    );
+    scan.unreadable_count()
+}
 
-    let res = File::open(&args.config)
+fn install_systemd(units: &SystemdUnits, scope: SystemdScope, dry_run: bool) -> Result<()> {
+    if dry_run {
+        println!("# {}.service\n{}", units.name, units.service);
+        if let Some(timer) = &units.timer {
+            println!("# {}.timer\n{timer}", units.name);
+        }
+        return Ok(());
+    }
+    for path in units.install(scope)? {
+        info!("Wrote {path:?}");
+    }
+    info!("Enable with: {}", units.enable_command(scope));
+    Ok(())
+}
+
+fn print_catalog(jobs: &[BackupConfig], json: bool) -> Result<()> {
+    if json {
+        let catalogs = jobs
+            .iter()
+            .map(|job| Ok((job.archive_base_name.clone(), job.catalog_entries()?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        println!("{}", serde_json::to_string_pretty(&catalogs)?);
+        return Ok(());
+    }
+    for job in jobs {
+        let entries = job.catalog_entries()?;
+        println!("Backups of {:?}:", job.archive_base_name);
+        for entry in entries.iter() {
+            let status = match entry.status {
+                CatalogStatus::Succeeded => "ok",
+                CatalogStatus::SucceededWithErrors => "errors",
+                CatalogStatus::Failed => "failed",
+            };
+            let entries: u64 = entry.sources.iter().map(|s| s.entries).sum();
+            println!(
+                "  {}  {status:<6}  {:>10}  {:>8}  {:>8} entries  {}",
+                entry.backup_time.format("%Y-%m-%d %H:%M:%S UTC"),
+                entry
+                    .size
+                    .map(|s| HumanSize(s).to_string())
+                    .unwrap_or_default(),
+                HumanDuration(entry.duration).to_string(),
+                entries,
+                entry
+                    .file_name
+                    .as_deref()
+                    .or(entry
+                        .errors
+                        .first()
+                        .and_then(|e| e.lines().last())
+                        .map(str::trim))
+                    .unwrap_or_default()
+            );
+        }
+        println!("{} backups", entries.len());
+    }
+    Ok(())
+}
+
+fn print_retention_plan(config: &BackupConfig) -> Result<()> {
+    let plan = config.retention_plan(Utc::now())?;
+    println!("Retention of {:?}:", config.archive_base_name);
+    for (archive, verdict) in plan.iter() {
+        let action = match verdict.is_kept() {
+            true => "keep",
+            false => "delete",
+        };
+        println!("  {action:<6} {archive:?}: {verdict}");
+    }
+    println!(
+        "{} archives, {} would be deleted",
+        plan.len(),
+        plan.iter().filter(|(_, v)| !v.is_kept()).count()
+    );
+    Ok(())
+}
+
+fn trigger(bc: &BackupConfig) -> Result<()> {
+    match send_trigger(&control_socket_path(&bc.state_dir_path()))? {
+        TriggerResponse::Created(file_path) => info!("Created backup file: {file_path:?}"),
+        TriggerResponse::Skipped => info!("Backup of {:?} skipped", bc.archive_base_name),
+        TriggerResponse::Failed(e) => {
+            return Err(std::io::Error::other(format!("triggered backup failed: {e}")).into())
+        }
+    }
+    Ok(())
+}
+
+/// Path given with `--config`, for commands that need one.
+fn require_config_path(config: Option<PathBuf>) -> Result<PathBuf> {
+    config.ok_or_else(|| std::io::Error::other("--config is required").into())
+}
+
+/// Config loaded from `--config`, for commands that need one.
+fn require_config(config: Option<PathBuf>) -> Result<BackupConfig> {
+    load_config(&require_config_path(config)?)
+}
+
+fn load_config(path: &Path) -> Result<BackupConfig> {
+    load_config_with_warnings(path).map(|(bc, _)| bc)
+}
+
+/// Load and validate the config, logging the warnings of the sanity checks.
+fn load_config_with_warnings(path: &Path) -> Result<(BackupConfig, Vec<String>)> {
+    let mut value = File::open(path).map_err(Error::from).and_then(|f| {
+        serde_yml::from_reader::<_, serde_yml::Value>(f)
+            .map_err(Error::from)
+            .with_msg(format!("Parse YAML config failed: {:?}", path))
+    })?;
+    expand_source_paths(&mut value)
+        .with_msg(format!("Expand source paths of config failed: {:?}", path))?;
+    let mut warnings = unused_secret_warnings(&value);
+    let bc = serde_yml::from_value::<BackupConfig>(value)
         .map_err(Error::from)
-        .and_then(|f| {
-            serde_yml::from_reader::<_, BackupConfig>(f)
-                .map_err(Error::from)
-                .with_msg(format!("Parse YAML config failed: {:?}", &args.config))
-        })
+        .with_msg(format!("Parse YAML config failed: {:?}", path))
         .and_then(|bc| {
             bc.validate()
                 .map_err(Error::from)
                 .map(|_| bc)
-                .with_msg(format!("Config validation failed: {:?}", &args.config))
-        })
-        .and_then(|bc| bc.start_loop(thread_pool.into()));
+                .with_msg(format!("Config validation failed: {:?}", path))
+        })?;
+    warnings.extend(sanity_warnings(&bc));
+    for warning in warnings.iter() {
+        warn!("Config {path:?}: {warning}");
+    }
+    Ok((bc, warnings))
+}
+
+fn main() {
+    // Closed backup, stage and source spans log their duration with their fields
+    tracing_subscriber::fmt()
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+    let args = Args::parse();
 
-    match res {
-        Ok(_) => error!("Loop should never break without error"),
-        Err(e) => error!("{e}"),
+    if let Some(command) = args.command {
+        let res = match command {
+            Command::Run { dry_run: true, .. } => require_config(args.config)
+                .and_then(|bc| bc.archive_jobs().iter().try_for_each(print_dry_run)),
+            Command::Run {
+                scheduled: true, ..
+            } => require_config(args.config).and_then(|bc| {
+                let thread_pool = Arc::new(ThreadPoolBuilder::new().build().unwrap());
+                bc.archive_jobs()
+                    .iter()
+                    .try_for_each(|job| job.run_scheduled(thread_pool.clone()).map(|_| ()))
+            }),
+            Command::Run { comment, .. } => require_config(args.config).and_then(|mut bc| {
+                if let Some(comment) = comment {
+                    bc.description = Some(comment.into());
+                }
+                let thread_pool = Arc::new(ThreadPoolBuilder::new().build().unwrap());
+                bc.archive_jobs()
+                    .iter()
+                    .try_for_each(|job| job.run_once(thread_pool.clone()).map(|_| ()))
+            }),
+            Command::Trigger => require_config(args.config)
+                .and_then(|bc| bc.archive_jobs().iter().try_for_each(trigger)),
+            Command::List { json } => {
+                require_config(args.config).and_then(|bc| print_catalog(&bc.archive_jobs(), json))
+            }
+            Command::Prune { dry_run: true } => require_config(args.config)
+                .and_then(|bc| bc.archive_jobs().iter().try_for_each(print_retention_plan)),
+            Command::Prune { dry_run: false } => require_config(args.config)
+                .and_then(|bc| {
+                    if args.accept_existing_files {
+                        bc.archive_jobs()
+                            .iter()
+                            .try_for_each(BackupConfig::confirm_out_dir)?;
+                    }
+                    let thread_pool = ThreadPoolBuilder::new().build().unwrap();
+                    bc.archive_jobs()
+                        .iter()
+                        .map(|job| job.prune(Utc::now(), &thread_pool))
+                        .flatten_ok()
+                        .collect::<Result<Vec<_>>>()
+                })
+                .map(|pruned| info!("Pruned {} archives out of retention", pruned.len())),
+            Command::Restore {
+                archive,
+                target,
+                identity_files,
+                chown,
+                strip_components,
+                umask,
+            } => restore(
+                args.config,
+                &archive_path(archive),
+                &target,
+                identity_files,
+                &RestoreOptions {
+                    chown,
+                    strip_components,
+                    umask,
+                },
+            ),
+            Command::Verify {
+                archive,
+                identity_files,
+                checksums,
+                sha256,
+            } => verify(
+                args.config,
+                &archive_path(archive),
+                identity_files,
+                checksums,
+                sha256,
+            ),
+            Command::Inspect {
+                archive,
+                identity_files,
+                decrypt,
+            } => inspect(args.config, &archive_path(archive), identity_files, decrypt),
+            Command::Ls {
+                archive,
+                identity_files,
+                json,
+            } => ls(args.config, &archive_path(archive), identity_files, json),
+            #[cfg(feature = "age")]
+            Command::KeyBackup { action } => key_backup(args.config, action),
+            Command::Sync => require_config(args.config)
+                .and_then(|bc| {
+                    bc.archive_jobs()
+                        .iter()
+                        .map(|job| job.sync_storage())
+                        .flatten_ok()
+                        .collect::<Result<Vec<_>>>()
+                })
+                .map(|uploaded| info!("Uploaded {} missing archives", uploaded.len())),
+            Command::VerifyRemote {
+                sample,
+                bandwidth_limit,
+                destination,
+            } => require_config(args.config)
+                .and_then(|bc| {
+                    let options = RemoteVerifyOptions {
+                        sample,
+                        bandwidth_limit,
+                        destination,
+                    };
+                    bc.archive_jobs()
+                        .iter()
+                        .map(|job| job.verify_remote(&options))
+                        .flatten_ok()
+                        .collect::<Result<Vec<_>>>()
+                })
+                .and_then(|verifications| {
+                    let mut failed = 0;
+                    for v in verifications.iter() {
+                        match &v.result {
+                            Ok(size) => {
+                                info!(
+                                    "Destination {} {:?}: OK, {}",
+                                    v.destination,
+                                    v.file_name,
+                                    HumanSize(*size)
+                                )
+                            }
+                            Err(e) => {
+                                failed += 1;
+                                error!("Destination {} {:?}: {e}", v.destination, v.file_name)
+                            }
+                        }
+                    }
+                    match failed {
+                        0 => Ok(()),
+                        _ => Err(std::io::Error::other(format!(
+                            "{failed} of {} remote archives failed verification",
+                            verifications.len()
+                        ))
+                        .into()),
+                    }
+                }),
+            Command::Drill => require_config(args.config).and_then(|bc| {
+                bc.archive_jobs()
+                    .iter()
+                    .try_for_each(|job| job.run_restore_drill().map(|_| ()))
+            }),
+            Command::Reconcile { repair } => require_config(args.config)
+                .and_then(|bc| {
+                    bc.archive_jobs()
+                        .iter()
+                        .map(|job| {
+                            let _lock = job.lock_archive_base_name()?;
+                            job.run_reconcile(
+                                repair
+                                    || job
+                                        .reconcile
+                                        .as_ref()
+                                        .is_some_and(|r| r.repair.unwrap_or(false)),
+                            )
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .and_then(|reports| {
+                    let left: usize = reports
+                        .iter()
+                        .map(|r| r.drifts.len() - r.repaired + r.unchecked_destinations.len())
+                        .sum();
+                    match left {
+                        0 => Ok(()),
+                        _ => Err(std::io::Error::other(format!(
+                            "{left} drifts left unrepaired or destinations unchecked"
+                        ))
+                        .into()),
+                    }
+                }),
+            Command::CheckConfig { strict } => {
+                require_config_path(args.config).and_then(|config| {
+                    let (_, warnings) = load_config_with_warnings(&config)?;
+                    info!("Config {config:?} is valid, {} warnings", warnings.len());
+                    match strict && !warnings.is_empty() {
+                        true => Err(std::io::Error::other("config has warnings").into()),
+                        false => Ok(()),
+                    }
+                })
+            }
+            Command::ScanPermissions => require_config(args.config).and_then(|bc| {
+                let unreadable: usize = bc.archive_jobs().iter().map(print_permission_scan).sum();
+                match unreadable {
+                    0 => Ok(()),
+                    _ => Err(std::io::Error::other(format!(
+                        "{unreadable} source paths are unreadable"
+                    ))
+                    .into()),
+                }
+            }),
+            Command::InstallSystemd {
+                user,
+                on_calendar,
+                dry_run,
+                ..
+            } => {
+                let scope = match user {
+                    true => SystemdScope::User,
+                    false => SystemdScope::System,
+                };
+                let mode = match on_calendar {
+                    Some(on_calendar) => SystemdRunMode::Timer { on_calendar },
+                    None => SystemdRunMode::Daemon {
+                        extra_args: [
+                            (args.accept_existing_files, "--accept-existing-files"),
+                            (args.watch_config, "--watch-config"),
+                        ]
+                        .into_iter()
+                        .filter(|(set, _)| *set)
+                        .map(|(_, arg)| arg.to_string())
+                        .collect(),
+                    },
+                };
+                require_config_path(args.config)
+                    .and_then(|config| Ok(std::path::absolute(config)?))
+                    .and_then(|config| {
+                        let bc = load_config(&config)?;
+                        let executable = std::env::current_exe()?;
+                        let units = SystemdUnits::new(&executable, &config, &bc, scope, &mode);
+                        install_systemd(&units, scope, dry_run)
+                    })
+            }
+            Command::Discover { root } => {
+                to_config_snippet(&discover(root)).map(|snippet| print!("{snippet}"))
+            }
+        };
+        if let Err(e) = res {
+            error!("{e}");
+            exit(1);
+        }
+        return;
+    }
+
+    let thread_pool = ThreadPoolBuilder::new().build().unwrap();
+    let config = args.config.expect("config is required without subcommand");
+
+    if let Err(e) = run_daemon(
+        &config,
+        args.accept_existing_files,
+        args.watch_config,
+        thread_pool.into(),
+    ) {
+        error!("{e}");
     }
 
     exit(1);
 }
+
+/// Run the schedule of the config at `path`, starting over with the reloaded config on SIGHUP or,
+/// with `--watch-config`, when the file changes. A config failing to load or validate is
+/// reported and the running one kept.
+fn run_daemon(
+    path: &Path,
+    accept_existing_files: bool,
+    watch_config: bool,
+    pre_process_pool: Arc<ThreadPool>,
+) -> Result<()> {
+    listen_for_sighup();
+    let mut reload = ReloadWatch::new(path, watch_config, false);
+    let mut bc = load_config(path)?;
+    if accept_existing_files {
+        bc.archive_jobs()
+            .iter()
+            .try_for_each(BackupConfig::confirm_out_dir)?;
+    }
+    loop {
+        bc.clone()
+            .with_reload(reload.into())
+            .start_loop(pre_process_pool.clone())?;
+        // Created before loading, a change made meanwhile triggers another reload
+        reload = ReloadWatch::new(path, watch_config, true);
+        match load_config(path) {
+            Ok(reloaded) => {
+                info!("Reloaded config {path:?}");
+                bc = reloaded;
+            }
+            Err(e) => error!("Reload failed, keeping the running config: {e}"),
+        }
+    }
+}
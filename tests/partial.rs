@@ -0,0 +1,58 @@
+//! Archives created with non-fatal errors and renamed `-partial` by `mark_partial`.
+use k_backup::backup::backup_config::{is_partial_archive, BackupConfig};
+use k_backup::backup::volume::{open_archive_file, volume_paths, VolumeManifest};
+use rayon::ThreadPoolBuilder;
+use std::sync::Arc;
+
+#[test]
+fn split_archive_is_marked_partial_with_its_volumes_and_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let src_dir = dir.path().join("src");
+    std::fs::create_dir(&src_dir).unwrap();
+    let data = (0..30_000u32)
+        .map(|i| (i * 7919 % 251) as u8)
+        .collect::<Vec<_>>();
+    std::fs::write(src_dir.join("big"), &data).unwrap();
+    // The failing command source is a non-fatal error, leaving the glob source archived
+    let config: BackupConfig = serde_yml::from_str(&format!(
+        "{{cron: '0 1 * * *', archive_base_name: backup, out_dir: {:?}, mark_partial: true, \
+         split_size: 8000, files: [{{type: glob, src_dir: {:?}, globset: ['**/*']}}, \
+         {{type: command, command: 'false', dst: fail.out}}], \
+         encryptor: {{encryptor_type: none}}, compressor: {{compressor_type: none}}}}",
+        dir.path().join("out"),
+        src_dir,
+    ))
+    .unwrap();
+    std::fs::create_dir(&config.out_dir).unwrap();
+
+    let pool = Arc::new(ThreadPoolBuilder::new().build().unwrap());
+    let archive_path = config.run_once(pool).unwrap();
+    assert!(is_partial_archive(&archive_path), "{archive_path:?}");
+    let volumes = volume_paths(&archive_path);
+    assert!(volumes.len() > 1);
+    let manifest = VolumeManifest::read(VolumeManifest::volumes_path(&archive_path)).unwrap();
+    assert_eq!(manifest.volumes.len(), volumes.len());
+
+    // Nothing is left under the name before the rename
+    let names = std::fs::read_dir(&config.out_dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("backup."))
+        .collect::<Vec<_>>();
+    assert!(
+        names.iter().all(|name| name.contains("-partial")),
+        "{names:?}"
+    );
+
+    let mut archive = tar::Archive::new(open_archive_file(&archive_path).unwrap());
+    let mut restored = None;
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        if entry.path().unwrap().ends_with("big") {
+            let mut content = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut content).unwrap();
+            restored = Some(content);
+        }
+    }
+    assert_eq!(restored, Some(data));
+}
@@ -0,0 +1,175 @@
+//! Archives crafted to write outside the restore target through `..`, symlink chains or
+//! symlinks planted in the target beforehand.
+use k_backup::backup::pack::{PackIndex, PackedFile, PACK_BLOB_EXT, PACK_INDEX_EXT};
+use k_backup::backup::restore::{extract, OwnerSpec, RestoreOptions};
+use k_backup::backup::result_error::result::Result;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+enum Entry<'a> {
+    Dir(&'a str),
+    File(&'a str, &'a [u8]),
+    Symlink(&'a str, &'a str),
+}
+
+/// Restore target `target` in a fresh directory holding `outside.txt` next to it.
+fn sandbox() -> (TempDir, PathBuf) {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join("outside.txt"), "outside").unwrap();
+    let target = root.path().join("target");
+    std::fs::create_dir(&target).unwrap();
+    (root, target)
+}
+
+fn archive(entries: &[Entry]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(1);
+        header.set_uid(0);
+        header.set_gid(0);
+        match entry {
+            Entry::Dir(path) => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o700);
+                header.set_size(0);
+                builder.append_data(&mut header, path, &[][..]).unwrap();
+            }
+            Entry::File(path, data) => {
+                header.set_mode(0o600);
+                header.set_size(data.len() as u64);
+                builder.append_data(&mut header, path, *data).unwrap();
+            }
+            Entry::Symlink(path, link) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(0o777);
+                header.set_size(0);
+                builder.append_link(&mut header, path, link).unwrap();
+            }
+        }
+    }
+    builder.into_inner().unwrap()
+}
+
+/// A pack of a single file `path` holding `data`, as the pack writer stores it.
+fn pack(path: &str, data: &[u8]) -> Vec<u8> {
+    let index = PackIndex {
+        files: vec![PackedFile {
+            path: Path::new(path).into(),
+            offset: 0,
+            size: data.len() as u64,
+            mode: 0o600,
+            mtime: 1,
+            uid: 0,
+            gid: 0,
+        }],
+    };
+    let index_json = serde_json::to_vec(&index).unwrap();
+    let index_path = PackIndex::entry_path(0, PACK_INDEX_EXT);
+    let blob_path = PackIndex::entry_path(0, PACK_BLOB_EXT);
+    archive(&[
+        Entry::File(index_path.to_str().unwrap(), &index_json),
+        Entry::File(blob_path.to_str().unwrap(), data),
+    ])
+}
+
+fn restore(data: &[u8], target: &Path) -> Result<()> {
+    // Owners left as they are, restoring them would need root
+    let options = RestoreOptions {
+        chown: Some(OwnerSpec {
+            uid: None,
+            gid: None,
+        }),
+        ..Default::default()
+    };
+    extract(tar::Archive::new(data), target, &options)
+}
+
+fn assert_escape_rejected(result: Result<()>) {
+    let error = format!("{:?}", result.unwrap_err());
+    assert!(error.contains("restore target"), "{error}");
+}
+
+fn assert_outside_untouched(root: &Path) {
+    let outside = root.join("outside.txt");
+    assert_eq!(std::fs::read_to_string(&outside).unwrap(), "outside");
+}
+
+#[test]
+fn symlinks_within_target_are_restored() {
+    let (_root, target) = sandbox();
+    let data = archive(&[
+        Entry::Dir("sub"),
+        Entry::Symlink("a", "sub"),
+        Entry::Symlink("sub/up", ".."),
+        Entry::File("a/file", b"data"),
+    ]);
+    restore(&data, &target).unwrap();
+    assert_eq!(std::fs::read(target.join("sub/file")).unwrap(), b"data");
+    assert_eq!(
+        std::fs::read_link(target.join("sub/up")).unwrap(),
+        Path::new("..")
+    );
+}
+
+#[test]
+fn parent_dir_entries_are_rejected() {
+    let (root, target) = sandbox();
+    // Set in the raw header, the builder refuses `..`
+    let mut header = tar::Header::new_gnu();
+    header.as_old_mut().name[..14].copy_from_slice(b"../outside.txt");
+    header.set_mode(0o600);
+    header.set_mtime(1);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_size(7);
+    header.set_cksum();
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.append(&header, &b"escaped"[..]).unwrap();
+    let data = builder.into_inner().unwrap();
+    assert_escape_rejected(restore(&data, &target));
+    assert_outside_untouched(root.path());
+}
+
+#[test]
+fn chained_symlinks_cannot_escape() {
+    for link in [
+        // Each link passes on its path, together `target/l` points to the parent of target
+        [Entry::Symlink("a", "."), Entry::Symlink("a/l", "..")],
+        [Entry::Symlink("a", "."), Entry::Symlink("l", "a/..")],
+    ] {
+        let (root, target) = sandbox();
+        let mut entries = Vec::from(link);
+        entries.push(Entry::Dir("l"));
+        entries.push(Entry::File("l/outside.txt", b"escaped"));
+        assert_escape_rejected(restore(&archive(&entries), &target));
+        assert!(!target.join("l").exists());
+        assert_outside_untouched(root.path());
+    }
+}
+
+#[test]
+fn directory_over_planted_symlink_is_rejected() {
+    let (root, target) = sandbox();
+    symlink(root.path(), target.join("l")).unwrap();
+    let before = std::fs::metadata(root.path()).unwrap().modified().unwrap();
+    assert_escape_rejected(restore(&archive(&[Entry::Dir("l")]), &target));
+    let after = std::fs::metadata(root.path()).unwrap().modified().unwrap();
+    assert_eq!(before, after);
+}
+
+#[test]
+fn pack_file_over_planted_symlink_is_rejected() {
+    let (root, target) = sandbox();
+    symlink(root.path().join("outside.txt"), target.join("f")).unwrap();
+    assert_escape_rejected(restore(&pack("f", b"escaped"), &target));
+    assert_outside_untouched(root.path());
+}
+
+#[test]
+fn pack_files_are_restored() {
+    let (_root, target) = sandbox();
+    restore(&pack("dir/f", b"packed"), &target).unwrap();
+    assert_eq!(std::fs::read(target.join("dir/f")).unwrap(), b"packed");
+}
@@ -0,0 +1,98 @@
+//! ISO week buckets of the weekly tier, alone and below the monthly tier.
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use k_backup::backup::retention::{
+    ItemWithDateTime, RetentionConfig, RetentionTier, RetentionVerdict,
+};
+use std::sync::Arc;
+
+static DAY: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+fn at(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(year, month, day, 1, 0, 0).unwrap()
+}
+
+/// Verdicts of one backup a day from `first` to `last`, classified the day after `last`.
+fn verdicts(
+    config: &RetentionConfig,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+) -> Vec<(NaiveDate, RetentionVerdict)> {
+    let backups = (0..=(last - first).num_days())
+        .map(|days| Arc::new(ItemWithDateTime::from(first + Duration::days(days))))
+        .collect::<Vec<_>>();
+    config
+        .classify(backups, last + Duration::days(1), |_: &()| true)
+        .map(|(backup, verdict)| (backup.date_time.date_naive(), verdict))
+        .collect()
+}
+
+fn kept(verdicts: &[(NaiveDate, RetentionVerdict)]) -> Vec<(String, RetentionVerdict)> {
+    verdicts
+        .iter()
+        .filter(|(_, verdict)| verdict.is_kept())
+        .map(|(date, verdict)| (date.to_string(), *verdict))
+        .collect()
+}
+
+#[test]
+fn weekly_buckets_span_the_year_boundary() {
+    // 2020-12-28 to 2021-01-03 is week 53 of 2020
+    let config = RetentionConfig {
+        weekly_retention: Some(DAY * 60),
+        ..Default::default()
+    };
+    let verdicts = verdicts(&config, at(2020, 12, 20), at(2021, 1, 10));
+    let weekly = RetentionVerdict::Tier(RetentionTier::Weekly);
+    assert_eq!(
+        kept(&verdicts),
+        [
+            ("2021-01-10".to_string(), weekly),
+            ("2021-01-03".to_string(), weekly),
+            ("2020-12-27".to_string(), weekly),
+            ("2020-12-20".to_string(), weekly),
+        ]
+    );
+}
+
+#[test]
+fn monthly_tier_keeps_the_newest_of_a_month_inside_a_kept_week() {
+    // 2020-12-31 is the newest of December but not of its week, kept by the monthly tier only
+    let config = RetentionConfig {
+        weekly_retention: Some(DAY * 60),
+        monthly_retention: Some(DAY * 365),
+        ..Default::default()
+    };
+    let verdicts = verdicts(&config, at(2020, 12, 20), at(2021, 1, 10));
+    let weekly = RetentionVerdict::Tier(RetentionTier::Weekly);
+    let monthly = RetentionVerdict::Tier(RetentionTier::Monthly);
+    assert_eq!(
+        kept(&verdicts),
+        [
+            ("2021-01-10".to_string(), monthly),
+            ("2021-01-03".to_string(), weekly),
+            ("2020-12-31".to_string(), monthly),
+            ("2020-12-27".to_string(), weekly),
+            ("2020-12-20".to_string(), weekly),
+        ]
+    );
+}
+
+#[test]
+fn weekly_tier_stops_at_its_retention() {
+    let config = RetentionConfig {
+        weekly_retention: Some(DAY * 10),
+        monthly_retention: Some(DAY * 365),
+        ..Default::default()
+    };
+    let verdicts = verdicts(&config, at(2020, 12, 20), at(2021, 1, 10));
+    let weekly = RetentionVerdict::Tier(RetentionTier::Weekly);
+    let monthly = RetentionVerdict::Tier(RetentionTier::Monthly);
+    assert_eq!(
+        kept(&verdicts),
+        [
+            ("2021-01-10".to_string(), monthly),
+            ("2021-01-03".to_string(), weekly),
+            ("2020-12-31".to_string(), monthly),
+        ]
+    );
+}
@@ -0,0 +1,136 @@
+//! Local time cron schedules around the daylight saving time changes of New York in 2021,
+//! spring-forward on March 14 at 2:00 and fall-back on November 7 at 2:00.
+use chrono::{DateTime, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use k_backup::backup::clock::CronTimezone;
+
+/// New York in 2021, given to the schedule as the local time zone so tests never touch `TZ`.
+#[derive(Clone, Copy, Debug)]
+struct NewYork;
+
+impl NewYork {
+    fn offset(dst: bool) -> FixedOffset {
+        FixedOffset::west_opt(if dst { 4 } else { 5 } * 3600).unwrap()
+    }
+}
+
+impl TimeZone for NewYork {
+    type Offset = FixedOffset;
+
+    fn from_offset(_offset: &FixedOffset) -> Self {
+        NewYork
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+        self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+        let mut offsets = [Self::offset(true), Self::offset(false)]
+            .into_iter()
+            .filter(|offset| self.offset_from_utc_datetime(&(*local - *offset)) == *offset);
+        match (offsets.next(), offsets.next()) {
+            (Some(first), Some(second)) => LocalResult::Ambiguous(first, second),
+            (Some(offset), None) => LocalResult::Single(offset),
+            _ => LocalResult::None,
+        }
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+        self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    fn offset_from_utc_datetime(&self, at: &NaiveDateTime) -> FixedOffset {
+        // From 2:00 EST on March 14 to 2:00 EDT on November 7
+        let dst = (utc(3, 14, 7, 0).naive_utc()..utc(11, 7, 6, 0).naive_utc()).contains(at);
+        Self::offset(dst)
+    }
+}
+
+fn utc(month: u32, day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2021, month, day, hour, min, 0)
+        .unwrap()
+}
+
+/// Runs of `cron` after `after` up to `until`, New York being the local time zone.
+fn runs(
+    timezone: CronTimezone,
+    cron: &str,
+    after: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Vec<DateTime<Utc>> {
+    std::iter::successors(Some(after), |after| {
+        Some(timezone.next_run_in(cron, after, &NewYork).unwrap())
+    })
+    .skip(1)
+    .take_while(|run| *run <= until)
+    .collect()
+}
+
+#[test]
+fn local_time_follows_the_offset_change() {
+    let timezone = CronTimezone::Local;
+    assert_eq!(
+        runs(timezone, "0 1 * * *", utc(3, 12, 12, 0), utc(3, 16, 12, 0)),
+        [
+            utc(3, 13, 6, 0),
+            utc(3, 14, 6, 0),
+            utc(3, 15, 5, 0),
+            utc(3, 16, 5, 0)
+        ]
+    );
+}
+
+#[test]
+fn time_skipped_by_spring_forward_does_not_run() {
+    let timezone = CronTimezone::Local;
+    assert_eq!(
+        runs(timezone, "30 2 * * *", utc(3, 12, 12, 0), utc(3, 16, 12, 0)),
+        [utc(3, 13, 7, 30), utc(3, 15, 6, 30), utc(3, 16, 6, 30)]
+    );
+}
+
+#[test]
+fn time_repeated_by_fall_back_runs_once_at_first_occurrence() {
+    let timezone = CronTimezone::Local;
+    assert_eq!(
+        runs(timezone, "30 1 * * *", utc(11, 5, 12, 0), utc(11, 8, 12, 0)),
+        [utc(11, 6, 5, 30), utc(11, 7, 5, 30), utc(11, 8, 6, 30)]
+    );
+    // Within the repeated hour 1:30 already ran, even if the run was missed
+    assert_eq!(
+        timezone
+            .next_run_in("30 1 * * *", &utc(11, 7, 6, 15), &NewYork)
+            .unwrap(),
+        utc(11, 8, 6, 30)
+    );
+}
+
+#[test]
+fn hourly_runs_skip_the_repeated_hour() {
+    let timezone = CronTimezone::Local;
+    // 0:00 EDT, 1:00 EDT, then 2:00 EST, 1:00 EST already ran
+    assert_eq!(
+        runs(timezone, "0 * * * *", utc(11, 7, 3, 30), utc(11, 7, 7, 0)),
+        [utc(11, 7, 4, 0), utc(11, 7, 5, 0), utc(11, 7, 7, 0)]
+    );
+    // Started within the repeated hour, the next run is the following local hour
+    assert_eq!(
+        timezone
+            .next_run_in("0 * * * *", &utc(11, 7, 5, 30), &NewYork)
+            .unwrap(),
+        utc(11, 7, 7, 0)
+    );
+}
+
+#[test]
+fn utc_ignores_the_local_time_zone() {
+    assert_eq!(
+        runs(
+            CronTimezone::Utc,
+            "30 6 * * *",
+            utc(3, 13, 12, 0),
+            utc(3, 15, 12, 0)
+        ),
+        [utc(3, 14, 6, 30), utc(3, 15, 6, 30)]
+    );
+}